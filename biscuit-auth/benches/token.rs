@@ -5,9 +5,11 @@ extern crate biscuit_auth as biscuit;
 
 use bencher::Bencher;
 use biscuit::{
-    builder::*, builder_ext::BuilderExt, datalog::SymbolTable, Biscuit, KeyPair, UnverifiedBiscuit,
+    builder::*, builder_ext::BuilderExt, datalog::SymbolTable, Authorizer, AuthorizerLimits,
+    Biscuit, KeyPair, UnverifiedBiscuit,
 };
 use rand::rngs::OsRng;
+use std::time::Duration;
 
 fn create_block_1(b: &mut Bencher) {
     let mut rng = OsRng;
@@ -548,6 +550,98 @@ fn checks_block_verify_only2(b: &mut Bencher) {
     });
 }
 
+/// token where each block delegates a right with a rule trusting the previous
+/// blocks, so generated facts come from many blocks
+fn delegation_token(root: &KeyPair, blocks: usize) -> Vec<u8> {
+    let mut rng: OsRng = OsRng;
+
+    let mut builder = Biscuit::builder();
+    builder
+        .add_code(r#"right("file1", "read", 0); right("file2", "read", 0);"#)
+        .unwrap();
+    let mut token = builder
+        .build_with_rng(root, SymbolTable::default(), &mut rng)
+        .unwrap();
+
+    for i in 1..blocks {
+        let mut block_builder = BlockBuilder::new();
+        block_builder
+            .add_code(format!(
+                r#"
+                delegated("file1", {i});
+                right($file, $op, {i}) <- right($file, $op, {previous}), delegated($file, {i}) trusting previous;
+                check if resource($file), operation($op), right($file, $op, {previous}) trusting previous;
+                "#,
+                i = i,
+                previous = i - 1
+            ))
+            .unwrap();
+        token = token
+            .append_with_keypair(&KeyPair::new_with_rng(&mut rng), block_builder)
+            .unwrap();
+    }
+
+    token.to_vec().unwrap()
+}
+
+fn authorize_delegation(b: &mut Bencher, blocks: usize) {
+    let root = KeyPair::new_with_rng(&mut OsRng);
+    let data = delegation_token(&root, blocks);
+    let token = Biscuit::from(&data, root.public()).unwrap();
+    let limits = AuthorizerLimits {
+        max_facts: 10000,
+        max_iterations: 1000,
+        max_time: Duration::from_secs(1),
+    };
+
+    let authorize = || {
+        let mut verifier = token.authorizer().unwrap();
+        verifier
+            .add_code(r#"resource("file1"); operation("read"); allow if true;"#)
+            .unwrap();
+        verifier.authorize_with_limits(limits.clone()).unwrap();
+    };
+    authorize();
+
+    b.bytes = data.len() as u64;
+    b.iter(authorize);
+}
+
+fn authorize_block_10(b: &mut Bencher) {
+    authorize_delegation(b, 10)
+}
+
+fn authorize_block_30(b: &mut Bencher) {
+    authorize_delegation(b, 30)
+}
+
+fn authorize_block_80(b: &mut Bencher) {
+    authorize_delegation(b, 80)
+}
+
+/// authorizer with 10k distinct strings, converted to datalog when authorizing
+fn authorize_10k_symbols(b: &mut Bencher) {
+    let mut authorizer = Authorizer::new();
+    for i in 0..10000 {
+        authorizer
+            .add_fact(fact("user", &[string(&format!("user{}", i))]))
+            .unwrap();
+    }
+    authorizer
+        .add_policy("allow if user(\"user9999\")")
+        .unwrap();
+    let limits = AuthorizerLimits {
+        max_facts: 20000,
+        max_iterations: 100,
+        max_time: Duration::from_secs(10),
+    };
+
+    b.iter(|| {
+        let mut authorizer = authorizer.clone();
+        authorizer.authorize_with_limits(limits.clone()).unwrap();
+    });
+}
+
 benchmark_group!(
     benchmarks,
     create_block_1,
@@ -561,6 +655,10 @@ benchmark_group!(
     check_signature_5,
     checks_block_2,
     checks_block_create_verifier2,
-    checks_block_verify_only2
+    checks_block_verify_only2,
+    authorize_block_10,
+    authorize_block_30,
+    authorize_block_80,
+    authorize_10k_symbols
 );
 benchmark_main!(benchmarks);
@@ -775,17 +775,15 @@ fn reordered_blocks(target: &str, root: &KeyPair, test: bool) -> TestResult {
         .unwrap();
     token = print_blocks(&biscuit3);
 
-    let mut serialized = biscuit3.container().clone();
-    let mut blocks = vec![];
-    blocks.push(serialized.blocks[1].clone());
-    blocks.push(serialized.blocks[0].clone());
-    serialized.blocks = blocks;
+    let mut proto = biscuit3.container().to_proto();
+    proto.blocks.swap(0, 1);
 
     let data = if test {
         let v = load_testcase(target, &filename);
         v
     } else {
-        let data = serialized.to_vec().unwrap();
+        let mut data = Vec::new();
+        proto.encode(&mut data).unwrap();
         write_testcase(target, &filename, &data[..]);
         data
     };
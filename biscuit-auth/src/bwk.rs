@@ -1,9 +1,14 @@
-use std::convert::TryFrom;
+use std::{
+    convert::TryFrom,
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use chrono::{DateTime, FixedOffset};
 use serde::{Deserialize, Serialize};
 
-use crate::{error, PublicKey};
+use crate::{error, Clock, PublicKey, RootKeyProvider, SystemClock};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 #[serde(into = "BiscuitWebKeyRepr")]
@@ -58,6 +63,166 @@ impl TryFrom<BiscuitWebKeyRepr> for BiscuitWebKey {
     }
 }
 
+impl BiscuitWebKey {
+    /// true if the key expired at or before `now`
+    pub fn is_expired_at(&self, now: SystemTime) -> bool {
+        match self.expires_at {
+            None => false,
+            Some(expires_at) => {
+                let expiration = u64::try_from(expires_at.timestamp())
+                    .map(|secs| {
+                        UNIX_EPOCH + Duration::new(secs, expires_at.timestamp_subsec_nanos())
+                    })
+                    .unwrap_or(UNIX_EPOCH);
+                now >= expiration
+            }
+        }
+    }
+}
+
+/// A set of root public keys, selected by the root key id of tokens
+///
+/// This supports key rotation: tokens created with
+/// [`BiscuitBuilder::set_root_key_id`](crate::builder::BiscuitBuilder::set_root_key_id)
+/// are verified with the key of the same id, as long as it has not expired.
+/// Tokens without a root key id are verified with the default key, if one is set.
+///
+/// ```rust
+/// # use biscuit_auth::{BiscuitWebKey, KeyPair, KeySet, Biscuit};
+/// let old = KeyPair::new();
+/// let new = KeyPair::new();
+///
+/// let key_set = KeySet::new(vec![
+///     BiscuitWebKey { public_key: old.public(), key_id: 1, issuer: None, expires_at: None },
+///     BiscuitWebKey { public_key: new.public(), key_id: 2, issuer: None, expires_at: None },
+/// ]).with_default_key_id(1);
+///
+/// let mut builder = Biscuit::builder();
+/// builder.add_fact("user(\"alice\")").unwrap();
+/// builder.set_root_key_id(2);
+/// let token = builder.build(&new).unwrap().to_vec().unwrap();
+///
+/// assert!(Biscuit::from(&token, &key_set).is_ok());
+///
+/// // the key set document can be published for other verifiers
+/// let document = key_set.to_json().unwrap();
+/// assert_eq!(KeySet::from_json(&document).unwrap().keys(), key_set.keys());
+/// ```
+#[derive(Clone)]
+pub struct KeySet {
+    keys: Vec<BiscuitWebKey>,
+    default_key_id: Option<u32>,
+    issuer: Option<String>,
+    clock: Arc<dyn Clock>,
+}
+
+/// JSON representation of a key set: `{ "keys": [...] }`
+#[derive(Serialize, Deserialize)]
+struct KeySetRepr {
+    keys: Vec<BiscuitWebKey>,
+}
+
+impl KeySet {
+    pub fn new(keys: Vec<BiscuitWebKey>) -> Self {
+        KeySet {
+            keys,
+            default_key_id: None,
+            issuer: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// loads a key set document
+    pub fn from_json(document: &str) -> Result<Self, error::Format> {
+        let repr: KeySetRepr = serde_json::from_str(document)
+            .map_err(|e| error::Format::DeserializationError(e.to_string()))?;
+        Ok(KeySet::new(repr.keys))
+    }
+
+    /// serializes the key set document, to publish it
+    pub fn to_json(&self) -> Result<String, error::Format> {
+        serde_json::to_string_pretty(&KeySetRepr {
+            keys: self.keys.clone(),
+        })
+        .map_err(|e| error::Format::SerializationError(e.to_string()))
+    }
+
+    /// the key used for tokens that do not specify a root key id
+    pub fn with_default_key_id(mut self, key_id: u32) -> Self {
+        self.default_key_id = Some(key_id);
+        self
+    }
+
+    /// only select keys published by this issuer
+    pub fn with_issuer<S: Into<String>>(mut self, issuer: S) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    /// sets the clock used to reject expired keys
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// adds a key, replacing the one with the same key id
+    pub fn add_key(&mut self, key: BiscuitWebKey) {
+        self.keys.retain(|k| k.key_id != key.key_id);
+        self.keys.push(key);
+    }
+
+    pub fn remove_key(&mut self, key_id: u32) -> Option<BiscuitWebKey> {
+        let index = self.keys.iter().position(|k| k.key_id == key_id)?;
+        Some(self.keys.remove(index))
+    }
+
+    /// removes the keys that expired according to the clock, before publishing
+    pub fn remove_expired(&mut self) {
+        let now = self.clock.now();
+        self.keys.retain(|k| !k.is_expired_at(now));
+    }
+
+    pub fn keys(&self) -> &[BiscuitWebKey] {
+        &self.keys
+    }
+
+    pub fn get(&self, key_id: u32) -> Option<&BiscuitWebKey> {
+        self.keys.iter().find(|k| k.key_id == key_id)
+    }
+}
+
+impl RootKeyProvider for KeySet {
+    fn choose(&self, key_id: Option<u32>) -> Result<PublicKey, error::Format> {
+        let key = key_id
+            .or(self.default_key_id)
+            .and_then(|key_id| self.get(key_id))
+            .filter(|key| self.issuer.is_none() || key.issuer == self.issuer)
+            .ok_or(error::Format::UnknownPublicKey)?;
+
+        if key.is_expired_at(self.clock.now()) {
+            return Err(error::Format::ExpiredPublicKey);
+        }
+
+        Ok(key.public_key)
+    }
+}
+
+impl RootKeyProvider for &KeySet {
+    fn choose(&self, key_id: Option<u32>) -> Result<PublicKey, error::Format> {
+        (*self).choose(key_id)
+    }
+}
+
+impl fmt::Debug for KeySet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeySet")
+            .field("keys", &self.keys)
+            .field("default_key_id", &self.default_key_id)
+            .field("issuer", &self.issuer)
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::KeyPair;
@@ -206,4 +371,78 @@ mod tests {
         )
         .is_err());
     }
+
+    #[test]
+    fn key_set() {
+        use crate::Biscuit;
+
+        let key = |key_id: u32, issuer: Option<&str>, expires_at: Option<&str>| {
+            let keypair = KeyPair::new();
+            let bwk = BiscuitWebKey {
+                public_key: keypair.public(),
+                key_id,
+                issuer: issuer.map(str::to_string),
+                expires_at: expires_at.map(|date| DateTime::parse_from_rfc3339(date).unwrap()),
+            };
+            (keypair, bwk)
+        };
+        let (old, old_bwk) = key(1, Some("issuer"), Some("2023-06-28T11:20:00+02:00"));
+        let (current, current_bwk) = key(2, Some("issuer"), None);
+        let (_, other_bwk) = key(3, Some("other"), None);
+
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let key_set = KeySet::new(vec![old_bwk.clone(), current_bwk.clone(), other_bwk])
+            .with_clock(move || now);
+
+        assert_eq!(key_set.choose(Some(2)), Ok(current.public()));
+        assert_eq!(
+            key_set.choose(Some(1)),
+            Err(error::Format::ExpiredPublicKey)
+        );
+        assert_eq!(
+            key_set.choose(Some(4)),
+            Err(error::Format::UnknownPublicKey)
+        );
+        assert_eq!(key_set.choose(None), Err(error::Format::UnknownPublicKey));
+        assert_eq!(
+            key_set.clone().with_default_key_id(2).choose(None),
+            Ok(current.public())
+        );
+        assert!(key_set.choose(Some(3)).is_ok());
+        assert_eq!(
+            key_set.clone().with_issuer("issuer").choose(Some(3)),
+            Err(error::Format::UnknownPublicKey)
+        );
+
+        // the old key was valid before its expiration date
+        let before = SystemTime::UNIX_EPOCH + Duration::from_secs(1_687_900_000);
+        let past = key_set.clone().with_clock(crate::FixedClock(before));
+        assert_eq!(past.choose(Some(1)), Ok(old.public()));
+
+        let mut builder = Biscuit::builder();
+        builder.add_fact("user(\"alice\")").unwrap();
+        builder.set_root_key_id(1);
+        let token = builder.build(&old).unwrap().to_vec().unwrap();
+        assert!(Biscuit::from(&token, &past).is_ok());
+        assert_eq!(
+            Biscuit::from(&token, &key_set).unwrap_err(),
+            error::Token::Format(error::Format::ExpiredPublicKey)
+        );
+
+        let mut published = key_set.clone();
+        published.remove_expired();
+        assert_eq!(published.keys().len(), 2);
+        let document = published.to_json().unwrap();
+        let loaded = KeySet::from_json(&document).unwrap();
+        assert_eq!(loaded.keys(), published.keys());
+
+        let mut rotated = loaded;
+        let (_, next_bwk) = key(2, Some("issuer"), None);
+        rotated.add_key(next_bwk.clone());
+        assert_eq!(rotated.get(2), Some(&next_bwk));
+        assert_eq!(rotated.remove_key(3).map(|k| k.key_id), Some(3));
+        assert_eq!(rotated.keys().len(), 1);
+
+        assert!(KeySet::from_json(r#"{ "keys": [{ "algorithm": "rsa" }] }"#).is_err());
+    }
 }
@@ -97,6 +97,15 @@ pub enum ErrorKind {
     FormatSignatureInvalidSignatureGeneration,
     AlreadySealed,
     Execution,
+    FormatExpiredPublicKey,
+    LogicReservedPredicate,
+    FormatThirdPartyRequestMismatch,
+    FormatGroupScope,
+    FormatMissingDecryptionKey,
+    FormatDecryption,
+    FactProvider,
+    Import,
+    FormatDuplicateSigner,
 }
 
 #[no_mangle]
@@ -120,6 +129,10 @@ pub extern "C" fn error_kind() -> ErrorKind {
                     Token::Format(Format::SealedSignature) => ErrorKind::FormatSealedSignature,
                     Token::Format(Format::EmptyKeys) => ErrorKind::FormatEmptyKeys,
                     Token::Format(Format::UnknownPublicKey) => ErrorKind::FormatUnknownPublicKey,
+                    Token::Format(Format::ExpiredPublicKey) => ErrorKind::FormatExpiredPublicKey,
+                    Token::Format(Format::ThirdPartyRequestMismatch) => {
+                        ErrorKind::FormatThirdPartyRequestMismatch
+                    }
                     Token::Format(Format::DeserializationError(_)) => {
                         ErrorKind::FormatDeserializationError
                     }
@@ -158,6 +171,12 @@ pub extern "C" fn error_kind() -> ErrorKind {
                         ErrorKind::FormatUnknownExternalKey
                     }
                     Token::Format(Format::UnknownSymbol(_)) => ErrorKind::FormatUnknownSymbol,
+                    Token::Format(Format::GroupScope) => ErrorKind::FormatGroupScope,
+                    Token::Format(Format::MissingDecryptionKey(_)) => {
+                        ErrorKind::FormatMissingDecryptionKey
+                    }
+                    Token::Format(Format::Decryption) => ErrorKind::FormatDecryption,
+                    Token::Format(Format::DuplicateSigner(_)) => ErrorKind::FormatDuplicateSigner,
                     Token::AppendOnSealed => ErrorKind::AppendOnSealed,
                     Token::AlreadySealed => ErrorKind::AlreadySealed,
                     Token::Language(_) => ErrorKind::LanguageError,
@@ -171,12 +190,17 @@ pub extern "C" fn error_kind() -> ErrorKind {
                     Token::FailedLogic(Logic::NoMatchingPolicy { .. }) => {
                         ErrorKind::LogicNoMatchingPolicy
                     }
+                    Token::FailedLogic(Logic::ReservedPredicate(_, _)) => {
+                        ErrorKind::LogicReservedPredicate
+                    }
                     Token::RunLimit(RunLimit::TooManyFacts) => ErrorKind::TooManyFacts,
                     Token::RunLimit(RunLimit::TooManyIterations) => ErrorKind::TooManyIterations,
                     Token::RunLimit(RunLimit::Timeout) => ErrorKind::Timeout,
                     Token::ConversionError(_) => ErrorKind::ConversionError,
                     Token::Base64(_) => ErrorKind::FormatDeserializationError,
                     Token::Execution(_) => ErrorKind::Execution,
+                    Token::FactProvider(_) => ErrorKind::FactProvider,
+                    Token::Import(_) => ErrorKind::Import,
                 }
             }
         },
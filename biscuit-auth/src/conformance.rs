@@ -0,0 +1,801 @@
+//! Conformance tests over the biscuit samples
+//!
+//! The `samples` directory of this crate contains serialized tokens and a
+//! `samples.json` file describing them, along with the results expected from
+//! authorizers. Those samples are shared by all biscuit implementations.
+//!
+//! This module loads a samples directory, verifies every token with a root key
+//! provider, replays the authorizer code of each validation, and compares the
+//! authorizer world, the authorization result and the revocation ids with the
+//! expected ones.
+//!
+//! ```rust,no_run
+//! use biscuit_auth::conformance::Samples;
+//!
+//! let samples = Samples::load("./samples").unwrap();
+//! let root = samples.root_public_key().unwrap();
+//! let report = samples.verify(&root);
+//!
+//! for sample in &report.samples {
+//!     for difference in sample.differences() {
+//!         println!("{}: {}", sample.filename, difference);
+//!     }
+//! }
+//! assert!(report.passed());
+//! ```
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    datalog::SymbolTable,
+    error,
+    format::{convert::v2 as convert, schema::origin::Content},
+    Authorizer, AuthorizerLimits, Biscuit, PublicKey, RootKeyProvider,
+};
+
+/// origin of the rules and checks of the authorizer in a [`World`]
+pub const AUTHORIZER_ORIGIN: usize = usize::MAX;
+
+/// error loading a samples directory
+#[derive(Debug, thiserror::Error)]
+pub enum LoadError {
+    #[error("cannot read {path}: {error}")]
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("invalid samples file: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// samples directory, described by its `samples.json` file
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Samples {
+    /// directory containing the token files
+    #[serde(skip)]
+    pub directory: PathBuf,
+    /// hex encoded root private key
+    #[serde(default)]
+    pub root_private_key: Option<String>,
+    /// hex encoded root public key
+    pub root_public_key: String,
+    pub testcases: Vec<Sample>,
+}
+
+/// a token and its expected validations
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub title: String,
+    /// name of the token file, relative to the samples directory
+    pub filename: String,
+    pub token: Vec<Block>,
+    /// validations, by name
+    pub validations: BTreeMap<String, Validation>,
+}
+
+/// content of a token block
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Block {
+    pub symbols: Vec<String>,
+    pub public_keys: Vec<String>,
+    pub external_key: Option<String>,
+    pub code: String,
+}
+
+/// authorization of the token with some authorizer code
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Validation {
+    /// absent if the token could not be loaded in an authorizer
+    pub world: Option<World>,
+    /// serialized `Result<usize, error::Token>` of the authorization
+    pub result: Value,
+    pub authorizer_code: String,
+    /// hex encoded revocation ids
+    pub revocation_ids: Vec<String>,
+}
+
+/// content of the authorizer after authorization
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct World {
+    pub facts: Vec<FactSet>,
+    pub rules: Vec<RuleSet>,
+    pub checks: Vec<CheckSet>,
+    pub policies: Vec<String>,
+}
+
+/// facts generated from a set of origins
+///
+/// an origin is the index of a block, or `None` for the authorizer
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct FactSet {
+    pub origin: BTreeSet<Option<usize>>,
+    pub facts: Vec<String>,
+}
+
+/// rules of a block, or of the authorizer with [`AUTHORIZER_ORIGIN`]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct RuleSet {
+    pub origin: Option<usize>,
+    pub rules: Vec<String>,
+}
+
+/// checks of a block, or of the authorizer with [`AUTHORIZER_ORIGIN`]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CheckSet {
+    pub origin: Option<usize>,
+    pub checks: Vec<String>,
+}
+
+/// difference between the expected and actual behaviour on a sample
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum Difference {
+    /// the token file could not be read
+    File(String),
+    BlockCount {
+        expected: usize,
+        actual: usize,
+    },
+    Block {
+        index: usize,
+        expected: Block,
+        actual: Block,
+    },
+    /// the authorizer code of the validation could not be loaded
+    AuthorizerCode(String),
+    /// the authorizer world could not be extracted
+    World(String),
+    /// a world was expected, but the token could not be loaded in the authorizer
+    MissingWorld,
+    /// the token was expected to be rejected before authorization
+    UnexpectedWorld,
+    Facts {
+        origin: BTreeSet<Option<usize>>,
+        missing: Vec<String>,
+        unexpected: Vec<String>,
+    },
+    Rules {
+        origin: Option<usize>,
+        missing: Vec<String>,
+        unexpected: Vec<String>,
+    },
+    Checks {
+        origin: Option<usize>,
+        missing: Vec<String>,
+        unexpected: Vec<String>,
+    },
+    Policies {
+        expected: Vec<String>,
+        actual: Vec<String>,
+    },
+    Result {
+        expected: Value,
+        actual: Value,
+    },
+    RevocationIds {
+        expected: Vec<String>,
+        actual: Vec<String>,
+    },
+}
+
+/// results of the conformance tests
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Report {
+    pub samples: Vec<SampleReport>,
+}
+
+/// differences found on a sample
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SampleReport {
+    pub title: String,
+    pub filename: String,
+    /// differences in the content of the token
+    pub token: Vec<Difference>,
+    /// differences for each validation, by name
+    pub validations: BTreeMap<String, Vec<Difference>>,
+}
+
+impl Samples {
+    /// loads the `samples.json` file of a samples directory
+    pub fn load<P: AsRef<Path>>(directory: P) -> Result<Self, LoadError> {
+        let directory = directory.as_ref();
+        let path = directory.join("samples.json");
+        let data = fs::read_to_string(&path).map_err(|error| LoadError::Io { path, error })?;
+
+        let mut samples: Samples = serde_json::from_str(&data)?;
+        samples.directory = directory.to_path_buf();
+        Ok(samples)
+    }
+
+    pub fn root_public_key(&self) -> Result<PublicKey, error::Format> {
+        PublicKey::from_bytes_hex(&self.root_public_key)
+    }
+
+    /// runs all the samples, verifying tokens with the key provider
+    pub fn verify<KP: RootKeyProvider>(&self, key_provider: &KP) -> Report {
+        Report {
+            samples: self
+                .testcases
+                .iter()
+                .map(|sample| sample.verify(&self.directory, key_provider))
+                .collect(),
+        }
+    }
+}
+
+impl Sample {
+    /// verifies the token file of the sample, found in `directory`
+    pub fn verify<KP: RootKeyProvider>(&self, directory: &Path, key_provider: &KP) -> SampleReport {
+        let mut report = SampleReport {
+            title: self.title.clone(),
+            filename: self.filename.clone(),
+            token: Vec::new(),
+            validations: BTreeMap::new(),
+        };
+
+        let data = match fs::read(directory.join(&self.filename)) {
+            Ok(data) => data,
+            Err(e) => {
+                report.token.push(Difference::File(e.to_string()));
+                return report;
+            }
+        };
+
+        let token = Biscuit::from(&data, |key_id| key_provider.choose(key_id));
+        if let Ok(token) = &token {
+            report.token = self.compare_blocks(token);
+        }
+
+        for (name, validation) in &self.validations {
+            report
+                .validations
+                .insert(name.clone(), validation.verify(&token));
+        }
+
+        report
+    }
+
+    fn compare_blocks(&self, token: &Biscuit) -> Vec<Difference> {
+        let actual = match blocks(token) {
+            Ok(blocks) => blocks,
+            Err(e) => return vec![Difference::File(e.to_string())],
+        };
+
+        if actual.len() != self.token.len() {
+            return vec![Difference::BlockCount {
+                expected: self.token.len(),
+                actual: actual.len(),
+            }];
+        }
+
+        self.token
+            .iter()
+            .zip(actual)
+            .enumerate()
+            .filter(|(_, (expected, actual))| *expected != actual)
+            .map(|(index, (expected, actual))| Difference::Block {
+                index,
+                expected: expected.clone(),
+                actual,
+            })
+            .collect()
+    }
+}
+
+impl Validation {
+    fn verify(&self, token: &Result<Biscuit, error::Token>) -> Vec<Difference> {
+        let mut differences = Vec::new();
+
+        let actual = match run(token, &self.authorizer_code) {
+            Ok(actual) => actual,
+            Err(difference) => return vec![*difference],
+        };
+
+        match (&self.world, &actual.world) {
+            (Some(expected), Some(actual)) => differences.extend(expected.compare(actual)),
+            (Some(_), None) => differences.push(Difference::MissingWorld),
+            (None, Some(_)) => differences.push(Difference::UnexpectedWorld),
+            (None, None) => {}
+        }
+
+        if self.result != actual.result {
+            differences.push(Difference::Result {
+                expected: self.result.clone(),
+                actual: actual.result,
+            });
+        }
+
+        if self.revocation_ids != actual.revocation_ids {
+            differences.push(Difference::RevocationIds {
+                expected: self.revocation_ids.clone(),
+                actual: actual.revocation_ids,
+            });
+        }
+
+        differences
+    }
+}
+
+/// authorizes the token with the authorizer code, in the same way the samples were generated
+fn run(
+    token: &Result<Biscuit, error::Token>,
+    authorizer_code: &str,
+) -> Result<Validation, Box<Difference>> {
+    let token = match token {
+        Ok(token) => token,
+        Err(e) => {
+            return Ok(Validation {
+                world: None,
+                result: result(Err(e.clone())),
+                authorizer_code: String::new(),
+                revocation_ids: Vec::new(),
+            })
+        }
+    };
+
+    let revocation_ids = token
+        .revocation_identifiers()
+        .iter()
+        .map(hex::encode)
+        .collect();
+
+    let mut authorizer = Authorizer::new();
+    // the samples check behaviour, not performance: regular expressions
+    // can take longer than the default limit in debug builds
+    authorizer.set_limits(AuthorizerLimits {
+        max_time: Duration::from_secs(1),
+        ..Default::default()
+    });
+    authorizer
+        .add_code(authorizer_code)
+        .map_err(|e| Box::new(Difference::AuthorizerCode(e.to_string())))?;
+    let authorizer_code = authorizer.dump_code();
+
+    if let Err(e) = authorizer.add_token(token) {
+        return Ok(Validation {
+            world: None,
+            result: result(Err(e)),
+            authorizer_code: String::new(),
+            revocation_ids,
+        });
+    }
+
+    let authorization = authorizer.authorize();
+    let world = world(&authorizer).map_err(|e| Box::new(Difference::World(e.to_string())))?;
+
+    Ok(Validation {
+        world: Some(world),
+        result: result(authorization),
+        authorizer_code,
+        revocation_ids,
+    })
+}
+
+fn result(result: Result<usize, error::Token>) -> Value {
+    serde_json::to_value(result).expect("authorization results can always be serialized")
+}
+
+fn blocks(token: &Biscuit) -> Result<Vec<Block>, error::Token> {
+    (0..token.block_count())
+        .map(|i| {
+            Ok(Block {
+                symbols: token.block_symbols(i)?,
+                public_keys: token
+                    .block_public_keys(i)?
+                    .into_inner()
+                    .iter()
+                    .map(PublicKey::print)
+                    .collect(),
+                external_key: token.block_external_key(i)?.map(|key| key.print()),
+                code: token.print_block_source(i)?,
+            })
+        })
+        .collect()
+}
+
+/// extracts the content of the authorizer from its snapshot
+fn world(authorizer: &Authorizer) -> Result<World, error::Format> {
+    let snapshot = authorizer.snapshot()?;
+    let version = snapshot.world.version.unwrap_or_default();
+
+    let public_keys = snapshot
+        .world
+        .public_keys
+        .iter()
+        .map(PublicKey::from_proto)
+        .collect::<Result<_, _>>()?;
+    let symbols =
+        SymbolTable::from_symbols_and_public_keys(snapshot.world.symbols.clone(), public_keys)?;
+
+    let mut world = World {
+        facts: Vec::new(),
+        rules: Vec::new(),
+        checks: Vec::new(),
+        policies: authorizer
+            .dump()
+            .3
+            .iter()
+            .map(|policy| policy.to_string())
+            .collect(),
+    };
+
+    let blocks = snapshot
+        .world
+        .blocks
+        .iter()
+        .enumerate()
+        .chain(std::iter::once((
+            AUTHORIZER_ORIGIN,
+            &snapshot.world.authorizer_block,
+        )));
+    for (origin, block) in blocks {
+        let mut rules = block
+            .rules_v2
+            .iter()
+            .map(|rule| {
+                convert::proto_rule_to_token_rule(rule, version)
+                    .map(|(rule, _)| symbols.print_rule(&rule))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if !rules.is_empty() {
+            rules.sort();
+            world.rules.push(RuleSet {
+                origin: Some(origin),
+                rules,
+            });
+        }
+
+        let mut checks = block
+            .checks_v2
+            .iter()
+            .map(|check| {
+                convert::proto_check_to_token_check(check, version)
+                    .map(|check| symbols.print_check(&check))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if !checks.is_empty() {
+            checks.sort();
+            world.checks.push(CheckSet {
+                origin: Some(origin),
+                checks,
+            });
+        }
+    }
+
+    for fact_set in &snapshot.world.generated_facts {
+        let origin = fact_set
+            .origins
+            .iter()
+            .map(|origin| match origin.content {
+                Some(Content::Authorizer(_)) => Ok(None),
+                Some(Content::Origin(i)) => Ok(Some(i as usize)),
+                None => Err(error::Format::DeserializationError(
+                    "invalid origin".to_string(),
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut facts = fact_set
+            .facts
+            .iter()
+            .map(|fact| convert::proto_fact_to_token_fact(fact).map(|f| symbols.print_fact(&f)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !facts.is_empty() {
+            facts.sort();
+            world.facts.push(FactSet { origin, facts });
+        }
+    }
+    world.facts.sort();
+
+    Ok(world)
+}
+
+impl World {
+    /// lists the differences with the actual world, by origin
+    pub fn compare(&self, actual: &World) -> Vec<Difference> {
+        let mut differences = Vec::new();
+
+        let facts = |world: &World| -> BTreeMap<BTreeSet<Option<usize>>, Vec<String>> {
+            world
+                .facts
+                .iter()
+                .map(|set| (set.origin.clone(), set.facts.clone()))
+                .collect()
+        };
+        for (origin, missing, unexpected) in compare_sets(facts(self), facts(actual)) {
+            differences.push(Difference::Facts {
+                origin,
+                missing,
+                unexpected,
+            });
+        }
+
+        let rules = |world: &World| -> BTreeMap<Option<usize>, Vec<String>> {
+            world
+                .rules
+                .iter()
+                .map(|set| (set.origin, set.rules.clone()))
+                .collect()
+        };
+        for (origin, missing, unexpected) in compare_sets(rules(self), rules(actual)) {
+            differences.push(Difference::Rules {
+                origin,
+                missing,
+                unexpected,
+            });
+        }
+
+        let checks = |world: &World| -> BTreeMap<Option<usize>, Vec<String>> {
+            world
+                .checks
+                .iter()
+                .map(|set| (set.origin, set.checks.clone()))
+                .collect()
+        };
+        for (origin, missing, unexpected) in compare_sets(checks(self), checks(actual)) {
+            differences.push(Difference::Checks {
+                origin,
+                missing,
+                unexpected,
+            });
+        }
+
+        if self.policies != actual.policies {
+            differences.push(Difference::Policies {
+                expected: self.policies.clone(),
+                actual: actual.policies.clone(),
+            });
+        }
+
+        differences
+    }
+}
+
+/// for each origin, the elements missing from `actual`, and the unexpected ones
+fn compare_sets<K: Ord + Clone>(
+    expected: BTreeMap<K, Vec<String>>,
+    actual: BTreeMap<K, Vec<String>>,
+) -> Vec<(K, Vec<String>, Vec<String>)> {
+    let origins = expected
+        .keys()
+        .chain(actual.keys())
+        .collect::<BTreeSet<_>>();
+    let empty = Vec::new();
+
+    origins
+        .into_iter()
+        .filter_map(|origin| {
+            let expected = expected.get(origin).unwrap_or(&empty);
+            let actual = actual.get(origin).unwrap_or(&empty);
+
+            let missing = expected
+                .iter()
+                .filter(|element| !actual.contains(element))
+                .cloned()
+                .collect::<Vec<_>>();
+            let unexpected = actual
+                .iter()
+                .filter(|element| !expected.contains(element))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if missing.is_empty() && unexpected.is_empty() {
+                None
+            } else {
+                Some((origin.clone(), missing, unexpected))
+            }
+        })
+        .collect()
+}
+
+impl Report {
+    /// true if no differences were found
+    pub fn passed(&self) -> bool {
+        self.samples.iter().all(SampleReport::passed)
+    }
+}
+
+impl SampleReport {
+    pub fn passed(&self) -> bool {
+        self.differences().next().is_none()
+    }
+
+    /// all the differences found on the sample
+    pub fn differences(&self) -> impl Iterator<Item = &Difference> {
+        self.token.iter().chain(self.validations.values().flatten())
+    }
+}
+
+fn origin(origin: &Option<usize>) -> String {
+    match origin {
+        None => "authorizer".to_string(),
+        Some(AUTHORIZER_ORIGIN) => "authorizer".to_string(),
+        Some(i) => format!("block {}", i),
+    }
+}
+
+fn elements(missing: &[String], unexpected: &[String]) -> String {
+    let mut s = String::new();
+    if !missing.is_empty() {
+        s.push_str(&format!(" missing [{}]", missing.join(", ")));
+    }
+    if !unexpected.is_empty() {
+        s.push_str(&format!(" unexpected [{}]", unexpected.join(", ")));
+    }
+    s
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::File(e) => write!(f, "cannot read token: {}", e),
+            Difference::BlockCount { expected, actual } => {
+                write!(f, "expected {} blocks, got {}", expected, actual)
+            }
+            Difference::Block {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "block {} differs: expected {:?}, got {:?}",
+                index, expected, actual
+            ),
+            Difference::AuthorizerCode(e) => write!(f, "invalid authorizer code: {}", e),
+            Difference::World(e) => write!(f, "cannot extract the authorizer world: {}", e),
+            Difference::MissingWorld => write!(f, "the token was rejected before authorization"),
+            Difference::UnexpectedWorld => {
+                write!(
+                    f,
+                    "the token was expected to be rejected before authorization"
+                )
+            }
+            Difference::Facts {
+                origin: origins,
+                missing,
+                unexpected,
+            } => write!(
+                f,
+                "facts from [{}]:{}",
+                origins.iter().map(origin).collect::<Vec<_>>().join(", "),
+                elements(missing, unexpected)
+            ),
+            Difference::Rules {
+                origin: o,
+                missing,
+                unexpected,
+            } => write!(
+                f,
+                "rules from {}:{}",
+                origin(o),
+                elements(missing, unexpected)
+            ),
+            Difference::Checks {
+                origin: o,
+                missing,
+                unexpected,
+            } => write!(
+                f,
+                "checks from {}:{}",
+                origin(o),
+                elements(missing, unexpected)
+            ),
+            Difference::Policies { expected, actual } => write!(
+                f,
+                "expected policies [{}], got [{}]",
+                expected.join(", "),
+                actual.join(", ")
+            ),
+            Difference::Result { expected, actual } => {
+                write!(f, "expected result {}, got {}", expected, actual)
+            }
+            Difference::RevocationIds { expected, actual } => write!(
+                f,
+                "expected revocation ids [{}], got [{}]",
+                expected.join(", "),
+                actual.join(", ")
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyPair;
+
+    fn samples() -> Samples {
+        Samples::load(concat!(env!("CARGO_MANIFEST_DIR"), "/samples")).unwrap()
+    }
+
+    #[test]
+    fn samples_conform() {
+        let samples = samples();
+        let root = samples.root_public_key().unwrap();
+        let report = samples.verify(&root);
+
+        let failures = report
+            .samples
+            .iter()
+            .flat_map(|sample| {
+                sample
+                    .differences()
+                    .map(move |difference| format!("{}: {}", sample.filename, difference))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(failures, Vec::<String>::new());
+        assert!(report.passed());
+        assert_eq!(report.samples.len(), samples.testcases.len());
+    }
+
+    #[test]
+    fn key_provider() {
+        let samples = samples();
+        let root = samples.root_public_key().unwrap();
+
+        // key providers are called with the root key id of each token
+        let report = samples.verify(&|key_id: Option<u32>| match key_id {
+            None => Ok(root),
+            Some(_) => Err(error::Format::UnknownPublicKey),
+        });
+        assert!(report.passed());
+
+        let other = KeyPair::new().public();
+        let report = samples.verify(&other);
+        let basic = &report.samples[0];
+        assert!(basic.token.is_empty());
+        let differences = &basic.validations[""];
+        assert_eq!(differences.len(), 3);
+        assert_eq!(differences[0], Difference::MissingWorld);
+        assert!(matches!(
+            &differences[1],
+            Difference::Result { actual, .. } if actual.pointer("/Err/Format/Signature").is_some()
+        ));
+        assert!(
+            matches!(&differences[2], Difference::RevocationIds { actual, .. } if actual.is_empty())
+        );
+    }
+
+    #[test]
+    fn differences() {
+        let mut samples = samples();
+        let root = samples.root_public_key().unwrap();
+
+        let basic = &mut samples.testcases[0];
+        basic.token[0].code = "right(\"file1\", \"read\");\n".to_string();
+        let validation = basic.validations.get_mut("").unwrap();
+        validation.result = serde_json::json!({ "Ok": 0 });
+        let world = validation.world.as_mut().unwrap();
+        world.facts[1].facts.remove(0);
+        world.facts[1]
+            .facts
+            .push("right(\"file3\", \"read\")".to_string());
+        world.checks[0].origin = Some(0);
+
+        let report = samples.testcases[0].verify(&samples.directory, &root);
+        let differences = report
+            .differences()
+            .map(|difference| difference.to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(differences.len(), 5);
+        assert!(differences[0].starts_with("block 0 differs"));
+        assert_eq!(
+            differences[1..4],
+            [
+                "facts from [block 0]: missing [right(\"file3\", \"read\")] unexpected [right(\"file1\", \"read\")]",
+                "checks from block 0: missing [check if resource($0), operation(\"read\"), right($0, \"read\")]",
+                "checks from block 1: unexpected [check if resource($0), operation(\"read\"), right($0, \"read\")]",
+            ]
+        );
+        assert!(
+            differences[4].starts_with("expected result {\"Ok\":0}, got {\"Err\":{\"FailedLogic\"")
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! encryption of private facts
+//!
+//! private facts are encrypted to the X25519 public key of an authorizer:
+//! an ephemeral key is generated for each payload, the shared secret goes
+//! through HKDF-SHA256, and the payload is encrypted with ChaCha20-Poly1305.
+//! The ciphertext is stored in the block, so the token's signatures cover it.
+use crate::{error, format::schema};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hkdf::Hkdf;
+use rand_core::{CryptoRng, RngCore};
+use sha2::Sha256;
+use std::{convert::TryInto, fmt::Display, ops::Drop};
+use zeroize::Zeroize;
+
+const KDF_INFO: &[u8] = b"biscuit private facts";
+
+/// pair of keys used to decrypt private facts
+#[derive(Debug)]
+pub struct EncryptionKeyPair {
+    private: EncryptionPrivateKey,
+}
+
+impl EncryptionKeyPair {
+    pub fn new() -> Self {
+        Self::new_with_rng(&mut rand::rngs::OsRng)
+    }
+
+    pub fn new_with_rng<T: RngCore + CryptoRng>(rng: &mut T) -> Self {
+        let mut bytes = [0u8; 32];
+        rng.fill_bytes(&mut bytes);
+
+        EncryptionKeyPair {
+            private: EncryptionPrivateKey(bytes),
+        }
+    }
+
+    pub fn from(key: &EncryptionPrivateKey) -> Self {
+        EncryptionKeyPair {
+            private: key.clone(),
+        }
+    }
+
+    pub fn private(&self) -> EncryptionPrivateKey {
+        self.private.clone()
+    }
+
+    pub fn public(&self) -> EncryptionPublicKey {
+        self.private.public()
+    }
+}
+
+impl std::default::Default for EncryptionKeyPair {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// the private part of an [EncryptionKeyPair]
+#[derive(Debug)]
+pub struct EncryptionPrivateKey([u8; 32]);
+
+impl EncryptionPrivateKey {
+    /// serializes to a byte array
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0
+    }
+
+    /// serializes to an hex-encoded string
+    pub fn to_bytes_hex(&self) -> String {
+        hex::encode(self.to_bytes())
+    }
+
+    /// deserializes from a byte array
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, error::Format> {
+        let bytes: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| error::Format::InvalidKeySize(bytes.len()))?;
+        Ok(EncryptionPrivateKey(bytes))
+    }
+
+    /// deserializes from an hex-encoded string
+    pub fn from_bytes_hex(str: &str) -> Result<Self, error::Format> {
+        let bytes = hex::decode(str).map_err(|e| error::Format::InvalidKey(e.to_string()))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// returns the matching public key
+    pub fn public(&self) -> EncryptionPublicKey {
+        EncryptionPublicKey(x25519_dalek::PublicKey::from(&self.secret()))
+    }
+
+    fn secret(&self) -> x25519_dalek::StaticSecret {
+        x25519_dalek::StaticSecret::from(self.0)
+    }
+}
+
+impl std::clone::Clone for EncryptionPrivateKey {
+    fn clone(&self) -> Self {
+        EncryptionPrivateKey(self.0)
+    }
+}
+
+impl Drop for EncryptionPrivateKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// the public part of an [EncryptionKeyPair], used to encrypt private facts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EncryptionPublicKey(x25519_dalek::PublicKey);
+
+impl EncryptionPublicKey {
+    /// serializes to a byte array
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+
+    /// serializes to an hex-encoded string
+    pub fn to_bytes_hex(&self) -> String {
+        hex::encode(self.to_bytes())
+    }
+
+    /// deserializes from a byte array
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, error::Format> {
+        let bytes: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| error::Format::InvalidKeySize(bytes.len()))?;
+        Ok(EncryptionPublicKey(x25519_dalek::PublicKey::from(bytes)))
+    }
+
+    /// deserializes from an hex-encoded string
+    pub fn from_bytes_hex(str: &str) -> Result<Self, error::Format> {
+        let bytes = hex::decode(str).map_err(|e| error::Format::InvalidKey(e.to_string()))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl Display for EncryptionPublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "x25519/{}", hex::encode(self.to_bytes()))
+    }
+}
+
+fn cipher(
+    shared_secret: &x25519_dalek::SharedSecret,
+    ephemeral_key: &[u8],
+    recipient: &[u8],
+) -> ChaCha20Poly1305 {
+    let mut salt = ephemeral_key.to_vec();
+    salt.extend_from_slice(recipient);
+
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared_secret.as_bytes())
+        .expand(KDF_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    key.zeroize();
+
+    cipher
+}
+
+/// encrypts a payload to the recipient's public key
+pub(crate) fn encrypt<T: RngCore + CryptoRng>(
+    rng: &mut T,
+    recipient: &EncryptionPublicKey,
+    plaintext: &[u8],
+) -> Result<schema::EncryptedFacts, error::Format> {
+    let ephemeral = EncryptionKeyPair::new_with_rng(rng);
+    let ephemeral_key = ephemeral.public().to_bytes();
+    let shared_secret = ephemeral.private.secret().diffie_hellman(&recipient.0);
+
+    let mut nonce = [0u8; 12];
+    rng.fill_bytes(&mut nonce);
+
+    let ciphertext = cipher(&shared_secret, &ephemeral_key, &recipient.to_bytes())
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|e| error::Format::SerializationError(format!("encryption error: {}", e)))?;
+
+    Ok(schema::EncryptedFacts {
+        recipient: recipient.to_bytes().to_vec(),
+        ephemeral_key: ephemeral_key.to_vec(),
+        nonce: nonce.to_vec(),
+        ciphertext,
+    })
+}
+
+/// decrypts a payload with the recipient's private key
+pub(crate) fn decrypt(
+    key: &EncryptionPrivateKey,
+    payload: &schema::EncryptedFacts,
+) -> Result<Vec<u8>, error::Format> {
+    let ephemeral_key = EncryptionPublicKey::from_bytes(&payload.ephemeral_key)?;
+    if payload.nonce.len() != 12 {
+        return Err(error::Format::Decryption);
+    }
+    let shared_secret = key.secret().diffie_hellman(&ephemeral_key.0);
+
+    cipher(&shared_secret, &payload.ephemeral_key, &payload.recipient)
+        .decrypt(Nonce::from_slice(&payload.nonce), &payload.ciphertext[..])
+        .map_err(|_| error::Format::Decryption)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encryption_round_trip() {
+        let recipient = EncryptionKeyPair::new();
+        let payload = encrypt(&mut rand::rngs::OsRng, &recipient.public(), b"hello").unwrap();
+        assert_eq!(payload.recipient, recipient.public().to_bytes().to_vec());
+        assert_eq!(decrypt(&recipient.private(), &payload).unwrap(), b"hello");
+
+        let other = EncryptionKeyPair::new();
+        assert_eq!(
+            decrypt(&other.private(), &payload),
+            Err(error::Format::Decryption)
+        );
+
+        let mut tampered = payload;
+        tampered.ciphertext[0] ^= 1;
+        assert_eq!(
+            decrypt(&recipient.private(), &tampered),
+            Err(error::Format::Decryption)
+        );
+
+        let key =
+            EncryptionPrivateKey::from_bytes_hex(&recipient.private().to_bytes_hex()).unwrap();
+        assert_eq!(key.public(), recipient.public());
+    }
+}
@@ -4,6 +4,7 @@ use super::Term;
 use super::{SymbolTable, TemporarySymbolTable};
 use regex::Regex;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 
 #[derive(Debug, Clone, PartialEq, Hash, Eq)]
 pub struct Expression {
@@ -40,6 +41,8 @@ impl Unary {
                 .ok_or(error::Expression::UnknownSymbol(i)),
             (Unary::Length, Term::Bytes(s)) => Ok(Term::Integer(s.len() as i64)),
             (Unary::Length, Term::Set(s)) => Ok(Term::Integer(s.len() as i64)),
+            (Unary::Length, Term::Array(a)) => Ok(Term::Integer(a.len() as i64)),
+            (Unary::Length, Term::Map(m)) => Ok(Term::Integer(m.len() as i64)),
             _ => {
                 //println!("unexpected value type on the stack");
                 Err(error::Expression::InvalidType)
@@ -80,6 +83,7 @@ pub enum Binary {
     BitwiseOr,
     BitwiseXor,
     NotEqual,
+    Get,
 }
 
 impl Binary {
@@ -202,6 +206,18 @@ impl Binary {
                 Ok(Term::Bool(set.contains(&Term::Bytes(i))))
             }
 
+            // array
+            (Binary::Equal, Term::Array(i), Term::Array(j)) => Ok(Term::Bool(i == j)),
+            (Binary::NotEqual, Term::Array(i), Term::Array(j)) => Ok(Term::Bool(i != j)),
+            (Binary::Contains, Term::Array(array), term) => Ok(Term::Bool(array.contains(&term))),
+
+            // map
+            (Binary::Equal, Term::Map(i), Term::Map(j)) => Ok(Term::Bool(i == j)),
+            (Binary::NotEqual, Term::Map(i), Term::Map(j)) => Ok(Term::Bool(i != j)),
+            (Binary::Contains, Term::Map(map), Term::Str(key)) => {
+                Ok(Term::Bool(map.contains_key(&key)))
+            }
+
             // boolean
             (Binary::And, Term::Bool(i), Term::Bool(j)) => Ok(Term::Bool(i & j)),
             (Binary::Or, Term::Bool(i), Term::Bool(j)) => Ok(Term::Bool(i | j)),
@@ -238,10 +254,23 @@ impl Binary {
             Binary::BitwiseAnd => format!("{} & {}", left, right),
             Binary::BitwiseOr => format!("{} | {}", left, right),
             Binary::BitwiseXor => format!("{} ^ {}", left, right),
+            Binary::Get => format!("{}.get({})", left, right),
         }
     }
 }
 
+/// element of an array by index, or of a map by key
+fn get(container: Term, key: Term) -> Result<Option<Term>, error::Expression> {
+    match (container, key) {
+        (Term::Array(mut array), Term::Integer(i)) => Ok(usize::try_from(i)
+            .ok()
+            .filter(|i| *i < array.len())
+            .map(|i| array.swap_remove(i))),
+        (Term::Map(mut map), Term::Str(key)) => Ok(map.remove(&key)),
+        _ => Err(error::Expression::InvalidType),
+    }
+}
+
 impl Expression {
     pub fn evaluate(
         &self,
@@ -269,6 +298,13 @@ impl Expression {
                     Some(term) => stack.push(unary.evaluate(term, symbols)?),
                 },
                 Op::Binary(binary) => match (stack.pop(), stack.pop()) {
+                    // reading a missing element makes the whole expression false
+                    (Some(right_term), Some(left_term)) if *binary == Binary::Get => {
+                        match get(left_term, right_term)? {
+                            Some(term) => stack.push(term),
+                            None => return Ok(Term::Bool(false)),
+                        }
+                    }
                     (Some(right_term), Some(left_term)) => {
                         stack.push(binary.evaluate(left_term, right_term, symbols)?)
                     }
@@ -426,6 +462,59 @@ mod tests {
         assert_eq!(res, Err(error::Expression::Overflow));
     }
 
+    #[test]
+    fn get() {
+        let mut symbols = SymbolTable::new();
+        let name = symbols.insert("name");
+        let user = symbols.insert("user") as u32;
+        let mut tmp_symbols = TemporarySymbolTable::new(&symbols);
+
+        let map = Term::Map([(name, Term::Array(vec![Term::Integer(1), Term::Integer(2)]))].into());
+        let values: HashMap<u32, Term> = [(user, map)].into();
+
+        for (index, expected) in [
+            (1, Term::Bool(true)),
+            (2, Term::Bool(false)),
+            (-1, Term::Bool(false)),
+        ] {
+            let ops = vec![
+                Op::Value(Term::Variable(user)),
+                Op::Value(Term::Str(name)),
+                Op::Binary(Binary::Get),
+                Op::Value(Term::Integer(index)),
+                Op::Binary(Binary::Get),
+                Op::Value(Term::Integer(2)),
+                Op::Binary(Binary::Equal),
+            ];
+            let e = Expression { ops };
+            assert_eq!(
+                e.print(&symbols).unwrap(),
+                format!("$user.get(\"name\").get({}) == 2", index)
+            );
+            assert_eq!(e.evaluate(&values, &mut tmp_symbols), Ok(expected));
+        }
+
+        // missing key
+        let ops = vec![
+            Op::Value(Term::Variable(user)),
+            Op::Value(Term::Str(name + 1)),
+            Op::Binary(Binary::Get),
+        ];
+        let e = Expression { ops };
+        assert_eq!(e.evaluate(&values, &mut tmp_symbols), Ok(Term::Bool(false)));
+
+        let ops = vec![
+            Op::Value(Term::Integer(1)),
+            Op::Value(Term::Integer(0)),
+            Op::Binary(Binary::Get),
+        ];
+        let e = Expression { ops };
+        assert_eq!(
+            e.evaluate(&values, &mut tmp_symbols),
+            Err(error::Expression::InvalidType)
+        );
+    }
+
     #[test]
     fn printer() {
         let mut symbols = SymbolTable::new();
@@ -4,7 +4,7 @@ use crate::error::Execution;
 use crate::time::Instant;
 use crate::token::{Scope, MIN_SCHEMA_VERSION};
 use crate::{builder, error};
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::AsRef;
 use std::fmt;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
@@ -25,6 +25,10 @@ pub enum Term {
     Bytes(Vec<u8>),
     Bool(bool),
     Set(BTreeSet<Term>),
+    /// ordered list of terms
+    Array(Vec<Term>),
+    /// terms indexed by string keys
+    Map(BTreeMap<SymbolIndex, Term>),
 }
 
 impl From<&Term> for Term {
@@ -37,6 +41,8 @@ impl From<&Term> for Term {
             Term::Bytes(ref b) => Term::Bytes(b.clone()),
             Term::Bool(ref b) => Term::Bool(*b),
             Term::Set(ref s) => Term::Set(s.clone()),
+            Term::Array(ref a) => Term::Array(a.clone()),
+            Term::Map(ref m) => Term::Map(m.clone()),
         }
     }
 }
@@ -559,6 +565,8 @@ pub fn match_preds(rule_pred: &Predicate, fact_pred: &Predicate) -> bool {
                 (Term::Bytes(i), Term::Bytes(j)) => i == j,
                 (Term::Bool(i), Term::Bool(j)) => i == j,
                 (Term::Set(i), Term::Set(j)) => i == j,
+                (Term::Array(i), Term::Array(j)) => i == j,
+                (Term::Map(i), Term::Map(j)) => i == j,
                 _ => false,
             })
 }
@@ -592,6 +600,9 @@ impl World {
         symbols: &SymbolTable,
         limits: RunLimits,
     ) -> Result<(), crate::error::Execution> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("run", facts = self.facts.len()).entered();
+
         let start = Instant::now();
         let time_limit = start + limits.max_time;
         let mut index = 0;
@@ -834,17 +845,33 @@ impl RuleSet {
             .iter()
             .flat_map(move |(ids, rules)| rules.iter().map(move |(_, rule)| (ids, rule)))
     }
+
+    pub fn merge(&mut self, other: RuleSet) {
+        for (scope, rules) in other.inner {
+            let entry = self.inner.entry(scope).or_default();
+            entry.extend(rules);
+        }
+    }
 }
 
 pub struct SchemaVersion {
     contains_scopes: bool,
     contains_v4: bool,
     contains_check_all: bool,
+    contains_v5: bool,
+    contains_v6: bool,
+    contains_encrypted_facts: bool,
 }
 
 impl SchemaVersion {
     pub fn version(&self) -> u32 {
-        if self.contains_scopes || self.contains_v4 || self.contains_check_all {
+        if self.contains_encrypted_facts {
+            7
+        } else if self.contains_v6 {
+            6
+        } else if self.contains_v5 {
+            5
+        } else if self.contains_scopes || self.contains_v4 || self.contains_check_all {
             4
         } else {
             MIN_SCHEMA_VERSION
@@ -852,6 +879,24 @@ impl SchemaVersion {
     }
 
     pub fn check_compatibility(&self, version: u32) -> Result<(), error::Format> {
+        if version < 7 && self.contains_encrypted_facts {
+            return Err(error::Format::DeserializationError(
+                "v3 to v6 blocks must not have private facts".to_string(),
+            ));
+        }
+
+        if version < 6 && self.contains_v6 {
+            return Err(error::Format::DeserializationError(
+                "v3 to v5 blocks must not have arrays, maps or .get()".to_string(),
+            ));
+        }
+
+        if version < 5 && self.contains_v5 {
+            return Err(error::Format::DeserializationError(
+                "v3 and v4 blocks must not have threshold or group scopes".to_string(),
+            ));
+        }
+
         if version < 4 {
             if self.contains_scopes {
                 Err(error::Format::DeserializationError(
@@ -875,11 +920,15 @@ impl SchemaVersion {
 }
 
 /// Determine the schema version given the elements of a block.
+///
+/// `contains_encrypted_facts` indicates that the block carries private facts,
+/// which older verifiers would ignore instead of rejecting the block
 pub fn get_schema_version(
-    _facts: &[Fact],
+    facts: &[Fact],
     rules: &[Rule],
     checks: &[Check],
     scopes: &[Scope],
+    contains_encrypted_facts: bool,
 ) -> SchemaVersion {
     let contains_scopes = !scopes.is_empty()
         || rules.iter().any(|r: &Rule| !r.scopes.is_empty())
@@ -889,6 +938,13 @@ pub fn get_schema_version(
 
     let contains_check_all = checks.iter().any(|c: &Check| c.kind == CheckKind::All);
 
+    let is_v5 = |scope: &Scope| matches!(scope, Scope::Threshold(..) | Scope::Group(_));
+    let contains_v5 = scopes.iter().any(is_v5)
+        || rules.iter().any(|r: &Rule| r.scopes.iter().any(is_v5))
+        || checks
+            .iter()
+            .any(|c: &Check| c.queries.iter().any(|q| q.scopes.iter().any(is_v5)));
+
     let contains_v4 = rules.iter().any(|rule| contains_v4_op(&rule.expressions))
         || checks.iter().any(|check| {
             check
@@ -897,13 +953,49 @@ pub fn get_schema_version(
                 .any(|query| contains_v4_op(&query.expressions))
         });
 
+    let contains_v6 = facts
+        .iter()
+        .any(|fact| fact.predicate.terms.iter().any(is_v6_term))
+        || rules.iter().any(contains_v6)
+        || checks
+            .iter()
+            .any(|check| check.queries.iter().any(contains_v6));
+
     SchemaVersion {
         contains_scopes,
         contains_v4,
         contains_check_all,
+        contains_v5,
+        contains_v6,
+        contains_encrypted_facts,
     }
 }
 
+fn is_v6_term(term: &Term) -> bool {
+    matches!(term, Term::Array(_) | Term::Map(_))
+}
+
+/// Determine whether a rule contains arrays, maps or the `.get()` operator,
+/// which are only supported in biscuits v6+
+fn contains_v6(rule: &Rule) -> bool {
+    rule.head
+        .terms
+        .iter()
+        .chain(
+            rule.body
+                .iter()
+                .flat_map(|predicate| predicate.terms.iter()),
+        )
+        .any(is_v6_term)
+        || rule.expressions.iter().any(|expression| {
+            expression.ops.iter().any(|op| match op {
+                Op::Value(term) => is_v6_term(term),
+                Op::Binary(binary) => *binary == Binary::Get,
+                Op::Unary(_) => false,
+            })
+        })
+}
+
 /// Determine whether any of the expression contain a v4 operator.
 /// Bitwise operators and != are only supported in biscuits v4+
 pub fn contains_v4_op(expressions: &[Expression]) -> bool {
@@ -1,66 +1,183 @@
+use std::cmp::Ordering;
 use std::collections::BTreeSet;
 use std::collections::HashMap;
-use std::fmt::Display;
+use std::fmt::{self, Display};
 use std::hash::Hash;
 use std::iter::FromIterator;
 
 use crate::token::Scope;
 
-#[derive(Clone, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+/// blocks that generated a fact, `usize::MAX` being the authorizer
+///
+/// blocks are stored in a bitset, kept in a single word for the first 64 blocks,
+/// so unions and inclusion checks are word operations
+#[derive(Clone, Default, Hash, PartialEq, Eq)]
 pub struct Origin {
-    pub(crate) inner: BTreeSet<usize>,
+    authorizer: bool,
+    blocks: Blocks,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq)]
+enum Blocks {
+    Inline(u64),
+    /// used once a block id is 64 or more, the last word is never 0
+    Spilled(Vec<u64>),
+}
+
+impl Default for Blocks {
+    fn default() -> Self {
+        Blocks::Inline(0)
+    }
+}
+
+impl Blocks {
+    fn words(&self) -> &[u64] {
+        match self {
+            Blocks::Inline(word) => std::slice::from_ref(word),
+            Blocks::Spilled(words) => words,
+        }
+    }
+
+    fn insert(&mut self, i: usize) {
+        let (index, bit) = (i / 64, 1 << (i % 64));
+        match self {
+            Blocks::Inline(word) if index == 0 => *word |= bit,
+            Blocks::Inline(word) => {
+                let mut words = vec![0; index + 1];
+                words[0] = *word;
+                words[index] = bit;
+                *self = Blocks::Spilled(words);
+            }
+            Blocks::Spilled(words) => {
+                if words.len() <= index {
+                    words.resize(index + 1, 0);
+                }
+                words[index] |= bit;
+            }
+        }
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        match (self, other) {
+            (Blocks::Inline(a), Blocks::Inline(b)) => Blocks::Inline(a | b),
+            _ => {
+                let (long, short) = if self.words().len() >= other.words().len() {
+                    (self.words(), other.words())
+                } else {
+                    (other.words(), self.words())
+                };
+                let mut words = long.to_vec();
+                for (word, other) in words.iter_mut().zip(short) {
+                    *word |= other;
+                }
+                Blocks::Spilled(words)
+            }
+        }
+    }
+
+    fn is_superset(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Blocks::Inline(a), Blocks::Inline(b)) => b & !a == 0,
+            _ => {
+                let words = self.words();
+                other
+                    .words()
+                    .iter()
+                    .enumerate()
+                    .all(|(i, word)| word & !words.get(i).copied().unwrap_or(0) == 0)
+            }
+        }
+    }
 }
 
 impl Origin {
     pub fn insert(&mut self, i: usize) {
-        self.inner.insert(i);
+        if i == usize::MAX {
+            self.authorizer = true;
+        } else {
+            self.blocks.insert(i);
+        }
     }
 
     pub fn union(&self, other: &Self) -> Self {
         Origin {
-            inner: self.inner.union(&other.inner).cloned().collect(),
+            authorizer: self.authorizer || other.authorizer,
+            blocks: self.blocks.union(&other.blocks),
         }
     }
 
     pub fn is_superset(&self, other: &Self) -> bool {
-        self.inner.is_superset(&other.inner)
+        (self.authorizer || !other.authorizer) && self.blocks.is_superset(&other.blocks)
+    }
+
+    /// block ids in increasing order, followed by `usize::MAX` for the authorizer
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.blocks
+            .words()
+            .iter()
+            .enumerate()
+            .flat_map(|(i, word)| {
+                (0..64)
+                    .filter(move |bit| word & (1 << bit) != 0)
+                    .map(move |bit| i * 64 + bit)
+            })
+            .chain(self.authorizer.then_some(usize::MAX))
+    }
+}
+
+/// same order as a sorted set of block ids
+impl Ord for Origin {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl PartialOrd for Origin {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Debug for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
     }
 }
 
 impl<'a> Extend<&'a usize> for Origin {
     fn extend<T: IntoIterator<Item = &'a usize>>(&mut self, iter: T) {
-        self.inner.extend(iter)
+        self.extend(iter.into_iter().copied())
     }
 }
 
 impl Extend<usize> for Origin {
     fn extend<T: IntoIterator<Item = usize>>(&mut self, iter: T) {
-        self.inner.extend(iter)
+        for i in iter {
+            self.insert(i);
+        }
     }
 }
 
 impl<'a> FromIterator<&'a usize> for Origin {
     fn from_iter<T: IntoIterator<Item = &'a usize>>(iter: T) -> Self {
-        Self {
-            inner: iter.into_iter().cloned().collect(),
-        }
+        iter.into_iter().copied().collect()
     }
 }
 
 impl FromIterator<usize> for Origin {
     fn from_iter<T: IntoIterator<Item = usize>>(iter: T) -> Self {
-        Self {
-            inner: iter.into_iter().collect(),
-        }
+        let mut origin = Origin::default();
+        origin.extend(iter);
+        origin
     }
 }
 
 impl Display for Origin {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut it = self.inner.iter();
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut it = self.iter();
 
         if let Some(i) = it.next() {
-            if *i == usize::MAX {
+            if i == usize::MAX {
                 write!(f, "authorizer")?;
             } else {
                 write!(f, "{i}")?;
@@ -68,7 +185,7 @@ impl Display for Origin {
         }
 
         for i in it {
-            if *i == usize::MAX {
+            if i == usize::MAX {
                 write!(f, ", authorizer")?;
             } else {
                 write!(f, ", {i}")?;
@@ -122,6 +239,28 @@ impl TrustedOrigins {
                         origins.extend(block_ids.iter())
                     }
                 }
+                Scope::Threshold(threshold, key_ids) => {
+                    // number of distinct listed keys that signed each block
+                    let mut signatures: HashMap<usize, u32> = HashMap::new();
+                    let key_ids = key_ids.iter().collect::<BTreeSet<_>>();
+                    for key_id in key_ids {
+                        if let Some(block_ids) = public_key_to_block_id.get(&(*key_id as usize)) {
+                            for block_id in block_ids {
+                                *signatures.entry(*block_id).or_default() += 1;
+                            }
+                        }
+                    }
+
+                    origins.extend(
+                        signatures
+                            .into_iter()
+                            .filter(|(_, count)| count >= threshold)
+                            .map(|(block_id, _)| block_id),
+                    )
+                }
+                // the authorizer replaces group scopes with the keys of the group,
+                // an unresolved group does not trust any block
+                Scope::Group(_) => {}
             }
         }
 
@@ -144,3 +283,95 @@ impl<'a> FromIterator<&'a usize> for TrustedOrigins {
         Self(iter.into_iter().cloned().collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitset() {
+        let small = Origin::from_iter([0, 3, usize::MAX]);
+        let large = Origin::from_iter([3, 64, 130]);
+        assert_eq!(small.iter().collect::<Vec<_>>(), vec![0, 3, usize::MAX]);
+        assert_eq!(large.iter().collect::<Vec<_>>(), vec![3, 64, 130]);
+        assert_eq!(small.to_string(), "0, 3, authorizer");
+
+        let union = small.union(&large);
+        assert_eq!(union, large.union(&small));
+        assert_eq!(union, Origin::from_iter([0, 3, 64, 130, usize::MAX]));
+        assert!(union.is_superset(&small));
+        assert!(union.is_superset(&large));
+        assert!(!small.is_superset(&large));
+        assert!(!large.is_superset(&small));
+        assert!(small.is_superset(&Origin::from_iter([3])));
+        assert!(!Origin::from_iter([3]).is_superset(&Origin::from_iter([3, usize::MAX])));
+
+        // spilled origins compare equal to the same blocks built in another order
+        let mut spilled = Origin::from_iter([200]);
+        spilled.extend([3, 64, 130]);
+        assert_eq!(spilled.union(&large), Origin::from_iter([3, 64, 130, 200]));
+
+        // same order as a sorted set of block ids
+        let mut origins = [
+            large.clone(),
+            small.clone(),
+            union.clone(),
+            Origin::from_iter([usize::MAX]),
+            Origin::default(),
+            Origin::from_iter([1]),
+        ];
+        let mut sets = origins
+            .iter()
+            .map(|origin| origin.iter().collect::<BTreeSet<_>>())
+            .collect::<Vec<_>>();
+        origins.sort();
+        sets.sort();
+        assert_eq!(
+            origins
+                .iter()
+                .map(|origin| origin.iter().collect::<BTreeSet<_>>())
+                .collect::<Vec<_>>(),
+            sets
+        );
+    }
+
+    #[test]
+    fn scopes_and_public_keys() {
+        // key 0 signed block 1, key 1 signed blocks 1 and 2
+        let mut public_key_to_block_id = HashMap::new();
+        public_key_to_block_id.insert(0, vec![1]);
+        public_key_to_block_id.insert(1, vec![1, 2]);
+
+        let origins = |scopes: &[Scope]| {
+            TrustedOrigins::from_scopes(
+                scopes,
+                &TrustedOrigins::default(),
+                3,
+                &public_key_to_block_id,
+            )
+        };
+
+        assert_eq!(
+            origins(&[Scope::PublicKey(1)]),
+            TrustedOrigins::from_iter([1, 2, 3, usize::MAX])
+        );
+        assert_eq!(
+            origins(&[Scope::Threshold(2, vec![0, 1])]),
+            TrustedOrigins::from_iter([1, 3, usize::MAX])
+        );
+        // unknown keys do not trust any block
+        assert_eq!(
+            origins(&[Scope::PublicKey(7)]),
+            TrustedOrigins::from_iter([3, usize::MAX])
+        );
+        // groups must be replaced by their keys before computing the origins
+        assert_eq!(
+            origins(&[Scope::Group(0)]),
+            TrustedOrigins::from_iter([3, usize::MAX])
+        );
+        assert_eq!(
+            origins(&[Scope::Group(0), Scope::Authority]),
+            TrustedOrigins::from_iter([0, 3, usize::MAX])
+        );
+    }
+}
@@ -1,5 +1,9 @@
 //! Symbol table implementation
-use std::collections::HashSet;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, OnceLock},
+};
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
 pub type SymbolIndex = u64;
@@ -9,9 +13,21 @@ use crate::{error, token::public_keys::PublicKeys};
 
 use super::{Check, Fact, Predicate, Rule, Term, World};
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Symbols and public keys used by a token or an authorizer
+///
+/// A table can be derived from a shared one: it then only stores the symbols
+/// and keys added after it was derived, so appending a block to a token does
+/// not copy the symbols of the previous blocks
+#[derive(Clone)]
 pub struct SymbolTable {
+    /// symbols shared with the table this one was derived from
+    base: Option<Arc<SymbolTable>>,
+    /// number of symbols in `base`
+    offset: usize,
     symbols: Vec<String>,
+    /// position in the whole table of each symbol in `symbols`, the first
+    /// one if it appears twice
+    index: HashMap<String, usize>,
     pub(crate) public_keys: PublicKeys,
 }
 
@@ -48,24 +64,98 @@ const DEFAULT_SYMBOLS: [&str; 28] = [
 
 const OFFSET: usize = 1024;
 
+/// number of tables a derived table can go through before it is flattened.
+/// Lookups go through every table of the chain, and dropping it is recursive
+pub(crate) const MAX_DERIVE_DEPTH: usize = 8;
+
+fn default_symbol(s: &str) -> Option<usize> {
+    static INDEX: OnceLock<HashMap<&'static str, usize>> = OnceLock::new();
+
+    INDEX
+        .get_or_init(|| {
+            DEFAULT_SYMBOLS
+                .iter()
+                .enumerate()
+                .map(|(i, sym)| (*sym, i))
+                .collect()
+        })
+        .get(s)
+        .copied()
+}
+
+/// adds the symbols starting at `start` to the index, keeping the first
+/// position of duplicated symbols. Positions are shifted by `offset`
+fn index_symbols(
+    index: &mut HashMap<String, usize>,
+    symbols: &[String],
+    start: usize,
+    offset: usize,
+) {
+    for (i, sym) in symbols.iter().enumerate().skip(start) {
+        index.entry(sym.clone()).or_insert(offset + i);
+    }
+}
+
 impl SymbolTable {
     pub fn new() -> Self {
         SymbolTable {
+            base: None,
+            offset: 0,
             symbols: vec![],
+            index: HashMap::new(),
             public_keys: PublicKeys::new(),
         }
     }
 
-    pub fn from(symbols: Vec<String>) -> Result<Self, error::Format> {
-        let h1 = DEFAULT_SYMBOLS.iter().copied().collect::<HashSet<_>>();
-        let h2 = symbols.iter().map(|s| s.as_str()).collect::<HashSet<_>>();
+    /// creates an empty table on top of `base`, sharing its symbols and
+    /// public keys instead of copying them
+    ///
+    /// if `base` is already derived from [`MAX_DERIVE_DEPTH`] tables, its
+    /// symbols are copied in a single table first
+    pub fn derive(base: &Arc<SymbolTable>) -> Self {
+        let base = if base.tables().count() >= MAX_DERIVE_DEPTH {
+            Arc::new(base.flatten())
+        } else {
+            base.clone()
+        };
 
-        if !h1.is_disjoint(&h2) {
+        SymbolTable {
+            offset: base.current_offset(),
+            symbols: vec![],
+            index: HashMap::new(),
+            public_keys: PublicKeys::derive(Arc::new(base.public_keys.clone())),
+            base: Some(base),
+        }
+    }
+
+    /// copies the symbols of the shared tables into a new table
+    fn flatten(&self) -> Self {
+        let symbols = self.strings();
+        let mut index = HashMap::with_capacity(symbols.len());
+        index_symbols(&mut index, &symbols, 0, 0);
+
+        SymbolTable {
+            base: None,
+            offset: 0,
+            symbols,
+            index,
+            public_keys: self.public_keys.clone(),
+        }
+    }
+
+    pub fn from(symbols: Vec<String>) -> Result<Self, error::Format> {
+        if symbols.iter().any(|s| default_symbol(s).is_some()) {
             return Err(error::Format::SymbolTableOverlap);
         }
 
+        let mut index = HashMap::with_capacity(symbols.len());
+        index_symbols(&mut index, &symbols, 0, 0);
+
         Ok(SymbolTable {
+            base: None,
+            offset: 0,
             symbols,
+            index,
             public_keys: PublicKeys::new(),
         })
     }
@@ -83,23 +173,21 @@ impl SymbolTable {
         if !self.is_disjoint(other) {
             return Err(error::Format::SymbolTableOverlap);
         }
-        self.symbols.extend(other.symbols.iter().cloned());
+        let start = self.symbols.len();
+        self.symbols.extend(other.strings());
+        index_symbols(&mut self.index, &self.symbols, start, self.offset);
         self.public_keys.extend(&other.public_keys)?;
         Ok(())
     }
 
     pub fn insert(&mut self, s: &str) -> SymbolIndex {
-        if let Some(index) = DEFAULT_SYMBOLS.iter().position(|sym| *sym == s) {
-            return index as u64;
+        if let Some(index) = self.get(s) {
+            return index;
         }
 
-        match self.symbols.iter().position(|sym| sym.as_str() == s) {
-            Some(index) => (OFFSET + index) as u64,
-            None => {
-                self.symbols.push(s.to_string());
-                (OFFSET + (self.symbols.len() - 1)) as u64
-            }
-        }
+        self.index.insert(s.to_string(), self.current_offset());
+        self.symbols.push(s.to_string());
+        (OFFSET + (self.current_offset() - 1)) as u64
     }
 
     pub fn add(&mut self, s: &str) -> Term {
@@ -108,41 +196,74 @@ impl SymbolTable {
     }
 
     pub fn get(&self, s: &str) -> Option<SymbolIndex> {
-        if let Some(index) = DEFAULT_SYMBOLS.iter().position(|sym| *sym == s) {
+        if let Some(index) = default_symbol(s) {
             return Some(index as u64);
         }
 
-        self.symbols
-            .iter()
-            .position(|sym| sym.as_str() == s)
-            .map(|i| (OFFSET + i) as SymbolIndex)
+        self.position(s).map(|i| (OFFSET + i) as SymbolIndex)
+    }
+
+    fn position(&self, s: &str) -> Option<usize> {
+        self.base
+            .as_ref()
+            .and_then(|base| base.position(s))
+            .or_else(|| self.index.get(s).copied())
+    }
+
+    /// this table and the ones it was derived from, most recent first
+    fn tables(&self) -> impl Iterator<Item = &SymbolTable> {
+        std::iter::successors(Some(self), |table| table.base.as_deref())
     }
 
     pub fn strings(&self) -> Vec<String> {
-        self.symbols.clone()
+        let mut tables = self.tables().collect::<Vec<_>>();
+        tables.reverse();
+        tables
+            .into_iter()
+            .flat_map(|table| table.symbols.iter().cloned())
+            .collect()
     }
 
     pub fn current_offset(&self) -> usize {
-        self.symbols.len()
+        self.offset + self.symbols.len()
     }
 
     pub fn split_at(&mut self, offset: usize) -> SymbolTable {
+        if offset < self.offset {
+            // the split goes through the shared symbols, they are copied first
+            self.symbols = self.strings();
+            self.index.clear();
+            index_symbols(&mut self.index, &self.symbols, 0, 0);
+            self.base = None;
+            self.offset = 0;
+        }
+
         let mut table = SymbolTable::new();
-        table.symbols = self.symbols.split_off(offset);
+        table.symbols = self.symbols.split_off(offset - self.offset);
+        index_symbols(&mut table.index, &table.symbols, 0, 0);
+        self.index.retain(|_, i| *i < offset);
         table
     }
 
     pub fn is_disjoint(&self, other: &SymbolTable) -> bool {
-        let h1 = self.symbols.iter().collect::<HashSet<_>>();
-        let h2 = other.symbols.iter().collect::<HashSet<_>>();
+        let (small, large) = if self.current_offset() <= other.current_offset() {
+            (self, other)
+        } else {
+            (other, self)
+        };
 
-        h1.is_disjoint(&h2)
+        small
+            .tables()
+            .flat_map(|table| table.index.keys())
+            .all(|s| large.position(s).is_none())
     }
 
     pub fn get_symbol(&self, i: SymbolIndex) -> Option<&str> {
         if i >= OFFSET as u64 {
-            self.symbols
-                .get((i - OFFSET as u64) as usize)
+            let i = (i - OFFSET as u64) as usize;
+            self.tables()
+                .find(|table| i >= table.offset)
+                .and_then(|table| table.symbols.get(i - table.offset))
                 .map(|s| s.as_str())
         } else {
             DEFAULT_SYMBOLS.get(i as usize).copied()
@@ -204,6 +325,26 @@ impl SymbolTable {
                     .collect::<Vec<_>>();
                 format!("[{}]", terms.join(", "))
             }
+            Term::Array(a) => {
+                let terms = a
+                    .iter()
+                    .map(|term| self.print_term(term))
+                    .collect::<Vec<_>>();
+                format!("array[{}]", terms.join(", "))
+            }
+            Term::Map(m) => {
+                let entries = m
+                    .iter()
+                    .map(|(key, term)| {
+                        format!(
+                            "\"{}\": {}",
+                            self.print_symbol_default(*key),
+                            self.print_term(term)
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                format!("{{{}}}", entries.join(", "))
+            }
         }
     }
     pub fn print_fact(&self, f: &Fact) -> String {
@@ -254,11 +395,18 @@ impl SymbolTable {
                 .map(|scope| match scope {
                     crate::token::Scope::Authority => "authority".to_string(),
                     crate::token::Scope::Previous => "previous".to_string(),
-                    crate::token::Scope::PublicKey(key_id) => {
-                        match self.public_keys.get_key(*key_id) {
-                            Some(key) => format!("ed25519/{}", hex::encode(key.to_bytes())),
-                            None => "<unknown public key id>".to_string(),
-                        }
+                    crate::token::Scope::PublicKey(key_id) => self.print_scope_key(*key_id),
+                    crate::token::Scope::Threshold(threshold, key_ids) => format!(
+                        "{} of {{{}}}",
+                        threshold,
+                        key_ids
+                            .iter()
+                            .map(|key_id| self.print_scope_key(*key_id))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                    crate::token::Scope::Group(name) => {
+                        format!("group(\"{}\")", self.print_symbol_default(*name))
                     }
                 })
                 .collect();
@@ -268,6 +416,13 @@ impl SymbolTable {
         format!("{}{}{}", preds.join(", "), e, scopes)
     }
 
+    fn print_scope_key(&self, key_id: u64) -> String {
+        match self.public_keys.get_key(key_id) {
+            Some(key) => format!("ed25519/{}", hex::encode(key.to_bytes())),
+            None => "<unknown public key id>".to_string(),
+        }
+    }
+
     pub fn print_rule(&self, r: &Rule) -> String {
         let res = self.print_predicate(&r.head);
 
@@ -298,11 +453,29 @@ impl Default for SymbolTable {
     }
 }
 
+impl PartialEq for SymbolTable {
+    fn eq(&self, other: &Self) -> bool {
+        self.strings() == other.strings() && self.public_keys == other.public_keys
+    }
+}
+
+impl Eq for SymbolTable {}
+
+impl fmt::Debug for SymbolTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SymbolTable")
+            .field("symbols", &self.strings())
+            .field("public_keys", &self.public_keys)
+            .finish()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TemporarySymbolTable<'a> {
     base: &'a SymbolTable,
     offset: usize,
     symbols: Vec<String>,
+    index: HashMap<String, usize>,
 }
 
 impl<'a> TemporarySymbolTable<'a> {
@@ -313,6 +486,7 @@ impl<'a> TemporarySymbolTable<'a> {
             base,
             offset,
             symbols: vec![],
+            index: HashMap::new(),
         }
     }
 
@@ -331,12 +505,145 @@ impl<'a> TemporarySymbolTable<'a> {
             return index as u64;
         }
 
-        match self.symbols.iter().position(|sym| sym.as_str() == s) {
+        match self.index.get(s) {
             Some(index) => (self.offset + index) as u64,
             None => {
+                self.index.insert(s.to_string(), self.symbols.len());
                 self.symbols.push(s.to_string());
                 (self.offset + (self.symbols.len() - 1)) as u64
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index() {
+        let mut symbols = SymbolTable::new();
+        assert_eq!(symbols.insert("read"), 0);
+        assert_eq!(symbols.insert("a"), OFFSET as u64);
+        assert_eq!(symbols.insert("b"), OFFSET as u64 + 1);
+        assert_eq!(symbols.insert("a"), OFFSET as u64);
+        assert_eq!(symbols.get("b"), Some(OFFSET as u64 + 1));
+        assert_eq!(symbols.get("c"), None);
+
+        assert_eq!(
+            SymbolTable::from(vec!["c".to_string(), "read".to_string()]),
+            Err(error::Format::SymbolTableOverlap)
+        );
+
+        // duplicated symbols resolve to their first position
+        let block =
+            SymbolTable::from(vec!["c".to_string(), "d".to_string(), "c".to_string()]).unwrap();
+        assert!(symbols.is_disjoint(&block));
+        symbols.extend(&block).unwrap();
+        assert_eq!(symbols.get("c"), Some(OFFSET as u64 + 2));
+        assert_eq!(symbols.insert("d"), OFFSET as u64 + 3);
+        assert_eq!(
+            symbols.extend(&block),
+            Err(error::Format::SymbolTableOverlap)
+        );
+
+        let block = symbols.split_at(2);
+        assert_eq!(block.strings(), vec!["c", "d", "c"]);
+        assert_eq!(block.get("d"), Some(OFFSET as u64 + 1));
+        assert_eq!(symbols.get("c"), None);
+        assert_eq!(symbols.insert("e"), OFFSET as u64 + 2);
+        assert_eq!(
+            symbols,
+            SymbolTable::from(vec!["a".to_string(), "b".to_string(), "e".to_string()]).unwrap()
+        );
+
+        let mut temporary = TemporarySymbolTable::new(&symbols);
+        assert_eq!(temporary.insert("b"), OFFSET as u64 + 1);
+        assert_eq!(temporary.insert("f"), OFFSET as u64 + 3);
+        assert_eq!(temporary.insert("f"), OFFSET as u64 + 3);
+        assert_eq!(temporary.get_symbol(OFFSET as u64 + 3), Some("f"));
+    }
+
+    #[test]
+    fn derive() {
+        let (key1, key2) = (
+            crate::KeyPair::new().public(),
+            crate::KeyPair::new().public(),
+        );
+        let base = Arc::new(
+            SymbolTable::from_symbols_and_public_keys(
+                vec!["a".to_string(), "b".to_string()],
+                vec![key1],
+            )
+            .unwrap(),
+        );
+
+        let mut symbols = SymbolTable::derive(&base);
+        assert_eq!(symbols.current_offset(), 2);
+        assert_eq!(symbols.insert("b"), OFFSET as u64 + 1);
+        assert_eq!(symbols.insert("c"), OFFSET as u64 + 2);
+        assert_eq!(symbols.get_symbol(OFFSET as u64), Some("a"));
+        assert_eq!(symbols.get_symbol(OFFSET as u64 + 2), Some("c"));
+        assert_eq!(symbols.get_symbol(OFFSET as u64 + 3), None);
+        assert_eq!(symbols.public_keys.insert(&key1), 0);
+        assert_eq!(symbols.public_keys.insert(&key2), 1);
+        assert_eq!(symbols.public_keys.get_key(1), Some(&key2));
+
+        // the base table is shared, not modified
+        assert_eq!(base.strings(), vec!["a", "b"]);
+        assert_eq!(base.public_keys.current_offset(), 1);
+
+        // tables compare by content, however they are built
+        let mut flat =
+            SymbolTable::from(vec!["a".to_string(), "b".to_string(), "c".to_string()]).unwrap();
+        flat.public_keys = PublicKeys::from(vec![key1, key2]);
+        assert_eq!(symbols, flat);
+
+        let block = SymbolTable::from(vec!["d".to_string()]).unwrap();
+        assert!(symbols.is_disjoint(&block));
+        assert!(!symbols.is_disjoint(&SymbolTable::from(vec!["a".to_string()]).unwrap()));
+
+        let mut derived = SymbolTable::derive(&Arc::new(symbols));
+        derived.extend(&block).unwrap();
+        assert_eq!(derived.get("d"), Some(OFFSET as u64 + 3));
+        assert_eq!(derived.get("a"), Some(OFFSET as u64));
+        assert_eq!(derived.strings(), vec!["a", "b", "c", "d"]);
+
+        // splitting through the shared symbols
+        let split = derived.split_at(1);
+        assert_eq!(split.strings(), vec!["b", "c", "d"]);
+        assert_eq!(derived.strings(), vec!["a"]);
+        assert_eq!(derived.get("c"), None);
+        assert_eq!(derived.insert("e"), OFFSET as u64 + 1);
+        assert_eq!(derived.public_keys.current_offset(), 2);
+    }
+
+    #[test]
+    fn derive_many() {
+        let keys = (0..100)
+            .map(|_| crate::KeyPair::new().public())
+            .collect::<Vec<_>>();
+
+        let mut symbols = Arc::new(SymbolTable::new());
+        for (i, key) in keys.iter().enumerate() {
+            let mut derived = SymbolTable::derive(&symbols);
+            assert_eq!(derived.insert(&format!("s{}", i)), (OFFSET + i) as u64);
+            assert_eq!(derived.public_keys.insert(key), i as u64);
+            symbols = Arc::new(derived);
+
+            assert!(symbols.tables().count() <= MAX_DERIVE_DEPTH);
+            assert!(symbols.public_keys.depth() <= MAX_DERIVE_DEPTH);
+        }
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(symbols.get(&format!("s{}", i)), Some((OFFSET + i) as u64));
+            assert_eq!(symbols.public_keys.get(key), Some(i as u64));
+            assert_eq!(symbols.public_keys.get_key(i as u64), Some(key));
+        }
+        assert_eq!(symbols.strings().len(), 100);
+        assert_eq!(
+            symbols.public_keys.iter().copied().collect::<Vec<_>>(),
+            keys
+        );
+    }
+}
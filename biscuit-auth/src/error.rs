@@ -28,6 +28,10 @@ pub enum Token {
     Base64(Base64Error),
     #[error("Datalog  execution failure: {0}")]
     Execution(Expression),
+    #[error("fact provider error: {0}")]
+    FactProvider(String),
+    #[error("cannot import facts from {} rows", .0.len())]
+    Import(Vec<ImportError>),
 }
 
 impl From<Infallible> for Token {
@@ -114,6 +118,10 @@ pub enum Format {
     EmptyKeys,
     #[error("the root public key was not recognized")]
     UnknownPublicKey,
+    #[error("the root public key has expired")]
+    ExpiredPublicKey,
+    #[error("the third party block request was not created from this token")]
+    ThirdPartyRequestMismatch,
     #[error("could not deserialize the wrapper object")]
     DeserializationError(String),
     #[error("could not serialize the wrapper object")]
@@ -150,6 +158,14 @@ pub enum Format {
     UnknownExternalKey,
     #[error("the symbol id was not in the table")]
     UnknownSymbol(u64),
+    #[error("group scopes can only be used by the authorizer")]
+    GroupScope,
+    #[error("no decryption key was provided for the private facts encrypted to {0}")]
+    MissingDecryptionKey(String),
+    #[error("could not decrypt the private facts")]
+    Decryption,
+    #[error("the block is signed more than once by {0}")]
+    DuplicateSigner(String),
 }
 
 /// Signature errors
@@ -179,6 +195,8 @@ pub enum Logic {
     },
     #[error("the authorizer already contains a token")]
     AuthorizerNotEmpty,
+    #[error("block {0} uses the predicate name {1}, which is reserved for token metadata")]
+    ReservedPredicate(u32, String),
     #[error("no matching policy was found")]
     NoMatchingPolicy {
         /// list of checks that failed validation
@@ -222,6 +240,17 @@ pub struct FailedAuthorizerCheck {
     pub rule: String,
 }
 
+/// a row of data that could not be converted to a fact
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+#[error("row {row}, field {field}: {message}")]
+pub struct ImportError {
+    /// index of the row in a JSON array, or line number in a CSV document
+    pub row: usize,
+    pub field: String,
+    pub message: String,
+}
+
 /// Datalog execution errors
 #[derive(Error, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
@@ -37,12 +37,8 @@ pub fn token_block_to_proto_block(input: &Block) -> schema::Block {
             .iter()
             .map(v2::token_scope_to_proto_scope)
             .collect(),
-        public_keys: input
-            .public_keys
-            .keys
-            .iter()
-            .map(|key| key.to_proto())
-            .collect(),
+        public_keys: input.public_keys.iter().map(|key| key.to_proto()).collect(),
+        encrypted_facts: input.encrypted_facts.clone(),
     }
 }
 
@@ -93,14 +89,20 @@ pub fn proto_block_to_token_block(
         public_keys.insert_fallible(&PublicKey::from_proto(pk)?)?;
     }
 
-    let detected_schema_version = get_schema_version(&facts, &rules, &checks, &scopes);
+    let detected_schema_version = get_schema_version(
+        &facts,
+        &rules,
+        &checks,
+        &scopes,
+        input.encrypted_facts.is_some(),
+    );
 
     detected_schema_version.check_compatibility(version)?;
 
     let scopes: Result<Vec<Scope>, _> =
         input.scope.iter().map(proto_scope_to_token_scope).collect();
 
-    Ok(Block {
+    let block = Block {
         symbols,
         facts,
         rules,
@@ -108,9 +110,14 @@ pub fn proto_block_to_token_block(
         context,
         version,
         external_key,
+        co_signers: vec![],
         public_keys,
         scopes: scopes?,
-    })
+        encrypted_facts: input.encrypted_facts.clone(),
+    };
+    block.check_group_scopes()?;
+
+    Ok(block)
 }
 
 pub fn token_block_to_proto_snapshot_block(input: &Block) -> schema::SnapshotBlock {
@@ -138,6 +145,7 @@ pub fn token_block_to_proto_snapshot_block(input: &Block) -> schema::SnapshotBlo
             .map(v2::token_scope_to_proto_scope)
             .collect(),
         external_key: input.external_key.map(|key| key.to_proto()),
+        co_signers: input.co_signers.iter().map(|key| key.to_proto()).collect(),
     }
 }
 
@@ -180,7 +188,7 @@ pub fn proto_snapshot_block_to_token_block(
 
     let context = input.context.clone();
 
-    let detected_schema_version = get_schema_version(&facts, &rules, &checks, &scopes);
+    let detected_schema_version = get_schema_version(&facts, &rules, &checks, &scopes, false);
 
     detected_schema_version.check_compatibility(version)?;
 
@@ -192,6 +200,12 @@ pub fn proto_snapshot_block_to_token_block(
         Some(key) => Some(PublicKey::from_proto(&key)?),
     };
 
+    let co_signers = input
+        .co_signers
+        .iter()
+        .map(PublicKey::from_proto)
+        .collect::<Result<Vec<_>, _>>()?;
+
     Ok(Block {
         symbols: SymbolTable::new(),
         facts,
@@ -200,10 +214,43 @@ pub fn proto_snapshot_block_to_token_block(
         context,
         version,
         external_key,
+        co_signers,
         public_keys: PublicKeys::default(),
         scopes: scopes?,
+        // private facts are stored with the other facts of the world
+        encrypted_facts: None,
     })
 }
+
+/// serializes private facts with their own symbol table, so that their
+/// strings do not appear in the token's symbols
+pub fn private_facts_to_proto(facts: &[crate::builder::Fact]) -> schema::PrivateFacts {
+    let mut symbols = SymbolTable::new();
+
+    let facts = facts
+        .iter()
+        .map(|fact| v2::token_fact_to_proto_fact(&fact.convert(&mut symbols)))
+        .collect();
+
+    schema::PrivateFacts {
+        symbols: symbols.strings(),
+        facts,
+    }
+}
+
+pub fn proto_private_facts_to_facts(
+    input: &schema::PrivateFacts,
+) -> Result<Vec<crate::builder::Fact>, error::Format> {
+    let symbols = SymbolTable::from(input.symbols.clone())?;
+
+    input
+        .facts
+        .iter()
+        .map(|fact| {
+            crate::builder::Fact::convert_from(&v2::proto_fact_to_token_fact(fact)?, &symbols)
+        })
+        .collect()
+}
 pub fn authorizer_to_proto_authorizer(input: &AuthorizerPolicies) -> schema::AuthorizerPolicies {
     let mut symbols = SymbolTable::default();
 
@@ -508,6 +555,22 @@ pub mod v2 {
                     set: s.iter().map(token_term_to_proto_id).collect(),
                 })),
             },
+            Term::Array(a) => schema::TermV2 {
+                content: Some(Content::Array(schema::TermArray {
+                    array: a.iter().map(token_term_to_proto_id).collect(),
+                })),
+            },
+            Term::Map(m) => schema::TermV2 {
+                content: Some(Content::Map(schema::TermMap {
+                    entries: m
+                        .iter()
+                        .map(|(key, value)| schema::MapEntry {
+                            key: *key,
+                            value: token_term_to_proto_id(value),
+                        })
+                        .collect(),
+                })),
+            },
         }
     }
 
@@ -545,6 +608,12 @@ pub mod v2 {
                                 "deserialization error: sets cannot contain other sets".to_string(),
                             ));
                         }
+                        Some(Content::Array(_)) | Some(Content::Map(_)) => {
+                            return Err(error::Format::DeserializationError(
+                                "deserialization error: sets cannot contain arrays or maps"
+                                    .to_string(),
+                            ));
+                        }
                         None => {
                             return Err(error::Format::DeserializationError(
                                 "deserialization error: ID content enum is empty".to_string(),
@@ -568,6 +637,30 @@ pub mod v2 {
 
                 Ok(Term::Set(set))
             }
+            Some(Content::Array(a)) => a
+                .array
+                .iter()
+                .map(proto_id_to_composite_element)
+                .collect::<Result<_, _>>()
+                .map(Term::Array),
+            Some(Content::Map(m)) => m
+                .entries
+                .iter()
+                .map(|entry| Ok((entry.key, proto_id_to_composite_element(&entry.value)?)))
+                .collect::<Result<_, _>>()
+                .map(Term::Map),
+        }
+    }
+
+    /// elements of arrays and maps can be any term except variables
+    fn proto_id_to_composite_element(input: &schema::TermV2) -> Result<Term, error::Format> {
+        use schema::term_v2::Content;
+
+        match input.content {
+            Some(Content::Variable(_)) => Err(error::Format::DeserializationError(
+                "deserialization error: arrays and maps cannot contain variables".to_string(),
+            )),
+            _ => proto_id_to_token_term(input),
         }
     }
 
@@ -616,6 +709,7 @@ pub mod v2 {
                                     Binary::BitwiseOr => Kind::BitwiseOr,
                                     Binary::BitwiseXor => Kind::BitwiseXor,
                                     Binary::NotEqual => Kind::NotEqual,
+                                    Binary::Get => Kind::Get,
                                 } as i32,
                             })
                         }
@@ -670,6 +764,7 @@ pub mod v2 {
                     Some(op_binary::Kind::BitwiseOr) => Op::Binary(Binary::BitwiseOr),
                     Some(op_binary::Kind::BitwiseXor) => Op::Binary(Binary::BitwiseXor),
                     Some(op_binary::Kind::NotEqual) => Op::Binary(Binary::NotEqual),
+                    Some(op_binary::Kind::Get) => Op::Binary(Binary::Get),
                     None => {
                         return Err(error::Format::DeserializationError(
                             "deserialization error: binary operation is empty".to_string(),
@@ -698,6 +793,13 @@ pub mod v2 {
                     schema::scope::Content::ScopeType(schema::scope::ScopeType::Previous as i32)
                 }
                 crate::token::Scope::PublicKey(i) => schema::scope::Content::PublicKey(*i as i64),
+                crate::token::Scope::Threshold(threshold, keys) => {
+                    schema::scope::Content::Threshold(schema::ThresholdScope {
+                        threshold: *threshold,
+                        public_keys: keys.iter().map(|i| *i as i64).collect(),
+                    })
+                }
+                crate::token::Scope::Group(name) => schema::scope::Content::Group(*name),
             }),
         }
     }
@@ -719,6 +821,20 @@ pub mod v2 {
                     }
                 }
                 schema::scope::Content::PublicKey(i) => Ok(Scope::PublicKey(*i as u64)),
+                schema::scope::Content::Threshold(t) => {
+                    if t.threshold == 0 || t.threshold as usize > t.public_keys.len() {
+                        return Err(error::Format::DeserializationError(format!(
+                            "deserialization error: invalid threshold `{}` for {} public keys",
+                            t.threshold,
+                            t.public_keys.len()
+                        )));
+                    }
+                    Ok(Scope::Threshold(
+                        t.threshold,
+                        t.public_keys.iter().map(|i| *i as u64).collect(),
+                    ))
+                }
+                schema::scope::Content::Group(name) => Ok(Scope::Group(*name)),
             },
             None => Err(error::Format::DeserializationError(
                 "deserialization error: expected `content` field in Scope".to_string(),
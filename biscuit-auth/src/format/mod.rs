@@ -13,9 +13,11 @@ use super::error;
 use super::token::Block;
 use crate::crypto::ExternalSignature;
 use crate::datalog::SymbolTable;
+use crate::token::public_keys::PublicKeys;
 use crate::token::RootKeyProvider;
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::sync::Arc;
 
 /// Structures generated from the Protobuf schema
 pub mod schema; /*{
@@ -30,15 +32,28 @@ use self::convert::*;
 ///
 /// This structure contains the blocks serialized to byte arrays. Those arrays
 /// will be used for the signature
+///
+/// blocks are shared between the tokens derived from this one, so appending
+/// a block does not copy the previous ones
 #[derive(Clone, Debug)]
 pub struct SerializedBiscuit {
     pub root_key_id: Option<u32>,
-    pub authority: crypto::Block,
-    pub blocks: Vec<crypto::Block>,
+    pub(crate) authority: Arc<crypto::Block>,
+    pub(crate) blocks: Vec<Arc<crypto::Block>>,
     pub proof: crypto::TokenNext,
 }
 
 impl SerializedBiscuit {
+    /// the authority block
+    pub fn authority(&self) -> &crypto::Block {
+        &self.authority
+    }
+
+    /// the blocks following the authority block, in order
+    pub fn blocks(&self) -> impl ExactSizeIterator<Item = &crypto::Block> {
+        self.blocks.iter().map(|block| block.as_ref())
+    }
+
     pub fn from_slice<KP>(slice: &[u8], key_provider: KP) -> Result<Self, error::Format>
     where
         KP: RootKeyProvider,
@@ -52,6 +67,9 @@ impl SerializedBiscuit {
     }
 
     pub(crate) fn deserialize(slice: &[u8]) -> Result<Self, error::Format> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("parse", size = slice.len()).entered();
+
         let data = schema::Biscuit::decode(slice).map_err(|e| {
             error::Format::DeserializationError(format!("deserialization error: {:?}", e))
         })?;
@@ -64,18 +82,19 @@ impl SerializedBiscuit {
 
         let signature = ed25519_dalek::Signature::from_bytes(&bytes);
 
-        if data.authority.external_signature.is_some() {
+        if data.authority.external_signature.is_some() || !data.authority.co_signatures.is_empty() {
             return Err(error::Format::DeserializationError(
                 "the authority block must not contain an external signature".to_string(),
             ));
         }
 
-        let authority = crypto::Block {
+        let authority = Arc::new(crypto::Block {
             data: data.authority.block,
             next_key,
             signature,
             external_signature: None,
-        };
+            co_signatures: vec![],
+        });
 
         let mut blocks = Vec::new();
         for block in &data.blocks {
@@ -87,29 +106,24 @@ impl SerializedBiscuit {
 
             let signature = ed25519_dalek::Signature::from_bytes(&bytes);
 
-            let external_signature = if let Some(ex) = block.external_signature.as_ref() {
-                let public_key = PublicKey::from_proto(&ex.public_key)?;
-
-                let bytes: [u8; 64] = (&ex.signature[..])
-                    .try_into()
-                    .map_err(|_| error::Format::InvalidSignatureSize(ex.signature.len()))?;
-
-                let signature = ed25519_dalek::Signature::from_bytes(&bytes);
-
-                Some(ExternalSignature {
-                    public_key,
-                    signature,
-                })
-            } else {
-                None
-            };
-
-            blocks.push(crypto::Block {
+            let external_signature = block
+                .external_signature
+                .as_ref()
+                .map(proto_external_signature)
+                .transpose()?;
+            let co_signatures = block
+                .co_signatures
+                .iter()
+                .map(proto_external_signature)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            blocks.push(Arc::new(crypto::Block {
                 data: block.block.clone(),
                 next_key,
                 signature,
                 external_signature,
-            });
+                co_signatures,
+            }));
         }
 
         let proof = match data.proof.content {
@@ -143,16 +157,7 @@ impl SerializedBiscuit {
     pub(crate) fn extract_blocks(
         &self,
         symbols: &mut SymbolTable,
-    ) -> Result<
-        (
-            schema::Block,
-            Vec<schema::Block>,
-            HashMap<usize, Vec<usize>>,
-        ),
-        error::Token,
-    > {
-        let mut block_external_keys = Vec::new();
-
+    ) -> Result<(Arc<schema::Block>, Vec<Arc<schema::Block>>), error::Token> {
         let authority = schema::Block::decode(&self.authority.data[..]).map_err(|e| {
             error::Token::Format(error::Format::BlockDeserializationError(format!(
                 "error deserializing authority block: {:?}",
@@ -167,8 +172,6 @@ impl SerializedBiscuit {
                 .public_keys
                 .insert_fallible(&PublicKey::from_proto(pk)?)?;
         }
-        // the authority block should not have an external key
-        block_external_keys.push(None);
         //FIXME: return an error if the authority block has an external key
 
         let mut blocks = vec![];
@@ -182,10 +185,14 @@ impl SerializedBiscuit {
             })?;
 
             if let Some(external_signature) = &block.external_signature {
-                symbols.public_keys.insert(&external_signature.public_key);
-                block_external_keys.push(Some(external_signature.public_key));
+                let signers = std::iter::once(external_signature)
+                    .chain(&block.co_signatures)
+                    .map(|signature| signature.public_key)
+                    .collect::<Vec<_>>();
+                for key in &signers {
+                    symbols.public_keys.insert(key);
+                }
             } else {
-                block_external_keys.push(None);
                 symbols.extend(&SymbolTable::from(deser.symbols.clone())?)?;
             }
 
@@ -195,21 +202,31 @@ impl SerializedBiscuit {
                     .insert_fallible(&PublicKey::from_proto(pk)?)?;
             }
 
-            blocks.push(deser);
+            blocks.push(Arc::new(deser));
         }
 
+        Ok((Arc::new(authority), blocks))
+    }
+
+    /// indexes of the blocks signed by each external key, the keys being
+    /// identified by their position in `public_keys`
+    pub(crate) fn public_key_to_block_id(
+        &self,
+        public_keys: &PublicKeys,
+    ) -> HashMap<usize, Vec<usize>> {
         let mut public_key_to_block_id: HashMap<usize, Vec<usize>> = HashMap::new();
-        for (index, opt_key) in block_external_keys.into_iter().enumerate() {
-            if let Some(key) = opt_key {
-                if let Some(key_index) = symbols.public_keys.get(&key) {
+        for (index, block) in self.blocks.iter().enumerate() {
+            for signature in block.external_signature.iter().chain(&block.co_signatures) {
+                if let Some(key_index) = public_keys.get(&signature.public_key) {
+                    // the authority block is the block 0
                     public_key_to_block_id
                         .entry(key_index as usize)
                         .or_default()
-                        .push(index);
+                        .push(index + 1);
                 }
             }
         }
-        Ok((authority, blocks, public_key_to_block_id))
+        public_key_to_block_id
     }
 
     /// serializes the token
@@ -219,6 +236,7 @@ impl SerializedBiscuit {
             next_key: self.authority.next_key.to_proto(),
             signature: self.authority.signature.to_bytes().to_vec(),
             external_signature: None,
+            co_signatures: vec![],
         };
 
         let mut blocks = Vec::new();
@@ -227,12 +245,15 @@ impl SerializedBiscuit {
                 block: block.data.clone(),
                 next_key: block.next_key.to_proto(),
                 signature: block.signature.to_bytes().to_vec(),
-                external_signature: block.external_signature.as_ref().map(|external_signature| {
-                    schema::ExternalSignature {
-                        signature: external_signature.signature.to_bytes().to_vec(),
-                        public_key: external_signature.public_key.to_proto(),
-                    }
-                }),
+                external_signature: block
+                    .external_signature
+                    .as_ref()
+                    .map(external_signature_to_proto),
+                co_signatures: block
+                    .co_signatures
+                    .iter()
+                    .map(external_signature_to_proto)
+                    .collect(),
             };
 
             blocks.push(b);
@@ -277,6 +298,8 @@ impl SerializedBiscuit {
         next_keypair: &KeyPair,
         authority: &Block,
     ) -> Result<Self, error::Token> {
+        authority.check_group_scopes()?;
+
         let mut v = Vec::new();
         token_block_to_proto_block(authority)
             .encode(&mut v)
@@ -288,12 +311,13 @@ impl SerializedBiscuit {
 
         Ok(SerializedBiscuit {
             root_key_id,
-            authority: crypto::Block {
+            authority: Arc::new(crypto::Block {
                 data: v,
                 next_key: next_keypair.public(),
                 signature,
                 external_signature: None,
-            },
+                co_signatures: vec![],
+            }),
             blocks: vec![],
             proof: TokenNext::Secret(next_keypair.private()),
         })
@@ -307,6 +331,7 @@ impl SerializedBiscuit {
         external_signature: Option<ExternalSignature>,
     ) -> Result<Self, error::Token> {
         let keypair = self.proof.keypair()?;
+        block.check_group_scopes()?;
 
         let mut v = Vec::new();
         token_block_to_proto_block(block)
@@ -322,12 +347,13 @@ impl SerializedBiscuit {
 
         // Add new block
         let mut blocks = self.blocks.clone();
-        blocks.push(crypto::Block {
+        blocks.push(Arc::new(crypto::Block {
             data: v,
             next_key: next_keypair.public(),
             signature,
             external_signature,
-        });
+            co_signatures: vec![],
+        }));
 
         Ok(SerializedBiscuit {
             root_key_id: self.root_key_id,
@@ -343,6 +369,7 @@ impl SerializedBiscuit {
         next_keypair: &KeyPair,
         block: Vec<u8>,
         external_signature: Option<ExternalSignature>,
+        co_signatures: Vec<ExternalSignature>,
     ) -> Result<Self, error::Token> {
         let keypair = self.proof.keypair()?;
 
@@ -350,17 +377,21 @@ impl SerializedBiscuit {
         if let Some(signature) = &external_signature {
             v.extend_from_slice(&signature.signature.to_bytes());
         }
+        for signature in &co_signatures {
+            v.extend_from_slice(&signature.signature.to_bytes());
+        }
 
         let signature = crypto::sign(&keypair, next_keypair, &v)?;
 
         // Add new block
         let mut blocks = self.blocks.clone();
-        blocks.push(crypto::Block {
+        blocks.push(Arc::new(crypto::Block {
             data: block,
             next_key: next_keypair.public(),
             signature,
             external_signature,
-        });
+            co_signatures,
+        }));
 
         Ok(SerializedBiscuit {
             root_key_id: self.root_key_id,
@@ -372,6 +403,9 @@ impl SerializedBiscuit {
 
     /// checks the signature on a deserialized token
     pub fn verify(&self, root: &PublicKey) -> Result<(), error::Format> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("verify", blocks = self.blocks.len() + 1).entered();
+
         //FIXME: try batched signature verification
         let mut current_pub = root;
 
@@ -453,6 +487,30 @@ impl SerializedBiscuit {
     }
 }
 
+pub(crate) fn proto_external_signature(
+    input: &schema::ExternalSignature,
+) -> Result<ExternalSignature, error::Format> {
+    let public_key = PublicKey::from_proto(&input.public_key)?;
+
+    let bytes: [u8; 64] = (&input.signature[..])
+        .try_into()
+        .map_err(|_| error::Format::InvalidSignatureSize(input.signature.len()))?;
+
+    let signature = ed25519_dalek::Signature::from_bytes(&bytes);
+
+    Ok(ExternalSignature {
+        public_key,
+        signature,
+    })
+}
+
+pub(crate) fn external_signature_to_proto(input: &ExternalSignature) -> schema::ExternalSignature {
+    schema::ExternalSignature {
+        signature: input.signature.to_bytes().to_vec(),
+        public_key: input.public_key.to_proto(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Read;
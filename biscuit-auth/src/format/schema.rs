@@ -19,6 +19,8 @@ pub struct SignedBlock {
     pub signature: ::prost::alloc::vec::Vec<u8>,
     #[prost(message, optional, tag="4")]
     pub external_signature: ::core::option::Option<ExternalSignature>,
+    #[prost(message, repeated, tag="5")]
+    pub co_signatures: ::prost::alloc::vec::Vec<ExternalSignature>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ExternalSignature {
@@ -75,10 +77,30 @@ pub struct Block {
     pub scope: ::prost::alloc::vec::Vec<Scope>,
     #[prost(message, repeated, tag="8")]
     pub public_keys: ::prost::alloc::vec::Vec<PublicKey>,
+    #[prost(message, optional, tag="9")]
+    pub encrypted_facts: ::core::option::Option<EncryptedFacts>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct EncryptedFacts {
+    #[prost(bytes="vec", required, tag="1")]
+    pub recipient: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes="vec", required, tag="2")]
+    pub ephemeral_key: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes="vec", required, tag="3")]
+    pub nonce: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes="vec", required, tag="4")]
+    pub ciphertext: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PrivateFacts {
+    #[prost(string, repeated, tag="1")]
+    pub symbols: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(message, repeated, tag="2")]
+    pub facts: ::prost::alloc::vec::Vec<FactV2>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Scope {
-    #[prost(oneof="scope::Content", tags="1, 2")]
+    #[prost(oneof="scope::Content", tags="1, 2, 3, 4")]
     pub content: ::core::option::Option<scope::Content>,
 }
 /// Nested message and enum types in `Scope`.
@@ -95,9 +117,20 @@ pub mod scope {
         ScopeType(i32),
         #[prost(int64, tag="2")]
         PublicKey(i64),
+        #[prost(message, tag="3")]
+        Threshold(super::ThresholdScope),
+        #[prost(uint64, tag="4")]
+        Group(u64),
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ThresholdScope {
+    #[prost(uint32, required, tag="1")]
+    pub threshold: u32,
+    #[prost(int64, repeated, packed="false", tag="2")]
+    pub public_keys: ::prost::alloc::vec::Vec<i64>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct FactV2 {
     #[prost(message, required, tag="1")]
     pub predicate: PredicateV2,
@@ -138,7 +171,7 @@ pub struct PredicateV2 {
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct TermV2 {
-    #[prost(oneof="term_v2::Content", tags="1, 2, 3, 4, 5, 6, 7")]
+    #[prost(oneof="term_v2::Content", tags="1, 2, 3, 4, 5, 6, 7, 9, 10")]
     pub content: ::core::option::Option<term_v2::Content>,
 }
 /// Nested message and enum types in `TermV2`.
@@ -159,6 +192,10 @@ pub mod term_v2 {
         Bool(bool),
         #[prost(message, tag="7")]
         Set(super::TermSet),
+        #[prost(message, tag="9")]
+        Array(super::TermArray),
+        #[prost(message, tag="10")]
+        Map(super::TermMap),
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -167,6 +204,23 @@ pub struct TermSet {
     pub set: ::prost::alloc::vec::Vec<TermV2>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TermArray {
+    #[prost(message, repeated, tag="1")]
+    pub array: ::prost::alloc::vec::Vec<TermV2>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TermMap {
+    #[prost(message, repeated, tag="1")]
+    pub entries: ::prost::alloc::vec::Vec<MapEntry>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MapEntry {
+    #[prost(uint64, required, tag="1")]
+    pub key: u64,
+    #[prost(message, required, tag="2")]
+    pub value: TermV2,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ExpressionV2 {
     #[prost(message, repeated, tag="1")]
     pub ops: ::prost::alloc::vec::Vec<Op>,
@@ -234,6 +288,7 @@ pub mod op_binary {
         BitwiseOr = 18,
         BitwiseXor = 19,
         NotEqual = 20,
+        Get = 21,
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -273,6 +328,17 @@ pub struct ThirdPartyBlockRequest {
     pub previous_key: PublicKey,
     #[prost(message, repeated, tag="2")]
     pub public_keys: ::prost::alloc::vec::Vec<PublicKey>,
+    #[prost(message, optional, tag="3")]
+    pub context: ::core::option::Option<ThirdPartyRequestContext>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ThirdPartyRequestContext {
+    #[prost(string, repeated, tag="1")]
+    pub symbols: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(message, repeated, tag="2")]
+    pub facts: ::prost::alloc::vec::Vec<FactV2>,
+    #[prost(bytes="vec", optional, tag="3")]
+    pub token: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ThirdPartyBlockContents {
@@ -280,6 +346,8 @@ pub struct ThirdPartyBlockContents {
     pub payload: ::prost::alloc::vec::Vec<u8>,
     #[prost(message, required, tag="2")]
     pub external_signature: ExternalSignature,
+    #[prost(message, repeated, tag="3")]
+    pub co_signatures: ::prost::alloc::vec::Vec<ExternalSignature>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct AuthorizerSnapshot {
@@ -289,6 +357,8 @@ pub struct AuthorizerSnapshot {
     pub execution_time: u64,
     #[prost(message, required, tag="3")]
     pub world: AuthorizerWorld,
+    #[prost(uint64, optional, tag="4")]
+    pub time: ::core::option::Option<u64>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct RunLimits {
@@ -359,4 +429,6 @@ pub struct SnapshotBlock {
     pub scope: ::prost::alloc::vec::Vec<Scope>,
     #[prost(message, optional, tag="7")]
     pub external_key: ::core::option::Option<PublicKey>,
+    #[prost(message, repeated, tag="8")]
+    pub co_signers: ::prost::alloc::vec::Vec<PublicKey>,
 }
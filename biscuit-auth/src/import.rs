@@ -0,0 +1,566 @@
+//! loading facts from JSON and CSV documents
+//!
+//! a [`FactMapping`] describes how a row of data becomes a fact: the name of the
+//! predicate, and for each term, the field it is read from and its type.
+//!
+//! ```rust
+//! # use biscuit_auth::{Authorizer, import::{FactMapping, TermKind}};
+//! let claims = serde_json::json!({
+//!     "sub": "alice",
+//!     "groups": ["admin", "dev"],
+//!     "exp": "2030-01-01T00:00:00Z",
+//! });
+//!
+//! let mut authorizer = Authorizer::new();
+//! authorizer
+//!     .add_facts_from_json(
+//!         &FactMapping::new(
+//!             "claims",
+//!             [
+//!                 ("sub", TermKind::String),
+//!                 ("groups", TermKind::set_of(TermKind::String)),
+//!                 ("exp", TermKind::Date),
+//!             ],
+//!         ),
+//!         &claims,
+//!     )
+//!     .unwrap();
+//!
+//! authorizer
+//!     .add_policy(r#"allow if claims("alice", $groups, $exp), $groups.contains("admin")"#)
+//!     .unwrap();
+//! assert_eq!(authorizer.authorize(), Ok(0));
+//! ```
+use std::{collections::BTreeSet, convert::TryFrom, io::Read};
+
+use serde_json::Value;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    builder::{fact, BlockBuilder, Fact, Term},
+    error::{self, ImportError},
+    Authorizer,
+};
+
+/// type of the term read from a field
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TermKind {
+    Integer,
+    String,
+    Bool,
+    /// RFC 3339 string, or number of seconds since the UNIX epoch
+    Date,
+    /// hex encoded string
+    Bytes,
+    /// JSON array, or CSV field with elements separated by `;`
+    Set(Box<TermKind>),
+    /// type deduced from the value: JSON numbers, strings, booleans and arrays,
+    /// or CSV integers, booleans and strings. The elements of a set must all
+    /// have the same type
+    Any,
+}
+
+impl TermKind {
+    pub fn set_of(kind: TermKind) -> Self {
+        TermKind::Set(Box::new(kind))
+    }
+}
+
+/// describes how rows of a document are converted to facts
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FactMapping {
+    predicate: String,
+    fields: Vec<(String, TermKind)>,
+}
+
+impl FactMapping {
+    /// creates a mapping generating `predicate` facts, with one term per field
+    ///
+    /// JSON fields are object keys, or JSON pointers if they start with `/`.
+    /// CSV fields are column names from the header row
+    pub fn new<I, S>(predicate: &str, fields: I) -> Self
+    where
+        I: IntoIterator<Item = (S, TermKind)>,
+        S: Into<String>,
+    {
+        FactMapping {
+            predicate: predicate.to_string(),
+            fields: fields
+                .into_iter()
+                .map(|(field, kind)| (field.into(), kind))
+                .collect(),
+        }
+    }
+
+    /// converts a JSON document to facts
+    ///
+    /// an array generates one fact per element, any other value generates one fact
+    pub fn facts_from_json(&self, value: &Value) -> Result<Vec<Fact>, error::Token> {
+        let rows = match value {
+            Value::Array(rows) => rows.iter().collect(),
+            row => vec![row],
+        };
+
+        let mut facts = Vec::new();
+        let mut errors = Vec::new();
+        for (i, row) in rows.into_iter().enumerate() {
+            match self.json_row(row) {
+                Ok(terms) => facts.push(fact(&self.predicate, &terms)),
+                Err((field, message)) => errors.push(ImportError {
+                    row: i,
+                    field,
+                    message,
+                }),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(facts)
+        } else {
+            Err(error::Token::Import(errors))
+        }
+    }
+
+    /// converts a CSV document with a header row to facts, one per record
+    pub fn facts_from_csv<R: Read>(&self, reader: R) -> Result<Vec<Fact>, error::Token> {
+        let mut reader = csv::Reader::from_reader(reader);
+        let headers = reader
+            .headers()
+            .map_err(|e| error::Token::Import(vec![csv_error(&e)]))?
+            .clone();
+
+        let columns = self
+            .fields
+            .iter()
+            .map(|(field, _)| {
+                headers
+                    .iter()
+                    .position(|header| header == field)
+                    .ok_or_else(|| ImportError {
+                        row: 1,
+                        field: field.clone(),
+                        message: "missing column".to_string(),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| error::Token::Import(vec![e]))?;
+
+        let mut facts = Vec::new();
+        let mut errors = Vec::new();
+        for record in reader.records() {
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    errors.push(csv_error(&e));
+                    continue;
+                }
+            };
+            let row = record.position().map(|p| p.line() as usize).unwrap_or(0);
+
+            let terms = self
+                .fields
+                .iter()
+                .zip(&columns)
+                .map(|((field, kind), column)| {
+                    text_term(record.get(*column).unwrap_or(""), kind)
+                        .map_err(|message| (field.clone(), message))
+                })
+                .collect::<Result<Vec<_>, _>>();
+            match terms {
+                Ok(terms) => facts.push(fact(&self.predicate, &terms)),
+                Err((field, message)) => errors.push(ImportError {
+                    row,
+                    field,
+                    message,
+                }),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(facts)
+        } else {
+            Err(error::Token::Import(errors))
+        }
+    }
+
+    fn json_row(&self, row: &Value) -> Result<Vec<Term>, (String, String)> {
+        self.fields
+            .iter()
+            .map(|(field, kind)| {
+                let value = if field.starts_with('/') {
+                    row.pointer(field)
+                } else {
+                    row.get(field)
+                };
+
+                value
+                    .ok_or_else(|| "missing field".to_string())
+                    .and_then(|value| json_term(value, kind))
+                    .map_err(|message| (field.clone(), message))
+            })
+            .collect()
+    }
+}
+
+fn csv_error(e: &csv::Error) -> ImportError {
+    ImportError {
+        row: e.position().map(|p| p.line() as usize).unwrap_or(0),
+        field: String::new(),
+        message: e.to_string(),
+    }
+}
+
+fn json_term(value: &Value, kind: &TermKind) -> Result<Term, String> {
+    let term = match (kind, value) {
+        (TermKind::Integer, Value::Number(n)) => n.as_i64().map(Term::Integer),
+        (TermKind::String, Value::String(s)) => Some(Term::Str(s.clone())),
+        (TermKind::Bool, Value::Bool(b)) => Some(Term::Bool(*b)),
+        (TermKind::Date, Value::Number(n)) => n.as_u64().map(Term::Date),
+        (TermKind::Date, Value::String(s)) => return parse_date(s),
+        (TermKind::Bytes, Value::String(s)) => return parse_bytes(s),
+        (TermKind::Set(kind), Value::Array(values)) => {
+            if let TermKind::Set(_) = **kind {
+                return Err("sets cannot contain sets".to_string());
+            }
+            return values
+                .iter()
+                .map(|value| json_term(value, kind))
+                .collect::<Result<BTreeSet<_>, _>>()
+                .map(Term::Set);
+        }
+        (TermKind::Any, Value::Number(n)) => n.as_i64().map(Term::Integer),
+        (TermKind::Any, Value::String(s)) => Some(Term::Str(s.clone())),
+        (TermKind::Any, Value::Bool(b)) => Some(Term::Bool(*b)),
+        (TermKind::Any, Value::Array(values)) => {
+            return values
+                .iter()
+                .map(|value| match value {
+                    Value::Array(_) => Err("sets cannot contain sets".to_string()),
+                    value => json_term(value, &TermKind::Any),
+                })
+                .collect::<Result<BTreeSet<_>, _>>()
+                .and_then(set_term);
+        }
+        _ => None,
+    };
+
+    term.ok_or_else(|| format!("expected {}, got {}", kind_name(kind), value))
+}
+
+fn text_term(text: &str, kind: &TermKind) -> Result<Term, String> {
+    match kind {
+        TermKind::Integer => text
+            .parse()
+            .map(Term::Integer)
+            .map_err(|_| format!("expected an integer, got {:?}", text)),
+        TermKind::String => Ok(Term::Str(text.to_string())),
+        TermKind::Bool => match text {
+            "true" => Ok(Term::Bool(true)),
+            "false" => Ok(Term::Bool(false)),
+            _ => Err(format!("expected a boolean, got {:?}", text)),
+        },
+        TermKind::Date => match text.parse() {
+            Ok(seconds) => Ok(Term::Date(seconds)),
+            Err(_) => parse_date(text),
+        },
+        TermKind::Bytes => parse_bytes(text),
+        TermKind::Set(kind) => {
+            if let TermKind::Set(_) = **kind {
+                return Err("sets cannot contain sets".to_string());
+            }
+            text.split(';')
+                .map(str::trim)
+                .filter(|element| !element.is_empty())
+                .map(|element| text_term(element, kind))
+                .collect::<Result<BTreeSet<_>, _>>()
+                .and_then(set_term)
+        }
+        TermKind::Any => Ok(match text {
+            "true" => Term::Bool(true),
+            "false" => Term::Bool(false),
+            text => text
+                .parse()
+                .map(Term::Integer)
+                .unwrap_or_else(|_| Term::Str(text.to_string())),
+        }),
+    }
+}
+
+/// elements deduced from the values can have different types, which sets do not support
+fn set_term(elements: BTreeSet<Term>) -> Result<Term, String> {
+    let mut kinds = elements.iter().map(std::mem::discriminant);
+    if let Some(kind) = kinds.next() {
+        if kinds.any(|other| other != kind) {
+            return Err("set elements must have the same type".to_string());
+        }
+    }
+
+    Ok(Term::Set(elements))
+}
+
+fn parse_date(text: &str) -> Result<Term, String> {
+    let date = OffsetDateTime::parse(text, &Rfc3339)
+        .map_err(|e| format!("expected an RFC 3339 date, got {:?}: {}", text, e))?;
+    u64::try_from(date.unix_timestamp())
+        .map(Term::Date)
+        .map_err(|_| format!("date {:?} is before the UNIX epoch", text))
+}
+
+fn parse_bytes(text: &str) -> Result<Term, String> {
+    hex::decode(text)
+        .map(Term::Bytes)
+        .map_err(|e| format!("expected hex encoded bytes, got {:?}: {}", text, e))
+}
+
+fn kind_name(kind: &TermKind) -> &'static str {
+    match kind {
+        TermKind::Integer => "an integer",
+        TermKind::String => "a string",
+        TermKind::Bool => "a boolean",
+        TermKind::Date => "a date",
+        TermKind::Bytes => "a hex string",
+        TermKind::Set(_) => "an array",
+        TermKind::Any => "a number, string, boolean or array",
+    }
+}
+
+impl BlockBuilder {
+    /// adds the facts generated from a JSON document, see [`FactMapping::facts_from_json`]
+    pub fn add_facts_from_json(
+        &mut self,
+        mapping: &FactMapping,
+        value: &Value,
+    ) -> Result<(), error::Token> {
+        for fact in mapping.facts_from_json(value)? {
+            self.add_fact(fact)?;
+        }
+        Ok(())
+    }
+
+    /// adds the facts generated from a CSV document, see [`FactMapping::facts_from_csv`]
+    pub fn add_facts_from_csv<R: Read>(
+        &mut self,
+        mapping: &FactMapping,
+        reader: R,
+    ) -> Result<(), error::Token> {
+        for fact in mapping.facts_from_csv(reader)? {
+            self.add_fact(fact)?;
+        }
+        Ok(())
+    }
+}
+
+impl Authorizer {
+    /// adds the facts generated from a JSON document, see [`FactMapping::facts_from_json`]
+    pub fn add_facts_from_json(
+        &mut self,
+        mapping: &FactMapping,
+        value: &Value,
+    ) -> Result<(), error::Token> {
+        for fact in mapping.facts_from_json(value)? {
+            self.add_fact(fact)?;
+        }
+        Ok(())
+    }
+
+    /// adds the facts generated from a CSV document, see [`FactMapping::facts_from_csv`]
+    pub fn add_facts_from_csv<R: Read>(
+        &mut self,
+        mapping: &FactMapping,
+        reader: R,
+    ) -> Result<(), error::Token> {
+        for fact in mapping.facts_from_csv(reader)? {
+            self.add_fact(fact)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{int, set, string};
+
+    #[test]
+    fn json() {
+        let mapping = FactMapping::new(
+            "user",
+            [
+                ("id", TermKind::Integer),
+                ("/profile/name", TermKind::String),
+                ("roles", TermKind::set_of(TermKind::String)),
+                ("created", TermKind::Date),
+                ("key", TermKind::Bytes),
+                ("extra", TermKind::Any),
+            ],
+        );
+
+        let facts = mapping
+            .facts_from_json(&serde_json::json!([
+                {
+                    "id": 1,
+                    "profile": { "name": "alice" },
+                    "roles": ["admin", "dev"],
+                    "created": "1970-01-01T00:01:00Z",
+                    "key": "0a0b",
+                    "extra": [1, 2],
+                },
+                {
+                    "id": 2,
+                    "profile": { "name": "bob" },
+                    "roles": [],
+                    "created": 120,
+                    "key": "",
+                    "extra": "x",
+                },
+            ]))
+            .unwrap();
+        assert_eq!(
+            facts.iter().map(|f| f.to_string()).collect::<Vec<_>>(),
+            vec![
+                "user(1, \"alice\", [\"admin\", \"dev\"], 1970-01-01T00:01:00Z, hex:0a0b, [1, 2])",
+                "user(2, \"bob\", [], 1970-01-01T00:02:00Z, hex:, \"x\")",
+            ]
+        );
+
+        // the imported facts can be serialized in a token
+        let root = crate::KeyPair::new();
+        let mut builder = crate::Biscuit::builder();
+        for fact in facts.iter().cloned() {
+            builder.add_fact(fact).unwrap();
+        }
+        let token = builder.build(&root).unwrap();
+        let token = crate::Biscuit::from(&token.to_vec().unwrap(), root.public()).unwrap();
+        let mut authorizer = token.authorizer().unwrap();
+        authorizer
+            .add_policy(
+                "allow if user(1, $name, $roles, $created, $key, $extra), $extra.contains(2)",
+            )
+            .unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+
+        let mut block = BlockBuilder::new();
+        block
+            .add_facts_from_json(
+                &FactMapping::new("claim", [("sub", TermKind::String)]),
+                &serde_json::json!({ "sub": "alice" }),
+            )
+            .unwrap();
+        assert_eq!(block.facts, vec![fact("claim", &[string("alice")])]);
+
+        let res = mapping.facts_from_json(&serde_json::json!([
+            { "id": "1" },
+            {
+                "id": 1,
+                "profile": { "name": "alice" },
+                "roles": [["admin"]],
+            },
+            {
+                "id": 3,
+                "profile": { "name": "carol" },
+                "roles": [],
+                "created": 0,
+                "key": "",
+                "extra": [1, true],
+            },
+        ]));
+        assert_eq!(
+            res,
+            Err(error::Token::Import(vec![
+                ImportError {
+                    row: 0,
+                    field: "id".to_string(),
+                    message: "expected an integer, got \"1\"".to_string(),
+                },
+                ImportError {
+                    row: 1,
+                    field: "roles".to_string(),
+                    message: "expected a string, got [\"admin\"]".to_string(),
+                },
+                ImportError {
+                    row: 2,
+                    field: "extra".to_string(),
+                    message: "set elements must have the same type".to_string(),
+                },
+            ]))
+        );
+    }
+
+    #[test]
+    fn csv() {
+        let document = "\
+user,resource,operations,expires
+alice,file1,read;write,2030-01-01T00:00:00Z
+bob,file2,read,1893456000
+";
+        let mapping = FactMapping::new(
+            "right",
+            [
+                ("user", TermKind::String),
+                ("resource", TermKind::String),
+                ("operations", TermKind::set_of(TermKind::String)),
+                ("expires", TermKind::Date),
+            ],
+        );
+
+        let mut authorizer = Authorizer::new();
+        authorizer
+            .add_facts_from_csv(&mapping, document.as_bytes())
+            .unwrap();
+        authorizer
+            .add_policy(
+                r#"allow if right("alice", "file1", $ops, 2030-01-01T00:00:00Z), $ops.contains("write")"#,
+            )
+            .unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+
+        let facts = FactMapping::new("count", [("user", TermKind::Any), ("n", TermKind::Any)])
+            .facts_from_csv("user,n\nalice,12\n".as_bytes())
+            .unwrap();
+        assert_eq!(facts, vec![fact("count", &[string("alice"), int(12)])]);
+
+        let res = FactMapping::new("tags", [("tags", TermKind::set_of(TermKind::Any))])
+            .facts_from_csv("tags\n1;admin\n".as_bytes());
+        assert_eq!(
+            res,
+            Err(error::Token::Import(vec![ImportError {
+                row: 2,
+                field: "tags".to_string(),
+                message: "set elements must have the same type".to_string(),
+            }]))
+        );
+
+        let res = mapping.facts_from_csv(
+            "\
+user,resource,operations,expires
+alice,file1,read,yesterday
+bob,file2,read,0
+carol,file3,,12
+"
+            .as_bytes(),
+        );
+        assert_eq!(
+            res,
+            Err(error::Token::Import(vec![ImportError {
+                row: 2,
+                field: "expires".to_string(),
+                message: "expected an RFC 3339 date, got \"yesterday\": the 'year' component could not be parsed".to_string(),
+            }]))
+        );
+        let res = FactMapping::new("user", [("name", TermKind::String)])
+            .facts_from_csv("user\nalice\n".as_bytes());
+        assert_eq!(
+            res,
+            Err(error::Token::Import(vec![ImportError {
+                row: 1,
+                field: "name".to_string(),
+                message: "missing column".to_string(),
+            }]))
+        );
+
+        let empty = FactMapping::new("r", [("s", TermKind::set_of(TermKind::Integer))])
+            .facts_from_csv("s\n\"\"\n".as_bytes())
+            .unwrap();
+        assert_eq!(empty, vec![fact("r", &[set(BTreeSet::new())])]);
+    }
+}
@@ -227,9 +227,19 @@ pub mod parser;
 mod token;
 
 pub use crypto::{KeyPair, PrivateKey, PublicKey};
-pub use token::authorizer::{Authorizer, AuthorizerLimits};
+#[cfg(feature = "private-facts")]
+#[cfg_attr(feature = "docsrs", doc(cfg(feature = "private-facts")))]
+pub use crypto::{EncryptionKeyPair, EncryptionPrivateKey, EncryptionPublicKey};
+pub use token::authorizer::{Authorizer, AuthorizerLimits, BatchReplay, ReplayOutcome, WhatIf};
 pub use token::builder;
 pub use token::builder_ext;
+pub use token::clock::{Clock, FixedClock, OffsetClock, SystemClock};
+pub use token::issuer::{AuditRecord, ThirdPartyIssuer};
+pub use token::observer::{AuthorizationRecord, AuthorizerObserver};
+pub use token::provider::FactProvider;
+#[cfg(feature = "tracing")]
+#[cfg_attr(feature = "docsrs", doc(cfg(feature = "tracing")))]
+pub use token::observer::TracingObserver;
 pub use token::unverified::UnverifiedBiscuit;
 pub use token::Biscuit;
 pub use token::RootKeyProvider;
@@ -241,9 +251,10 @@ mod capi;
 #[cfg(cargo_c)]
 pub use capi::*;
 
-#[cfg(bwk)]
+#[cfg(feature = "bwk")]
+#[cfg_attr(feature = "docsrs", doc(cfg(feature = "bwk")))]
 mod bwk;
-#[cfg(bwk)]
+#[cfg(feature = "bwk")]
 pub use bwk::*;
 
 mod time;
@@ -252,3 +263,23 @@ mod time;
 #[cfg(feature = "datalog-macro")]
 #[cfg_attr(feature = "docsrs", doc(cfg(feature = "datalog-macro")))]
 pub mod macros;
+
+/// Conformance tests over the biscuit samples
+#[cfg(feature = "conformance")]
+#[cfg_attr(feature = "docsrs", doc(cfg(feature = "conformance")))]
+pub mod conformance;
+
+/// Loading facts from JSON and CSV documents
+#[cfg(feature = "fact-import")]
+#[cfg_attr(feature = "docsrs", doc(cfg(feature = "fact-import")))]
+pub mod import;
+
+/// JSON export and comparison of authorizer snapshots
+#[cfg(feature = "snapshot-json")]
+#[cfg_attr(feature = "docsrs", doc(cfg(feature = "snapshot-json")))]
+pub mod snapshot;
+
+/// Declarative policy test suites
+#[cfg(feature = "test-suite")]
+#[cfg_attr(feature = "docsrs", doc(cfg(feature = "test-suite")))]
+pub mod test_suite;
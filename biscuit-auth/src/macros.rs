@@ -208,6 +208,18 @@ pub use biscuit_quote::rule;
 ///   user_id = "1234"
 /// );
 /// ```
+///
+/// Parameters can be annotated with their type, and the values given to the
+/// macro are checked against it at compile time:
+///
+/// ```rust,compile_fail
+/// use biscuit_auth::macros::fact;
+///
+/// let f = fact!(
+///   r#"count({count: int})"#,
+///   count = "one"
+/// );
+/// ```
 pub use biscuit_quote::fact;
 
 /// Create a `Check` from a datalog string and optional parameters.
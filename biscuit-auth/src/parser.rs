@@ -13,7 +13,10 @@
 //! All of the methods in [BiscuitBuilder](`crate::token::builder::BiscuitBuilder`)
 //! and [BlockBuilder](`crate::token::builder::BlockBuilder`) can take strings
 //! as arguments too
+//!
+//! Datalog source can be printed in a canonical layout with the [`formatter`] module
 
+pub use biscuit_parser::formatter;
 pub use biscuit_parser::parser::*;
 
 #[cfg(test)]
@@ -161,6 +164,8 @@ mod tests {
                 input: "right($0, $test)",
                 code: ErrorKind::Satisfy,
                 message: Some("rule head contains variables that are not used in predicates of the rule's body: $test".to_string()),
+                span: None,
+                snippet: None,
             }))
         );
     }
@@ -207,6 +212,8 @@ mod tests {
                 input: "and",
                 code: ErrorKind::Eof,
                 message: Some("expected either the next term after ',' or the next check variant after 'or', but got 'and'".to_string()),
+                span: None,
+                snippet: None,
             }))
         );
 
@@ -218,6 +225,8 @@ mod tests {
                 input: ")",
                 code: ErrorKind::Eof,
                 message: Some("unexpected parens".to_string()),
+                span: None,
+                snippet: None,
             }))
         );
 
@@ -229,6 +238,8 @@ mod tests {
                 input: "&&",
                 code: ErrorKind::Eof,
                 message: Some("expected either the next term after ',' or the next check variant after 'or', but got '&&'".to_string()),
+                span: None,
+                snippet: None,
             }))
         );
     }
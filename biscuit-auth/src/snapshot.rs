@@ -0,0 +1,647 @@
+//! JSON representation of authorizer snapshots
+//!
+//! [`Authorizer::snapshot`] produces a protobuf message where facts and rules
+//! refer to an interned symbol table. A [`JsonSnapshot`] holds the same data
+//! written as Datalog, so that it can be read in logs and bug reports, and
+//! converted back to an authorizer:
+//!
+//! ```rust
+//! use biscuit_auth::{snapshot::JsonSnapshot, Authorizer};
+//!
+//! let mut authorizer = Authorizer::new();
+//! authorizer
+//!     .add_code(r#"resource("file1"); allow if resource("file1");"#)
+//!     .unwrap();
+//! authorizer.authorize().unwrap();
+//!
+//! let snapshot = JsonSnapshot::from_authorizer(&authorizer).unwrap();
+//! let json = snapshot.to_json();
+//! assert!(json.contains(r#"resource(\"file1\")"#));
+//!
+//! let mut restored = JsonSnapshot::from_json(&json).unwrap().to_authorizer().unwrap();
+//! assert_eq!(restored.authorize(), Ok(0));
+//! ```
+//!
+//! A [`SnapshotDiff`] compares two snapshots, as an example to see how a
+//! policy change affects the same request.
+use std::{
+    collections::BTreeSet,
+    convert::TryFrom,
+    fmt::{self, Write},
+};
+
+use biscuit_parser::parser::parse_block_source;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    builder::{BlockBuilder, Convert, Fact, Policy, Scope},
+    datalog::SymbolTable,
+    error,
+    format::{
+        convert::{
+            proto_snapshot_block_to_token_block, token_block_to_proto_snapshot_block,
+            v2::{
+                policy_to_proto_policy, proto_fact_to_token_fact, proto_policy_to_policy,
+                token_fact_to_proto_fact,
+            },
+        },
+        schema,
+    },
+    token::default_symbol_table,
+    Authorizer, PublicKey,
+};
+
+/// an authorizer snapshot, with its Datalog written as source code
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JsonSnapshot {
+    /// format version of the snapshot
+    pub version: u32,
+    pub limits: JsonLimits,
+    /// time spent evaluating Datalog, in nanoseconds
+    pub execution_time: u64,
+    /// UNIX timestamp added by `Authorizer::set_time`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<u64>,
+    pub iterations: u64,
+    /// token blocks, starting with the authority block
+    #[serde(default)]
+    pub blocks: Vec<JsonBlock>,
+    pub authorizer: JsonBlock,
+    #[serde(default)]
+    pub policies: Vec<String>,
+    /// facts of the world, grouped by origin
+    #[serde(default)]
+    pub facts: Vec<JsonFacts>,
+}
+
+/// run limits of the authorizer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JsonLimits {
+    pub max_facts: u64,
+    pub max_iterations: u64,
+    /// in nanoseconds
+    pub max_time: u64,
+}
+
+/// a token block, or the authorizer's own block
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JsonBlock {
+    /// facts, rules and checks, after the block's `trusting` annotation if any
+    pub code: String,
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub co_signers: Vec<String>,
+}
+
+/// facts sharing the same origin
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JsonFacts {
+    pub origin: BTreeSet<JsonOrigin>,
+    pub facts: Vec<String>,
+}
+
+/// a block a fact comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonOrigin {
+    Block(u32),
+    Authorizer,
+}
+
+impl fmt::Display for JsonOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonOrigin::Block(i) => write!(f, "{}", i),
+            JsonOrigin::Authorizer => write!(f, "authorizer"),
+        }
+    }
+}
+
+impl JsonSnapshot {
+    pub fn from_authorizer(authorizer: &Authorizer) -> Result<Self, error::Token> {
+        Self::from_snapshot(&authorizer.snapshot()?)
+    }
+
+    pub fn to_authorizer(&self) -> Result<Authorizer, error::Token> {
+        Authorizer::from_snapshot(self.to_snapshot()?)
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(data)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("snapshots can always be serialized")
+    }
+
+    pub fn from_snapshot(snapshot: &schema::AuthorizerSnapshot) -> Result<Self, error::Token> {
+        let world = &snapshot.world;
+        let version = world.version.unwrap_or(0);
+
+        let mut symbols = default_symbol_table();
+        for symbol in &world.symbols {
+            symbols.insert(symbol);
+        }
+        for public_key in &world.public_keys {
+            symbols
+                .public_keys
+                .insert(&PublicKey::from_proto(public_key)?);
+        }
+
+        let blocks = world
+            .blocks
+            .iter()
+            .map(|block| JsonBlock::from_proto(block, &symbols))
+            .collect::<Result<Vec<_>, _>>()?;
+        let authorizer = JsonBlock::from_proto(&world.authorizer_block, &symbols)?;
+        let policies = world
+            .authorizer_policies
+            .iter()
+            .map(|policy| proto_policy_to_policy(policy, &symbols, version).map(|p| p.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut facts = world
+            .generated_facts
+            .iter()
+            .map(|generated| {
+                let origin = generated
+                    .origins
+                    .iter()
+                    .map(|origin| match origin.content {
+                        Some(schema::origin::Content::Authorizer(_)) => Ok(JsonOrigin::Authorizer),
+                        Some(schema::origin::Content::Origin(i)) => Ok(JsonOrigin::Block(i)),
+                        None => Err(error::Format::DeserializationError(
+                            "invalid origin".to_string(),
+                        )),
+                    })
+                    .collect::<Result<_, _>>()?;
+                let mut facts = generated
+                    .facts
+                    .iter()
+                    .map(|fact| {
+                        Fact::convert_from(&proto_fact_to_token_fact(fact)?, &symbols)
+                            .map(|f| f.to_string())
+                    })
+                    .collect::<Result<Vec<_>, error::Format>>()?;
+                facts.sort();
+
+                Ok(JsonFacts { origin, facts })
+            })
+            .collect::<Result<Vec<_>, error::Format>>()?;
+        // the world does not keep facts in a stable order
+        facts.sort_by(|a, b| a.origin.cmp(&b.origin));
+
+        Ok(JsonSnapshot {
+            version,
+            limits: JsonLimits {
+                max_facts: snapshot.limits.max_facts,
+                max_iterations: snapshot.limits.max_iterations,
+                max_time: snapshot.limits.max_time,
+            },
+            execution_time: snapshot.execution_time,
+            time: snapshot.time,
+            iterations: world.iterations,
+            blocks,
+            authorizer,
+            policies,
+            facts,
+        })
+    }
+
+    pub fn to_snapshot(&self) -> Result<schema::AuthorizerSnapshot, error::Token> {
+        let mut symbols = default_symbol_table();
+
+        let authorizer_policies = self
+            .policies
+            .iter()
+            .map(|policy| {
+                Policy::try_from(policy.as_str())
+                    .map(|policy| policy_to_proto_policy(&policy, &mut symbols))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let authorizer_block = self.authorizer.to_proto(&mut symbols)?;
+        let blocks = self
+            .blocks
+            .iter()
+            .map(|block| block.to_proto(&mut symbols))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut generated_facts = Vec::new();
+        for JsonFacts { origin, facts } in &self.facts {
+            let origins = origin
+                .iter()
+                .map(|origin| schema::Origin {
+                    content: Some(match origin {
+                        JsonOrigin::Block(i) => schema::origin::Content::Origin(*i),
+                        JsonOrigin::Authorizer => {
+                            schema::origin::Content::Authorizer(schema::Empty {})
+                        }
+                    }),
+                })
+                .collect();
+            let facts = facts
+                .iter()
+                .map(|fact| {
+                    Fact::try_from(fact.as_str())
+                        .map(|fact| token_fact_to_proto_fact(&fact.convert(&mut symbols)))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            generated_facts.push(schema::GeneratedFacts { origins, facts });
+        }
+
+        Ok(schema::AuthorizerSnapshot {
+            limits: schema::RunLimits {
+                max_facts: self.limits.max_facts,
+                max_iterations: self.limits.max_iterations,
+                max_time: self.limits.max_time,
+            },
+            execution_time: self.execution_time,
+            world: schema::AuthorizerWorld {
+                version: Some(self.version),
+                symbols: symbols.strings(),
+                public_keys: symbols
+                    .public_keys
+                    .into_inner()
+                    .into_iter()
+                    .map(|key| key.to_proto())
+                    .collect(),
+                blocks,
+                authorizer_block,
+                authorizer_policies,
+                generated_facts,
+                iterations: self.iterations,
+            },
+            time: self.time,
+        })
+    }
+
+    /// compares this snapshot with a later one
+    pub fn diff(&self, after: &JsonSnapshot) -> SnapshotDiff {
+        SnapshotDiff::new(self, after)
+    }
+}
+
+impl JsonBlock {
+    fn from_proto(
+        block: &schema::SnapshotBlock,
+        symbols: &SymbolTable,
+    ) -> Result<Self, error::Format> {
+        let block = proto_snapshot_block_to_token_block(block)?;
+        let builder = BlockBuilder::convert_from(&block, symbols)?;
+
+        let mut code = String::new();
+        if !builder.scopes.is_empty() {
+            let scopes = builder
+                .scopes
+                .iter()
+                .map(|scope| scope.to_string())
+                .collect::<Vec<_>>();
+            let _ = writeln!(code, "trusting {};", scopes.join(", "));
+        }
+        let _ = write!(code, "{}", builder);
+
+        Ok(JsonBlock {
+            code,
+            version: block.version,
+            context: block.context,
+            external_key: block.external_key.map(|key| key.to_string()),
+            co_signers: block.co_signers.iter().map(|key| key.to_string()).collect(),
+        })
+    }
+
+    fn to_proto(&self, symbols: &mut SymbolTable) -> Result<schema::SnapshotBlock, error::Token> {
+        let mut builder = BlockBuilder::new();
+        builder.add_code(&self.code)?;
+        // the block's `trusting` annotation is not kept by `add_code`
+        let source = parse_block_source(&self.code).map_err(|e| {
+            let e: biscuit_parser::error::LanguageError = e.into();
+            e
+        })?;
+        for scope in source.scopes {
+            builder.add_scope(Scope::from(scope));
+        }
+        if let Some(context) = &self.context {
+            builder.set_context(context.clone());
+        }
+
+        let mut block = builder.build(symbols.clone());
+        block.version = self.version;
+        block.external_key = self
+            .external_key
+            .as_deref()
+            .map(str::parse::<PublicKey>)
+            .transpose()?;
+        block.co_signers = self
+            .co_signers
+            .iter()
+            .map(|key| key.parse())
+            .collect::<Result<_, _>>()?;
+
+        // the block was built on a copy of the symbol table
+        symbols.extend(&block.symbols)?;
+        symbols.public_keys.extend(&block.public_keys)?;
+
+        Ok(token_block_to_proto_snapshot_block(&block))
+    }
+}
+
+/// differences between two snapshots
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotDiff {
+    /// facts only present in the second snapshot
+    pub added_facts: Vec<JsonFacts>,
+    /// facts only present in the first snapshot
+    pub removed_facts: Vec<JsonFacts>,
+    /// policies that differ, compared by position
+    pub policies: Vec<PolicyChange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<LimitsChange>,
+}
+
+/// a policy that was added, removed or modified
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyChange {
+    pub index: usize,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LimitsChange {
+    pub before: JsonLimits,
+    pub after: JsonLimits,
+}
+
+impl SnapshotDiff {
+    pub fn new(before: &JsonSnapshot, after: &JsonSnapshot) -> Self {
+        let policies = (0..before.policies.len().max(after.policies.len()))
+            .filter_map(|index| {
+                let before = before.policies.get(index);
+                let after = after.policies.get(index);
+                (before != after).then(|| PolicyChange {
+                    index,
+                    before: before.cloned(),
+                    after: after.cloned(),
+                })
+            })
+            .collect();
+
+        let limits = (before.limits != after.limits).then_some(LimitsChange {
+            before: before.limits,
+            after: after.limits,
+        });
+
+        SnapshotDiff {
+            added_facts: missing_facts(&after.facts, &before.facts),
+            removed_facts: missing_facts(&before.facts, &after.facts),
+            policies,
+            limits,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added_facts.is_empty()
+            && self.removed_facts.is_empty()
+            && self.policies.is_empty()
+            && self.limits.is_none()
+    }
+}
+
+/// facts from `facts` that are not in `other` with the same origin
+fn missing_facts(facts: &[JsonFacts], other: &[JsonFacts]) -> Vec<JsonFacts> {
+    facts
+        .iter()
+        .filter_map(|group| {
+            let existing = other
+                .iter()
+                .filter(|g| g.origin == group.origin)
+                .flat_map(|g| g.facts.iter())
+                .collect::<BTreeSet<_>>();
+            let facts = group
+                .facts
+                .iter()
+                .filter(|fact| !existing.contains(fact))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            (!facts.is_empty()).then(|| JsonFacts {
+                origin: group.origin.clone(),
+                facts,
+            })
+        })
+        .collect()
+}
+
+fn print_origin(origin: &BTreeSet<JsonOrigin>) -> String {
+    origin
+        .iter()
+        .map(|o| o.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (sign, groups) in [("+", &self.added_facts), ("-", &self.removed_facts)] {
+            for group in groups {
+                for fact in &group.facts {
+                    writeln!(f, "{} [{}] {};", sign, print_origin(&group.origin), fact)?;
+                }
+            }
+        }
+
+        for PolicyChange {
+            index,
+            before,
+            after,
+        } in &self.policies
+        {
+            if let Some(before) = before {
+                writeln!(f, "- policy {}: {};", index, before)?;
+            }
+            if let Some(after) = after {
+                writeln!(f, "+ policy {}: {};", index, after)?;
+            }
+        }
+
+        if let Some(LimitsChange { before, after }) = &self.limits {
+            let limits = [
+                ("max_facts", before.max_facts, after.max_facts),
+                (
+                    "max_iterations",
+                    before.max_iterations,
+                    after.max_iterations,
+                ),
+                ("max_time", before.max_time, after.max_time),
+            ];
+            for (name, before, after) in limits {
+                if before != after {
+                    writeln!(f, "~ {}: {} -> {}", name, before, after)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{fact, string};
+    use crate::{Biscuit, KeyPair};
+
+    fn authorize(token: &Biscuit, code: &str) -> Authorizer {
+        let mut authorizer = token.authorizer().unwrap();
+        authorizer.add_code(code).unwrap();
+        let _ = authorizer.authorize();
+        authorizer
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+
+        let mut builder = Biscuit::builder();
+        builder
+            .add_code(
+                r#"user("alice"); right("file1", "read");
+                check if time($t), $t < 2100-01-01T00:00:00Z;"#,
+            )
+            .unwrap();
+        builder.set_context("session".to_string());
+        let token = builder.build(&root).unwrap();
+        let request = token.third_party_request().unwrap();
+        let mut block = BlockBuilder::new();
+        block.add_code("group(\"admin\");").unwrap();
+        let block = request.create_block(&external.private(), block).unwrap();
+        let token = token.append_third_party(external.public(), block).unwrap();
+
+        let mut authorizer = authorize(
+            &token,
+            &format!(
+                r#"time(2023-12-01T00:00:00Z);
+                admin($u) <- user($u), group("admin") trusting authority, {0};
+                allow if admin("alice") trusting authority, {0};"#,
+                external.public()
+            ),
+        );
+        assert_eq!(authorizer.authorize(), Ok(0));
+
+        let snapshot = JsonSnapshot::from_authorizer(&authorizer).unwrap();
+        assert_eq!(snapshot.blocks.len(), 2);
+        assert_eq!(snapshot.blocks[0].context, Some("session".to_string()));
+        assert_eq!(
+            snapshot.blocks[1].external_key,
+            Some(external.public().to_string())
+        );
+        assert_eq!(
+            snapshot.policies,
+            vec![format!(
+                "allow if admin(\"alice\") trusting authority, {}",
+                external.public()
+            )]
+        );
+        let authorizer_facts = snapshot
+            .facts
+            .iter()
+            .find(|group| group.origin == BTreeSet::from([JsonOrigin::Authorizer]))
+            .unwrap();
+        assert!(authorizer_facts
+            .facts
+            .contains(&"time(2023-12-01T00:00:00Z)".to_string()));
+        assert!(snapshot.facts.iter().any(|group| group.origin
+            == BTreeSet::from([
+                JsonOrigin::Block(0),
+                JsonOrigin::Block(1),
+                JsonOrigin::Authorizer
+            ])
+            && group.facts == vec!["admin(\"alice\")".to_string()]));
+
+        let json = snapshot.to_json();
+        let parsed = JsonSnapshot::from_json(&json).unwrap();
+        assert_eq!(parsed, snapshot);
+
+        let mut restored = parsed.to_authorizer().unwrap();
+        assert_eq!(JsonSnapshot::from_authorizer(&restored).unwrap(), snapshot);
+        assert_eq!(restored.authorize(), Ok(0));
+    }
+
+    #[test]
+    fn escaped_strings() {
+        let root = KeyPair::new();
+
+        let mut builder = Biscuit::builder();
+        builder
+            .add_fact(fact("path", &[string(r#"C:\data\"q"\"#)]))
+            .unwrap();
+        let token = builder.build(&root).unwrap();
+        let authorizer = authorize(&token, r#"allow if path("C:\\data\\\"q\"\\");"#);
+
+        let snapshot = JsonSnapshot::from_authorizer(&authorizer).unwrap();
+        assert!(snapshot
+            .facts
+            .iter()
+            .any(|group| group.facts == vec![r#"path("C:\\data\\\"q\"\\")"#.to_string()]));
+
+        let parsed = JsonSnapshot::from_json(&snapshot.to_json()).unwrap();
+        let mut restored = parsed.to_authorizer().unwrap();
+        assert_eq!(JsonSnapshot::from_authorizer(&restored).unwrap(), snapshot);
+        assert_eq!(restored.authorize(), Ok(0));
+    }
+
+    #[test]
+    fn snapshot_diff() {
+        let root = KeyPair::new();
+        let mut builder = Biscuit::builder();
+        builder.add_fact("user(\"alice\")").unwrap();
+        let token = builder.build(&root).unwrap();
+
+        let before = authorize(
+            &token,
+            r#"operation("read"); allow if user("alice"), operation("read");"#,
+        );
+        let mut after = authorize(
+            &token,
+            r#"operation("write"); allow if user("alice"), operation("write"); deny if true;"#,
+        );
+        after.set_limits(crate::AuthorizerLimits {
+            max_facts: 10,
+            ..Default::default()
+        });
+
+        let before = JsonSnapshot::from_authorizer(&before).unwrap();
+        let after = JsonSnapshot::from_authorizer(&after).unwrap();
+        assert!(before.diff(&before).is_empty());
+
+        let diff = before.diff(&after);
+        assert_eq!(
+            diff.to_string(),
+            r#"+ [authorizer] operation("write");
+- [authorizer] operation("read");
+- policy 0: allow if user("alice"), operation("read");
++ policy 0: allow if user("alice"), operation("write");
++ policy 1: deny if true;
+~ max_facts: 1000 -> 10
+"#
+        );
+        assert_eq!(
+            diff.policies[1],
+            PolicyChange {
+                index: 1,
+                before: None,
+                after: Some("deny if true".to_string()),
+            }
+        );
+    }
+}
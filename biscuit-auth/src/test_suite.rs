@@ -0,0 +1,735 @@
+//! Declarative policy tests
+//!
+//! A test suite describes tokens as lists of Datalog blocks, and authorization
+//! scenarios running against them: authorizer code, ambient facts, and the
+//! expected result. Suites are written in JSON:
+//!
+//! ```json
+//! {
+//!   "name": "file access",
+//!   "tokens": {
+//!     "alice": {
+//!       "blocks": [
+//!         { "code": "user(\"alice\"); right(\"file1\", \"read\");" },
+//!         { "code": "check if operation(\"read\");" },
+//!         { "code": "group(\"admin\");", "external_key": "directory" }
+//!       ]
+//!     }
+//!   },
+//!   "tests": [
+//!     {
+//!       "name": "alice can read file1",
+//!       "token": "alice",
+//!       "facts": ["resource(\"file1\")", "operation(\"read\")"],
+//!       "authorizer": "allow if resource($r), operation($op), right($r, $op);",
+//!       "expected": { "result": "allow" }
+//!     },
+//!     {
+//!       "name": "alice cannot write file1",
+//!       "token": "alice",
+//!       "facts": ["resource(\"file1\")", "operation(\"write\")"],
+//!       "authorizer": "allow if resource($r), operation($op), right($r, $op);",
+//!       "expected": {
+//!         "result": "deny",
+//!         "failed_checks": [{ "block": 1, "check": "check if operation(\"read\")" }]
+//!       }
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Keys are referred to by name: tokens are signed by the `root` key unless they
+//! specify a `root_key`, and blocks with an `external_key` are signed as third
+//! party blocks. Keys are generated, unless their private key is given in the
+//! `keys` map of the suite. Every key can be used as a scope parameter in Datalog
+//! code, as in `trusting {directory}`.
+//!
+//! Tokens are serialized and loaded back with their root public key before
+//! authorization, so signatures are verified as they would be in production.
+//!
+//! ```rust
+//! use biscuit_auth::test_suite::TestSuite;
+//!
+//! let suite = TestSuite::from_json(r#"{
+//!   "tokens": { "user": { "blocks": [{ "code": "user(\"alice\");" }] } },
+//!   "tests": [{
+//!     "name": "users are allowed",
+//!     "token": "user",
+//!     "authorizer": "allow if user($u);",
+//!     "expected": { "result": "allow", "policy": 0 }
+//!   }]
+//! }"#).unwrap();
+//!
+//! let report = suite.run();
+//! assert!(report.passed());
+//! println!("{}", report.to_junit());
+//! ```
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    convert::TryFrom,
+    fmt,
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    builder::{BlockBuilder, Check, Term},
+    error, Authorizer, Biscuit, KeyPair, PrivateKey, PublicKey,
+};
+
+/// a set of tokens, and the authorization tests running on them
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestSuite {
+    #[serde(default)]
+    pub name: String,
+    /// hex encoded private keys, by name
+    ///
+    /// keys used by tokens and not listed here are generated
+    #[serde(default)]
+    pub keys: BTreeMap<String, String>,
+    #[serde(default)]
+    pub tokens: BTreeMap<String, TokenSpec>,
+    pub tests: Vec<TestCase>,
+}
+
+/// description of a token
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenSpec {
+    /// name of the key signing the authority block
+    #[serde(default = "default_root_key")]
+    pub root_key: String,
+    /// blocks of the token, starting with the authority block
+    pub blocks: Vec<BlockSpec>,
+    #[serde(default)]
+    pub sealed: bool,
+}
+
+fn default_root_key() -> String {
+    "root".to_string()
+}
+
+/// description of a token block
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlockSpec {
+    /// Datalog code of the block
+    pub code: String,
+    /// values of the parameters used in the code: strings, integers, booleans,
+    /// or arrays of those for sets
+    #[serde(default)]
+    pub params: BTreeMap<String, Value>,
+    /// name of the key signing this block as a third party block
+    #[serde(default)]
+    pub external_key: Option<String>,
+}
+
+/// an authorization scenario
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestCase {
+    pub name: String,
+    /// name of the token to authorize, if any
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Datalog code of the authorizer
+    #[serde(default)]
+    pub authorizer: String,
+    /// values of the parameters used in the authorizer code
+    #[serde(default)]
+    pub params: BTreeMap<String, Value>,
+    /// ambient facts, added to the authorizer
+    #[serde(default)]
+    pub facts: Vec<String>,
+    pub expected: Outcome,
+}
+
+/// result of an authorization
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Allow,
+    Deny,
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decision::Allow => write!(f, "allow"),
+            Decision::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+/// expected or actual outcome of a test
+///
+/// in expectations, `policy` and `failed_checks` are only compared if they are present
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Outcome {
+    pub result: Decision,
+    /// index of the policy that matched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failed_checks: Option<Vec<CheckRef>>,
+}
+
+/// a check, from a token block or from the authorizer
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CheckRef {
+    /// index of the token block, absent for authorizer checks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block: Option<u32>,
+    pub check: String,
+}
+
+impl CheckRef {
+    /// checks are compared on their canonical text
+    fn normalized(&self) -> CheckRef {
+        CheckRef {
+            block: self.block,
+            check: Check::try_from(self.check.as_str())
+                .map(|check| check.to_string())
+                .unwrap_or_else(|_| self.check.trim().to_string()),
+        }
+    }
+}
+
+impl fmt::Display for CheckRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.block {
+            Some(block) => write!(f, "block {}: {}", block, self.check),
+            None => write!(f, "authorizer: {}", self.check),
+        }
+    }
+}
+
+impl From<&error::FailedCheck> for CheckRef {
+    fn from(check: &error::FailedCheck) -> Self {
+        match check {
+            error::FailedCheck::Block(error::FailedBlockCheck { block_id, rule, .. }) => CheckRef {
+                block: Some(*block_id),
+                check: rule.clone(),
+            },
+            error::FailedCheck::Authorizer(error::FailedAuthorizerCheck { rule, .. }) => CheckRef {
+                block: None,
+                check: rule.clone(),
+            },
+        }
+    }
+}
+
+/// results of a test suite
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub name: String,
+    pub tests: Vec<TestResult>,
+    /// duration of the whole suite, in seconds
+    pub time: f64,
+}
+
+/// result of a test
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TestResult {
+    pub name: String,
+    pub passed: bool,
+    pub expected: Outcome,
+    /// absent if the test could not run up to authorization
+    pub actual: Option<Outcome>,
+    /// why the test failed
+    pub failure: Option<String>,
+    /// duration of the test, in seconds
+    pub time: f64,
+}
+
+impl TestSuite {
+    pub fn from_json(data: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(data)
+    }
+
+    /// builds the tokens and runs all the tests
+    ///
+    /// errors in the suite (invalid keys, invalid Datalog code...) are reported
+    /// as failures of the tests they affect
+    pub fn run(&self) -> Report {
+        let start = Instant::now();
+
+        let keys = self.keys();
+        let tokens: BTreeMap<&str, Result<Biscuit, String>> = self
+            .tokens
+            .iter()
+            .map(|(name, spec)| {
+                let token = keys.as_ref().map_err(String::clone).and_then(|keys| {
+                    spec.build(keys)
+                        .map_err(|e| format!("could not build token '{}': {}", name, e))
+                });
+                (name.as_str(), token)
+            })
+            .collect();
+
+        let tests = self
+            .tests
+            .iter()
+            .map(|test| {
+                let start = Instant::now();
+                let actual = keys
+                    .as_ref()
+                    .map_err(String::clone)
+                    .and_then(|keys| test.authorize(keys, &tokens));
+                let failure = match &actual {
+                    Ok(actual) => test.expected.compare(actual),
+                    Err(e) => Some(e.clone()),
+                };
+
+                TestResult {
+                    name: test.name.clone(),
+                    passed: failure.is_none(),
+                    expected: test.expected.clone(),
+                    actual: actual.ok(),
+                    failure,
+                    time: start.elapsed().as_secs_f64(),
+                }
+            })
+            .collect();
+
+        Report {
+            name: self.name.clone(),
+            tests,
+            time: start.elapsed().as_secs_f64(),
+        }
+    }
+
+    /// loads the listed keys, and generates the other ones used by the tokens
+    fn keys(&self) -> Result<BTreeMap<String, KeyPair>, String> {
+        let mut keys = BTreeMap::new();
+        for (name, private_key) in &self.keys {
+            let private_key = PrivateKey::from_bytes_hex(private_key)
+                .map_err(|e| format!("invalid private key '{}': {}", name, e))?;
+            keys.insert(name.clone(), KeyPair::from(&private_key));
+        }
+
+        let used = self.tokens.values().flat_map(|token| {
+            std::iter::once(&token.root_key).chain(
+                token
+                    .blocks
+                    .iter()
+                    .filter_map(|block| block.external_key.as_ref()),
+            )
+        });
+        for name in used {
+            if !keys.contains_key(name) {
+                keys.insert(name.clone(), KeyPair::new());
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+impl TokenSpec {
+    fn build(&self, keys: &BTreeMap<String, KeyPair>) -> Result<Biscuit, String> {
+        let root = &keys[&self.root_key];
+        let scope_params = scope_params(keys);
+
+        let mut blocks = self.blocks.iter();
+        let authority = blocks
+            .next()
+            .ok_or_else(|| "a token needs at least one block".to_string())?;
+        if authority.external_key.is_some() {
+            return Err("the authority block cannot be a third party block".to_string());
+        }
+
+        let mut builder = Biscuit::builder();
+        builder
+            .add_code_with_params(
+                &authority.code,
+                params(&authority.params)?,
+                scope_params.clone(),
+            )
+            .map_err(|e| format!("block 0: {}", e))?;
+        let mut token = builder.build(root).map_err(|e| e.to_string())?;
+
+        for (index, block) in blocks.enumerate() {
+            let mut builder = BlockBuilder::new();
+            builder
+                .add_code_with_params(&block.code, params(&block.params)?, scope_params.clone())
+                .and_then(|_| match &block.external_key {
+                    None => token.append(builder),
+                    Some(name) => {
+                        let external = &keys[name];
+                        token
+                            .third_party_request()
+                            .and_then(|request| request.create_block(&external.private(), builder))
+                            .and_then(|block| token.append_third_party(external.public(), block))
+                    }
+                })
+                .map(|appended| token = appended)
+                .map_err(|e| format!("block {}: {}", index + 1, e))?;
+        }
+
+        if self.sealed {
+            token = token.seal().map_err(|e| e.to_string())?;
+        }
+
+        // serializing and loading the token back verifies its signatures
+        token
+            .to_vec()
+            .and_then(|data| Biscuit::from(&data, root.public()))
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl TestCase {
+    fn authorize(
+        &self,
+        keys: &BTreeMap<String, KeyPair>,
+        tokens: &BTreeMap<&str, Result<Biscuit, String>>,
+    ) -> Result<Outcome, String> {
+        let mut authorizer = Authorizer::new();
+
+        if let Some(name) = &self.token {
+            let token = tokens
+                .get(name.as_str())
+                .ok_or_else(|| format!("unknown token '{}'", name))?
+                .as_ref()
+                .map_err(String::clone)?;
+            authorizer.add_token(token).map_err(|e| e.to_string())?;
+        }
+
+        for fact in &self.facts {
+            authorizer
+                .add_fact(fact.as_str())
+                .map_err(|e| format!("invalid fact '{}': {}", fact, e))?;
+        }
+
+        authorizer
+            .add_code_with_params(&self.authorizer, params(&self.params)?, scope_params(keys))
+            .map_err(|e| format!("invalid authorizer code: {}", e))?;
+
+        let (result, policy, checks) = match authorizer.authorize() {
+            Ok(policy) => (Decision::Allow, Some(policy), Vec::new()),
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized { policy, checks })) => {
+                let policy = match policy {
+                    error::MatchedPolicy::Allow(index) | error::MatchedPolicy::Deny(index) => index,
+                };
+                (Decision::Deny, Some(policy), checks)
+            }
+            Err(error::Token::FailedLogic(error::Logic::NoMatchingPolicy { checks })) => {
+                (Decision::Deny, None, checks)
+            }
+            Err(e) => return Err(format!("authorization error: {}", e)),
+        };
+
+        Ok(Outcome {
+            result,
+            policy,
+            failed_checks: Some(checks.iter().map(CheckRef::from).collect()),
+        })
+    }
+}
+
+impl Outcome {
+    /// describes the differences with the actual outcome, if any
+    fn compare(&self, actual: &Outcome) -> Option<String> {
+        let actual_checks = actual
+            .failed_checks
+            .iter()
+            .flatten()
+            .map(CheckRef::normalized)
+            .collect::<BTreeSet<_>>();
+
+        if self.result != actual.result {
+            let mut failure = format!("expected {}, got {}", self.result, actual.result);
+            if !actual_checks.is_empty() {
+                failure.push_str(&format!(
+                    " with failed checks: {}",
+                    list(actual_checks.iter())
+                ));
+            }
+            return Some(failure);
+        }
+
+        if self.policy.is_some() && self.policy != actual.policy {
+            return Some(format!(
+                "expected policy {} to match, got {}",
+                self.policy.unwrap_or_default(),
+                actual
+                    .policy
+                    .map(|policy| format!("policy {}", policy))
+                    .unwrap_or_else(|| "no matching policy".to_string())
+            ));
+        }
+
+        if let Some(expected_checks) = &self.failed_checks {
+            let expected_checks = expected_checks
+                .iter()
+                .map(CheckRef::normalized)
+                .collect::<BTreeSet<_>>();
+            if expected_checks != actual_checks {
+                return Some(format!(
+                    "expected failed checks: {}, got: {}",
+                    list(expected_checks.iter()),
+                    list(actual_checks.iter())
+                ));
+            }
+        }
+
+        None
+    }
+}
+
+fn list<'a>(checks: impl Iterator<Item = &'a CheckRef>) -> String {
+    let checks = checks.map(|check| check.to_string()).collect::<Vec<_>>();
+    if checks.is_empty() {
+        "none".to_string()
+    } else {
+        format!("[{}]", checks.join(", "))
+    }
+}
+
+fn scope_params(keys: &BTreeMap<String, KeyPair>) -> HashMap<String, PublicKey> {
+    keys.iter()
+        .map(|(name, key)| (name.clone(), key.public()))
+        .collect()
+}
+
+fn params(params: &BTreeMap<String, Value>) -> Result<HashMap<String, Term>, String> {
+    params
+        .iter()
+        .map(|(name, value)| {
+            term(value)
+                .map(|term| (name.clone(), term))
+                .ok_or_else(|| format!("unsupported value for parameter '{}': {}", name, value))
+        })
+        .collect()
+}
+
+fn term(value: &Value) -> Option<Term> {
+    match value {
+        Value::Bool(b) => Some(Term::Bool(*b)),
+        Value::Number(n) => n.as_i64().map(Term::Integer),
+        Value::String(s) => Some(Term::Str(s.clone())),
+        Value::Array(values) => values
+            .iter()
+            .map(term)
+            .collect::<Option<BTreeSet<_>>>()
+            .map(Term::Set),
+        _ => None,
+    }
+}
+
+impl Report {
+    /// true if all the tests passed
+    pub fn passed(&self) -> bool {
+        self.tests.iter().all(|test| test.passed)
+    }
+
+    pub fn failures(&self) -> usize {
+        self.tests.iter().filter(|test| !test.passed).count()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("reports can always be serialized")
+    }
+
+    /// writes the report in the JUnit XML format, understood by most CI systems
+    pub fn to_junit(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" time=\"{:.6}\">\n",
+            escape_xml(&self.name),
+            self.tests.len(),
+            self.failures(),
+            self.time
+        ));
+
+        for test in &self.tests {
+            xml.push_str(&format!(
+                "  <testcase name=\"{}\" classname=\"{}\" time=\"{:.6}\"",
+                escape_xml(&test.name),
+                escape_xml(&self.name),
+                test.time
+            ));
+            match &test.failure {
+                None => xml.push_str("/>\n"),
+                Some(failure) => xml.push_str(&format!(
+                    ">\n    <failure message=\"{}\"/>\n  </testcase>\n",
+                    escape_xml(failure)
+                )),
+            }
+        }
+
+        xml.push_str("</testsuite>\n");
+        xml
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUITE: &str = r#"{
+      "name": "file access",
+      "tokens": {
+        "alice": {
+          "blocks": [
+            { "code": "user({user}); right(\"file1\", \"read\");", "params": { "user": "alice" } },
+            { "code": "check if operation(\"read\");" },
+            { "code": "group(\"admin\");", "external_key": "directory" }
+          ]
+        },
+        "sealed": {
+          "root_key": "other_root",
+          "blocks": [{ "code": "user(\"bob\");" }],
+          "sealed": true
+        }
+      },
+      "tests": [
+        {
+          "name": "alice can read file1",
+          "token": "alice",
+          "facts": ["resource(\"file1\")", "operation(\"read\")"],
+          "authorizer": "allow if resource($r), operation($op), right($r, $op);",
+          "expected": { "result": "allow", "policy": 0, "failed_checks": [] }
+        },
+        {
+          "name": "alice cannot write file1",
+          "token": "alice",
+          "facts": ["resource(\"file1\")", "operation(\"write\")"],
+          "authorizer": "allow if resource($r), operation($op), right($r, $op); deny if true;",
+          "expected": {
+            "result": "deny",
+            "policy": 1,
+            "failed_checks": [{ "block": 1, "check": "check if  operation( \"read\" )" }]
+          }
+        },
+        {
+          "name": "third party facts are trusted explicitly",
+          "token": "alice",
+          "facts": ["operation(\"read\")"],
+          "authorizer": "check if group(\"admin\") trusting {directory}; allow if {admins}.contains(\"alice\");",
+          "params": { "admins": ["alice", "carol"] },
+          "expected": { "result": "allow" }
+        },
+        {
+          "name": "sealed tokens are verified with their root key",
+          "token": "sealed",
+          "authorizer": "allow if user(\"bob\");",
+          "expected": { "result": "allow" }
+        },
+        {
+          "name": "wrong expectation",
+          "token": "alice",
+          "authorizer": "check if operation(\"write\"); allow if true;",
+          "expected": { "result": "allow" }
+        },
+        {
+          "name": "unknown token",
+          "token": "carol",
+          "expected": { "result": "deny" }
+        }
+      ]
+    }"#;
+
+    #[test]
+    fn run_suite() {
+        let suite = TestSuite::from_json(SUITE).unwrap();
+        let report = suite.run();
+
+        let failures = report
+            .tests
+            .iter()
+            .map(|test| (test.name.as_str(), test.failure.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            failures,
+            vec![
+                ("alice can read file1", None),
+                ("alice cannot write file1", None),
+                ("third party facts are trusted explicitly", None),
+                ("sealed tokens are verified with their root key", None),
+                (
+                    "wrong expectation",
+                    Some("expected allow, got deny with failed checks: [authorizer: check if operation(\"write\"), block 1: check if operation(\"read\")]")
+                ),
+                ("unknown token", Some("unknown token 'carol'")),
+            ]
+        );
+        assert!(!report.passed());
+        assert_eq!(report.failures(), 2);
+        assert_eq!(report.tests[1].actual.as_ref().unwrap().policy, Some(1));
+        assert!(report.tests[5].actual.is_none());
+    }
+
+    #[test]
+    fn invalid_suite() {
+        assert!(TestSuite::from_json(r#"{ "tests": [], "unknown": 1 }"#).is_err());
+
+        let suite = TestSuite::from_json(
+            r#"{
+              "keys": { "root": "not hex" },
+              "tokens": { "t": { "blocks": [{ "code": "a(1);" }] } },
+              "tests": [{ "name": "test", "token": "t", "expected": { "result": "allow" } }]
+            }"#,
+        )
+        .unwrap();
+        let report = suite.run();
+        assert!(report.tests[0]
+            .failure
+            .as_ref()
+            .unwrap()
+            .starts_with("invalid private key 'root'"));
+
+        let suite = TestSuite::from_json(
+            r#"{
+              "tokens": { "t": { "blocks": [{ "code": "a(1);" }, { "code": "invalid(" }] } },
+              "tests": [{ "name": "test", "token": "t", "expected": { "result": "allow" } }]
+            }"#,
+        )
+        .unwrap();
+        let report = suite.run();
+        assert!(report.tests[0]
+            .failure
+            .as_ref()
+            .unwrap()
+            .starts_with("could not build token 't': block 1:"));
+    }
+
+    #[test]
+    fn reports() {
+        let suite = TestSuite::from_json(SUITE).unwrap();
+        let mut report = suite.run();
+        report.time = 1.5;
+        for test in report.tests.iter_mut() {
+            test.time = 0.25;
+        }
+
+        let junit = report.to_junit();
+        assert!(junit.starts_with(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuite name=\"file access\" tests=\"6\" failures=\"2\" errors=\"0\" time=\"1.500000\">\n"
+        ));
+        assert!(junit.contains(
+            "  <testcase name=\"alice can read file1\" classname=\"file access\" time=\"0.250000\"/>\n"
+        ));
+        assert!(junit.contains(
+            "  <testcase name=\"unknown token\" classname=\"file access\" time=\"0.250000\">\n    <failure message=\"unknown token &apos;carol&apos;\"/>\n  </testcase>\n"
+        ));
+        assert!(junit.ends_with("</testsuite>\n"));
+
+        let json: Report = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json, report);
+    }
+}
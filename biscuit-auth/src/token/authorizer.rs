@@ -1,14 +1,24 @@
 //! Authorizer structure and associated functions
 use super::builder::{
-    constrained_rule, date, fact, pred, rule, string, var, Binary, BlockBuilder, Check, Expression,
-    Fact, Op, Policy, PolicyKind, Rule, Scope, Term,
+    boolean, bytes, constrained_rule, date, fact, int, pred, rule, string, var, Binary,
+    BlockBuilder, Check, Expression, Fact, Op, Policy, PolicyKind, PredicateSchema, Rule, Scope,
+    Term,
 };
 use super::builder_ext::{AuthorizerExt, BuilderExt};
+use super::clock::{Clock, SystemClock};
+use super::observer::{AuthorizationRecord, AuthorizerObserver};
+use super::provider::{FactProvider, FactProviders};
+use super::typing;
 use super::{Biscuit, Block};
 use crate::builder::{CheckKind, Convert};
 use crate::crypto::PublicKey;
+#[cfg(feature = "private-facts")]
+use crate::crypto::{encryption, EncryptionPrivateKey};
 use crate::datalog::{self, Origin, RunLimits, SymbolTable, TrustedOrigins};
 use crate::error;
+#[cfg(feature = "private-facts")]
+use crate::format::convert::proto_private_facts_to_facts;
+use crate::format::schema;
 use crate::time::Instant;
 use crate::token;
 use biscuit_parser::parser::parse_source;
@@ -20,11 +30,37 @@ use std::{
     convert::{TryFrom, TryInto},
     default::Default,
     fmt::Write,
+    sync::Arc,
     time::SystemTime,
 };
 
+mod replay;
 mod snapshot;
 
+pub use replay::{BatchReplay, ReplayOutcome, WhatIf};
+
+/// prefix of the predicates added by [`Authorizer::set_token_metadata`]
+const TOKEN_METADATA_PREFIX: &str = "biscuit:";
+
+/// token blocks and fact providers cannot define token metadata facts
+pub(crate) fn check_reserved_predicate(
+    token_metadata: bool,
+    symbols: &SymbolTable,
+    block_id: usize,
+    name: u64,
+) -> Result<(), error::Token> {
+    if !token_metadata {
+        return Ok(());
+    }
+
+    let name = symbols.print_symbol_default(name);
+    if name.starts_with(TOKEN_METADATA_PREFIX) {
+        Err(error::Logic::ReservedPredicate(block_id as u32, name).into())
+    } else {
+        Ok(())
+    }
+}
+
 /// used to check authorization policies on a token
 ///
 /// can be created from [Biscuit::authorizer] or [Authorizer::new]
@@ -39,6 +75,16 @@ pub struct Authorizer {
     public_key_to_block_id: HashMap<usize, Vec<usize>>,
     limits: AuthorizerLimits,
     execution_time: Duration,
+    clock: Arc<dyn Clock>,
+    time: Option<SystemTime>,
+    token_metadata: bool,
+    key_groups: HashMap<String, Vec<PublicKey>>,
+    #[cfg(feature = "private-facts")]
+    decryption_keys: Vec<EncryptionPrivateKey>,
+    require_private_facts: bool,
+    revocation_ids: Vec<Vec<u8>>,
+    observers: Vec<Arc<dyn AuthorizerObserver>>,
+    fact_providers: FactProviders,
 }
 
 impl Authorizer {
@@ -73,6 +119,16 @@ impl Authorizer {
             public_key_to_block_id: HashMap::new(),
             limits: AuthorizerLimits::default(),
             execution_time: Duration::default(),
+            clock: Arc::new(SystemClock),
+            time: None,
+            token_metadata: false,
+            key_groups: HashMap::new(),
+            #[cfg(feature = "private-facts")]
+            decryption_keys: vec![],
+            require_private_facts: false,
+            revocation_ids: vec![],
+            observers: vec![],
+            fact_providers: FactProviders::default(),
         }
     }
 
@@ -87,16 +143,56 @@ impl Authorizer {
             return Err(error::Logic::AuthorizerNotEmpty.into());
         }
 
-        for (key_id, block_ids) in &token.public_key_to_block_id {
+        // the blocks are translated and validated in a separate world, and only
+        // added to the authorizer once they are all valid. Symbols are only ever
+        // appended, so an invalid token leaves the authorizer unchanged
+        let symbols_start = self.symbols.current_offset();
+        let public_keys_start = self.symbols.public_keys.current_offset();
+        let mut world = datalog::World::new();
+
+        let blocks = match self.load_token(token, &mut world) {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                self.symbols.split_at(symbols_start);
+                self.symbols.public_keys.split_at(public_keys_start);
+                self.public_key_to_block_id.clear();
+                return Err(e);
+            }
+        };
+
+        self.world.facts.merge(world.facts);
+        self.world.rules.merge(world.rules);
+        self.blocks = Some(blocks);
+        self.token_origins = TrustedOrigins::from_scopes(
+            &[token::Scope::Previous],
+            &TrustedOrigins::default(),
+            token.block_count(),
+            &self.public_key_to_block_id,
+        );
+        self.revocation_ids = token.revocation_identifiers();
+
+        if self.token_metadata {
+            self.add_token_metadata(token);
+        }
+
+        Ok(())
+    }
+
+    fn load_token(
+        &mut self,
+        token: &Biscuit,
+        world: &mut datalog::World,
+    ) -> Result<Vec<Block>, error::Token> {
+        for (key_id, block_ids) in token.public_key_to_block_id() {
             let key = token
                 .symbols
                 .public_keys
-                .get_key(*key_id as u64)
+                .get_key(key_id as u64)
                 .ok_or(error::Format::UnknownExternalKey)?;
             let new_key_id = self.symbols.public_keys.insert(key);
 
             self.public_key_to_block_id
-                .insert(new_key_id as usize, block_ids.clone());
+                .insert(new_key_id as usize, block_ids);
         }
 
         let mut blocks = Vec::new();
@@ -104,28 +200,92 @@ impl Authorizer {
         for i in 0..token.block_count() {
             let mut block = token.block(i)?;
 
-            self.load_and_translate_block(&mut block, i, &token.symbols)?;
+            self.load_and_translate_block(&mut block, i, &token.symbols, world)?;
 
             blocks.push(block);
         }
 
-        self.blocks = Some(blocks);
-        self.token_origins = TrustedOrigins::from_scopes(
-            &[token::Scope::Previous],
-            &TrustedOrigins::default(),
-            token.block_count(),
-            &self.public_key_to_block_id,
-        );
+        Ok(blocks)
+    }
 
-        Ok(())
+    /// adds facts describing the token in [`Authorizer::add_token`]
+    ///
+    /// This must be called before the token is added. The following facts are
+    /// generated with the authorizer's origin:
+    /// * `biscuit:block_count($count)`
+    /// * `biscuit:sealed($sealed)`, with a boolean
+    /// * `biscuit:root_key_id($id)`, if the token has a root key id
+    /// * `biscuit:revocation_id($block_id, $id)` for each block, with the id as bytes
+    /// * `biscuit:external_key($block_id, $key)` for each third party block, with the
+    ///   public key as bytes
+    ///
+    /// To prevent blocks from forging those facts, tokens containing facts
+    /// or rules with the `biscuit:` prefix are then rejected with
+    /// [`error::Logic::ReservedPredicate`].
+    ///
+    /// ```rust
+    /// # use biscuit_auth::{Authorizer, Biscuit, KeyPair};
+    /// let root = KeyPair::new();
+    /// let mut builder = Biscuit::builder();
+    /// builder.set_root_key_id(3);
+    /// let token = builder.build(&root).unwrap().seal().unwrap();
+    ///
+    /// let mut authorizer = Authorizer::new();
+    /// authorizer.set_token_metadata(true);
+    /// authorizer.add_token(&token).unwrap();
+    /// authorizer
+    ///     .add_policy("allow if biscuit:root_key_id(3), biscuit:sealed(true)")
+    ///     .unwrap();
+    /// assert!(authorizer.authorize().is_ok());
+    /// ```
+    pub fn set_token_metadata(&mut self, enabled: bool) {
+        self.token_metadata = enabled;
+        self.fact_providers.token_metadata = enabled;
+    }
+
+    fn add_token_metadata(&mut self, token: &Biscuit) {
+        let name = |suffix: &str| format!("{}{}", TOKEN_METADATA_PREFIX, suffix);
+
+        let mut facts = vec![
+            fact(&name("block_count"), &[int(token.block_count() as i64)]),
+            fact(&name("sealed"), &[boolean(token.is_sealed())]),
+        ];
+        if let Some(root_key_id) = token.root_key_id() {
+            facts.push(fact(&name("root_key_id"), &[int(root_key_id as i64)]));
+        }
+        for (block_id, id) in token.revocation_identifiers().iter().enumerate() {
+            facts.push(fact(
+                &name("revocation_id"),
+                &[int(block_id as i64), bytes(id)],
+            ));
+        }
+        for (block_id, key) in token.external_public_keys().iter().enumerate() {
+            if let Some(key) = key {
+                facts.push(fact(
+                    &name("external_key"),
+                    &[int(block_id as i64), bytes(&key.to_bytes())],
+                ));
+            }
+        }
+
+        let mut authorizer_origin = Origin::default();
+        authorizer_origin.insert(usize::MAX);
+        for fact in facts {
+            self.world
+                .facts
+                .insert(&authorizer_origin, fact.convert(&mut self.symbols));
+        }
     }
 
     /// we need to modify the block loaded from the token, because the authorizer's and th token's symbol table can differ
+    ///
+    /// the block's facts and rules are added to `world`
     fn load_and_translate_block(
         &mut self,
         block: &mut Block,
         i: usize,
         token_symbols: &SymbolTable,
+        world: &mut datalog::World,
     ) -> Result<(), error::Token> {
         // if it is a 3rd party block, it should not affect the main symbol table
         let block_symbols = if i == 0 || block.external_key.is_none() {
@@ -148,7 +308,22 @@ impl Authorizer {
 
         for fact in block.facts.iter_mut() {
             *fact = Fact::convert_from(fact, &block_symbols)?.convert(&mut self.symbols);
-            self.world.facts.insert(&block_origin, fact.clone());
+            check_reserved_predicate(self.token_metadata, &self.symbols, i, fact.predicate.name)?;
+            world.facts.insert(&block_origin, fact.clone());
+        }
+
+        // private facts are only added to the world, they stay encrypted in the block
+        if let Some(encrypted_facts) = &block.encrypted_facts {
+            for fact in self.private_facts(encrypted_facts)? {
+                let fact = fact.convert(&mut self.symbols);
+                check_reserved_predicate(
+                    self.token_metadata,
+                    &self.symbols,
+                    i,
+                    fact.predicate.name,
+                )?;
+                world.facts.insert(&block_origin, fact);
+            }
         }
 
         for rule in block.rules.iter_mut() {
@@ -158,6 +333,7 @@ impl Authorizer {
                 );
             }
             *rule = rule.translate(&block_symbols, &mut self.symbols)?;
+            check_reserved_predicate(self.token_metadata, &self.symbols, i, rule.head.name)?;
 
             let rule_trusted_origins = TrustedOrigins::from_scopes(
                 &rule.scopes,
@@ -166,9 +342,7 @@ impl Authorizer {
                 &self.public_key_to_block_id,
             );
 
-            self.world
-                .rules
-                .insert(i, &rule_trusted_origins, rule.clone());
+            world.rules.insert(i, &rule_trusted_origins, rule.clone());
         }
 
         for check in block.checks.iter_mut() {
@@ -247,6 +421,31 @@ impl Authorizer {
         self.authorizer_block_builder.add_check(check)
     }
 
+    /// declares the arity and term types of a predicate
+    ///
+    /// facts, rules, checks and policies of the authorizer must match it. Facts
+    /// and rules coming from the token are not checked.
+    ///
+    /// ```rust
+    /// # use biscuit_auth::Authorizer;
+    /// let mut authorizer = Authorizer::new();
+    /// authorizer.add_schema("schema right(string, string)").unwrap();
+    /// authorizer.add_fact(r#"right("file1", "read")"#).unwrap();
+    /// assert!(authorizer.add_fact(r#"right("file1")"#).is_err());
+    /// assert!(authorizer.add_policy(r#"allow if right($file, 1)"#).is_err());
+    /// ```
+    pub fn add_schema<S: TryInto<PredicateSchema>>(&mut self, schema: S) -> Result<(), error::Token>
+    where
+        error::Token: From<<S as TryInto<PredicateSchema>>::Error>,
+    {
+        let schema = schema.try_into()?;
+        for policy in &self.policies {
+            typing::check_policy(std::slice::from_ref(&schema), policy)?;
+        }
+        self.authorizer_block_builder
+            .add_schema::<PredicateSchema>(schema)
+    }
+
     /// adds some datalog code to the authorizer
     ///
     /// ```rust
@@ -282,6 +481,10 @@ impl Authorizer {
             e2
         })?;
 
+        for (_, schema) in source_result.schemas.into_iter() {
+            self.add_schema(PredicateSchema::from(schema))?;
+        }
+
         for (_, fact) in source_result.facts.into_iter() {
             let mut fact: Fact = fact.into();
             for (name, value) in &params {
@@ -297,6 +500,7 @@ impl Authorizer {
                 res?;
             }
             fact.validate()?;
+            typing::check_fact(&self.authorizer_block_builder.schemas, &fact)?;
             self.authorizer_block_builder.facts.push(fact);
         }
 
@@ -327,6 +531,7 @@ impl Authorizer {
                 res?;
             }
             rule.validate_parameters()?;
+            typing::check_rule(&self.authorizer_block_builder.schemas, &rule)?;
             self.authorizer_block_builder.rules.push(rule);
         }
 
@@ -357,6 +562,7 @@ impl Authorizer {
                 res?;
             }
             check.validate_parameters()?;
+            typing::check_check(&self.authorizer_block_builder.schemas, &check)?;
             self.authorizer_block_builder.checks.push(check);
         }
         for (_, policy) in source_result.policies.into_iter() {
@@ -386,6 +592,7 @@ impl Authorizer {
                 res?;
             }
             policy.validate_parameters()?;
+            typing::check_policy(&self.authorizer_block_builder.schemas, &policy)?;
             self.policies.push(policy);
         }
 
@@ -473,7 +680,7 @@ impl Authorizer {
         limits: AuthorizerLimits,
     ) -> Result<Vec<T>, error::Token> {
         let rule_trusted_origins = TrustedOrigins::from_scopes(
-            &rule.scopes,
+            &self.resolve_key_groups(&rule.scopes),
             &TrustedOrigins::default(), // for queries, we don't want to default on the authorizer trust
             // queries are there to explore the final state of the world,
             // whereas authorizer contents are there to authorize or not
@@ -482,7 +689,15 @@ impl Authorizer {
             &self.public_key_to_block_id,
         );
 
-        self.world.run_with_limits(&self.symbols, limits)?;
+        self.fact_providers
+            .run(&mut self.world, &mut self.symbols, limits.clone())?;
+        self.fact_providers.fetch_for_query(
+            &mut self.world,
+            &mut self.symbols,
+            &rule,
+            &rule_trusted_origins,
+            &limits,
+        )?;
         let res = self
             .world
             .query_rule(rule, usize::MAX, &rule_trusted_origins, &self.symbols)?;
@@ -565,13 +780,14 @@ impl Authorizer {
         rule: datalog::Rule,
         limits: AuthorizerLimits,
     ) -> Result<Vec<T>, error::Token> {
-        self.world.run_with_limits(&self.symbols, limits)?;
+        self.fact_providers
+            .run(&mut self.world, &mut self.symbols, limits.clone())?;
 
         let rule_trusted_origins = if rule.scopes.is_empty() {
             self.token_origins.clone()
         } else {
             TrustedOrigins::from_scopes(
-                &rule.scopes,
+                &self.resolve_key_groups(&rule.scopes),
                 &TrustedOrigins::default(), // for queries, we don't want to default on the authorizer trust
                 // queries are there to explore the final state of the world,
                 // whereas authorizer contents are there to authorize or not
@@ -581,6 +797,13 @@ impl Authorizer {
             )
         };
 
+        self.fact_providers.fetch_for_query(
+            &mut self.world,
+            &mut self.symbols,
+            &rule,
+            &rule_trusted_origins,
+            &limits,
+        )?;
         let res = self
             .world
             .query_rule(rule, 0, &rule_trusted_origins, &self.symbols)?;
@@ -596,10 +819,185 @@ impl Authorizer {
             .collect::<Result<Vec<T>, _>>()
     }
 
-    /// adds a fact with the current time
+    /// adds a fact with the current time, as given by the authorizer's clock
+    ///
+    /// the time is recorded in snapshots, so that a restored authorizer
+    /// reaches the same decision
     pub fn set_time(&mut self) {
-        let fact = fact("time", &[date(&SystemTime::now())]);
+        let now = self.clock.now();
+        // dates are stored with a precision of one second
+        let seconds = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+
+        let fact = fact("time", &[date(&now)]);
         self.authorizer_block_builder.add_fact(fact).unwrap();
+        self.time = Some(now);
+    }
+
+    /// sets the clock used by [`Authorizer::set_time`]
+    ///
+    /// the system clock is used by default
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+    }
+
+    /// returns the clock used by [`Authorizer::set_time`]
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    /// returns the time added by [`Authorizer::set_time`], if it was called
+    pub fn time(&self) -> Option<SystemTime> {
+        self.time
+    }
+
+    /// adds a key used to decrypt the private facts of the token's blocks
+    ///
+    /// it must be called before [`Authorizer::add_token`]. Since
+    /// [`Biscuit::authorizer`] loads the token right away, the recipient of
+    /// private facts creates the authorizer with [`Authorizer::new`] instead
+    #[cfg(feature = "private-facts")]
+    #[cfg_attr(feature = "docsrs", doc(cfg(feature = "private-facts")))]
+    pub fn add_decryption_key(&mut self, key: EncryptionPrivateKey) {
+        self.decryption_keys.push(key);
+    }
+
+    /// requires the private facts of the token's blocks to be decrypted
+    ///
+    /// by default, private facts encrypted to a key that was not given to
+    /// [`Authorizer::add_decryption_key`] are skipped, since they are meant for
+    /// another authorizer. When enabled, [`Authorizer::add_token`] fails
+    /// with [`error::Format::MissingDecryptionKey`] instead
+    pub fn set_require_private_facts(&mut self, required: bool) {
+        self.require_private_facts = required;
+    }
+
+    /// private facts without a matching key are not an error unless they are required
+    fn missing_decryption_key(
+        &self,
+        encrypted_facts: &schema::EncryptedFacts,
+    ) -> Result<Vec<Fact>, error::Format> {
+        if self.require_private_facts {
+            Err(error::Format::MissingDecryptionKey(format!(
+                "x25519/{}",
+                hex::encode(&encrypted_facts.recipient)
+            )))
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    #[cfg(not(feature = "private-facts"))]
+    fn private_facts(
+        &self,
+        encrypted_facts: &schema::EncryptedFacts,
+    ) -> Result<Vec<Fact>, error::Format> {
+        self.missing_decryption_key(encrypted_facts)
+    }
+
+    #[cfg(feature = "private-facts")]
+    fn private_facts(
+        &self,
+        encrypted_facts: &schema::EncryptedFacts,
+    ) -> Result<Vec<Fact>, error::Format> {
+        let key = match self
+            .decryption_keys
+            .iter()
+            .find(|key| key.public().to_bytes()[..] == encrypted_facts.recipient[..])
+        {
+            Some(key) => key,
+            None => return self.missing_decryption_key(encrypted_facts),
+        };
+
+        let plaintext = encryption::decrypt(key, encrypted_facts)?;
+        let private_facts = schema::PrivateFacts::decode(&plaintext[..]).map_err(|e| {
+            error::Format::DeserializationError(format!("deserialization error: {:?}", e))
+        })?;
+
+        proto_private_facts_to_facts(&private_facts)
+    }
+
+    /// registers the public keys of a key group
+    ///
+    /// the authorizer's rules, checks and policies can then trust the blocks
+    /// signed by any of those keys with `trusting group("name")`. Calling it
+    /// again with the same name replaces the keys of the group.
+    ///
+    /// key groups are not stored in snapshots, they must be registered
+    /// again on a restored authorizer
+    pub fn set_key_group<I: IntoIterator<Item = PublicKey>>(&mut self, name: &str, keys: I) {
+        self.key_groups
+            .insert(name.to_string(), keys.into_iter().collect());
+    }
+
+    /// replaces group scopes with the keys registered in the group
+    ///
+    /// keys that do not appear in the token cannot match any block, so
+    /// they are left out. A group without any such key, or an unknown group,
+    /// is kept as is: it trusts no block, while an empty list of scopes
+    /// would trust the authority block
+    fn resolve_key_groups(&self, scopes: &[token::Scope]) -> Vec<token::Scope> {
+        let mut resolved = Vec::with_capacity(scopes.len());
+
+        for scope in scopes {
+            match scope {
+                token::Scope::Group(name) => {
+                    let keys = self
+                        .key_groups
+                        .get(&self.symbols.print_symbol_default(*name))
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|key| self.symbols.public_keys.get(key))
+                        .map(token::Scope::PublicKey)
+                        .collect::<Vec<_>>();
+
+                    if keys.is_empty() {
+                        resolved.push(scope.clone());
+                    } else {
+                        resolved.extend(keys);
+                    }
+                }
+                scope => resolved.push(scope.clone()),
+            }
+        }
+
+        resolved
+    }
+
+    /// adds a function called with the result of each authorization
+    ///
+    /// observers are kept when the authorizer is cloned
+    pub fn add_observer<O: AuthorizerObserver + 'static>(&mut self, observer: O) {
+        self.observers.push(Arc::new(observer));
+    }
+
+    fn notify_observers(&self, result: &Result<usize, error::Token>) {
+        if self.observers.is_empty() {
+            return;
+        }
+
+        let record = AuthorizationRecord::new(
+            self.revocation_ids.clone(),
+            self.world.iterations,
+            self.world.facts.len(),
+            self.execution_time,
+            result.clone(),
+        );
+        for observer in &self.observers {
+            observer.on_authorization(&record);
+        }
+    }
+
+    /// registers a provider for the facts of a predicate
+    ///
+    /// the provider is called during authorization and queries, with the terms
+    /// bound by the rules, checks and policies using the predicate. Results are
+    /// cached in the authorizer, and the facts count toward the run limits
+    pub fn add_fact_provider<P: FactProvider + 'static>(&mut self, name: &str, provider: P) {
+        self.fact_providers.insert(name, Arc::new(provider));
     }
 
     /// add a policy to the authorizer
@@ -609,6 +1007,7 @@ impl Authorizer {
     {
         let policy = policy.try_into()?;
         policy.validate_parameters()?;
+        typing::check_policy(&self.authorizer_block_builder.schemas, &policy)?;
         self.policies.push(policy);
         Ok(())
     }
@@ -647,7 +1046,9 @@ impl Authorizer {
         let mut limits = self.limits.clone();
         limits.max_iterations -= self.world.iterations;
         if self.execution_time >= limits.max_time {
-            return Err(error::Token::RunLimit(error::RunLimit::Timeout));
+            let result = Err(error::Token::RunLimit(error::RunLimit::Timeout));
+            self.notify_observers(&result);
+            return result;
         }
         limits.max_time -= self.execution_time;
 
@@ -664,10 +1065,14 @@ impl Authorizer {
         &mut self,
         limits: AuthorizerLimits,
     ) -> Result<usize, error::Token> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("authorize").entered();
+
         let start = Instant::now();
         let result = self.authorize_inner(limits);
         self.execution_time += start.elapsed();
 
+        self.notify_observers(&result);
         result
     }
 
@@ -691,7 +1096,7 @@ impl Authorizer {
             .collect();
 
         let authorizer_trusted_origins = TrustedOrigins::from_scopes(
-            &authorizer_scopes,
+            &self.resolve_key_groups(&authorizer_scopes),
             &TrustedOrigins::default(),
             usize::MAX,
             &self.public_key_to_block_id,
@@ -707,7 +1112,7 @@ impl Authorizer {
             let rule = rule.convert(&mut self.symbols);
 
             let rule_trusted_origins = TrustedOrigins::from_scopes(
-                &rule.scopes,
+                &self.resolve_key_groups(&rule.scopes),
                 &authorizer_trusted_origins,
                 usize::MAX,
                 &self.public_key_to_block_id,
@@ -719,7 +1124,8 @@ impl Authorizer {
         }
 
         limits.max_time = time_limit - Instant::now();
-        self.world.run_with_limits(&self.symbols, limits.clone())?;
+        self.fact_providers
+            .run(&mut self.world, &mut self.symbols, limits.clone())?;
 
         let authorizer_scopes: Vec<token::Scope> = self
             .authorizer_block_builder
@@ -730,7 +1136,7 @@ impl Authorizer {
             .collect();
 
         let authorizer_trusted_origins = TrustedOrigins::from_scopes(
-            &authorizer_scopes,
+            &self.resolve_key_groups(&authorizer_scopes),
             &TrustedOrigins::default(),
             usize::MAX,
             &self.public_key_to_block_id,
@@ -743,11 +1149,18 @@ impl Authorizer {
             for query in check.queries.iter() {
                 let query = query.convert(&mut self.symbols);
                 let rule_trusted_origins = TrustedOrigins::from_scopes(
-                    &query.scopes,
+                    &self.resolve_key_groups(&query.scopes),
                     &authorizer_trusted_origins,
                     usize::MAX,
                     &self.public_key_to_block_id,
                 );
+                self.fact_providers.fetch_for_query(
+                    &mut self.world,
+                    &mut self.symbols,
+                    &query,
+                    &rule_trusted_origins,
+                    &limits,
+                )?;
                 let res = match check.kind {
                     CheckKind::One => self.world.query_match(
                         query,
@@ -800,6 +1213,13 @@ impl Authorizer {
                         0,
                         &self.public_key_to_block_id,
                     );
+                    self.fact_providers.fetch_for_query(
+                        &mut self.world,
+                        &mut self.symbols,
+                        query,
+                        &rule_trusted_origins,
+                        &limits,
+                    )?;
                     let res = match check.kind {
                         CheckKind::One => self.world.query_match(
                             query.clone(),
@@ -835,16 +1255,27 @@ impl Authorizer {
             }
         }
 
+        #[cfg(feature = "tracing")]
+        let policy_span = tracing::debug_span!("policy", policies = self.policies.len()).entered();
+
         'policies_test: for (i, policy) in self.policies.iter().enumerate() {
             for query in policy.queries.iter() {
                 let query = query.convert(&mut self.symbols);
                 let rule_trusted_origins = TrustedOrigins::from_scopes(
-                    &query.scopes,
+                    &self.resolve_key_groups(&query.scopes),
                     &authorizer_trusted_origins,
                     usize::MAX,
                     &self.public_key_to_block_id,
                 );
 
+                self.fact_providers.fetch_for_query(
+                    &mut self.world,
+                    &mut self.symbols,
+                    &query,
+                    &rule_trusted_origins,
+                    &limits,
+                )?;
+
                 let res = self.world.query_match(
                     query,
                     usize::MAX,
@@ -867,6 +1298,9 @@ impl Authorizer {
             }
         }
 
+        #[cfg(feature = "tracing")]
+        drop(policy_span);
+
         if let Some(blocks) = self.blocks.as_ref() {
             for (i, block) in (&blocks[1..]).iter().enumerate() {
                 let block_trusted_origins = TrustedOrigins::from_scopes(
@@ -880,7 +1314,8 @@ impl Authorizer {
                 limits.max_iterations -= self.world.iterations - current_iterations;
                 current_iterations = self.world.iterations;
 
-                self.world.run_with_limits(&self.symbols, limits.clone())?;
+                self.fact_providers
+                    .run(&mut self.world, &mut self.symbols, limits.clone())?;
 
                 for (j, check) in block.checks.iter().enumerate() {
                     let mut successful = false;
@@ -892,6 +1327,13 @@ impl Authorizer {
                             i + 1,
                             &self.public_key_to_block_id,
                         );
+                        self.fact_providers.fetch_for_query(
+                            &mut self.world,
+                            &mut self.symbols,
+                            query,
+                            &rule_trusted_origins,
+                            &limits,
+                        )?;
 
                         let res = match check.kind {
                             CheckKind::One => self.world.query_match(
@@ -1395,6 +1837,31 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn failed_check_source_span() {
+        let code = "resource(\"file1\");\ncheck if resource(\"file1\");\ncheck if operation(\"read\");\nallow if true;";
+        let mut authorizer = Authorizer::new();
+        authorizer.add_code(code).unwrap();
+
+        let check_id = match authorizer.authorize() {
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized { checks, .. })) => {
+                match &checks[..] {
+                    [error::FailedCheck::Authorizer(error::FailedAuthorizerCheck {
+                        check_id,
+                        ..
+                    })] => *check_id as usize,
+                    other => panic!("unexpected failed checks: {:?}", other),
+                }
+            }
+            other => panic!("unexpected result: {:?}", other),
+        };
+
+        let (_, _, checks, _) = authorizer.dump();
+        let span = checks[check_id].span().unwrap();
+        assert_eq!((span.start.line, span.start.column), (3, 10));
+        assert_eq!(span.lines(code), "check if operation(\"read\");");
+    }
+
     #[test]
     fn forbid_unbound_parameters() {
         let mut builder = Authorizer::new();
@@ -1727,4 +2194,437 @@ allow if true;
         let authorizer = Authorizer::new();
         assert_eq!("", authorizer.to_string())
     }
+
+    #[test]
+    fn clock_and_snapshot_replay() {
+        use crate::{FixedClock, OffsetClock};
+
+        let root = KeyPair::new();
+        let mut builder = Biscuit::builder();
+        builder
+            .add_check("check if time($time), $time < 2023-11-15T00:00:00Z")
+            .unwrap();
+        let biscuit = builder.build(&root).unwrap();
+
+        let before_expiry = FixedClock::from_timestamp(1_699_000_000);
+
+        let mut authorizer = biscuit.authorizer().unwrap();
+        authorizer.set_clock(before_expiry);
+        authorizer.set_time();
+        authorizer.allow().unwrap();
+        assert_eq!(authorizer.time(), Some(before_expiry.0));
+        assert_eq!(authorizer.authorize(), Ok(0));
+
+        let mut expired = biscuit.authorizer().unwrap();
+        expired.set_clock(OffsetClock::ahead(
+            before_expiry,
+            Duration::from_secs(86400 * 30),
+        ));
+        expired.set_time();
+        expired.allow().unwrap();
+        assert!(expired.authorize().is_err());
+
+        // the token is expired now, but the snapshot records the time used
+        let snapshot = authorizer.to_base64_snapshot().unwrap();
+        let mut replayed = Authorizer::from_base64_snapshot(&snapshot).unwrap();
+        assert_eq!(replayed.time(), Some(before_expiry.0));
+        assert_eq!(replayed.clock().now(), before_expiry.0);
+        replayed.set_time();
+        assert_eq!(replayed.authorize(), Ok(0));
+
+        let snapshot = Authorizer::new().snapshot().unwrap();
+        assert_eq!(snapshot.time, None);
+        let restored = Authorizer::from_snapshot(snapshot).unwrap();
+        assert_eq!(restored.time(), None);
+    }
+
+    #[test]
+    fn token_metadata() {
+        let root = KeyPair::new();
+        let external = KeyPair::new();
+
+        let mut builder = Biscuit::builder();
+        builder.add_fact("user(\"alice\")").unwrap();
+        builder.set_root_key_id(3);
+        let biscuit1 = builder.build(&root).unwrap();
+
+        let request = biscuit1.third_party_request().unwrap();
+        let mut block = BlockBuilder::new();
+        block.add_fact("group(\"admin\")").unwrap();
+        let block = request.create_block(&external.private(), block).unwrap();
+        let biscuit2 = biscuit1
+            .append_third_party(external.public(), block)
+            .unwrap();
+
+        let mut authorizer = Authorizer::new();
+        authorizer.set_token_metadata(true);
+        authorizer.add_token(&biscuit2).unwrap();
+        authorizer.allow().unwrap();
+        authorizer.authorize().unwrap();
+
+        let res: Vec<(i64, bool)> = authorizer
+            .query("data($count, $sealed) <- biscuit:block_count($count), biscuit:sealed($sealed)")
+            .unwrap();
+        assert_eq!(res, vec![(2, false)]);
+        let res: Vec<(i64,)> = authorizer
+            .query("data($id) <- biscuit:root_key_id($id)")
+            .unwrap();
+        assert_eq!(res, vec![(3,)]);
+        let res: Vec<(i64, Vec<u8>)> = authorizer
+            .query("data($block, $id) <- biscuit:revocation_id($block, $id)")
+            .unwrap();
+        let mut ids = res.into_iter().map(|(_, id)| id).collect::<Vec<_>>();
+        ids.sort();
+        let mut expected = biscuit2.revocation_identifiers();
+        expected.sort();
+        assert_eq!(ids, expected);
+        let res: Vec<(i64, Vec<u8>)> = authorizer
+            .query("data($block, $key) <- biscuit:external_key($block, $key)")
+            .unwrap();
+        assert_eq!(res, vec![(1, external.public().to_bytes().to_vec())]);
+
+        let mut authorizer = Authorizer::new();
+        authorizer.set_token_metadata(true);
+        authorizer.add_token(&biscuit2.seal().unwrap()).unwrap();
+        authorizer
+            .add_policy("allow if biscuit:sealed(true)")
+            .unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+
+        // blocks cannot forge metadata facts
+        let mut block = BlockBuilder::new();
+        block.add_fact("biscuit:sealed(true)").unwrap();
+        let forged = biscuit2.append(block).unwrap();
+        let mut authorizer = Authorizer::new();
+        authorizer.set_token_metadata(true);
+        let symbols = authorizer.symbols.current_offset();
+        let public_keys = authorizer.symbols.public_keys.current_offset();
+        let error = error::Logic::ReservedPredicate(2, "biscuit:sealed".to_string());
+        assert_eq!(
+            error.to_string(),
+            "block 2 uses the predicate name biscuit:sealed, which is reserved for token metadata"
+        );
+        assert_eq!(
+            authorizer.add_token(&forged),
+            Err(error::Token::FailedLogic(error))
+        );
+
+        // the facts and rules of the previous blocks are not loaded
+        assert!(authorizer.world.facts.is_empty());
+        assert!(authorizer.world.rules.inner.is_empty());
+        assert_eq!(authorizer.symbols.current_offset(), symbols);
+        assert_eq!(authorizer.symbols.public_keys.current_offset(), public_keys);
+        assert!(authorizer.public_key_to_block_id.is_empty());
+        authorizer.add_token(&biscuit2).unwrap();
+
+        let mut block = BlockBuilder::new();
+        block
+            .add_rule("biscuit:root_key_id(1) <- user(\"alice\")")
+            .unwrap();
+        let forged = biscuit2.append(block).unwrap();
+        let mut authorizer = Authorizer::new();
+        authorizer.set_token_metadata(true);
+        assert!(authorizer.add_token(&forged).is_err());
+
+        // without metadata, those names are not reserved
+        let mut authorizer = Authorizer::new();
+        authorizer.add_token(&forged).unwrap();
+    }
+    #[test]
+    fn key_group_scopes() {
+        let root = KeyPair::new();
+        let partner = KeyPair::new();
+        let other = KeyPair::new();
+
+        let mut builder = Biscuit::builder();
+        builder.add_fact("user(\"alice\")").unwrap();
+        let biscuit1 = builder.build(&root).unwrap();
+
+        let request = biscuit1.third_party_request().unwrap();
+        let mut block = BlockBuilder::new();
+        block.add_fact("attested(\"release\")").unwrap();
+        let block = request.create_block(&partner.private(), block).unwrap();
+        let biscuit2 = biscuit1
+            .append_third_party(partner.public(), block)
+            .unwrap();
+
+        let mut authorizer = biscuit2.authorizer().unwrap();
+        authorizer
+            .add_code(
+                r#"
+            check if attested("release") trusting group("partners");
+            allow if true;
+            "#,
+            )
+            .unwrap();
+
+        let mut trusted = authorizer.clone();
+        trusted.set_key_group("partners", vec![other.public(), partner.public()]);
+        assert_eq!(trusted.authorize(), Ok(0));
+        let attested: Vec<(String,)> = trusted
+            .query("data($x) <- attested($x) trusting group(\"partners\")")
+            .unwrap();
+        assert_eq!(attested, vec![("release".to_string(),)]);
+
+        // after a rotation, the partner key is not trusted anymore
+        let mut rotated = authorizer.clone();
+        rotated.set_key_group("partners", vec![partner.public()]);
+        rotated.set_key_group("partners", vec![other.public()]);
+        assert!(matches!(
+            rotated.authorize(),
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized { .. }))
+        ));
+
+        // an unknown group does not trust any block
+        let mut unknown = authorizer.clone();
+        unknown.set_key_group("auditors", vec![partner.public()]);
+        assert!(unknown.authorize().is_err());
+
+        // including the authority block
+        let mut builder = Biscuit::builder();
+        builder.add_fact("attested(\"release\")").unwrap();
+        let authority = builder.build(&root).unwrap();
+        let mut authorizer = authority.authorizer().unwrap();
+        authorizer
+            .add_code(
+                r#"
+            check if attested("release") trusting group("partners");
+            allow if true;
+            "#,
+            )
+            .unwrap();
+        assert!(matches!(
+            authorizer.clone().authorize(),
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized { .. }))
+        ));
+        let mut unresolved = authorizer.clone();
+        unresolved.set_key_group("partners", vec![partner.public()]);
+        assert!(matches!(
+            unresolved.authorize(),
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized { .. }))
+        ));
+        let attested: Vec<(String,)> = authorizer
+            .query("data($x) <- attested($x) trusting group(\"partners\")")
+            .unwrap();
+        assert!(attested.is_empty());
+
+        // tokens cannot use group scopes
+        let mut builder = Biscuit::builder();
+        builder
+            .add_check("check if attested(\"release\") trusting group(\"partners\")")
+            .unwrap();
+        assert_eq!(
+            builder.build(&root).unwrap_err(),
+            error::Token::Format(error::Format::GroupScope)
+        );
+
+        let mut block = BlockBuilder::new();
+        block
+            .add_rule("trusted($x) <- attested($x) trusting group(\"partners\")")
+            .unwrap();
+        assert_eq!(
+            biscuit1.append(block.clone()).unwrap_err(),
+            error::Token::Format(error::Format::GroupScope)
+        );
+        let request = biscuit1.third_party_request().unwrap();
+        assert_eq!(
+            request.create_block(&partner.private(), block).unwrap_err(),
+            error::Token::Format(error::Format::GroupScope)
+        );
+    }
+    #[test]
+    #[cfg(feature = "private-facts")]
+    fn private_facts() {
+        use crate::EncryptionKeyPair;
+
+        let root = KeyPair::new();
+        let recipient = EncryptionKeyPair::new();
+
+        let mut builder = Biscuit::builder();
+        builder.add_fact("user(\"alice\")").unwrap();
+        builder
+            .set_private_facts(&recipient.public(), vec!["internal_id(\"u-8812\")"])
+            .unwrap();
+        let token = builder.build(&root).unwrap();
+
+        // the private facts are hidden from holders
+        let serialized = token.to_vec().unwrap();
+        assert!(!token.to_string().contains("u-8812"));
+        assert!(!serialized.windows(6).any(|w| w == b"u-8812"));
+        let token = Biscuit::from(&serialized, root.public()).unwrap();
+
+        // authorizers that are not the recipient skip the private facts
+        let mut authorizer = token.authorizer().unwrap();
+        authorizer.add_code(r#"allow if user("alice");"#).unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+        let ids: Vec<(String,)> = authorizer
+            .query("data($id) <- internal_id($id) trusting authority")
+            .unwrap();
+        assert!(ids.is_empty());
+
+        let mut authorizer = Authorizer::new();
+        authorizer.add_decryption_key(EncryptionKeyPair::new().private());
+        authorizer.add_token(&token).unwrap();
+        authorizer
+            .add_code(r#"allow if internal_id("u-8812");"#)
+            .unwrap();
+        assert!(matches!(
+            authorizer.authorize(),
+            Err(error::Token::FailedLogic(
+                error::Logic::NoMatchingPolicy { .. }
+            ))
+        ));
+
+        // unless they are required
+        let mut authorizer = Authorizer::new();
+        authorizer.set_require_private_facts(true);
+        assert_eq!(
+            authorizer.add_token(&token),
+            Err(error::Token::Format(error::Format::MissingDecryptionKey(
+                recipient.public().to_string()
+            )))
+        );
+
+        let mut authorizer = Authorizer::new();
+        authorizer.set_require_private_facts(true);
+        authorizer.add_decryption_key(EncryptionKeyPair::new().private());
+        assert!(matches!(
+            authorizer.add_token(&token),
+            Err(error::Token::Format(error::Format::MissingDecryptionKey(_)))
+        ));
+
+        let mut authorizer = Authorizer::new();
+        authorizer.add_decryption_key(recipient.private());
+        authorizer.add_token(&token).unwrap();
+        authorizer
+            .add_code(r#"allow if user("alice"), internal_id("u-8812");"#)
+            .unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+        let ids: Vec<(String,)> = authorizer
+            .query("data($id) <- internal_id($id) trusting authority")
+            .unwrap();
+        assert_eq!(ids, vec![("u-8812".to_string(),)]);
+
+        // the signatures cover the ciphertext
+        let block = schema::Block::decode(&token.container().authority.data[..]).unwrap();
+        let ciphertext = block.encrypted_facts.unwrap().ciphertext;
+        let position = serialized
+            .windows(ciphertext.len())
+            .position(|w| w == &ciphertext[..])
+            .unwrap();
+        let mut tampered = serialized.clone();
+        tampered[position] ^= 1;
+        assert!(matches!(
+            Biscuit::from(&tampered, root.public()),
+            Err(error::Token::Format(error::Format::Signature(_)))
+        ));
+
+        // older verifiers must reject the block instead of ignoring the private facts
+        assert_eq!(block.version, Some(7));
+        let mut block = schema::Block::decode(&token.container().authority.data[..]).unwrap();
+        block.version = Some(6);
+        assert_eq!(
+            crate::format::convert::proto_block_to_token_block(&block, None).unwrap_err(),
+            error::Format::DeserializationError(
+                "v3 to v6 blocks must not have private facts".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn arrays_and_maps() {
+        let root = KeyPair::new();
+        let mut builder = Biscuit::builder();
+        builder
+            .add_fact(r#"user({"name": "alice", "groups": array["admin", "dev"]})"#)
+            .unwrap();
+        let token = builder.build(&root).unwrap();
+
+        let serialized = token.to_vec().unwrap();
+        let block = schema::Block::decode(&token.container().authority.data[..]).unwrap();
+        assert_eq!(block.version, Some(6));
+        let token = Biscuit::from(&serialized, root.public()).unwrap();
+
+        let mut authorizer = token.authorizer().unwrap();
+        authorizer
+            .add_code(
+                r#"
+                check if user($u), $u.get("groups").contains("admin");
+                check if user($u), $u.get("groups").get(1) == "dev";
+                allow if user($u), $u.get("name") == "alice";
+                "#,
+            )
+            .unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+
+        // reading a missing element makes the expression false
+        let mut authorizer = token.authorizer().unwrap();
+        authorizer
+            .add_code(
+                r#"
+                check if user($u), $u.get("email").length() > 0;
+                check if user($u), !$u.get("groups").get(5).contains("x");
+                allow if true;
+                "#,
+            )
+            .unwrap();
+        match authorizer.authorize() {
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized { checks, .. })) => {
+                assert_eq!(checks.len(), 2)
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn authorization_observers() {
+        use std::sync::Mutex;
+
+        let root = KeyPair::new();
+        let mut builder = Biscuit::builder();
+        builder.add_check("check if operation(\"read\")").unwrap();
+        let token = builder.build(&root).unwrap();
+
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let observed = records.clone();
+
+        let mut authorizer = token.authorizer().unwrap();
+        authorizer.add_observer(move |record: &AuthorizationRecord| {
+            observed.lock().unwrap().push(record.clone());
+        });
+        authorizer.add_fact("operation(\"write\")").unwrap();
+        authorizer
+            .add_policy("deny if operation(\"delete\")")
+            .unwrap();
+        authorizer.allow().unwrap();
+
+        let mut cloned = authorizer.clone();
+        assert!(authorizer.authorize().is_err());
+        // observers are kept in clones
+        assert!(cloned.authorize().is_err());
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 2);
+        let record = &records[0];
+        assert_eq!(record.revocation_ids, token.revocation_identifiers());
+        assert_eq!(record.policy, Some(error::MatchedPolicy::Allow(1)));
+        assert_eq!(
+            record.failed_checks,
+            vec![error::FailedCheck::Block(error::FailedBlockCheck {
+                block_id: 0,
+                check_id: 0,
+                rule: "check if operation(\"read\")".to_string(),
+            })]
+        );
+        assert_eq!(record.iterations, authorizer.iterations());
+        assert_eq!(record.fact_count, authorizer.fact_count());
+        assert_eq!(record.execution_time, authorizer.execution_time());
+        assert_eq!(
+            record.result,
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized {
+                policy: error::MatchedPolicy::Allow(1),
+                checks: record.failed_checks.clone(),
+            }))
+        );
+    }
 }
@@ -0,0 +1,298 @@
+//! what-if replays of authorizer snapshots
+//!
+//! a snapshot contains everything needed to run the authorization again, without
+//! the token or its keys. Replaying it with a different policy set, or with more
+//! facts, shows how a decision would change before deploying a new policy.
+use prost::Message;
+use std::{
+    convert::TryInto,
+    fmt::{self, Display},
+};
+
+use crate::{
+    builder::{BlockBuilder, Check, Fact, Policy, Rule},
+    error,
+    format::schema,
+};
+
+use super::Authorizer;
+
+/// changes applied to a snapshot before replaying its decision
+///
+/// facts, rules and checks are added to the recorded ones. If any policy is
+/// added, the policies replace all the recorded policies, otherwise the
+/// recorded policies are kept.
+///
+/// ```rust
+/// # use biscuit_auth::{Authorizer, WhatIf};
+/// let mut authorizer = Authorizer::new();
+/// authorizer.add_code(r#"operation("write"); allow if true;"#).unwrap();
+/// authorizer.authorize().unwrap();
+/// let snapshot = authorizer.to_base64_snapshot().unwrap();
+///
+/// let mut candidate = WhatIf::new();
+/// candidate
+///     .add_code(r#"allow if operation("read"); deny if true;"#)
+///     .unwrap();
+///
+/// let outcome = candidate.replay_base64(&snapshot).unwrap();
+/// assert!(outcome.is_flipped());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct WhatIf {
+    block: BlockBuilder,
+    policies: Vec<Policy>,
+}
+
+impl WhatIf {
+    pub fn new() -> Self {
+        WhatIf::default()
+    }
+
+    /// adds a fact to the replayed authorizer
+    pub fn add_fact<F: TryInto<Fact>>(&mut self, fact: F) -> Result<(), error::Token>
+    where
+        error::Token: From<<F as TryInto<Fact>>::Error>,
+    {
+        self.block.add_fact(fact)
+    }
+
+    /// adds a rule to the replayed authorizer
+    pub fn add_rule<R: TryInto<Rule>>(&mut self, rule: R) -> Result<(), error::Token>
+    where
+        error::Token: From<<R as TryInto<Rule>>::Error>,
+    {
+        self.block.add_rule(rule)
+    }
+
+    /// adds a check to the replayed authorizer
+    pub fn add_check<C: TryInto<Check>>(&mut self, check: C) -> Result<(), error::Token>
+    where
+        error::Token: From<<C as TryInto<Check>>::Error>,
+    {
+        self.block.add_check(check)
+    }
+
+    /// adds a policy, replacing the recorded policies
+    pub fn add_policy<P: TryInto<Policy>>(&mut self, policy: P) -> Result<(), error::Token>
+    where
+        error::Token: From<<P as TryInto<Policy>>::Error>,
+    {
+        let policy = policy.try_into()?;
+        policy.validate_parameters()?;
+        self.policies.push(policy);
+        Ok(())
+    }
+
+    /// adds facts, rules, checks and policies from datalog source code,
+    /// like a candidate policy file
+    pub fn add_code<T: AsRef<str>>(&mut self, source: T) -> Result<(), error::Token> {
+        let mut authorizer = Authorizer::new();
+        authorizer.add_code(source)?;
+
+        self.block.merge(authorizer.authorizer_block_builder);
+        self.policies.extend(authorizer.policies);
+        Ok(())
+    }
+
+    /// replays a snapshot, with and without the changes
+    ///
+    /// both runs use the full limits of the snapshot, the execution time
+    /// recorded in the snapshot is not counted
+    pub fn replay(
+        &self,
+        snapshot: schema::AuthorizerSnapshot,
+    ) -> Result<ReplayOutcome, error::Token> {
+        let mut before = Authorizer::from_snapshot(snapshot)?;
+        let mut after = before.clone();
+
+        after.authorizer_block_builder.merge(self.block.clone());
+        if !self.policies.is_empty() {
+            after.policies = self.policies.clone();
+        }
+
+        let before = before.authorize_with_limits(before.limits.clone());
+        let after = after.authorize_with_limits(after.limits.clone());
+
+        Ok(ReplayOutcome { before, after })
+    }
+
+    /// replays a snapshot serialized with [`Authorizer::to_raw_snapshot`]
+    pub fn replay_raw(&self, snapshot: &[u8]) -> Result<ReplayOutcome, error::Token> {
+        let snapshot = schema::AuthorizerSnapshot::decode(snapshot).map_err(|e| {
+            error::Format::DeserializationError(format!("deserialization error: {:?}", e))
+        })?;
+        self.replay(snapshot)
+    }
+
+    /// replays a snapshot serialized with [`Authorizer::to_base64_snapshot`]
+    pub fn replay_base64(&self, snapshot: &str) -> Result<ReplayOutcome, error::Token> {
+        let bytes = base64::decode_config(snapshot, base64::URL_SAFE)?;
+        self.replay_raw(&bytes)
+    }
+
+    /// replays a list of base64 snapshots and reports the decisions that flip
+    ///
+    /// snapshots that cannot be loaded are reported without stopping the batch
+    pub fn replay_batch<I, S>(&self, snapshots: I) -> BatchReplay
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut report = BatchReplay::default();
+
+        for (i, snapshot) in snapshots.into_iter().enumerate() {
+            report.replayed += 1;
+            match self.replay_base64(snapshot.as_ref()) {
+                Ok(outcome) if outcome.is_flipped() => report.flips.push((i, outcome)),
+                Ok(_) => {}
+                Err(e) => report.errors.push((i, e)),
+            }
+        }
+
+        report
+    }
+}
+
+/// decisions of a snapshot replayed without and with a [`WhatIf`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayOutcome {
+    /// result of the recorded authorizer
+    pub before: Result<usize, error::Token>,
+    /// result once the changes are applied
+    pub after: Result<usize, error::Token>,
+}
+
+impl ReplayOutcome {
+    /// the request was allowed and is now denied, or the reverse
+    pub fn is_flipped(&self) -> bool {
+        self.before.is_ok() != self.after.is_ok()
+    }
+
+    /// the result is different, even if the decision is the same
+    pub fn is_changed(&self) -> bool {
+        self.before != self.after
+    }
+}
+
+fn write_decision(f: &mut fmt::Formatter<'_>, result: &Result<usize, error::Token>) -> fmt::Result {
+    match result {
+        Ok(i) => write!(f, "allow (policy {})", i),
+        Err(e) => write!(f, "deny ({})", e),
+    }
+}
+
+impl Display for ReplayOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_decision(f, &self.before)?;
+        write!(f, " -> ")?;
+        write_decision(f, &self.after)
+    }
+}
+
+/// report of [`WhatIf::replay_batch`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BatchReplay {
+    /// number of snapshots in the batch
+    pub replayed: usize,
+    /// decisions that flipped, with the index of their snapshot
+    pub flips: Vec<(usize, ReplayOutcome)>,
+    /// snapshots that could not be replayed, with their index
+    pub errors: Vec<(usize, error::Token)>,
+}
+
+impl Display for BatchReplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} snapshots replayed, {} flipped, {} errors",
+            self.replayed,
+            self.flips.len(),
+            self.errors.len()
+        )?;
+        for (i, outcome) in &self.flips {
+            writeln!(f, "snapshot {}: {}", i, outcome)?;
+        }
+        for (i, e) in &self.errors {
+            writeln!(f, "snapshot {}: error: {}", i, e)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Biscuit, KeyPair};
+
+    #[test]
+    fn what_if() {
+        let root = KeyPair::new();
+        let mut builder = Biscuit::builder();
+        builder
+            .add_code(r#"right("file1", "read"); right("file1", "write");"#)
+            .unwrap();
+        let biscuit = builder.build(&root).unwrap();
+
+        let snapshots = ["read", "write", "delete"]
+            .iter()
+            .map(|operation| {
+                let mut authorizer = biscuit.authorizer().unwrap();
+                authorizer.add_fact(r#"resource("file1")"#).unwrap();
+                authorizer
+                    .add_fact(format!("operation(\"{}\")", operation).as_str())
+                    .unwrap();
+                authorizer
+                    .add_policy("allow if resource($r), operation($op), right($r, $op)")
+                    .unwrap();
+                let _ = authorizer.authorize();
+                authorizer.to_base64_snapshot().unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        // read only policy
+        let mut candidate = WhatIf::new();
+        candidate
+            .add_code(
+                r#"
+                allow if resource($r), operation("read"), right($r, "read");
+                deny if true;
+                "#,
+            )
+            .unwrap();
+
+        let outcome = candidate.replay_base64(&snapshots[0]).unwrap();
+        assert!(!outcome.is_flipped());
+        assert_eq!(outcome.before, Ok(0));
+        assert_eq!(outcome.after, Ok(0));
+
+        let outcome = candidate.replay_base64(&snapshots[1]).unwrap();
+        assert!(outcome.is_flipped());
+        assert_eq!(
+            outcome.to_string().split(" -> ").next(),
+            Some("allow (policy 0)")
+        );
+
+        let report = candidate.replay_batch(snapshots.iter().chain(&["AAAA".to_string()]));
+        assert_eq!(report.replayed, 4);
+        assert_eq!(
+            report.flips.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].0, 3);
+
+        // extra facts, keeping the recorded policies
+        let mut extra = WhatIf::new();
+        extra.add_fact(r#"right("file1", "delete")"#).unwrap();
+        let outcome = extra.replay_base64(&snapshots[2]).unwrap();
+        assert!(outcome.is_flipped());
+        assert!(outcome.before.is_err());
+        assert_eq!(outcome.after, Ok(0));
+
+        let report = extra.replay_batch(&snapshots);
+        assert_eq!(report.flips.len(), 1);
+        assert_eq!(report.flips[0].0, 2);
+        assert!(report.errors.is_empty());
+    }
+}
@@ -1,9 +1,12 @@
 use prost::Message;
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
 
 use crate::{
     builder::{BlockBuilder, Convert, Policy},
-    datalog::{Origin, RunLimits, TrustedOrigins},
+    datalog::{Origin, RunLimits, TrustedOrigins, World},
     error,
     format::{
         convert::{
@@ -16,7 +19,7 @@ use crate::{
         schema::{self, GeneratedFacts},
     },
     token::{default_symbol_table, MAX_SCHEMA_VERSION, MIN_SCHEMA_VERSION},
-    PublicKey,
+    FixedClock, PublicKey,
 };
 
 impl super::Authorizer {
@@ -25,6 +28,7 @@ impl super::Authorizer {
             limits,
             execution_time,
             world,
+            time,
         } = input;
 
         let limits = RunLimits {
@@ -70,8 +74,15 @@ impl super::Authorizer {
         authorizer.policies = policies;
         authorizer.limits = limits;
         authorizer.execution_time = execution_time;
+        // replays use the recorded time instead of the system clock
+        if let Some(time) = time {
+            let clock = FixedClock::from_timestamp(time);
+            authorizer.time = Some(clock.0);
+            authorizer.set_clock(clock);
+        }
 
         let mut public_key_to_block_id: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut token_world = World::new();
         let mut blocks = Vec::new();
         for (i, block) in world.blocks.iter().enumerate() {
             let token_symbols = if block.external_key.is_none() {
@@ -84,18 +95,22 @@ impl super::Authorizer {
 
             let mut block = proto_snapshot_block_to_token_block(block)?;
 
-            if let Some(key) = block.external_key.as_ref() {
+            for key in block.external_key.iter().chain(&block.co_signers) {
                 public_key_to_block_id
                     .entry(authorizer.symbols.public_keys.insert(key) as usize)
                     .or_default()
                     .push(i);
             }
 
-            authorizer.load_and_translate_block(&mut block, i, &token_symbols)?;
+            authorizer.load_and_translate_block(&mut block, i, &token_symbols, &mut token_world)?;
             blocks.push(block);
         }
 
+        authorizer.world.facts.merge(token_world.facts);
+        authorizer.world.rules.merge(token_world.rules);
+
         authorizer.public_key_to_block_id = public_key_to_block_id;
+        let blocks_count = blocks.len();
 
         if !blocks.is_empty() {
             authorizer.token_origins = TrustedOrigins::from_scopes(
@@ -108,7 +123,7 @@ impl super::Authorizer {
         }
 
         for GeneratedFacts { origins, facts } in world.generated_facts {
-            let origin = proto_origin_to_authorizer_origin(&origins)?;
+            let origin = proto_origin_to_authorizer_origin(&origins, blocks_count)?;
 
             for fact in &facts {
                 let fact = proto_fact_to_token_fact(fact)?;
@@ -206,6 +221,11 @@ impl super::Authorizer {
                 max_iterations: self.limits.max_iterations,
                 max_time: self.limits.max_time.as_nanos() as u64,
             },
+            time: self.time.map(|time| {
+                time.duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0)
+            }),
         })
     }
 
@@ -226,23 +246,26 @@ impl super::Authorizer {
 
 fn authorizer_origin_to_proto_origin(origin: &Origin) -> Vec<schema::Origin> {
     origin
-        .inner
         .iter()
         .map(|o| {
-            if *o == usize::MAX {
+            if o == usize::MAX {
                 schema::Origin {
                     content: Some(schema::origin::Content::Authorizer(schema::Empty {})),
                 }
             } else {
                 schema::Origin {
-                    content: Some(schema::origin::Content::Origin(*o as u32)),
+                    content: Some(schema::origin::Content::Origin(o as u32)),
                 }
             }
         })
         .collect()
 }
 
-fn proto_origin_to_authorizer_origin(origins: &[schema::Origin]) -> Result<Origin, error::Format> {
+/// origins can only refer to the blocks of the snapshot, or to the authorizer
+fn proto_origin_to_authorizer_origin(
+    origins: &[schema::Origin],
+    blocks_count: usize,
+) -> Result<Origin, error::Format> {
     let mut new_origin = Origin::default();
 
     for origin in origins {
@@ -250,7 +273,9 @@ fn proto_origin_to_authorizer_origin(origins: &[schema::Origin]) -> Result<Origi
             Some(schema::origin::Content::Authorizer(schema::Empty {})) => {
                 new_origin.insert(usize::MAX)
             }
-            Some(schema::origin::Content::Origin(o)) => new_origin.insert(o as usize),
+            Some(schema::origin::Content::Origin(o)) if (o as usize) < blocks_count => {
+                new_origin.insert(o as usize)
+            }
             _ => {
                 return Err(error::Format::DeserializationError(
                     "invalid origin".to_string(),
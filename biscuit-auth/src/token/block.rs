@@ -3,6 +3,7 @@ use crate::{
     crypto::PublicKey,
     datalog::{Check, Fact, Rule, SymbolTable, Term},
     error,
+    format::schema,
 };
 
 use super::{public_keys::PublicKeys, Scope};
@@ -25,10 +26,14 @@ pub struct Block {
     pub version: u32,
     /// key used in optional external signature
     pub external_key: Option<PublicKey>,
+    /// keys of the additional external signatures
+    pub co_signers: Vec<PublicKey>,
     /// list of public keys referenced by this block
     pub public_keys: PublicKeys,
     /// list of scopes defining which blocks are trusted by this block
     pub scopes: Vec<Scope>,
+    /// facts encrypted to the public key of an authorizer
+    pub encrypted_facts: Option<schema::EncryptedFacts>,
 }
 
 impl Block {
@@ -69,6 +74,24 @@ impl Block {
         res
     }
 
+    /// group scopes are resolved by the authorizer from its own key sets, a block
+    /// must not use them to widen the trust of its rules and checks
+    pub(crate) fn check_group_scopes(&self) -> Result<(), error::Format> {
+        let is_group = |scope: &Scope| matches!(scope, Scope::Group(_));
+
+        if self.scopes.iter().any(is_group)
+            || self.rules.iter().any(|r| r.scopes.iter().any(is_group))
+            || self
+                .checks
+                .iter()
+                .any(|c| c.queries.iter().any(|q| q.scopes.iter().any(is_group)))
+        {
+            Err(error::Format::GroupScope)
+        } else {
+            Ok(())
+        }
+    }
+
     pub(crate) fn translate(
         &self,
         from_symbols: &SymbolTable,
@@ -100,6 +123,7 @@ impl Block {
             context: self.context.clone(),
             version: self.version.clone(),
             external_key: self.external_key.clone(),
+            co_signers: self.co_signers.clone(),
             public_keys: self.public_keys.clone(),
             scopes: self
                 .scopes
@@ -108,6 +132,7 @@ impl Block {
                     builder::Scope::convert_from(s, from_symbols).map(|s| s.convert(to_symbols))
                 })
                 .collect::<Result<Vec<Scope>, error::Format>>()?,
+            encrypted_facts: self.encrypted_facts.clone(),
         })
     }
 }
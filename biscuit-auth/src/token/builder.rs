@@ -1,15 +1,24 @@
 //! helper functions and structure to create tokens and blocks
 use super::{default_symbol_table, Biscuit, Block};
+#[cfg(feature = "private-facts")]
+use crate::crypto::{encryption, EncryptionPublicKey};
 use crate::crypto::{KeyPair, PublicKey};
 use crate::datalog::{self, get_schema_version, SymbolTable};
 use crate::error;
+#[cfg(feature = "private-facts")]
+use crate::format::convert::private_facts_to_proto;
+use crate::format::schema;
 use crate::token::builder_ext::BuilderExt;
+use crate::token::typing;
+use biscuit_parser::formatter::escape;
 use biscuit_parser::parser::parse_block_source;
 use nom::Finish;
+#[cfg(feature = "private-facts")]
+use prost::Message;
 use rand_core::{CryptoRng, RngCore};
 use std::str::FromStr;
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
     convert::{TryFrom, TryInto},
     fmt::{self, Write},
     time::{Duration, SystemTime, UNIX_EPOCH},
@@ -17,15 +26,21 @@ use std::{
 
 // reexport those because the builder uses the same definitions
 pub use crate::datalog::{Binary, Expression as DatalogExpression, Op as DatalogOp, Unary};
+pub use biscuit_parser::builder::ParameterType;
+pub use biscuit_parser::error::Span;
 
 /// creates a Block content to append to an existing token
 #[derive(Clone, Debug, Default)]
 pub struct BlockBuilder {
+    /// predicate schemas checked when adding facts, rules and checks,
+    /// they are not serialized in the block
+    pub schemas: Vec<PredicateSchema>,
     pub facts: Vec<Fact>,
     pub rules: Vec<Rule>,
     pub checks: Vec<Check>,
     pub scopes: Vec<Scope>,
     pub context: Option<String>,
+    pub encrypted_facts: Option<schema::EncryptedFacts>,
 }
 
 impl BlockBuilder {
@@ -34,6 +49,11 @@ impl BlockBuilder {
     }
 
     pub fn merge(&mut self, mut other: BlockBuilder) {
+        for schema in other.schemas {
+            if !self.schemas.contains(&schema) {
+                self.schemas.push(schema);
+            }
+        }
         self.facts.append(&mut other.facts);
         self.rules.append(&mut other.rules);
         self.checks.append(&mut other.checks);
@@ -41,6 +61,9 @@ impl BlockBuilder {
         if let Some(c) = other.context {
             self.set_context(c);
         }
+        if other.encrypted_facts.is_some() {
+            self.encrypted_facts = other.encrypted_facts;
+        }
     }
 
     pub fn add_fact<F: TryInto<Fact>>(&mut self, fact: F) -> Result<(), error::Token>
@@ -49,6 +72,7 @@ impl BlockBuilder {
     {
         let fact = fact.try_into()?;
         fact.validate()?;
+        typing::check_fact(&self.schemas, &fact)?;
 
         self.facts.push(fact);
         Ok(())
@@ -60,6 +84,7 @@ impl BlockBuilder {
     {
         let rule = rule.try_into()?;
         rule.validate_parameters()?;
+        typing::check_rule(&self.schemas, &rule)?;
         self.rules.push(rule);
         Ok(())
     }
@@ -70,10 +95,40 @@ impl BlockBuilder {
     {
         let check = check.try_into()?;
         check.validate_parameters()?;
+        typing::check_check(&self.schemas, &check)?;
         self.checks.push(check);
         Ok(())
     }
 
+    /// declares the arity and term types of a predicate
+    ///
+    /// facts, rules and checks already in the builder must match it, and
+    /// those added later are rejected if they do not
+    pub fn add_schema<S: TryInto<PredicateSchema>>(&mut self, schema: S) -> Result<(), error::Token>
+    where
+        error::Token: From<<S as TryInto<PredicateSchema>>::Error>,
+    {
+        let schema = schema.try_into()?;
+        typing::check_schema(&self.schemas, &schema)?;
+        if self.schemas.contains(&schema) {
+            return Ok(());
+        }
+
+        let schemas = std::slice::from_ref(&schema);
+        for fact in &self.facts {
+            typing::check_fact(schemas, fact)?;
+        }
+        for rule in &self.rules {
+            typing::check_rule(schemas, rule)?;
+        }
+        for check in &self.checks {
+            typing::check_check(schemas, check)?;
+        }
+
+        self.schemas.push(schema);
+        Ok(())
+    }
+
     pub fn add_code<T: AsRef<str>>(&mut self, source: T) -> Result<(), error::Token> {
         self.add_code_with_params(source, HashMap::new(), HashMap::new())
     }
@@ -93,6 +148,10 @@ impl BlockBuilder {
             e2
         })?;
 
+        for (_, schema) in source_result.schemas.into_iter() {
+            self.add_schema(PredicateSchema::from(schema))?;
+        }
+
         for (_, fact) in source_result.facts.into_iter() {
             let mut fact: Fact = fact.into();
             for (name, value) in &params {
@@ -108,6 +167,7 @@ impl BlockBuilder {
                 res?;
             }
             fact.validate()?;
+            typing::check_fact(&self.schemas, &fact)?;
             self.facts.push(fact);
         }
 
@@ -138,6 +198,7 @@ impl BlockBuilder {
                 res?;
             }
             rule.validate_parameters()?;
+            typing::check_rule(&self.schemas, &rule)?;
             self.rules.push(rule);
         }
 
@@ -168,6 +229,7 @@ impl BlockBuilder {
                 res?;
             }
             check.validate_parameters()?;
+            typing::check_check(&self.schemas, &check)?;
             self.checks.push(check);
         }
 
@@ -182,6 +244,40 @@ impl BlockBuilder {
         self.context = Some(context);
     }
 
+    /// encrypts facts to the public key of an authorizer
+    ///
+    /// those facts are hidden from the holders of the token, and only loaded
+    /// by an authorizer that was given the matching private key with
+    /// [`Authorizer::add_decryption_key`](crate::Authorizer::add_decryption_key).
+    /// A block carries a single encrypted payload, calling this again replaces it.
+    #[cfg(feature = "private-facts")]
+    #[cfg_attr(feature = "docsrs", doc(cfg(feature = "private-facts")))]
+    pub fn set_private_facts<F: TryInto<Fact>, I: IntoIterator<Item = F>>(
+        &mut self,
+        recipient: &EncryptionPublicKey,
+        facts: I,
+    ) -> Result<(), error::Token>
+    where
+        error::Token: From<<F as TryInto<Fact>>::Error>,
+    {
+        let mut private_facts = Vec::new();
+        for fact in facts {
+            let fact = fact.try_into()?;
+            fact.validate()?;
+            private_facts.push(fact);
+        }
+
+        let mut v = Vec::new();
+        private_facts_to_proto(&private_facts)
+            .encode(&mut v)
+            .map_err(|e| {
+                error::Format::SerializationError(format!("serialization error: {:?}", e))
+            })?;
+        self.encrypted_facts = Some(encryption::encrypt(&mut rand::rngs::OsRng, recipient, &v)?);
+
+        Ok(())
+    }
+
     pub(crate) fn build(self, mut symbols: SymbolTable) -> Block {
         let symbols_start = symbols.current_offset();
         let public_keys_start = symbols.public_keys.current_offset();
@@ -208,7 +304,13 @@ impl BlockBuilder {
 
         let new_syms = symbols.split_at(symbols_start);
         let public_keys = symbols.public_keys.split_at(public_keys_start);
-        let schema_version = get_schema_version(&facts, &rules, &checks, &scopes);
+        let schema_version = get_schema_version(
+            &facts,
+            &rules,
+            &checks,
+            &scopes,
+            self.encrypted_facts.is_some(),
+        );
 
         Block {
             symbols: new_syms,
@@ -218,8 +320,10 @@ impl BlockBuilder {
             context: self.context,
             version: schema_version.version(),
             external_key: None,
+            co_signers: vec![],
             public_keys,
             scopes,
+            encrypted_facts: self.encrypted_facts,
         }
     }
 
@@ -228,6 +332,7 @@ impl BlockBuilder {
         symbols: &SymbolTable,
     ) -> Result<Self, error::Format> {
         Ok(BlockBuilder {
+            schemas: Vec::new(),
             facts: block
                 .facts
                 .iter()
@@ -249,6 +354,7 @@ impl BlockBuilder {
                 .map(|s| Scope::convert_from(s, &symbols))
                 .collect::<Result<Vec<Scope>, error::Format>>()?,
             context: block.context.clone(),
+            encrypted_facts: block.encrypted_facts.clone(),
         })
     }
 
@@ -271,6 +377,9 @@ impl BlockBuilder {
 
 impl fmt::Display for BlockBuilder {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for schema in &self.schemas {
+            writeln!(f, "{};", schema)?;
+        }
         for mut fact in self.facts.clone().into_iter() {
             fact.apply_parameters();
             writeln!(f, "{};", &fact)?;
@@ -327,6 +436,13 @@ impl BiscuitBuilder {
         self.inner.add_check(check)
     }
 
+    pub fn add_schema<S: TryInto<PredicateSchema>>(&mut self, schema: S) -> Result<(), error::Token>
+    where
+        error::Token: From<<S as TryInto<PredicateSchema>>::Error>,
+    {
+        self.inner.add_schema(schema)
+    }
+
     pub fn add_code<T: AsRef<str>>(&mut self, source: T) -> Result<(), error::Token> {
         self.inner
             .add_code_with_params(source, HashMap::new(), HashMap::new())
@@ -355,6 +471,21 @@ impl BiscuitBuilder {
         self.inner.set_context(context);
     }
 
+    /// encrypts authority facts to the public key of an authorizer, see
+    /// [`BlockBuilder::set_private_facts`]
+    #[cfg(feature = "private-facts")]
+    #[cfg_attr(feature = "docsrs", doc(cfg(feature = "private-facts")))]
+    pub fn set_private_facts<F: TryInto<Fact>, I: IntoIterator<Item = F>>(
+        &mut self,
+        recipient: &EncryptionPublicKey,
+        facts: I,
+    ) -> Result<(), error::Token>
+    where
+        error::Token: From<<F as TryInto<Fact>>::Error>,
+    {
+        self.inner.set_private_facts(recipient, facts)
+    }
+
     pub fn set_root_key_id(&mut self, root_key_id: u32) {
         self.root_key_id = Some(root_key_id);
     }
@@ -429,6 +560,8 @@ pub enum Term {
     Bool(bool),
     Set(BTreeSet<Term>),
     Parameter(String),
+    Array(Vec<Term>),
+    Map(BTreeMap<String, Term>),
 }
 
 impl Convert<datalog::Term> for Term {
@@ -441,6 +574,12 @@ impl Convert<datalog::Term> for Term {
             Term::Bytes(s) => datalog::Term::Bytes(s.clone()),
             Term::Bool(b) => datalog::Term::Bool(*b),
             Term::Set(s) => datalog::Term::Set(s.iter().map(|i| i.convert(symbols)).collect()),
+            Term::Array(a) => datalog::Term::Array(a.iter().map(|i| i.convert(symbols)).collect()),
+            Term::Map(m) => datalog::Term::Map(
+                m.iter()
+                    .map(|(key, value)| (symbols.insert(key), value.convert(symbols)))
+                    .collect(),
+            ),
             // The error is caught in the `add_xxx` functions, so this should
             // not happen™
             Term::Parameter(s) => panic!("Remaining parameter {}", &s),
@@ -460,6 +599,21 @@ impl Convert<datalog::Term> for Term {
                     .map(|i| Term::convert_from(i, symbols))
                     .collect::<Result<BTreeSet<_>, error::Format>>()?,
             ),
+            datalog::Term::Array(a) => Term::Array(
+                a.iter()
+                    .map(|i| Term::convert_from(i, symbols))
+                    .collect::<Result<Vec<_>, error::Format>>()?,
+            ),
+            datalog::Term::Map(m) => Term::Map(
+                m.iter()
+                    .map(|(key, value)| {
+                        Ok((
+                            symbols.print_symbol(*key)?,
+                            Term::convert_from(value, symbols)?,
+                        ))
+                    })
+                    .collect::<Result<BTreeMap<_, _>, error::Format>>()?,
+            ),
         })
     }
 }
@@ -475,6 +629,8 @@ impl From<&Term> for Term {
             Term::Bool(b) => Term::Bool(*b),
             Term::Set(ref s) => Term::Set(s.clone()),
             Term::Parameter(ref p) => Term::Parameter(p.clone()),
+            Term::Array(ref a) => Term::Array(a.clone()),
+            Term::Map(ref m) => Term::Map(m.clone()),
         }
     }
 }
@@ -492,6 +648,12 @@ impl From<biscuit_parser::builder::Term> for Term {
                 Term::Set(s.into_iter().map(|t| t.into()).collect())
             }
             biscuit_parser::builder::Term::Parameter(ref p) => Term::Parameter(p.clone()),
+            biscuit_parser::builder::Term::Array(a) => {
+                Term::Array(a.into_iter().map(|t| t.into()).collect())
+            }
+            biscuit_parser::builder::Term::Map(m) => {
+                Term::Map(m.into_iter().map(|(k, t)| (k, t.into())).collect())
+            }
         }
     }
 }
@@ -502,12 +664,63 @@ impl AsRef<Term> for Term {
     }
 }
 
+impl Term {
+    /// checks whether the term is a valid value for a parameter of the given type
+    pub fn has_type(&self, parameter_type: &ParameterType) -> bool {
+        match (parameter_type, self) {
+            (ParameterType::Integer, Term::Integer(_)) => true,
+            (ParameterType::String, Term::Str(_)) => true,
+            (ParameterType::Date, Term::Date(_)) => true,
+            (ParameterType::Bytes, Term::Bytes(_)) => true,
+            (ParameterType::Bool, Term::Bool(_)) => true,
+            (ParameterType::Set(t), Term::Set(s)) => s.iter().all(|term| term.has_type(t)),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn type_name(&self) -> String {
+        match self {
+            Term::Variable(_) => "variable".to_string(),
+            Term::Integer(_) => "int".to_string(),
+            Term::Str(_) => "string".to_string(),
+            Term::Date(_) => "date".to_string(),
+            Term::Bytes(_) => "bytes".to_string(),
+            Term::Bool(_) => "bool".to_string(),
+            Term::Set(s) => match s.iter().next() {
+                Some(term) => format!("set<{}>", term.type_name()),
+                None => "set".to_string(),
+            },
+            Term::Parameter(_) => "parameter".to_string(),
+            Term::Array(_) => "array".to_string(),
+            Term::Map(_) => "map".to_string(),
+        }
+    }
+}
+
+/// verifies that a parameter value matches its type annotation, if there is one
+fn check_parameter_type(
+    parameter_types: &HashMap<String, ParameterType>,
+    name: &str,
+    term: &Term,
+) -> Result<(), error::Token> {
+    match parameter_types.get(name) {
+        Some(parameter_type) if !term.has_type(parameter_type) => Err(error::Token::Language(
+            biscuit_parser::error::LanguageError::ParameterType {
+                name: name.to_string(),
+                expected: parameter_type.to_string(),
+                actual: term.type_name(),
+            },
+        )),
+        _ => Ok(()),
+    }
+}
+
 impl fmt::Display for Term {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Term::Variable(i) => write!(f, "${}", i),
             Term::Integer(i) => write!(f, "{}", i),
-            Term::Str(s) => write!(f, "\"{}\"", s),
+            Term::Str(s) => write!(f, "\"{}\"", escape(s)),
             Term::Date(d) => {
                 let date = time::OffsetDateTime::from_unix_timestamp(*d as i64)
                     .ok()
@@ -534,6 +747,17 @@ impl fmt::Display for Term {
             Term::Parameter(s) => {
                 write!(f, "{{{}}}", s)
             }
+            Term::Array(a) => {
+                let terms = a.iter().map(|term| term.to_string()).collect::<Vec<_>>();
+                write!(f, "array[{}]", terms.join(", "))
+            }
+            Term::Map(m) => {
+                let entries = m
+                    .iter()
+                    .map(|(key, term)| format!("\"{}\": {}", escape(key), term))
+                    .collect::<Vec<_>>();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
         }
     }
 }
@@ -549,6 +773,39 @@ pub enum Scope {
     PublicKey(PublicKey),
     /// Used for parameter substitution
     Parameter(String),
+    /// Trusts the current block and any block signed by at least `threshold`
+    /// of the keys, which are `PublicKey` or `Parameter` scopes
+    Threshold(u32, Vec<Scope>),
+    /// Trusts the current block and any block signed by a key the authorizer
+    /// registered under this name, with [`Authorizer::set_key_group`](crate::Authorizer::set_key_group)
+    Group(String),
+}
+
+impl Scope {
+    /// names of the parameters used in this scope
+    pub fn parameters(&self) -> Vec<&str> {
+        match self {
+            Scope::Parameter(name) => vec![name.as_str()],
+            Scope::Threshold(_, keys) => keys.iter().flat_map(Scope::parameters).collect(),
+            _ => vec![],
+        }
+    }
+
+    fn apply_parameters(self, parameters: &HashMap<String, Option<PublicKey>>) -> Scope {
+        match self {
+            Scope::Parameter(name) => match parameters.get(&name) {
+                Some(Some(pubkey)) => Scope::PublicKey(*pubkey),
+                _ => Scope::Parameter(name),
+            },
+            Scope::Threshold(threshold, keys) => Scope::Threshold(
+                threshold,
+                keys.into_iter()
+                    .map(|key| key.apply_parameters(parameters))
+                    .collect(),
+            ),
+            scope => scope,
+        }
+    }
 }
 
 impl Convert<super::Scope> for Scope {
@@ -562,19 +819,42 @@ impl Convert<super::Scope> for Scope {
             // The error is caught in the `add_xxx` functions, so this should
             // not happen™
             Scope::Parameter(s) => panic!("Remaining parameter {}", &s),
+            Scope::Threshold(threshold, keys) => crate::token::Scope::Threshold(
+                *threshold,
+                keys.iter()
+                    .filter_map(|key| match key {
+                        Scope::PublicKey(key) => Some(symbols.public_keys.insert(key)),
+                        Scope::Parameter(s) => panic!("Remaining parameter {}", &s),
+                        // only keys can be counted towards the threshold
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            Scope::Group(name) => crate::token::Scope::Group(symbols.insert(name)),
         }
     }
 
     fn convert_from(scope: &super::Scope, symbols: &SymbolTable) -> Result<Self, error::Format> {
+        let key = |key_id: u64| {
+            symbols
+                .public_keys
+                .get_key(key_id)
+                .copied()
+                .ok_or(error::Format::UnknownExternalKey)
+        };
+
         Ok(match scope {
             super::Scope::Authority => Scope::Authority,
             super::Scope::Previous => Scope::Previous,
-            super::Scope::PublicKey(key_id) => Scope::PublicKey(
-                *symbols
-                    .public_keys
-                    .get_key(*key_id)
-                    .ok_or(error::Format::UnknownExternalKey)?,
+            super::Scope::PublicKey(key_id) => Scope::PublicKey(key(*key_id)?),
+            super::Scope::Threshold(threshold, key_ids) => Scope::Threshold(
+                *threshold,
+                key_ids
+                    .iter()
+                    .map(|key_id| key(*key_id).map(Scope::PublicKey))
+                    .collect::<Result<_, _>>()?,
             ),
+            super::Scope::Group(name) => Scope::Group(symbols.print_symbol(*name)?),
         })
     }
 }
@@ -588,6 +868,17 @@ impl fmt::Display for Scope {
             Scope::Parameter(s) => {
                 write!(f, "{{{}}}", s)
             }
+            Scope::Threshold(threshold, keys) => {
+                write!(f, "{} of {{", threshold)?;
+                for (i, key) in keys.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", key)?;
+                }
+                write!(f, "}}")
+            }
+            Scope::Group(name) => write!(f, "group(\"{}\")", escape(name)),
         }
     }
 }
@@ -601,6 +892,10 @@ impl From<biscuit_parser::builder::Scope> for Scope {
                 Scope::PublicKey(PublicKey::from_bytes(&pk).expect("invalid public key"))
             }
             biscuit_parser::builder::Scope::Parameter(s) => Scope::Parameter(s),
+            biscuit_parser::builder::Scope::Threshold(threshold, keys) => {
+                Scope::Threshold(threshold, keys.into_iter().map(Scope::from).collect())
+            }
+            biscuit_parser::builder::Scope::Group(name) => Scope::Group(name),
         }
     }
 }
@@ -678,10 +973,22 @@ impl From<biscuit_parser::builder::Predicate> for Predicate {
 }
 
 /// Builder for a Datalog fact
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Eq)]
 pub struct Fact {
     pub predicate: Predicate,
     pub parameters: Option<HashMap<String, Option<Term>>>,
+    pub parameter_types: HashMap<String, ParameterType>,
+    /// location of the fact in the source text it was parsed from
+    pub span: Option<Span>,
+}
+
+// the span is not part of the fact's identity
+impl PartialEq for Fact {
+    fn eq(&self, other: &Self) -> bool {
+        self.predicate == other.predicate
+            && self.parameters == other.parameters
+            && self.parameter_types == other.parameter_types
+    }
 }
 
 impl Fact {
@@ -697,6 +1004,8 @@ impl Fact {
         Fact {
             predicate: Predicate::new(name, terms),
             parameters: Some(parameters),
+            parameter_types: HashMap::new(),
+            span: None,
         }
     }
 
@@ -743,7 +1052,9 @@ impl Fact {
                     },
                 )),
                 Some(v) => {
-                    *v = Some(term.into());
+                    let term = term.into();
+                    check_parameter_type(&self.parameter_types, name, &term)?;
+                    *v = Some(term);
                     Ok(())
                 }
             }
@@ -764,7 +1075,9 @@ impl Fact {
             match parameters.get_mut(name) {
                 None => Ok(()),
                 Some(v) => {
-                    *v = Some(term.into());
+                    let term = term.into();
+                    check_parameter_type(&self.parameter_types, name, &term)?;
+                    *v = Some(term);
                     Ok(())
                 }
             }
@@ -790,7 +1103,7 @@ impl Fact {
         }
     }
 
-    fn apply_parameters(&mut self) {
+    pub(crate) fn apply_parameters(&mut self) {
         if let Some(parameters) = self.parameters.clone() {
             self.predicate.terms = self
                 .predicate
@@ -823,6 +1136,8 @@ impl Convert<datalog::Fact> for Fact {
         Ok(Fact {
             predicate: Predicate::convert_from(&f.predicate, symbols)?,
             parameters: None,
+            parameter_types: HashMap::new(),
+            span: None,
         })
     }
 }
@@ -846,6 +1161,46 @@ impl From<biscuit_parser::builder::Fact> for Fact {
                     .map(|(k, v)| (k, v.map(|term| term.into())))
                     .collect()
             }),
+            parameter_types: f.parameter_types,
+            span: f.span,
+        }
+    }
+}
+
+/// Builder for a predicate schema, as in `schema right(string, string)`
+#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+pub struct PredicateSchema {
+    pub name: String,
+    pub types: Vec<ParameterType>,
+}
+
+impl PredicateSchema {
+    pub fn new<T: Into<Vec<ParameterType>>>(name: String, types: T) -> PredicateSchema {
+        PredicateSchema {
+            name,
+            types: types.into(),
+        }
+    }
+}
+
+impl fmt::Display for PredicateSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "schema {}(", self.name)?;
+        for (i, t) in self.types.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", t)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl From<biscuit_parser::builder::PredicateSchema> for PredicateSchema {
+    fn from(s: biscuit_parser::builder::PredicateSchema) -> Self {
+        PredicateSchema {
+            name: s.name,
+            types: s.types,
         }
     }
 }
@@ -968,19 +1323,36 @@ impl From<biscuit_parser::builder::Binary> for Binary {
             biscuit_parser::builder::Binary::BitwiseOr => Binary::BitwiseOr,
             biscuit_parser::builder::Binary::BitwiseXor => Binary::BitwiseXor,
             biscuit_parser::builder::Binary::NotEqual => Binary::NotEqual,
+            biscuit_parser::builder::Binary::Get => Binary::Get,
         }
     }
 }
 
 /// Builder for a Datalog rule
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Eq)]
 pub struct Rule {
     pub head: Predicate,
     pub body: Vec<Predicate>,
     pub expressions: Vec<Expression>,
     pub parameters: Option<HashMap<String, Option<Term>>>,
+    pub parameter_types: HashMap<String, ParameterType>,
     pub scopes: Vec<Scope>,
     pub scope_parameters: Option<HashMap<String, Option<PublicKey>>>,
+    /// location of the rule (or check query) in the source text it was parsed from
+    pub span: Option<Span>,
+}
+
+// the span is not part of the rule's identity
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        self.head == other.head
+            && self.body == other.body
+            && self.expressions == other.expressions
+            && self.parameters == other.parameters
+            && self.parameter_types == other.parameter_types
+            && self.scopes == other.scopes
+            && self.scope_parameters == other.scope_parameters
+    }
 }
 
 impl Rule {
@@ -1015,7 +1387,7 @@ impl Rule {
         }
 
         for scope in &scopes {
-            if let Scope::Parameter(name) = &scope {
+            for name in scope.parameters() {
                 scope_parameters.insert(name.to_string(), None);
             }
         }
@@ -1025,8 +1397,10 @@ impl Rule {
             body,
             expressions,
             parameters: Some(parameters),
+            parameter_types: HashMap::new(),
             scopes,
             scope_parameters: Some(scope_parameters),
+            span: None,
         }
     }
 
@@ -1126,7 +1500,9 @@ impl Rule {
                     },
                 )),
                 Some(v) => {
-                    *v = Some(term.into());
+                    let term = term.into();
+                    check_parameter_type(&self.parameter_types, name, &term)?;
+                    *v = Some(term);
                     Ok(())
                 }
             }
@@ -1147,7 +1523,9 @@ impl Rule {
             match parameters.get_mut(name) {
                 None => Ok(()),
                 Some(v) => {
-                    *v = Some(term.into());
+                    let term = term.into();
+                    check_parameter_type(&self.parameter_types, name, &term)?;
+                    *v = Some(term);
                     Ok(())
                 }
             }
@@ -1219,7 +1597,7 @@ impl Rule {
         }
     }
 
-    fn apply_parameters(&mut self) {
+    pub(crate) fn apply_parameters(&mut self) {
         if let Some(parameters) = self.parameters.clone() {
             self.head.terms = self
                 .head
@@ -1270,14 +1648,7 @@ impl Rule {
             self.scopes = self
                 .scopes
                 .drain(..)
-                .map(|scope| {
-                    if let Scope::Parameter(name) = &scope {
-                        if let Some(Some(pubkey)) = parameters.get(name) {
-                            return Scope::PublicKey(*pubkey);
-                        }
-                    }
-                    scope
-                })
+                .map(|scope| scope.apply_parameters(&parameters))
                 .collect();
         }
     }
@@ -1302,16 +1673,7 @@ impl Convert<datalog::Rule> for Rule {
         }
 
         for scope in r.scopes.iter() {
-            scopes.push(match scope {
-                Scope::Authority => crate::token::Scope::Authority,
-                Scope::Previous => crate::token::Scope::Previous,
-                Scope::PublicKey(key) => {
-                    crate::token::Scope::PublicKey(symbols.public_keys.insert(key))
-                }
-                // The error is caught in the `add_xxx` functions, so this should
-                // not happen™
-                Scope::Parameter(s) => panic!("Remaining parameter {}", &s),
-            })
+            scopes.push(scope.convert(symbols));
         }
         datalog::Rule {
             head,
@@ -1335,12 +1697,14 @@ impl Convert<datalog::Rule> for Rule {
                 .map(|c| Expression::convert_from(c, symbols))
                 .collect::<Result<Vec<_>, error::Format>>()?,
             parameters: None,
+            parameter_types: HashMap::new(),
             scopes: r
                 .scopes
                 .iter()
                 .map(|scope| Scope::convert_from(scope, symbols))
                 .collect::<Result<Vec<Scope>, error::Format>>()?,
             scope_parameters: None,
+            span: None,
         })
     }
 }
@@ -1406,6 +1770,7 @@ impl From<biscuit_parser::builder::Rule> for Rule {
                     .map(|(k, v)| (k, v.map(|term| term.into())))
                     .collect()
             }),
+            parameter_types: r.parameter_types,
             scopes: r.scopes.into_iter().map(|s| s.into()).collect(),
             scope_parameters: r.scope_parameters.map(|h| {
                 h.into_iter()
@@ -1419,6 +1784,7 @@ impl From<biscuit_parser::builder::Rule> for Rule {
                     })
                     .collect()
             }),
+            span: r.span,
         }
     }
 }
@@ -1430,6 +1796,13 @@ pub struct Check {
     pub kind: CheckKind,
 }
 
+fn queries_span(queries: &[Rule]) -> Option<Span> {
+    queries
+        .iter()
+        .filter_map(|query| query.span)
+        .reduce(|acc, span| acc.merge(&span))
+}
+
 /// Builder for a Biscuit check
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CheckKind {
@@ -1438,6 +1811,11 @@ pub enum CheckKind {
 }
 
 impl Check {
+    /// location of the check in the source text it was parsed from
+    pub fn span(&self) -> Option<Span> {
+        queries_span(&self.queries)
+    }
+
     /// replace a parameter with the term argument
     pub fn set<T: Into<Term>>(&mut self, name: &str, term: T) -> Result<(), error::Token> {
         let term = term.into();
@@ -1447,8 +1825,12 @@ impl Check {
     fn set_inner(&mut self, name: &str, term: Term) -> Result<(), error::Token> {
         let mut found = false;
         for query in &mut self.queries {
-            if query.set(name, term.clone()).is_ok() {
-                found = true;
+            match query.set(name, term.clone()) {
+                Ok(()) => found = true,
+                Err(error::Token::Language(biscuit_parser::error::LanguageError::Parameters {
+                    ..
+                })) => {}
+                Err(e) => return Err(e),
             }
         }
 
@@ -1631,6 +2013,11 @@ pub struct Policy {
 }
 
 impl Policy {
+    /// location of the policy in the source text it was parsed from
+    pub fn span(&self) -> Option<Span> {
+        queries_span(&self.queries)
+    }
+
     /// replace a parameter with the term argument
     pub fn set<T: Into<Term>>(&mut self, name: &str, term: T) -> Result<(), error::Token> {
         let term = term.into();
@@ -1640,8 +2027,12 @@ impl Policy {
     pub fn set_inner(&mut self, name: &str, term: Term) -> Result<(), error::Token> {
         let mut found = false;
         for query in &mut self.queries {
-            if query.set(name, term.clone()).is_ok() {
-                found = true;
+            match query.set(name, term.clone()) {
+                Ok(()) => found = true,
+                Err(error::Token::Language(biscuit_parser::error::LanguageError::Parameters {
+                    ..
+                })) => {}
+                Err(e) => return Err(e),
             }
         }
 
@@ -1857,6 +2248,16 @@ pub fn set(s: BTreeSet<Term>) -> Term {
     Term::Set(s)
 }
 
+/// creates an array
+pub fn array(a: Vec<Term>) -> Term {
+    Term::Array(a)
+}
+
+/// creates a map with string keys
+pub fn map(m: BTreeMap<String, Term>) -> Term {
+    Term::Map(m)
+}
+
 /// creates a parameter
 pub fn parameter(p: &str) -> Term {
     Term::Parameter(p.to_string())
@@ -1873,6 +2274,48 @@ pub trait ToAnyParam {
     fn to_any_param(&self) -> AnyParam;
 }
 
+/// marker types for parameter type annotations, used by the macros to check
+/// parameter values at compile time
+#[cfg(feature = "datalog-macro")]
+#[doc(hidden)]
+pub mod parameter_type {
+    use std::marker::PhantomData;
+
+    pub struct Integer;
+    pub struct String;
+    pub struct Date;
+    pub struct Bytes;
+    pub struct Bool;
+    pub struct Set<T>(PhantomData<T>);
+}
+
+/// implemented by the macro parameter values that match the type annotation `P`
+#[cfg(feature = "datalog-macro")]
+#[doc(hidden)]
+pub trait HasParameterType<P> {}
+
+#[cfg(feature = "datalog-macro")]
+#[doc(hidden)]
+pub fn assert_parameter_type<P, T: HasParameterType<P> + ?Sized>(_value: &T) {}
+
+#[cfg(feature = "datalog-macro")]
+mod has_parameter_type {
+    use super::{parameter_type, HasParameterType, Term};
+    use std::{collections::BTreeSet, time::SystemTime};
+
+    impl HasParameterType<parameter_type::Integer> for i64 {}
+    impl HasParameterType<parameter_type::String> for String {}
+    impl HasParameterType<parameter_type::String> for &str {}
+    impl HasParameterType<parameter_type::Date> for SystemTime {}
+    impl HasParameterType<parameter_type::Bytes> for Vec<u8> {}
+    impl HasParameterType<parameter_type::Bytes> for [u8] {}
+    #[cfg(feature = "uuid")]
+    impl HasParameterType<parameter_type::Bytes> for uuid::Uuid {}
+    impl HasParameterType<parameter_type::Bool> for bool {}
+    // set elements are checked when the parameter is set
+    impl<T> HasParameterType<parameter_type::Set<T>> for BTreeSet<Term> {}
+}
+
 impl From<i64> for Term {
     fn from(i: i64) -> Self {
         Term::Integer(i)
@@ -2063,6 +2506,61 @@ impl<T: Ord + TryFrom<Term, Error = error::Token>> TryFrom<Term> for BTreeSet<T>
     }
 }
 
+impl From<Vec<Term>> for Term {
+    fn from(value: Vec<Term>) -> Term {
+        array(value)
+    }
+}
+
+#[cfg(feature = "datalog-macro")]
+impl ToAnyParam for Vec<Term> {
+    fn to_any_param(&self) -> AnyParam {
+        AnyParam::Term((self.clone()).into())
+    }
+}
+
+impl<T: TryFrom<Term, Error = error::Token>> TryFrom<Term> for Vec<T> {
+    type Error = error::Token;
+    fn try_from(value: Term) -> Result<Self, Self::Error> {
+        match value {
+            Term::Array(a) => a.into_iter().map(TryFrom::try_from).collect(),
+            _ => Err(error::Token::ConversionError(format!(
+                "expected array, got {:?}",
+                value
+            ))),
+        }
+    }
+}
+
+impl From<BTreeMap<String, Term>> for Term {
+    fn from(value: BTreeMap<String, Term>) -> Term {
+        map(value)
+    }
+}
+
+#[cfg(feature = "datalog-macro")]
+impl ToAnyParam for BTreeMap<String, Term> {
+    fn to_any_param(&self) -> AnyParam {
+        AnyParam::Term((self.clone()).into())
+    }
+}
+
+impl<T: TryFrom<Term, Error = error::Token>> TryFrom<Term> for BTreeMap<String, T> {
+    type Error = error::Token;
+    fn try_from(value: Term) -> Result<Self, Self::Error> {
+        match value {
+            Term::Map(m) => m
+                .into_iter()
+                .map(|(key, value)| Ok((key, T::try_from(value)?)))
+                .collect(),
+            _ => Err(error::Token::ConversionError(format!(
+                "expected map, got {:?}",
+                value
+            ))),
+        }
+    }
+}
+
 macro_rules! tuple_try_from(
     ($ty1:ident, $ty2:ident, $($ty:ident),*) => (
         tuple_try_from!(__impl $ty1, $ty2; $($ty),*);
@@ -2140,6 +2638,28 @@ impl TryFrom<&str> for Rule {
     }
 }
 
+impl TryFrom<&str> for PredicateSchema {
+    type Error = error::Token;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Ok(biscuit_parser::parser::schema(value)
+            .finish()
+            .map(|(_, o)| o.into())
+            .map_err(biscuit_parser::error::LanguageError::from)?)
+    }
+}
+
+impl FromStr for PredicateSchema {
+    type Err = error::Token;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(biscuit_parser::parser::schema(s)
+            .finish()
+            .map(|(_, o)| o.into())
+            .map_err(biscuit_parser::error::LanguageError::from)?)
+    }
+}
+
 impl FromStr for Fact {
     type Err = error::Token;
 
@@ -2405,6 +2925,97 @@ check if true trusting ed25519/6e9e6d5a75cf0c0e87ec1256b4dfed0ca3ba452912d213fcc
         );
     }
 
+    #[test]
+    fn composite_conversions() {
+        let term: Term = vec![int(1), int(2)].into();
+        assert_eq!(term, array(vec![int(1), int(2)]));
+        assert_eq!(Vec::<i64>::try_from(term.clone()), Ok(vec![1, 2]));
+        assert!(Vec::<String>::try_from(term.clone()).is_err());
+        assert!(BTreeMap::<String, i64>::try_from(term).is_err());
+
+        // byte arrays are still converted from and to bytes terms
+        assert_eq!(Vec::<u8>::try_from(Term::Bytes(vec![1])), Ok(vec![1]));
+        assert!(Vec::<u8>::try_from(array(vec![int(1)])).is_err());
+
+        let mut claims = BTreeMap::new();
+        claims.insert("sub".to_string(), string("alice"));
+        claims.insert("roles".to_string(), vec![string("admin")].into());
+        let term: Term = claims.clone().into();
+        assert_eq!(term, map(claims));
+
+        // all the values must have the requested type
+        assert!(BTreeMap::<String, String>::try_from(term).is_err());
+
+        let mut scopes: BTreeMap<String, Term> = BTreeMap::new();
+        scopes.insert("read".to_string(), vec![string("file1")].into());
+        let fact = fact("claims", &[map(scopes)]);
+        assert_eq!(fact.to_string(), r#"claims({"read": array["file1"]})"#);
+        let (scopes,): (BTreeMap<String, Vec<String>>,) = fact.try_into().unwrap();
+        assert_eq!(scopes["read"], vec!["file1".to_string()]);
+    }
+
+    #[test]
+    fn typed_parameters() {
+        let mut rule = Rule::try_from(
+            "valid($id) <- token($id, {ids: set<int>}), time($t), $t < {expiry: date}",
+        )
+        .unwrap();
+
+        let res = rule.set("expiry", "2030-01-01T00:00:00Z");
+        assert_eq!(
+            res,
+            Err(error::Token::Language(
+                biscuit_parser::error::LanguageError::ParameterType {
+                    name: "expiry".to_string(),
+                    expected: "date".to_string(),
+                    actual: "string".to_string(),
+                }
+            ))
+        );
+        rule.set("expiry", Term::Date(1893456000)).unwrap();
+
+        let mut ids = BTreeSet::new();
+        ids.insert(string("a"));
+        let res = rule.set("ids", ids);
+        assert_eq!(
+            res,
+            Err(error::Token::Language(
+                biscuit_parser::error::LanguageError::ParameterType {
+                    name: "ids".to_string(),
+                    expected: "set<int>".to_string(),
+                    actual: "set<string>".to_string(),
+                }
+            ))
+        );
+        let mut ids = BTreeSet::new();
+        ids.insert(int(1));
+        rule.set("ids", ids).unwrap();
+
+        let mut check = Check::try_from("check if a({p: int}) or b({p})").unwrap();
+        assert!(matches!(
+            check.set("p", true),
+            Err(error::Token::Language(
+                biscuit_parser::error::LanguageError::ParameterType { .. }
+            ))
+        ));
+        check.set("p", 1i64).unwrap();
+
+        let mut builder = BlockBuilder::new();
+        let mut params = HashMap::new();
+        params.insert("expiry".to_string(), "tomorrow".into());
+        let res = builder.add_code_with_params(
+            "check if time($t), $t < {expiry: date}",
+            params,
+            HashMap::new(),
+        );
+        assert!(matches!(
+            res,
+            Err(error::Token::Language(
+                biscuit_parser::error::LanguageError::ParameterType { .. }
+            ))
+        ));
+    }
+
     #[test]
     fn forbid_unbound_parameters() {
         let mut builder = BlockBuilder::new();
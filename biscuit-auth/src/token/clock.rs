@@ -0,0 +1,158 @@
+//! sources of the current time
+//!
+//! the authorizer and the root key expiry logic read the time from a [`Clock`],
+//! so that tests and replays of past decisions can use a fixed or shifted time
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// provides the current time
+///
+/// this is implemented by closures returning a `SystemTime`:
+///
+/// ```rust
+/// # use biscuit_auth::{Authorizer, Clock};
+/// # use std::time::{Duration, SystemTime};
+/// let mut authorizer = Authorizer::new();
+/// authorizer.set_clock(|| SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+/// authorizer.set_time();
+///
+/// assert_eq!(
+///     authorizer.time(),
+///     Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+/// );
+/// ```
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> SystemTime + Send + Sync,
+{
+    fn now(&self) -> SystemTime {
+        self()
+    }
+}
+
+/// the system clock, used by default
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// a clock that always returns the same time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FixedClock(pub SystemTime);
+
+impl FixedClock {
+    /// a clock fixed at a UNIX timestamp, in seconds
+    pub fn from_timestamp(seconds: u64) -> Self {
+        FixedClock(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.0
+    }
+}
+
+/// a clock shifted from another clock by a fixed duration
+///
+/// this is useful to check how a token will be authorized in the future,
+/// or to tolerate clock skew
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OffsetClock<C> {
+    clock: C,
+    offset: Duration,
+    ahead: bool,
+}
+
+impl<C: Clock> OffsetClock<C> {
+    /// returns the time of `clock`, plus `offset`, saturating at the latest
+    /// representable time
+    pub fn ahead(clock: C, offset: Duration) -> Self {
+        OffsetClock {
+            clock,
+            offset,
+            ahead: true,
+        }
+    }
+
+    /// returns the time of `clock`, minus `offset`, saturating at the unix epoch
+    pub fn behind(clock: C, offset: Duration) -> Self {
+        OffsetClock {
+            clock,
+            offset,
+            ahead: false,
+        }
+    }
+}
+
+impl<C: Clock> Clock for OffsetClock<C> {
+    fn now(&self) -> SystemTime {
+        let now = self.clock.now();
+        if self.ahead {
+            now.checked_add(self.offset)
+                .unwrap_or_else(|| saturating_add(now, self.offset))
+        } else {
+            // dates cannot go before the epoch
+            now.checked_sub(self.offset)
+                .filter(|time| *time >= UNIX_EPOCH)
+                .unwrap_or(UNIX_EPOCH)
+        }
+    }
+}
+
+/// adds as much of `offset` as `time` can hold
+///
+/// the range of `SystemTime` depends on the platform, so the latest
+/// representable time is found by bisecting the offset
+fn saturating_add(mut time: SystemTime, mut offset: Duration) -> SystemTime {
+    while !offset.is_zero() {
+        match time.checked_add(offset) {
+            Some(t) => time = t,
+            None => offset /= 2,
+        }
+    }
+    time
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clocks() {
+        let fixed = FixedClock::from_timestamp(1_000);
+        assert_eq!(
+            fixed.now(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_000)
+        );
+
+        let ahead = OffsetClock::ahead(fixed, Duration::from_secs(10));
+        assert_eq!(ahead.now(), FixedClock::from_timestamp(1_010).now());
+        let behind = OffsetClock::behind(fixed, Duration::from_secs(10));
+        assert_eq!(behind.now(), FixedClock::from_timestamp(990).now());
+
+        let saturated = OffsetClock::ahead(fixed, Duration::MAX).now();
+        assert!(saturated > FixedClock::from_timestamp(u32::MAX as u64).now());
+        assert_eq!(saturated.checked_add(Duration::from_nanos(1)), None);
+        assert_eq!(
+            OffsetClock::ahead(OffsetClock::ahead(fixed, Duration::MAX), Duration::MAX).now(),
+            saturated
+        );
+
+        let behind = OffsetClock::behind(fixed, Duration::from_secs(1_001));
+        assert_eq!(behind.now(), SystemTime::UNIX_EPOCH);
+        let behind = OffsetClock::behind(SystemClock, Duration::MAX);
+        assert_eq!(behind.now(), SystemTime::UNIX_EPOCH);
+
+        let closure = || SystemTime::UNIX_EPOCH;
+        assert_eq!(closure.now(), SystemTime::UNIX_EPOCH);
+        assert!(SystemClock.now() > SystemTime::UNIX_EPOCH);
+    }
+}
@@ -0,0 +1,432 @@
+//! third party block signing service
+use std::{convert::TryInto, fmt, sync::Arc, time::SystemTime};
+
+use super::{Biscuit, RootKeyProvider, ThirdPartyBlock, ThirdPartyRequest};
+use crate::{
+    builder::{BlockBuilder, Fact, Rule},
+    error, Authorizer, KeyPair, PublicKey,
+};
+
+/// Signs third party blocks for tokens it trusts
+///
+/// Attestation services (MFA, group membership...) receive a token and a
+/// [`ThirdPartyRequest`] created from it. The issuer:
+/// * verifies the token with its trusted root keys, and checks that the request was created from it
+/// * runs an issuer-side [`Authorizer`], provided by the service for each request, optionally
+///   loaded with the token's content. If authorization fails, nothing is signed
+/// * generates the block's facts from the attestation rules, queried on that authorizer
+/// * reports every signed block to the audit function
+///
+/// ```rust
+/// # use biscuit_auth::{Authorizer, Biscuit, KeyPair, ThirdPartyIssuer};
+/// let root = KeyPair::new();
+/// let mut builder = Biscuit::builder();
+/// builder.add_fact("user(\"alice\")").unwrap();
+/// let token = builder.build(&root).unwrap();
+///
+/// // attestation service
+/// let mut issuer = ThirdPartyIssuer::new(KeyPair::new(), root.public());
+/// issuer.set_inspect_token(true);
+/// issuer
+///     .add_attestation("group($user, $group) <- user($user), member($user, $group)")
+///     .unwrap();
+/// issuer.set_audit(|record| println!("signed {:?}", record.facts));
+///
+/// let mut authorizer = Authorizer::new();
+/// authorizer.add_fact("member(\"alice\", \"admin\")").unwrap();
+/// authorizer.add_policy("allow if user($user)").unwrap();
+///
+/// let request = token.third_party_request().unwrap().serialize().unwrap();
+/// let block = issuer
+///     .issue(&request, token.to_vec().unwrap(), authorizer)
+///     .unwrap();
+///
+/// let token = token.append_third_party(issuer.public_key(), block).unwrap();
+/// assert_eq!(token.block_count(), 2);
+/// ```
+pub struct ThirdPartyIssuer {
+    keypair: KeyPair,
+    root: Arc<dyn RootKeyProvider + Send + Sync>,
+    attestations: Vec<Rule>,
+    inspect_token: bool,
+    audit: Option<Box<AuditFn>>,
+}
+
+type AuditFn = dyn Fn(&AuditRecord) + Send + Sync;
+
+/// Description of a block signed by a [`ThirdPartyIssuer`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditRecord {
+    /// time of signature, according to the authorizer's clock
+    pub time: SystemTime,
+    /// public key of the issuer
+    pub external_key: PublicKey,
+    /// key of the token's last block, to which the signature is bound
+    pub previous_key: PublicKey,
+    pub root_key_id: Option<u32>,
+    /// revocation ids of the token the block was signed for
+    pub revocation_ids: Vec<Vec<u8>>,
+    /// index of the issuer-side policy that matched
+    pub policy: usize,
+    /// facts of the signed block
+    pub facts: Vec<Fact>,
+}
+
+impl ThirdPartyIssuer {
+    /// creates an issuer signing with `keypair`, for tokens verified with `root`
+    pub fn new<KP>(keypair: KeyPair, root: KP) -> Self
+    where
+        KP: RootKeyProvider + Send + Sync + 'static,
+    {
+        ThirdPartyIssuer {
+            keypair,
+            root: Arc::new(root),
+            attestations: Vec::new(),
+            inspect_token: false,
+            audit: None,
+        }
+    }
+
+    /// the public key verifying the signed blocks
+    pub fn public_key(&self) -> PublicKey {
+        self.keypair.public()
+    }
+
+    /// adds a rule generating facts for the signed blocks
+    ///
+    /// the rule is queried on the issuer-side authorizer, and
+    /// each resulting fact is added to the block
+    pub fn add_attestation<R: TryInto<Rule>>(&mut self, rule: R) -> Result<(), error::Token>
+    where
+        error::Token: From<<R as TryInto<Rule>>::Error>,
+    {
+        let rule = rule.try_into()?;
+        rule.validate_parameters()?;
+        self.attestations.push(rule);
+        Ok(())
+    }
+
+    /// loads the token's facts, rules and checks in the issuer-side authorizer
+    ///
+    /// by default, the token is only verified, and the authorizer
+    /// only contains the data provided by the service
+    pub fn set_inspect_token(&mut self, inspect_token: bool) {
+        self.inspect_token = inspect_token;
+    }
+
+    /// sets a function called with a description of each signed block
+    pub fn set_audit<F>(&mut self, audit: F)
+    where
+        F: Fn(&AuditRecord) + Send + Sync + 'static,
+    {
+        self.audit = Some(Box::new(audit));
+    }
+
+    /// signs a third party block for a serialized request and the token it was created from
+    ///
+    /// `authorizer` contains the service's policies and the context of the request.
+    /// Context facts carried by the request are ignored, use
+    /// [`ThirdPartyIssuer::issue_attached`] to check them
+    pub fn issue<R, T>(
+        &self,
+        request: R,
+        token: T,
+        authorizer: Authorizer,
+    ) -> Result<ThirdPartyBlock, error::Token>
+    where
+        R: AsRef<[u8]>,
+        T: AsRef<[u8]>,
+    {
+        let mut request = ThirdPartyRequest::deserialize(request.as_ref())?;
+        request.attach_token(token);
+        let token = request
+            .verify_token(self.root.clone())?
+            .ok_or(error::Format::ThirdPartyRequestMismatch)?;
+
+        self.sign(request, token, authorizer)
+    }
+
+    /// signs a third party block for a serialized request carrying its token
+    ///
+    /// the request's context facts are claims of the token holder, so they are not
+    /// trusted: only the facts accepted by `accept_context` are added to `authorizer`.
+    /// It should only accept facts that the service checked, or that cannot be
+    /// mistaken for its own data, like a `requested_group` predicate that its
+    /// policies refuse or allow
+    pub fn issue_attached<R, F>(
+        &self,
+        request: R,
+        mut authorizer: Authorizer,
+        mut accept_context: F,
+    ) -> Result<ThirdPartyBlock, error::Token>
+    where
+        R: AsRef<[u8]>,
+        F: FnMut(&Fact) -> bool,
+    {
+        let request = ThirdPartyRequest::deserialize(request.as_ref())?;
+        let token = request
+            .verify_token(self.root.clone())?
+            .ok_or(error::Format::ThirdPartyRequestMismatch)?;
+
+        for fact in request.context_facts() {
+            if accept_context(fact) {
+                authorizer.add_fact(fact.clone())?;
+            }
+        }
+
+        self.sign(request, token, authorizer)
+    }
+
+    fn sign(
+        &self,
+        request: ThirdPartyRequest,
+        token: Biscuit,
+        mut authorizer: Authorizer,
+    ) -> Result<ThirdPartyBlock, error::Token> {
+        // the block is built from the token's request, so that the public
+        // keys table cannot be altered by the requester
+        let token_request = token.third_party_request()?;
+
+        if self.inspect_token {
+            authorizer.add_token(&token)?;
+        }
+        let policy = authorizer.authorize()?;
+
+        let mut builder = BlockBuilder::new();
+        let mut facts = Vec::new();
+        for rule in &self.attestations {
+            let generated: Vec<Fact> = authorizer.query(rule.clone())?;
+            for fact in generated {
+                if !facts.contains(&fact) {
+                    builder.add_fact(fact.clone())?;
+                    facts.push(fact);
+                }
+            }
+        }
+
+        let block = token_request.create_block(&self.keypair.private(), builder)?;
+
+        if let Some(audit) = &self.audit {
+            audit(&AuditRecord {
+                time: authorizer.clock().now(),
+                external_key: self.keypair.public(),
+                previous_key: request.previous_key,
+                root_key_id: token.root_key_id(),
+                revocation_ids: token.revocation_identifiers(),
+                policy,
+                facts,
+            });
+        }
+
+        Ok(block)
+    }
+}
+
+impl fmt::Debug for ThirdPartyIssuer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThirdPartyIssuer")
+            .field("public_key", &self.keypair.public())
+            .field("attestations", &self.attestations)
+            .field("inspect_token", &self.inspect_token)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::FixedClock;
+
+    #[test]
+    fn issue() {
+        let root = KeyPair::new();
+        let mut builder = Biscuit::builder();
+        builder.add_fact("user(\"alice\")").unwrap();
+        let token = builder.build(&root).unwrap();
+        let request = token.third_party_request().unwrap().serialize().unwrap();
+
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let mut issuer = ThirdPartyIssuer::new(KeyPair::new(), root.public());
+        issuer.set_inspect_token(true);
+        issuer
+            .add_attestation("group($user, $group) <- user($user), member($user, $group)")
+            .unwrap();
+        issuer
+            .add_attestation("mfa($user) <- user($user), mfa_verified(true)")
+            .unwrap();
+        let audit = records.clone();
+        issuer.set_audit(move |record| audit.lock().unwrap().push(record.clone()));
+
+        let context = |mfa: bool| {
+            let mut authorizer = Authorizer::new();
+            authorizer.set_clock(FixedClock::from_timestamp(1_700_000_000));
+            authorizer.add_fact("member(\"alice\", \"admin\")").unwrap();
+            authorizer.add_fact("member(\"bob\", \"admin\")").unwrap();
+            authorizer
+                .add_fact(crate::builder::fact(
+                    "mfa_verified",
+                    &[crate::builder::boolean(mfa)],
+                ))
+                .unwrap();
+            authorizer.add_policy("allow if user($user)").unwrap();
+            authorizer
+        };
+
+        let block = issuer
+            .issue(&request, token.to_vec().unwrap(), context(true))
+            .unwrap();
+        let attested = token
+            .append_third_party(issuer.public_key(), block)
+            .unwrap();
+        assert_eq!(
+            attested.print_block_source(1).unwrap(),
+            "group(\"alice\", \"admin\");\nmfa(\"alice\");\n"
+        );
+
+        {
+            let records = records.lock().unwrap();
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].time, FixedClock::from_timestamp(1_700_000_000).0);
+            assert_eq!(records[0].external_key, issuer.public_key());
+            assert_eq!(records[0].revocation_ids, token.revocation_identifiers());
+            assert_eq!(records[0].policy, 0);
+            assert_eq!(records[0].facts.len(), 2);
+        }
+
+        // without MFA, only the group is attested
+        let block = issuer
+            .issue(&request, token.to_vec().unwrap(), context(false))
+            .unwrap();
+        let attested = token
+            .append_third_party(issuer.public_key(), block)
+            .unwrap();
+        assert_eq!(
+            attested.print_block_source(1).unwrap(),
+            "group(\"alice\", \"admin\");\n"
+        );
+
+        // issuer-side policies gate the signature
+        let mut denied = Authorizer::new();
+        denied.add_policy("deny if true").unwrap();
+        assert!(matches!(
+            issuer.issue(&request, token.to_vec().unwrap(), denied),
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized { .. }))
+        ));
+        assert_eq!(records.lock().unwrap().len(), 2);
+
+        // the request must come from the token
+        let other = Biscuit::builder().build(&root).unwrap();
+        let other_request = other.third_party_request().unwrap().serialize().unwrap();
+        assert_eq!(
+            issuer
+                .issue(&other_request, token.to_vec().unwrap(), context(true))
+                .unwrap_err(),
+            error::Token::Format(error::Format::ThirdPartyRequestMismatch)
+        );
+
+        // context facts are ignored by `issue`
+        let mut forged = token.third_party_request().unwrap();
+        forged
+            .add_context_fact("member(\"alice\", \"root\")")
+            .unwrap();
+        let block = issuer
+            .issue(
+                forged.serialize().unwrap(),
+                token.to_vec().unwrap(),
+                context(false),
+            )
+            .unwrap();
+        let attested = token
+            .append_third_party(issuer.public_key(), block)
+            .unwrap();
+        assert_eq!(
+            attested.print_block_source(1).unwrap(),
+            "group(\"alice\", \"admin\");\n"
+        );
+
+        // the token and context can be carried by the request
+        let attached = |facts: &[&str]| {
+            let mut request = token.third_party_request().unwrap();
+            request.attach_token(token.to_vec().unwrap());
+            for fact in facts {
+                request.add_context_fact(*fact).unwrap();
+            }
+            request.serialize().unwrap()
+        };
+        let requested_group = |fact: &Fact| fact.predicate.name == "requested_group";
+        let gated = || {
+            let mut authorizer = Authorizer::new();
+            authorizer.add_fact("member(\"alice\", \"admin\")").unwrap();
+            authorizer
+                .add_policy("deny if requested_group(\"root\")")
+                .unwrap();
+            authorizer.add_policy("allow if user($user)").unwrap();
+            authorizer
+        };
+        let block = issuer
+            .issue_attached(
+                attached(&["requested_group(\"admin\")"]),
+                gated(),
+                requested_group,
+            )
+            .unwrap();
+        let attested = token
+            .append_third_party(issuer.public_key(), block)
+            .unwrap();
+        assert_eq!(
+            attested.print_block_source(1).unwrap(),
+            "group(\"alice\", \"admin\");\n"
+        );
+        assert!(issuer
+            .issue_attached(
+                attached(&["requested_group(\"root\")"]),
+                gated(),
+                requested_group
+            )
+            .is_err());
+
+        // forged context facts are not accepted, and do not produce issued facts
+        let block = issuer
+            .issue_attached(
+                attached(&["member(\"alice\", \"root\")"]),
+                gated(),
+                requested_group,
+            )
+            .unwrap();
+        let attested = token
+            .append_third_party(issuer.public_key(), block)
+            .unwrap();
+        assert_eq!(
+            attested.print_block_source(1).unwrap(),
+            "group(\"alice\", \"admin\");\n"
+        );
+
+        assert_eq!(
+            issuer
+                .issue_attached(
+                    token.third_party_request().unwrap().serialize().unwrap(),
+                    gated(),
+                    requested_group
+                )
+                .unwrap_err(),
+            error::Token::Format(error::Format::ThirdPartyRequestMismatch)
+        );
+
+        // the token must be signed by a trusted root key
+        let untrusted = Biscuit::builder().build(&KeyPair::new()).unwrap();
+        let untrusted_request = untrusted
+            .third_party_request()
+            .unwrap()
+            .serialize()
+            .unwrap();
+        assert!(issuer
+            .issue(
+                &untrusted_request,
+                untrusted.to_vec().unwrap(),
+                context(true)
+            )
+            .is_err());
+    }
+}
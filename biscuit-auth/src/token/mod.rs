@@ -2,6 +2,7 @@
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Display;
+use std::sync::Arc;
 
 use self::public_keys::PublicKeys;
 
@@ -15,6 +16,7 @@ use rand_core::{CryptoRng, RngCore};
 
 use crate::crypto::{self};
 use crate::format::convert::proto_block_to_token_block;
+use crate::format::proto_external_signature;
 use crate::format::schema::{self, ThirdPartyBlockContents};
 use authorizer::Authorizer;
 
@@ -22,8 +24,13 @@ pub mod authorizer;
 pub(crate) mod block;
 pub mod builder;
 pub mod builder_ext;
+pub(crate) mod clock;
+pub(crate) mod issuer;
+pub(crate) mod observer;
+pub(crate) mod provider;
 pub(crate) mod public_keys;
 pub(crate) mod third_party;
+pub(crate) mod typing;
 pub mod unverified;
 
 pub use block::Block;
@@ -32,7 +39,7 @@ pub use third_party::*;
 /// minimum supported version of the serialization format
 pub const MIN_SCHEMA_VERSION: u32 = 3;
 /// maximum supported version of the serialization format
-pub const MAX_SCHEMA_VERSION: u32 = 4;
+pub const MAX_SCHEMA_VERSION: u32 = 7;
 
 /// some symbols are predefined and available in every implementation, to avoid
 /// transmitting them with every token
@@ -71,14 +78,16 @@ pub fn default_symbol_table() -> SymbolTable {
 ///   let token2 = token1.append(builder2).unwrap();
 /// }
 /// ```
+///
+/// blocks and the symbol table are shared between clones and with the tokens
+/// created by appending blocks
 #[derive(Clone, Debug)]
 pub struct Biscuit {
     pub(crate) root_key_id: Option<u32>,
-    pub(crate) authority: schema::Block,
-    pub(crate) blocks: Vec<schema::Block>,
-    pub(crate) symbols: SymbolTable,
+    pub(crate) authority: Arc<schema::Block>,
+    pub(crate) blocks: Vec<Arc<schema::Block>>,
+    pub(crate) symbols: Arc<SymbolTable>,
     pub(crate) container: SerializedBiscuit,
-    pub(crate) public_key_to_block_id: HashMap<usize, Vec<usize>>,
 }
 
 impl Biscuit {
@@ -125,6 +134,11 @@ impl Biscuit {
         Ok(self.container.serialized_size())
     }
 
+    /// returns true if the token was sealed, and cannot be attenuated anymore
+    pub fn is_sealed(&self) -> bool {
+        matches!(self.container.proof, crypto::TokenNext::Seal(_))
+    }
+
     /// creates a sealed version of the token
     ///
     /// sealed tokens cannot be attenuated
@@ -255,11 +269,10 @@ impl Biscuit {
 
         Ok(Biscuit {
             root_key_id,
-            authority,
+            authority: Arc::new(authority),
             blocks,
-            symbols,
+            symbols: Arc::new(symbols),
             container,
-            public_key_to_block_id: HashMap::new(),
         })
     }
 
@@ -282,7 +295,7 @@ impl Biscuit {
         container: SerializedBiscuit,
         mut symbols: SymbolTable,
     ) -> Result<Self, error::Token> {
-        let (authority, blocks, public_key_to_block_id) = container.extract_blocks(&mut symbols)?;
+        let (authority, blocks) = container.extract_blocks(&mut symbols)?;
 
         let root_key_id = container.root_key_id;
 
@@ -290,9 +303,8 @@ impl Biscuit {
             root_key_id,
             authority,
             blocks,
-            symbols,
+            symbols: Arc::new(symbols),
             container,
-            public_key_to_block_id,
         })
     }
 
@@ -315,6 +327,12 @@ impl Biscuit {
         &self.container
     }
 
+    /// indexes of the blocks signed by each external key of the symbol table
+    pub(crate) fn public_key_to_block_id(&self) -> HashMap<usize, Vec<usize>> {
+        self.container
+            .public_key_to_block_id(&self.symbols.public_keys)
+    }
+
     /// adds a new block to the token, using the provided CSPRNG
     ///
     /// since the public key is integrated into the token, the keypair can be
@@ -324,7 +342,7 @@ impl Biscuit {
         keypair: &KeyPair,
         block_builder: BlockBuilder,
     ) -> Result<Self, error::Token> {
-        let block = block_builder.build(self.symbols.clone());
+        let block = block_builder.build(SymbolTable::derive(&self.symbols));
 
         if !self.symbols.is_disjoint(&block.symbols) {
             return Err(error::Token::Format(error::Format::SymbolTableOverlap));
@@ -332,24 +350,12 @@ impl Biscuit {
 
         let authority = self.authority.clone();
         let mut blocks = self.blocks.clone();
-        let mut symbols = self.symbols.clone();
-        let mut public_key_to_block_id = self.public_key_to_block_id.clone();
+        let mut symbols = SymbolTable::derive(&self.symbols);
 
         let container = self.container.append(keypair, &block, None)?;
 
         symbols.extend(&block.symbols)?;
         symbols.public_keys.extend(&block.public_keys)?;
-
-        if let Some(index) = block
-            .external_key
-            .as_ref()
-            .and_then(|pk| symbols.public_keys.get(pk))
-        {
-            public_key_to_block_id
-                .entry(index as usize)
-                .or_default()
-                .push(self.block_count() + 1);
-        }
         let deser = schema::Block::decode(
             &container
                 .blocks
@@ -363,15 +369,14 @@ impl Biscuit {
                 e
             )))
         })?;
-        blocks.push(deser);
+        blocks.push(Arc::new(deser));
 
         Ok(Biscuit {
             root_key_id: self.root_key_id,
             authority,
             blocks,
-            symbols,
+            symbols: Arc::new(symbols),
             container,
-            public_key_to_block_id,
         })
     }
 
@@ -397,6 +402,7 @@ impl Biscuit {
         let ThirdPartyBlockContents {
             payload,
             external_signature,
+            co_signatures,
         } = response.0;
 
         if external_signature.public_key.algorithm != schema::public_key::Algorithm::Ed25519 as i32
@@ -419,17 +425,22 @@ impl Biscuit {
             .last()
             .unwrap_or(&self.container.authority)
             .next_key;
-        let mut to_verify = payload.clone();
-        to_verify
-            .extend(&(crate::format::schema::public_key::Algorithm::Ed25519 as i32).to_le_bytes());
-        to_verify.extend(&previous_key.to_bytes());
-
-        external_key
-            .0
-            .verify_strict(&to_verify, &signature)
-            .map_err(|s| s.to_string())
-            .map_err(error::Signature::InvalidSignature)
-            .map_err(error::Format::Signature)?;
+
+        let external_signature = crypto::ExternalSignature {
+            public_key: external_key,
+            signature,
+        };
+        let co_signatures = co_signatures
+            .iter()
+            .map(proto_external_signature)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        crypto::verify_external_signatures(
+            &payload,
+            &previous_key,
+            &external_signature,
+            &co_signatures,
+        )?;
 
         let block = schema::Block::decode(&payload[..]).map_err(|e| {
             error::Token::Format(error::Format::DeserializationError(format!(
@@ -438,44 +449,39 @@ impl Biscuit {
             )))
         })?;
 
-        let external_signature = crypto::ExternalSignature {
-            public_key: external_key,
-            signature,
-        };
-
-        let mut symbols = self.symbols.clone();
-        let mut public_key_to_block_id = self.public_key_to_block_id.clone();
+        let mut symbols = SymbolTable::derive(&self.symbols);
         let mut blocks = self.blocks.clone();
 
-        let container =
-            self.container
-                .append_serialized(&next_keypair, payload, Some(external_signature))?;
-
-        let token_block = proto_block_to_token_block(&block, Some(external_key)).unwrap();
-        for key in &token_block.public_keys.keys {
-            symbols.public_keys.insert_fallible(key)?;
+        let co_signers = co_signatures
+            .iter()
+            .map(|signature| signature.public_key)
+            .collect::<Vec<_>>();
+        let container = self.container.append_serialized(
+            &next_keypair,
+            payload,
+            Some(external_signature),
+            co_signatures,
+        )?;
+
+        // each signer of the block can be trusted with a scope. The signers are
+        // inserted before the block's public keys, as when deserializing the token
+        for key in std::iter::once(&external_key).chain(&co_signers) {
+            symbols.public_keys.insert(key);
         }
 
-        if let Some(index) = token_block
-            .external_key
-            .as_ref()
-            .and_then(|pk| symbols.public_keys.get(pk))
-        {
-            public_key_to_block_id
-                .entry(index as usize)
-                .or_default()
-                .push(self.block_count());
+        let token_block = proto_block_to_token_block(&block, Some(external_key))?;
+        for key in token_block.public_keys.iter() {
+            symbols.public_keys.insert_fallible(key)?;
         }
 
-        blocks.push(block);
+        blocks.push(Arc::new(block));
 
         Ok(Biscuit {
             root_key_id: self.root_key_id,
             authority: self.authority.clone(),
             blocks,
-            symbols,
+            symbols: Arc::new(symbols),
             container,
-            public_key_to_block_id,
         })
     }
 
@@ -529,6 +535,25 @@ impl Biscuit {
             .map(|signature| signature.public_key))
     }
 
+    /// gets the keys of the additional external signatures of a block
+    ///
+    /// third party blocks can be signed by several keys, to be trusted
+    /// by threshold scopes like `trusting 2 of {ed25519/..., ed25519/...}`
+    pub fn block_co_signers(&self, index: usize) -> Result<Vec<PublicKey>, error::Token> {
+        if index == 0 {
+            return Ok(vec![]);
+        }
+
+        match self.container.blocks.get(index - 1) {
+            None => Err(error::Token::Format(error::Format::InvalidBlockId(index))),
+            Some(block) => Ok(block
+                .co_signatures
+                .iter()
+                .map(|signature| signature.public_key)
+                .collect()),
+        }
+    }
+
     /// returns the number of blocks (at least 1)
     pub fn block_count(&self) -> usize {
         1 + self.blocks.len()
@@ -562,6 +587,14 @@ impl Biscuit {
             .map_err(error::Token::Format)?
         };
 
+        if index > 0 {
+            block.co_signers = self.container.blocks[index - 1]
+                .co_signatures
+                .iter()
+                .map(|signature| signature.public_key)
+                .collect();
+        }
+
         // we have to add the entire list of public keys here because
         // they are used to validate 3rd party tokens
         block.symbols.public_keys = self.symbols.public_keys.clone();
@@ -587,7 +620,7 @@ impl Display for Biscuit {
 
         write!(f, "Biscuit {{\n    symbols: {:?}\n    public keys: {:?}\n    authority: {}\n    blocks: [\n        {}\n    ]\n}}",
         self.symbols.strings(),
-        self.symbols.public_keys.keys.iter().map(|pk| hex::encode(pk.to_bytes())).collect::<Vec<_>>(),
+        self.symbols.public_keys.iter().map(|pk| hex::encode(pk.to_bytes())).collect::<Vec<_>>(),
         authority,
         blocks.join(",\n\t")
     )
@@ -633,7 +666,7 @@ fn print_block(symbols: &SymbolTable, block: &Block) -> String {
         block.version,
         block.context.as_deref().unwrap_or(""),
         block.external_key.as_ref().map(|k| hex::encode(k.to_bytes())).unwrap_or_else(String::new),
-        block.public_keys.keys.iter().map(|k | hex::encode(k.to_bytes())).collect::<Vec<_>>(),
+        block.public_keys.iter().map(|k | hex::encode(k.to_bytes())).collect::<Vec<_>>(),
         block.scopes,
         facts,
         rules,
@@ -647,6 +680,10 @@ pub enum Scope {
     Previous,
     // index of the public key in the token's list
     PublicKey(u64),
+    // blocks signed by at least this number of the public keys
+    Threshold(u32, Vec<u64>),
+    // symbol of a key group, resolved by the authorizer
+    Group(u64),
 }
 
 /// Chooses a root public key to verify the token
@@ -677,6 +714,12 @@ impl RootKeyProvider for std::sync::Arc<dyn RootKeyProvider> {
     }
 }
 
+impl RootKeyProvider for std::sync::Arc<dyn RootKeyProvider + Send + Sync> {
+    fn choose(&self, key_id: Option<u32>) -> Result<PublicKey, error::Format> {
+        self.as_ref().choose(key_id)
+    }
+}
+
 impl RootKeyProvider for PublicKey {
     fn choose(&self, _: Option<u32>) -> Result<PublicKey, error::Format> {
         Ok(*self)
@@ -1465,4 +1508,101 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn shared_blocks() {
+        let root = KeyPair::new();
+        let mut builder = Biscuit::builder();
+        builder.add_fact("right(\"file1\", \"read\")").unwrap();
+        let token1 = builder.build(&root).unwrap();
+
+        let mut block_builder = BlockBuilder::new();
+        block_builder
+            .add_check("check if operation(\"read\")")
+            .unwrap();
+        block_builder.add_fact("audience(\"api\")").unwrap();
+        let token2 = token1.append(block_builder).unwrap();
+
+        let mut block_builder = BlockBuilder::new();
+        block_builder
+            .add_check("check if resource(\"file1\")")
+            .unwrap();
+        let token3 = token2.append(block_builder).unwrap();
+
+        // appending keeps the previous blocks, their bytes and decoded contents
+        assert!(Arc::ptr_eq(&token1.authority, &token3.authority));
+        assert!(Arc::ptr_eq(&token2.blocks[0], &token3.blocks[0]));
+        assert!(Arc::ptr_eq(
+            &token1.container.authority,
+            &token3.container.authority
+        ));
+        assert!(Arc::ptr_eq(
+            &token2.container.blocks[0],
+            &token3.container.blocks[0]
+        ));
+
+        let clone = token3.clone();
+        assert!(Arc::ptr_eq(&clone.symbols, &token3.symbols));
+        assert!(Arc::ptr_eq(&clone.blocks[1], &token3.blocks[1]));
+
+        // the shared blocks are still serialized and verified in order
+        let serialized = clone.to_vec().unwrap();
+        assert_eq!(serialized, token3.to_vec().unwrap());
+        let token = Biscuit::from(&serialized, root.public()).unwrap();
+        // the symbols shared with the previous tokens are found as in a
+        // deserialized token
+        assert_eq!(token.symbols, token3.symbols);
+        assert_eq!(
+            token.print_block_source(2).unwrap(),
+            token3.print_block_source(2).unwrap()
+        );
+        assert_eq!(token2.block_count(), 2);
+
+        let mut authorizer = token.authorizer().unwrap();
+        authorizer
+            .add_code(r#"operation("read"); resource("file1"); allow if right("file1", "read");"#)
+            .unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+    }
+
+    #[test]
+    fn many_appends() {
+        let root = KeyPair::new();
+        let mut token = Biscuit::builder().build(&root).unwrap();
+        for i in 0..30 {
+            let mut block_builder = BlockBuilder::new();
+            block_builder
+                .add_check(format!("check if resource(\"file{}\")", i % 10).as_str())
+                .unwrap();
+            block_builder
+                .add_fact(format!("tag(\"tag{}\")", i).as_str())
+                .unwrap();
+            token = token.append(block_builder).unwrap();
+        }
+
+        let serialized = token.to_vec().unwrap();
+        let deserialized = Biscuit::from(&serialized, root.public()).unwrap();
+        assert_eq!(deserialized.symbols, token.symbols);
+        assert_eq!(
+            deserialized.print_block_source(30).unwrap(),
+            token.print_block_source(30).unwrap()
+        );
+
+        let mut authorizer = token.authorizer().unwrap();
+        authorizer.set_limits(crate::AuthorizerLimits {
+            max_time: Duration::from_secs(10),
+            ..Default::default()
+        });
+        for i in 0..10 {
+            authorizer
+                .add_fact(format!("resource(\"file{}\")", i).as_str())
+                .unwrap();
+        }
+        authorizer.add_policy("allow if true").unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+        let tags: Vec<(String,)> = authorizer
+            .query_all("data($tag) <- tag($tag)")
+            .unwrap();
+        assert_eq!(tags.len(), 30);
+    }
 }
@@ -0,0 +1,148 @@
+//! hooks called on authorization decisions
+//!
+//! an [`AuthorizerObserver`] added to an [`Authorizer`](crate::Authorizer) is called
+//! after each call to `authorize`, to write audit logs or record metrics in one place
+use std::time::Duration;
+
+use crate::error;
+
+/// called with the result of each authorization
+///
+/// this is implemented by closures taking an [`AuthorizationRecord`]:
+///
+/// ```rust
+/// # use biscuit_auth::{Authorizer, AuthorizationRecord, Biscuit, KeyPair};
+/// # use std::sync::{Arc, Mutex};
+/// let root = KeyPair::new();
+/// let token = Biscuit::builder().build(&root).unwrap();
+///
+/// let denied = Arc::new(Mutex::new(0));
+/// let counter = denied.clone();
+///
+/// let mut authorizer = token.authorizer().unwrap();
+/// authorizer.add_observer(move |record: &AuthorizationRecord| {
+///     if record.result.is_err() {
+///         *counter.lock().unwrap() += 1;
+///     }
+/// });
+/// authorizer.deny().unwrap();
+/// assert!(authorizer.authorize().is_err());
+/// assert_eq!(*denied.lock().unwrap(), 1);
+/// ```
+pub trait AuthorizerObserver: Send + Sync {
+    fn on_authorization(&self, record: &AuthorizationRecord);
+}
+
+impl<F> AuthorizerObserver for F
+where
+    F: Fn(&AuthorizationRecord) + Send + Sync,
+{
+    fn on_authorization(&self, record: &AuthorizationRecord) {
+        self(record)
+    }
+}
+
+/// description of an authorization decision
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthorizationRecord {
+    /// revocation ids of the token, empty if there is no token or if the
+    /// authorizer was restored from a snapshot
+    pub revocation_ids: Vec<Vec<u8>>,
+    /// the policy that matched, if any
+    pub policy: Option<error::MatchedPolicy>,
+    /// list of checks that failed validation
+    pub failed_checks: Vec<error::FailedCheck>,
+    /// number of fact generation iterations
+    pub iterations: u64,
+    /// number of facts in the authorizer
+    pub fact_count: usize,
+    /// total execution time of the authorizer
+    pub execution_time: Duration,
+    /// result returned by `authorize`
+    pub result: Result<usize, error::Token>,
+}
+
+impl AuthorizationRecord {
+    pub(crate) fn new(
+        revocation_ids: Vec<Vec<u8>>,
+        iterations: u64,
+        fact_count: usize,
+        execution_time: Duration,
+        result: Result<usize, error::Token>,
+    ) -> Self {
+        let (policy, failed_checks) = match &result {
+            Ok(i) => (Some(error::MatchedPolicy::Allow(*i)), Vec::new()),
+            Err(error::Token::FailedLogic(error::Logic::Unauthorized { policy, checks })) => {
+                (Some(policy.clone()), checks.clone())
+            }
+            Err(error::Token::FailedLogic(error::Logic::NoMatchingPolicy { checks })) => {
+                (None, checks.clone())
+            }
+            Err(_) => (None, Vec::new()),
+        };
+
+        AuthorizationRecord {
+            revocation_ids,
+            policy,
+            failed_checks,
+            iterations,
+            fact_count,
+            execution_time,
+            result,
+        }
+    }
+}
+
+/// emits an event for each authorization, with the fields of the [`AuthorizationRecord`]
+///
+/// revocation ids are hex encoded
+#[cfg(feature = "tracing")]
+#[cfg_attr(feature = "docsrs", doc(cfg(feature = "tracing")))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TracingObserver;
+
+#[cfg(feature = "tracing")]
+impl AuthorizerObserver for TracingObserver {
+    fn on_authorization(&self, record: &AuthorizationRecord) {
+        let revocation_ids = record
+            .revocation_ids
+            .iter()
+            .map(hex::encode)
+            .collect::<Vec<_>>()
+            .join(",");
+        let policy = record.policy.as_ref().map(|policy| match policy {
+            error::MatchedPolicy::Allow(i) => format!("allow {}", i),
+            error::MatchedPolicy::Deny(i) => format!("deny {}", i),
+        });
+        let failed_checks = record
+            .failed_checks
+            .iter()
+            .map(|check| match check {
+                error::FailedCheck::Block(check) => check.rule.clone(),
+                error::FailedCheck::Authorizer(check) => check.rule.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        match &record.result {
+            Ok(_) => tracing::info!(
+                revocation_ids = %revocation_ids,
+                policy = policy.as_deref(),
+                iterations = record.iterations,
+                fact_count = record.fact_count,
+                execution_time = ?record.execution_time,
+                "authorization succeeded"
+            ),
+            Err(e) => tracing::warn!(
+                revocation_ids = %revocation_ids,
+                policy = policy.as_deref(),
+                failed_checks = %failed_checks,
+                iterations = record.iterations,
+                fact_count = record.fact_count,
+                execution_time = ?record.execution_time,
+                error = %e,
+                "authorization failed"
+            ),
+        }
+    }
+}
@@ -0,0 +1,504 @@
+//! facts loaded on demand during authorization
+//!
+//! a [`FactProvider`] is registered for a predicate name on the [`Authorizer`](crate::Authorizer).
+//! When a rule, check or policy refers to that predicate, the provider is called with
+//! the terms bound by the other predicates of the rule, and the facts it returns are
+//! added with the authorizer's origin. When a rule joins several provided predicates,
+//! they are fetched in the order of the rule's body, each one bound by the facts
+//! fetched for the previous ones. Each pattern is fetched once per authorizer,
+//! and the facts count toward the run limits.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::{
+    builder::{Convert, Fact, Predicate},
+    datalog::{
+        self, match_preds, CombineIt, MatchedVariables, Origin, RunLimits, SymbolIndex,
+        SymbolTable, TrustedOrigins, World,
+    },
+    error,
+    time::Instant,
+    token::authorizer::check_reserved_predicate,
+};
+
+/// returns the facts of a predicate, on demand
+///
+/// the pattern has the name of the predicate. Terms bound by the rule being
+/// evaluated are set, the other terms are variables. Returned facts that do not
+/// match the pattern are ignored.
+///
+/// this is implemented by closures:
+///
+/// ```rust
+/// # use biscuit_auth::{Authorizer, builder::{fact, string, Predicate, Term}, error};
+/// let mut authorizer = Authorizer::new();
+/// authorizer.add_fact_provider("right", |pattern: &Predicate| {
+///     // look up the rights of the user in a database
+///     match &pattern.terms[0] {
+///         Term::Str(user) if user == "alice" => Ok(vec![fact(
+///             "right",
+///             &[string("alice"), string("file1"), string("read")],
+///         )]),
+///         _ => Ok(vec![]),
+///     }
+/// });
+///
+/// authorizer
+///     .add_code(
+///         r#"
+///         user("alice");
+///         resource("file1");
+///         operation("read");
+///         allow if user($user), resource($res), operation($op), right($user, $res, $op);
+///         "#,
+///     )
+///     .unwrap();
+/// assert_eq!(authorizer.authorize(), Ok(0));
+/// ```
+pub trait FactProvider: Send + Sync {
+    fn facts(&self, pattern: &Predicate) -> Result<Vec<Fact>, error::Token>;
+}
+
+impl<F> FactProvider for F
+where
+    F: Fn(&Predicate) -> Result<Vec<Fact>, error::Token> + Send + Sync,
+{
+    fn facts(&self, pattern: &Predicate) -> Result<Vec<Fact>, error::Token> {
+        self(pattern)
+    }
+}
+
+/// in memory provider, mainly useful for tests
+impl FactProvider for Vec<Fact> {
+    fn facts(&self, pattern: &Predicate) -> Result<Vec<Fact>, error::Token> {
+        Ok(self
+            .iter()
+            .filter(|fact| fact.predicate.name == pattern.name)
+            .cloned()
+            .collect())
+    }
+}
+
+/// providers registered on an authorizer, and the patterns they were called with
+#[derive(Clone, Default)]
+pub(crate) struct FactProviders {
+    providers: HashMap<String, Arc<dyn FactProvider>>,
+    fetched: HashSet<datalog::Predicate>,
+    /// provided facts cannot use the token metadata predicates
+    pub(crate) token_metadata: bool,
+}
+
+impl FactProviders {
+    pub(crate) fn insert(&mut self, name: &str, provider: Arc<dyn FactProvider>) {
+        self.providers.insert(name.to_string(), provider);
+    }
+
+    /// runs the rules of the world, fetching the provided facts they need
+    /// until no new facts are generated
+    pub(crate) fn run(
+        &mut self,
+        world: &mut World,
+        symbols: &mut SymbolTable,
+        mut limits: RunLimits,
+    ) -> Result<(), error::Token> {
+        let time_limit = Instant::now() + limits.max_time;
+        self.run_until(world, symbols, &mut limits, time_limit)
+    }
+
+    /// runs the rules of the world until `time_limit`
+    ///
+    /// the iterations of the run are removed from `limits`, so that calling
+    /// it again does not reset the budget
+    fn run_until(
+        &mut self,
+        world: &mut World,
+        symbols: &mut SymbolTable,
+        limits: &mut RunLimits,
+        time_limit: Instant,
+    ) -> Result<(), error::Token> {
+        loop {
+            let now = Instant::now();
+            if now >= time_limit {
+                return Err(error::Token::RunLimit(error::RunLimit::Timeout));
+            }
+            limits.max_time = time_limit - now;
+
+            let iterations = world.iterations;
+            world.run_with_limits(symbols, limits.clone())?;
+            limits.max_iterations = limits
+                .max_iterations
+                .saturating_sub(world.iterations - iterations);
+
+            if self.providers.is_empty() {
+                return Ok(());
+            }
+
+            let provided = self.provided_symbols(symbols);
+            let patterns = world
+                .rules
+                .iter_all()
+                .flat_map(|(scope, rule)| patterns(world, rule, scope, &provided, symbols))
+                .collect::<HashSet<_>>();
+            if !self.fetch(world, symbols, patterns, limits, time_limit)? {
+                return Ok(());
+            }
+
+            if limits.max_iterations == 0 {
+                return Err(error::Token::RunLimit(error::RunLimit::TooManyIterations));
+            }
+        }
+    }
+
+    /// fetches the provided facts needed by a check, policy or query
+    pub(crate) fn fetch_for_query(
+        &mut self,
+        world: &mut World,
+        symbols: &mut SymbolTable,
+        query: &datalog::Rule,
+        scope: &TrustedOrigins,
+        limits: &RunLimits,
+    ) -> Result<(), error::Token> {
+        if self.providers.is_empty() {
+            return Ok(());
+        }
+
+        let time_limit = Instant::now() + limits.max_time;
+        let mut limits = limits.clone();
+        loop {
+            let provided = self.provided_symbols(symbols);
+            let patterns = patterns(world, query, scope, &provided, symbols);
+            if !self.fetch(world, symbols, patterns, &limits, time_limit)? {
+                return Ok(());
+            }
+
+            // the new facts can trigger rules, which can bind more terms of the query
+            self.run_until(world, symbols, &mut limits, time_limit)?;
+        }
+    }
+
+    fn provided_symbols(&self, symbols: &SymbolTable) -> HashSet<SymbolIndex> {
+        self.providers
+            .keys()
+            .filter_map(|name| symbols.get(name))
+            .collect()
+    }
+
+    /// calls the providers for the patterns that were not fetched yet
+    ///
+    /// returns true if new facts were added
+    fn fetch(
+        &mut self,
+        world: &mut World,
+        symbols: &mut SymbolTable,
+        patterns: HashSet<datalog::Predicate>,
+        limits: &RunLimits,
+        time_limit: Instant,
+    ) -> Result<bool, error::Token> {
+        let mut authorizer_origin = Origin::default();
+        authorizer_origin.insert(usize::MAX);
+
+        let len = world.facts.len();
+        for pattern in patterns {
+            if self.fetched.contains(&pattern) {
+                continue;
+            }
+
+            let builder_pattern = Predicate::convert_from(&pattern, symbols)?;
+            let provider = match self.providers.get(&builder_pattern.name) {
+                Some(provider) => provider.clone(),
+                None => continue,
+            };
+
+            for fact in provider.facts(&builder_pattern)? {
+                fact.validate()?;
+                let fact = fact.convert(symbols);
+                check_reserved_predicate(
+                    self.token_metadata,
+                    symbols,
+                    usize::MAX,
+                    fact.predicate.name,
+                )?;
+                if match_preds(&pattern, &fact.predicate) {
+                    world.facts.insert(&authorizer_origin, fact);
+                }
+            }
+            self.fetched.insert(pattern);
+
+            if world.facts.len() >= limits.max_facts as usize {
+                return Err(error::Token::RunLimit(error::RunLimit::TooManyFacts));
+            }
+            if Instant::now() >= time_limit {
+                return Err(error::Token::RunLimit(error::RunLimit::Timeout));
+            }
+        }
+
+        Ok(world.facts.len() > len)
+    }
+}
+
+/// instances of the provided predicates needed to apply a rule
+///
+/// terms bound by the other predicates of the rule's body are set in the
+/// patterns, the other terms are left as variables. A provided predicate is
+/// only bound by the provided predicates that come before it in the body,
+/// since the facts of the following ones are not fetched yet
+fn patterns(
+    world: &World,
+    rule: &datalog::Rule,
+    scope: &TrustedOrigins,
+    provided: &HashSet<SymbolIndex>,
+    symbols: &SymbolTable,
+) -> HashSet<datalog::Predicate> {
+    let mut patterns = HashSet::new();
+
+    for (position, predicate) in rule.body.iter().enumerate() {
+        if !provided.contains(&predicate.name) {
+            continue;
+        }
+
+        let body = rule
+            .body
+            .iter()
+            .enumerate()
+            .filter(|(i, other)| {
+                *i < position || (*i > position && !provided.contains(&other.name))
+            })
+            .map(|(_, other)| other.clone())
+            .collect::<Vec<_>>();
+
+        let variables = body
+            .iter()
+            .flat_map(|predicate| predicate.terms.iter())
+            .filter_map(|term| match term {
+                datalog::Term::Variable(v) => Some(*v),
+                _ => None,
+            })
+            .collect();
+
+        for (_, bindings) in CombineIt::new(
+            MatchedVariables::new(variables),
+            &body,
+            world.facts.iterator(scope),
+            symbols,
+        ) {
+            let mut pattern = predicate.clone();
+            for term in pattern.terms.iter_mut() {
+                if let datalog::Term::Variable(v) = term {
+                    if let Some(value) = bindings.get(v) {
+                        *term = value.clone();
+                    }
+                }
+            }
+            patterns.insert(pattern);
+        }
+    }
+
+    patterns
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        builder::{fact, int, string, Term},
+        Authorizer, AuthorizerLimits, Biscuit, KeyPair,
+    };
+
+    #[test]
+    fn provided_facts() {
+        let root = KeyPair::new();
+        let mut builder = Biscuit::builder();
+        builder.add_fact("user(\"alice\")").unwrap();
+        builder
+            .add_check("check if right(\"alice\", \"file1\", \"read\")")
+            .unwrap();
+        let token = builder.build(&root).unwrap();
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+
+        let mut authorizer = token.authorizer().unwrap();
+        authorizer.add_fact_provider("right", move |pattern: &Predicate| {
+            recorded.lock().unwrap().push(pattern.to_string());
+            Ok(match &pattern.terms[0] {
+                Term::Str(user) if user == "alice" => vec![
+                    fact("right", &[string("alice"), string("file1"), string("read")]),
+                    fact("right", &[string("alice"), string("file2"), string("read")]),
+                    // does not match the pattern
+                    fact("right", &[string("bob"), string("file1"), string("read")]),
+                ],
+                _ => vec![],
+            })
+        });
+        authorizer
+            .add_code(
+                r#"
+                resource("file1");
+                can_read($user, $res) <- user($user), resource($res), right($user, $res, "read");
+                allow if can_read("alice", "file1");
+                "#,
+            )
+            .unwrap();
+
+        assert_eq!(authorizer.authorize(), Ok(0));
+        let res: Vec<(String,)> = authorizer
+            .query("data($user) <- right($user, \"file1\", \"read\")")
+            .unwrap();
+        assert_eq!(res, vec![("alice".to_string(),)]);
+
+        // each pattern is fetched once
+        let mut calls = calls.lock().unwrap().clone();
+        calls.sort();
+        assert_eq!(
+            calls,
+            vec![
+                "right(\"alice\", \"file1\", \"read\")".to_string(),
+                "right($user, \"file1\", \"read\")".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn joined_provided_facts() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+
+        let mut authorizer = Authorizer::new();
+        authorizer.add_fact_provider("right", |pattern: &Predicate| {
+            Ok(match &pattern.terms[0] {
+                Term::Str(user) if user == "alice" => vec![
+                    fact("right", &[string("alice"), string("file1"), string("read")]),
+                    fact(
+                        "right",
+                        &[string("alice"), string("file2"), string("write")],
+                    ),
+                ],
+                _ => vec![],
+            })
+        });
+        authorizer.add_fact_provider("owner", move |pattern: &Predicate| {
+            recorded.lock().unwrap().push(pattern.to_string());
+            Ok(vec![fact("owner", &[string("file1"), string("bob")])])
+        });
+        authorizer
+            .add_code(
+                r#"
+                user("alice");
+                allow if user($u), right($u, $r, "read"), owner($r, "bob");
+                "#,
+            )
+            .unwrap();
+        assert_eq!(authorizer.authorize(), Ok(0));
+
+        // owner is bound by the facts fetched for right
+        assert_eq!(
+            calls.lock().unwrap().clone(),
+            vec!["owner(\"file1\", \"bob\")".to_string()]
+        );
+    }
+
+    #[test]
+    fn provided_token_metadata() {
+        let mut authorizer = Authorizer::new();
+        authorizer.set_token_metadata(true);
+        authorizer.add_fact_provider("biscuit:sealed", |_: &Predicate| {
+            Ok(vec![fact("biscuit:sealed", &[Term::Bool(true)])])
+        });
+        authorizer
+            .add_policy("allow if biscuit:sealed(true)")
+            .unwrap();
+        assert_eq!(
+            authorizer.authorize(),
+            Err(error::Token::FailedLogic(error::Logic::ReservedPredicate(
+                u32::MAX,
+                "biscuit:sealed".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn provided_facts_timeout() {
+        let mut authorizer = Authorizer::new();
+        authorizer.add_fact_provider("next", |pattern: &Predicate| {
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            Ok(match &pattern.terms[0] {
+                Term::Integer(i) => vec![fact("next", &[int(*i), int(i + 1)])],
+                _ => vec![],
+            })
+        });
+        authorizer
+            .add_code(
+                r#"
+                reached(0);
+                reached($j) <- reached($i), next($i, $j);
+                allow if reached(1000);
+                "#,
+            )
+            .unwrap();
+
+        assert_eq!(
+            authorizer.authorize_with_limits(AuthorizerLimits {
+                max_time: std::time::Duration::from_millis(20),
+                max_iterations: 10_000,
+                ..Default::default()
+            }),
+            Err(error::Token::RunLimit(error::RunLimit::Timeout))
+        );
+    }
+
+    #[test]
+    fn in_memory_provider() {
+        let mut authorizer = Authorizer::new();
+        authorizer.add_fact_provider(
+            "group",
+            vec![
+                fact("group", &[string("alice"), string("admin")]),
+                fact("member", &[string("bob"), string("admin")]),
+            ],
+        );
+        authorizer
+            .add_code(
+                r#"
+                user("alice");
+                check if user($user), group($user, "admin");
+                deny if member($user, $group);
+                allow if true;
+                "#,
+            )
+            .unwrap();
+        assert_eq!(authorizer.authorize(), Ok(1));
+    }
+
+    #[test]
+    fn provided_facts_limits() {
+        let mut authorizer = Authorizer::new();
+        authorizer.add_fact_provider("number", |_: &Predicate| {
+            Ok((0..100).map(|i| fact("number", &[int(i)])).collect())
+        });
+        authorizer
+            .add_policy("allow if number($n), $n > 200")
+            .unwrap();
+
+        assert_eq!(
+            authorizer.authorize_with_limits(AuthorizerLimits {
+                max_facts: 50,
+                ..Default::default()
+            }),
+            Err(error::Token::RunLimit(error::RunLimit::TooManyFacts))
+        );
+
+        let mut failing = Authorizer::new();
+        failing.add_fact_provider("number", |_: &Predicate| {
+            Err(error::Token::FactProvider("connection refused".to_string()))
+        });
+        failing.add_policy("allow if number(1)").unwrap();
+        assert_eq!(
+            failing.authorize(),
+            Err(error::Token::FactProvider("connection refused".to_string()))
+        );
+    }
+}
@@ -1,75 +1,152 @@
 use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
 
-use crate::{crypto::PublicKey, error};
+use crate::{crypto::PublicKey, datalog::MAX_DERIVE_DEPTH, error};
 
-#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[derive(Clone, Default)]
 pub struct PublicKeys {
-    pub(crate) keys: Vec<PublicKey>,
+    /// keys shared with the table this one was derived from
+    base: Option<Arc<PublicKeys>>,
+    /// number of keys in `base`
+    offset: usize,
+    keys: Vec<PublicKey>,
 }
 
 impl PublicKeys {
     pub fn new() -> Self {
-        PublicKeys { keys: vec![] }
+        PublicKeys::default()
     }
 
     pub fn from(keys: Vec<PublicKey>) -> Self {
-        PublicKeys { keys }
+        PublicKeys {
+            keys,
+            ..PublicKeys::default()
+        }
+    }
+
+    /// creates an empty table on top of `base`, without copying its keys
+    ///
+    /// if `base` is already derived from too many tables, its keys are
+    /// copied in a single table first
+    pub fn derive(base: Arc<PublicKeys>) -> Self {
+        let base = if base.depth() >= MAX_DERIVE_DEPTH {
+            Arc::new(PublicKeys::from(base.iter().cloned().collect()))
+        } else {
+            base
+        };
+
+        PublicKeys {
+            offset: base.current_offset(),
+            base: Some(base),
+            keys: vec![],
+        }
     }
 
     pub fn extend(&mut self, other: &PublicKeys) -> Result<(), error::Format> {
         if !self.is_disjoint(other) {
             return Err(error::Format::PublicKeyTableOverlap);
         }
-        self.keys.extend(other.keys.iter().cloned());
+        self.keys.extend(other.iter().cloned());
         Ok(())
     }
 
     pub fn insert(&mut self, k: &PublicKey) -> u64 {
-        match self.keys.iter().position(|key| key == k) {
-            Some(index) => index as u64,
+        match self.get(k) {
+            Some(index) => index,
             None => {
                 self.keys.push(*k);
-                (self.keys.len() - 1) as u64
+                (self.current_offset() - 1) as u64
             }
         }
     }
 
     pub fn insert_fallible(&mut self, k: &PublicKey) -> Result<u64, error::Format> {
-        match self.keys.iter().position(|key| key == k) {
+        match self.get(k) {
             Some(_) => Err(error::Format::PublicKeyTableOverlap),
             None => {
                 self.keys.push(*k);
-                Ok((self.keys.len() - 1) as u64)
+                Ok((self.current_offset() - 1) as u64)
             }
         }
     }
 
     pub fn get(&self, k: &PublicKey) -> Option<u64> {
-        self.keys.iter().position(|key| key == k).map(|i| i as u64)
+        self.base.as_ref().and_then(|base| base.get(k)).or_else(|| {
+            self.keys
+                .iter()
+                .position(|key| key == k)
+                .map(|i| (self.offset + i) as u64)
+        })
     }
 
     pub fn current_offset(&self) -> usize {
-        self.keys.len()
+        self.offset + self.keys.len()
     }
 
     pub fn split_at(&mut self, offset: usize) -> PublicKeys {
+        if offset < self.offset {
+            *self = PublicKeys::from(self.iter().cloned().collect());
+        }
+
         let mut table = PublicKeys::new();
-        table.keys = self.keys.split_off(offset);
+        table.keys = self.keys.split_off(offset - self.offset);
         table
     }
 
     pub fn is_disjoint(&self, other: &PublicKeys) -> bool {
-        let h1 = self.keys.iter().collect::<HashSet<_>>();
-        let h2 = other.keys.iter().collect::<HashSet<_>>();
+        let h1 = self.iter().collect::<HashSet<_>>();
+        let h2 = other.iter().collect::<HashSet<_>>();
 
         h1.is_disjoint(&h2)
     }
 
     pub fn get_key(&self, i: u64) -> Option<&PublicKey> {
-        self.keys.get(i as usize)
+        let i = i as usize;
+        if i < self.offset {
+            self.base.as_ref().and_then(|base| base.get_key(i as u64))
+        } else {
+            self.keys.get(i - self.offset)
+        }
+    }
+
+    /// iterates over the keys, in the order of their indexes
+    pub fn iter(&self) -> impl Iterator<Item = &PublicKey> {
+        let mut tables = self.tables().collect::<Vec<_>>();
+        tables.reverse();
+        tables.into_iter().flat_map(|table| table.keys.iter())
+    }
+
+    /// this table and the ones it was derived from, most recent first
+    fn tables(&self) -> impl Iterator<Item = &PublicKeys> {
+        std::iter::successors(Some(self), |table| table.base.as_deref())
+    }
+
+    /// number of tables the keys are stored in
+    pub(crate) fn depth(&self) -> usize {
+        self.tables().count()
     }
 
     pub fn into_inner(self) -> Vec<PublicKey> {
-        self.keys
+        match self.base {
+            None => self.keys,
+            Some(_) => self.iter().cloned().collect(),
+        }
+    }
+}
+
+impl PartialEq for PublicKeys {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for PublicKeys {}
+
+impl fmt::Debug for PublicKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PublicKeys")
+            .field("keys", &self.iter().collect::<Vec<_>>())
+            .finish()
     }
 }
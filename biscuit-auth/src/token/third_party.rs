@@ -1,22 +1,37 @@
+use std::convert::TryInto;
+
 use ed25519_dalek::Signer;
 use prost::Message;
 
 use crate::{
-    builder::BlockBuilder,
+    builder::{BlockBuilder, Convert, Fact},
     crypto::PublicKey,
     datalog::SymbolTable,
     error,
-    format::{convert::token_block_to_proto_block, schema, SerializedBiscuit},
-    KeyPair, PrivateKey,
+    format::{
+        convert::{
+            token_block_to_proto_block,
+            v2::{proto_fact_to_token_fact, token_fact_to_proto_fact},
+        },
+        schema, SerializedBiscuit,
+    },
+    Biscuit, KeyPair, PrivateKey, RootKeyProvider,
 };
 
 use super::public_keys::PublicKeys;
 
 /// Third party block request
+///
+/// Along with the previous key and public keys table, the token holder can
+/// attach context for the third party: facts describing the request, and the
+/// token itself, which the third party can verify with [`ThirdPartyRequest::verify_token`].
+/// Requests without context are compatible with previous versions.
 #[derive(Debug)]
 pub struct ThirdPartyRequest {
     pub(crate) previous_key: PublicKey,
     pub(crate) public_keys: PublicKeys,
+    pub(crate) context_facts: Vec<Fact>,
+    pub(crate) token: Option<Vec<u8>>,
 }
 
 impl ThirdPartyRequest {
@@ -57,22 +72,86 @@ impl ThirdPartyRequest {
         Ok(ThirdPartyRequest {
             previous_key,
             public_keys,
+            context_facts: Vec::new(),
+            token: None,
         })
     }
 
+    /// adds a fact to the request's context
+    ///
+    /// context facts are not signed: unless they can be checked against
+    /// the attached token, they are only claims of the token holder
+    pub fn add_context_fact<F: TryInto<Fact>>(&mut self, fact: F) -> Result<(), error::Token>
+    where
+        error::Token: From<<F as TryInto<Fact>>::Error>,
+    {
+        let fact = fact.try_into()?;
+        fact.validate()?;
+        self.context_facts.push(fact);
+        Ok(())
+    }
+
+    /// returns the facts of the request's context
+    pub fn context_facts(&self) -> &[Fact] {
+        &self.context_facts
+    }
+
+    /// attaches the serialized token this request was created from
+    pub fn attach_token<T: AsRef<[u8]>>(&mut self, token: T) {
+        self.token = Some(token.as_ref().to_vec());
+    }
+
+    /// returns the serialized token attached to the request, if any
+    pub fn attached_token(&self) -> Option<&[u8]> {
+        self.token.as_deref()
+    }
+
+    /// verifies the attached token's signatures, and that the request was created from it
+    ///
+    /// returns `None` if no token was attached
+    pub fn verify_token<KP: RootKeyProvider>(
+        &self,
+        key_provider: KP,
+    ) -> Result<Option<Biscuit>, error::Token> {
+        let token = match &self.token {
+            None => return Ok(None),
+            Some(token) => Biscuit::from(token, key_provider)?,
+        };
+
+        let request = token.third_party_request()?;
+        if request.previous_key != self.previous_key || request.public_keys != self.public_keys {
+            return Err(error::Format::ThirdPartyRequestMismatch.into());
+        }
+
+        Ok(Some(token))
+    }
+
     pub fn serialize(&self) -> Result<Vec<u8>, error::Token> {
-        let public_keys = self
-            .public_keys
-            .keys
-            .iter()
-            .map(|key| key.to_proto())
-            .collect();
+        let public_keys = self.public_keys.iter().map(|key| key.to_proto()).collect();
 
         let previous_key = self.previous_key.to_proto();
 
+        let context = if self.context_facts.is_empty() && self.token.is_none() {
+            None
+        } else {
+            let mut symbols = SymbolTable::from(Vec::new())?;
+            let facts = self
+                .context_facts
+                .iter()
+                .map(|fact| token_fact_to_proto_fact(&fact.convert(&mut symbols)))
+                .collect();
+
+            Some(schema::ThirdPartyRequestContext {
+                symbols: symbols.strings(),
+                facts,
+                token: self.token.clone(),
+            })
+        };
+
         let request = schema::ThirdPartyBlockRequest {
             previous_key,
             public_keys,
+            context,
         };
         let mut v = Vec::new();
 
@@ -101,9 +180,23 @@ impl ThirdPartyRequest {
             public_keys.insert(&PublicKey::from_proto(&key)?);
         }
 
+        let mut context_facts = Vec::new();
+        let mut token = None;
+        if let Some(context) = data.context {
+            let symbols = SymbolTable::from(context.symbols)?;
+
+            for fact in &context.facts {
+                let fact = proto_fact_to_token_fact(fact)?;
+                context_facts.push(Fact::convert_from(&fact, &symbols)?);
+            }
+            token = context.token;
+        }
+
         Ok(ThirdPartyRequest {
             previous_key,
             public_keys,
+            context_facts,
+            token,
         })
     }
 
@@ -121,10 +214,14 @@ impl ThirdPartyRequest {
         private_key: &PrivateKey,
         block_builder: BlockBuilder,
     ) -> Result<ThirdPartyBlock, error::Token> {
-        let mut symbols = SymbolTable::new();
-        symbols.public_keys = self.public_keys.clone();
+        let symbols = SymbolTable::from_symbols_and_public_keys(
+            Vec::new(),
+            self.public_keys.iter().cloned().collect(),
+        )?;
         let mut block = block_builder.build(symbols);
-        block.version = super::MAX_SCHEMA_VERSION;
+        // third party blocks need at least v4, v5 is only used for threshold scopes
+        block.version = block.version.max(4);
+        block.check_group_scopes()?;
 
         let mut v = Vec::new();
         token_block_to_proto_block(&block)
@@ -132,8 +229,52 @@ impl ThirdPartyRequest {
             .map_err(|e| {
                 error::Format::SerializationError(format!("serialization error: {:?}", e))
             })?;
-        let payload = v.clone();
+        let external_signature = self.sign(private_key, &v)?;
+        let content = schema::ThirdPartyBlockContents {
+            payload: v,
+            external_signature,
+            co_signatures: vec![],
+        };
 
+        Ok(ThirdPartyBlock(content))
+    }
+
+    /// Adds the signature of another third party to a [`ThirdPartyBlock`]
+    ///
+    /// the block can then be trusted by threshold scopes listing the keys
+    /// of its signers, like `trusting 2 of {ed25519/..., ed25519/...}`.
+    /// Each key can sign the block only once
+    pub fn co_sign(
+        &self,
+        private_key: &PrivateKey,
+        block: ThirdPartyBlock,
+    ) -> Result<ThirdPartyBlock, error::Token> {
+        let mut content = block.0;
+
+        let public_key = private_key.public().to_proto();
+        if content.external_signature.public_key == public_key
+            || content
+                .co_signatures
+                .iter()
+                .any(|signature| signature.public_key == public_key)
+        {
+            return Err(error::Token::Format(error::Format::DuplicateSigner(
+                private_key.public().to_string(),
+            )));
+        }
+
+        let signature = self.sign(private_key, &content.payload)?;
+        content.co_signatures.push(signature);
+
+        Ok(ThirdPartyBlock(content))
+    }
+
+    fn sign(
+        &self,
+        private_key: &PrivateKey,
+        payload: &[u8],
+    ) -> Result<schema::ExternalSignature, error::Token> {
+        let mut v = payload.to_vec();
         v.extend(&(crate::format::schema::public_key::Algorithm::Ed25519 as i32).to_le_bytes());
         v.extend(self.previous_key.to_bytes());
 
@@ -145,16 +286,10 @@ impl ThirdPartyRequest {
             .map_err(error::Signature::InvalidSignatureGeneration)
             .map_err(error::Format::Signature)?;
 
-        let public_key = keypair.public();
-        let content = schema::ThirdPartyBlockContents {
-            payload,
-            external_signature: schema::ExternalSignature {
-                signature: signature.to_bytes().to_vec(),
-                public_key: public_key.to_proto(),
-            },
-        };
-
-        Ok(ThirdPartyBlock(content))
+        Ok(schema::ExternalSignature {
+            signature: signature.to_bytes().to_vec(),
+            public_key: keypair.public().to_proto(),
+        })
     }
 }
 
@@ -180,3 +315,274 @@ impl ThirdPartyBlock {
         Ok(base64::encode_config(self.serialize()?, base64::URL_SAFE))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn request_context() {
+        let root = KeyPair::new();
+        let mut builder = Biscuit::builder();
+        builder.add_fact("user(\"alice\")").unwrap();
+        let token = builder.build(&root).unwrap();
+        let serialized_token = token.to_vec().unwrap();
+
+        let mut request = token.third_party_request().unwrap();
+        request
+            .add_context_fact("requested_group(\"admin\")")
+            .unwrap();
+        request.add_context_fact("ip(\"127.0.0.1\")").unwrap();
+        request.attach_token(&serialized_token);
+        assert!(request.add_context_fact("group({group})").is_err());
+
+        let request = ThirdPartyRequest::deserialize(&request.serialize().unwrap()).unwrap();
+        assert_eq!(
+            request
+                .context_facts()
+                .iter()
+                .map(|fact| fact.to_string())
+                .collect::<Vec<_>>(),
+            vec!["requested_group(\"admin\")", "ip(\"127.0.0.1\")"]
+        );
+        assert_eq!(request.attached_token(), Some(&serialized_token[..]));
+
+        let verified = request.verify_token(root.public()).unwrap().unwrap();
+        assert_eq!(
+            verified.revocation_identifiers(),
+            token.revocation_identifiers()
+        );
+        assert!(request.verify_token(KeyPair::new().public()).is_err());
+
+        // a token that did not create the request is rejected
+        let mut other_request = Biscuit::builder()
+            .build(&root)
+            .unwrap()
+            .third_party_request()
+            .unwrap();
+        other_request.attach_token(&serialized_token);
+        assert_eq!(
+            other_request.verify_token(root.public()).unwrap_err(),
+            error::Token::Format(error::Format::ThirdPartyRequestMismatch)
+        );
+
+        // requests without context are compatible with previous versions
+        let request = token.third_party_request().unwrap();
+        let serialized = request.serialize().unwrap();
+        let legacy = schema::ThirdPartyBlockRequest::decode(&serialized[..]).unwrap();
+        assert_eq!(legacy.context, None);
+        let request = ThirdPartyRequest::deserialize(&serialized).unwrap();
+        assert!(request.context_facts().is_empty());
+        assert!(request.verify_token(root.public()).unwrap().is_none());
+
+        // context symbols are validated like block symbols, and keep their positions
+        let context = |symbols: &[&str]| {
+            let mut symbols_table = SymbolTable::new();
+            let fact = crate::builder::fact("requested_group", &[crate::builder::string("ops")])
+                .convert(&mut symbols_table);
+            let mut request = legacy.clone();
+            request.context = Some(schema::ThirdPartyRequestContext {
+                symbols: symbols.iter().map(|s| s.to_string()).collect(),
+                facts: vec![token_fact_to_proto_fact(&fact)],
+                token: None,
+            });
+            let mut v = Vec::new();
+            request.encode(&mut v).unwrap();
+            ThirdPartyRequest::deserialize(&v)
+        };
+        assert_eq!(
+            context(&["requested_group", "ops"])
+                .unwrap()
+                .context_facts()[0]
+                .to_string(),
+            "requested_group(\"ops\")"
+        );
+        assert_eq!(
+            context(&["read", "ops"]).unwrap_err(),
+            error::Token::Format(error::Format::SymbolTableOverlap)
+        );
+        assert_eq!(
+            context(&["requested_group", "requested_group", "ops"])
+                .unwrap()
+                .context_facts()[0]
+                .to_string(),
+            "requested_group(\"requested_group\")"
+        );
+
+        let external = KeyPair::new();
+        let block = request
+            .create_block(&external.private(), BlockBuilder::new())
+            .unwrap();
+        assert!(token.append_third_party(external.public(), block).is_ok());
+    }
+
+    #[test]
+    fn co_signed_blocks() {
+        let root = KeyPair::new();
+        let token = Biscuit::builder().build(&root).unwrap();
+        let request = token.third_party_request().unwrap().serialize().unwrap();
+
+        let (legal, security, finance) = (KeyPair::new(), KeyPair::new(), KeyPair::new());
+        let mut builder = BlockBuilder::new();
+        builder.add_fact("approved(\"deploy\")").unwrap();
+        let block = ThirdPartyRequest::deserialize(&request)
+            .unwrap()
+            .create_block(&legal.private(), builder)
+            .unwrap();
+        let request = ThirdPartyRequest::deserialize(&request).unwrap();
+        let co_signed = request.co_sign(&security.private(), block.clone()).unwrap();
+        assert_eq!(
+            request
+                .co_sign(&security.private(), co_signed.clone())
+                .unwrap_err(),
+            error::Token::Format(error::Format::DuplicateSigner(
+                security.public().to_string()
+            ))
+        );
+        assert_eq!(
+            request
+                .co_sign(&legal.private(), block.clone())
+                .unwrap_err(),
+            error::Token::Format(error::Format::DuplicateSigner(legal.public().to_string()))
+        );
+
+        // a block carrying the same signature twice is rejected when appended
+        let mut duplicated = co_signed.clone();
+        duplicated
+            .0
+            .co_signatures
+            .push(duplicated.0.co_signatures[0].clone());
+        assert_eq!(
+            token
+                .append_third_party(legal.public(), duplicated)
+                .unwrap_err(),
+            error::Token::Format(error::Format::DuplicateSigner(
+                security.public().to_string()
+            ))
+        );
+
+        let authorize = |token: &Biscuit, threshold: u32| {
+            let mut authorizer = token.authorizer().unwrap();
+            authorizer
+                .add_code(format!(
+                    "allow if approved(\"deploy\") trusting {} of {{{}, {}, {}}}",
+                    threshold,
+                    legal.public(),
+                    security.public(),
+                    finance.public()
+                ))
+                .unwrap();
+            authorizer.authorize().is_ok()
+        };
+
+        let attenuated = token
+            .append_third_party(legal.public(), co_signed.clone())
+            .unwrap();
+        assert_eq!(
+            attenuated.block_co_signers(1).unwrap(),
+            vec![security.public()]
+        );
+        assert!(authorize(&attenuated, 2));
+        assert!(!authorize(&attenuated, 3));
+
+        let deserialized = Biscuit::from(attenuated.to_vec().unwrap(), root.public()).unwrap();
+        assert_eq!(
+            deserialized.block_co_signers(1).unwrap(),
+            vec![security.public()]
+        );
+        assert!(authorize(&deserialized, 2));
+
+        let mut authorizer = deserialized.authorizer().unwrap();
+        authorizer
+            .add_code_with_params(
+                "allow if approved(\"deploy\") trusting 2 of {{legal}, {security}}",
+                HashMap::new(),
+                HashMap::from([
+                    ("legal".to_string(), legal.public()),
+                    ("security".to_string(), security.public()),
+                ]),
+            )
+            .unwrap();
+        assert!(authorizer.authorize().is_ok());
+        assert!(authorizer.dump_code().contains(&format!(
+            "allow if approved(\"deploy\") trusting 2 of {{{}, {}}}",
+            legal.public(),
+            security.public()
+        )));
+
+        // a single signature is not enough
+        let attenuated = token.append_third_party(legal.public(), block).unwrap();
+        assert!(authorize(&attenuated, 1));
+        assert!(!authorize(&attenuated, 2));
+
+        // the signatures are bound to the token the block was requested for
+        let other = Biscuit::builder().build(&root).unwrap();
+        assert!(other.append_third_party(legal.public(), co_signed).is_err());
+    }
+
+    #[test]
+    fn co_signed_key_tables() {
+        let root = KeyPair::new();
+        let token = Biscuit::builder().build(&root).unwrap();
+        let request = token.third_party_request().unwrap();
+
+        // the block's public keys table contains a key that the token does not know yet
+        let (legal, security, auditor) = (KeyPair::new(), KeyPair::new(), KeyPair::new());
+        let mut builder = BlockBuilder::new();
+        builder.add_fact("approved(\"deploy\")").unwrap();
+        builder
+            .add_check(format!("check if audited(true) trusting {}", auditor.public()).as_str())
+            .unwrap();
+        let block = request
+            .co_sign(
+                &security.private(),
+                ThirdPartyRequest::deserialize(&request.serialize().unwrap())
+                    .unwrap()
+                    .create_block(&legal.private(), builder)
+                    .unwrap(),
+            )
+            .unwrap();
+
+        let unverified = crate::UnverifiedBiscuit::from(token.to_vec().unwrap())
+            .unwrap()
+            .append_third_party(&block.serialize().unwrap())
+            .unwrap();
+        let appended = token.append_third_party(legal.public(), block).unwrap();
+        let deserialized = Biscuit::from(appended.to_vec().unwrap(), root.public()).unwrap();
+
+        assert_eq!(
+            appended
+                .symbols
+                .public_keys
+                .iter()
+                .copied()
+                .collect::<Vec<_>>(),
+            vec![legal.public(), security.public(), auditor.public()]
+        );
+        assert_eq!(
+            appended.symbols.public_keys,
+            deserialized.symbols.public_keys
+        );
+        assert_eq!(
+            appended.public_key_to_block_id(),
+            deserialized.public_key_to_block_id()
+        );
+        assert_eq!(
+            unverified.symbols.public_keys,
+            deserialized.symbols.public_keys
+        );
+        assert_eq!(
+            unverified
+                .verify(root.public())
+                .unwrap()
+                .public_key_to_block_id(),
+            deserialized.public_key_to_block_id()
+        );
+        assert_eq!(
+            appended.print_block_source(1).unwrap(),
+            deserialized.print_block_source(1).unwrap()
+        );
+    }
+}
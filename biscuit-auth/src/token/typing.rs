@@ -0,0 +1,437 @@
+//! checks of facts, rules and expressions against predicate schemas
+//!
+//! a schema like `schema right(string, string)` fixes the arity and the term types
+//! of a predicate. Constant terms are checked directly. The types of variables are
+//! inferred from the body predicates that have a schema, then used to check the
+//! head of the rule and its expressions, before any evaluation.
+use std::{collections::HashMap, fmt};
+
+use biscuit_parser::error::LanguageError;
+
+use crate::{
+    builder::{
+        Binary, Check, Expression, Fact, Op, ParameterType, Policy, Predicate, PredicateSchema,
+        Rule, Term, Unary,
+    },
+    error,
+};
+
+/// type of a value in an expression, the type of set, array and map elements
+/// is not tracked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Type {
+    Integer,
+    String,
+    Date,
+    Bytes,
+    Bool,
+    Set,
+    Array,
+    Map,
+    Unknown,
+}
+
+impl From<&ParameterType> for Type {
+    fn from(t: &ParameterType) -> Self {
+        match t {
+            ParameterType::Integer => Type::Integer,
+            ParameterType::String => Type::String,
+            ParameterType::Date => Type::Date,
+            ParameterType::Bytes => Type::Bytes,
+            ParameterType::Bool => Type::Bool,
+            ParameterType::Set(_) => Type::Set,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Integer => write!(f, "int"),
+            Type::String => write!(f, "string"),
+            Type::Date => write!(f, "date"),
+            Type::Bytes => write!(f, "bytes"),
+            Type::Bool => write!(f, "bool"),
+            Type::Set => write!(f, "set"),
+            Type::Array => write!(f, "array"),
+            Type::Map => write!(f, "map"),
+            Type::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+fn find<'a>(schemas: &'a [PredicateSchema], name: &str) -> Option<&'a PredicateSchema> {
+    schemas.iter().find(|schema| schema.name == name)
+}
+
+/// a predicate can only be declared once, or again with the same types
+pub(crate) fn check_schema(
+    schemas: &[PredicateSchema],
+    schema: &PredicateSchema,
+) -> Result<(), error::Token> {
+    match find(schemas, &schema.name) {
+        Some(existing) if existing != schema => {
+            Err(error::Token::Language(LanguageError::SchemaConflict {
+                predicate: schema.name.clone(),
+            }))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn check_fact(schemas: &[PredicateSchema], fact: &Fact) -> Result<(), error::Token> {
+    if schemas.is_empty() {
+        return Ok(());
+    }
+
+    let mut fact = fact.clone();
+    fact.apply_parameters();
+    check_predicate(schemas, &fact.predicate, &mut HashMap::new())
+}
+
+pub(crate) fn check_rule(schemas: &[PredicateSchema], rule: &Rule) -> Result<(), error::Token> {
+    if schemas.is_empty() {
+        return Ok(());
+    }
+
+    let mut rule = rule.clone();
+    rule.apply_parameters();
+
+    let mut variables = HashMap::new();
+    for predicate in &rule.body {
+        check_predicate(schemas, predicate, &mut variables)?;
+    }
+    check_predicate(schemas, &rule.head, &mut variables)?;
+
+    for expression in &rule.expressions {
+        check_expression(expression, &variables)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_check(schemas: &[PredicateSchema], check: &Check) -> Result<(), error::Token> {
+    for query in &check.queries {
+        check_rule(schemas, query)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn check_policy(
+    schemas: &[PredicateSchema],
+    policy: &Policy,
+) -> Result<(), error::Token> {
+    for query in &policy.queries {
+        check_rule(schemas, query)?;
+    }
+    Ok(())
+}
+
+/// checks the arity and the constant terms of a predicate, and records
+/// the types of its variables
+///
+/// a variable already seen with another type can never match
+fn check_predicate(
+    schemas: &[PredicateSchema],
+    predicate: &Predicate,
+    variables: &mut HashMap<String, ParameterType>,
+) -> Result<(), error::Token> {
+    let schema = match find(schemas, &predicate.name) {
+        Some(schema) => schema,
+        None => return Ok(()),
+    };
+
+    if schema.types.len() != predicate.terms.len() {
+        return Err(error::Token::Language(LanguageError::SchemaArity {
+            predicate: schema.name.clone(),
+            expected: schema.types.len(),
+            actual: predicate.terms.len(),
+        }));
+    }
+
+    for (position, (term, expected)) in predicate.terms.iter().zip(&schema.types).enumerate() {
+        let actual = match term {
+            Term::Variable(name) => match variables.get(name) {
+                Some(t) if t != expected => t.to_string(),
+                Some(_) => continue,
+                None => {
+                    variables.insert(name.clone(), expected.clone());
+                    continue;
+                }
+            },
+            Term::Parameter(_) => continue,
+            term if term.has_type(expected) => continue,
+            term => term.type_name(),
+        };
+
+        return Err(error::Token::Language(LanguageError::SchemaType {
+            predicate: schema.name.clone(),
+            position,
+            expected: expected.to_string(),
+            actual,
+        }));
+    }
+
+    Ok(())
+}
+
+fn term_type(term: &Term, variables: &HashMap<String, ParameterType>) -> Type {
+    match term {
+        Term::Variable(name) => variables.get(name).map(Type::from).unwrap_or(Type::Unknown),
+        Term::Integer(_) => Type::Integer,
+        Term::Str(_) => Type::String,
+        Term::Date(_) => Type::Date,
+        Term::Bytes(_) => Type::Bytes,
+        Term::Bool(_) => Type::Bool,
+        Term::Set(_) => Type::Set,
+        Term::Array(_) => Type::Array,
+        Term::Map(_) => Type::Map,
+        Term::Parameter(_) => Type::Unknown,
+    }
+}
+
+/// result type of a unary operation, following the expression evaluator
+fn unary_type(unary: &Unary, value: Type) -> Option<Type> {
+    match (unary, value) {
+        (Unary::Parens, t) => Some(t),
+        (Unary::Negate, Type::Bool | Type::Unknown) => Some(Type::Bool),
+        (
+            Unary::Length,
+            Type::String | Type::Bytes | Type::Set | Type::Array | Type::Map | Type::Unknown,
+        ) => Some(Type::Integer),
+        _ => None,
+    }
+}
+
+/// result type of a binary operation, following the expression evaluator
+///
+/// operations on values of unknown type are accepted
+fn binary_type(binary: &Binary, left: Type, right: Type) -> Option<Type> {
+    let result = match binary {
+        Binary::LessThan
+        | Binary::GreaterThan
+        | Binary::LessOrEqual
+        | Binary::GreaterOrEqual
+        | Binary::Equal
+        | Binary::NotEqual
+        | Binary::Contains
+        | Binary::Prefix
+        | Binary::Suffix
+        | Binary::Regex
+        | Binary::And
+        | Binary::Or => Type::Bool,
+        Binary::Sub
+        | Binary::Mul
+        | Binary::Div
+        | Binary::BitwiseAnd
+        | Binary::BitwiseOr
+        | Binary::BitwiseXor => Type::Integer,
+        Binary::Intersection | Binary::Union => Type::Set,
+        Binary::Get => Type::Unknown,
+        Binary::Add => match (left, right) {
+            (Type::Unknown, t) | (t, _) => t,
+        },
+    };
+
+    if left == Type::Unknown || right == Type::Unknown {
+        return Some(result);
+    }
+
+    let valid = match binary {
+        Binary::LessThan | Binary::GreaterThan | Binary::LessOrEqual | Binary::GreaterOrEqual => {
+            left == right && matches!(left, Type::Integer | Type::Date)
+        }
+        Binary::Equal | Binary::NotEqual => left == right,
+        Binary::Contains => {
+            matches!(left, Type::Set | Type::Array)
+                || (matches!(left, Type::String | Type::Map) && right == Type::String)
+        }
+        Binary::Prefix | Binary::Suffix | Binary::Regex => {
+            left == Type::String && right == Type::String
+        }
+        Binary::Add => left == right && matches!(left, Type::Integer | Type::String),
+        Binary::Sub
+        | Binary::Mul
+        | Binary::Div
+        | Binary::BitwiseAnd
+        | Binary::BitwiseOr
+        | Binary::BitwiseXor => left == Type::Integer && right == Type::Integer,
+        Binary::And | Binary::Or => left == Type::Bool && right == Type::Bool,
+        Binary::Intersection | Binary::Union => left == Type::Set && right == Type::Set,
+        Binary::Get => {
+            (left == Type::Array && right == Type::Integer)
+                || (left == Type::Map && right == Type::String)
+        }
+    };
+
+    valid.then_some(result)
+}
+
+/// rejects expressions that would fail with an invalid type during evaluation,
+/// or that cannot evaluate to a boolean
+fn check_expression(
+    expression: &Expression,
+    variables: &HashMap<String, ParameterType>,
+) -> Result<(), error::Token> {
+    let invalid = |message: String| {
+        Err(error::Token::Language(LanguageError::ExpressionType {
+            expression: expression.to_string(),
+            message,
+        }))
+    };
+
+    let mut stack = Vec::new();
+    for op in &expression.ops {
+        match op {
+            Op::Value(term) => stack.push(term_type(term, variables)),
+            Op::Unary(unary) => {
+                let value = stack.pop().unwrap_or(Type::Unknown);
+                match unary_type(unary, value) {
+                    Some(t) => stack.push(t),
+                    None => return invalid(format!("{:?} cannot be applied to {}", unary, value)),
+                }
+            }
+            Op::Binary(binary) => {
+                let right = stack.pop().unwrap_or(Type::Unknown);
+                let left = stack.pop().unwrap_or(Type::Unknown);
+                match binary_type(binary, left, right) {
+                    Some(t) => stack.push(t),
+                    None => {
+                        return invalid(format!(
+                            "{:?} cannot be applied to {} and {}",
+                            binary, left, right
+                        ))
+                    }
+                }
+            }
+        }
+    }
+
+    match stack.pop() {
+        Some(Type::Bool) | Some(Type::Unknown) | None => Ok(()),
+        Some(t) => invalid(format!("evaluates to {} instead of bool", t)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::BlockBuilder, Authorizer};
+
+    fn language_error(e: error::Token) -> LanguageError {
+        match e {
+            error::Token::Language(e) => e,
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn facts_and_heads() {
+        let mut builder = BlockBuilder::new();
+        builder
+            .add_code(
+                r#"
+                schema right(string, string);
+                right("file1", "read");
+                "#,
+            )
+            .unwrap();
+
+        assert_eq!(
+            language_error(builder.add_fact(r#"right("file1")"#).unwrap_err()),
+            LanguageError::SchemaArity {
+                predicate: "right".to_string(),
+                expected: 2,
+                actual: 1,
+            }
+        );
+        assert_eq!(
+            language_error(builder.add_fact(r#"right("file1", 1)"#).unwrap_err()),
+            LanguageError::SchemaType {
+                predicate: "right".to_string(),
+                position: 1,
+                expected: "string".to_string(),
+                actual: "int".to_string(),
+            }
+        );
+
+        // the type of $n comes from the body
+        builder
+            .add_code("schema count(string, int); schema owner(string);")
+            .unwrap();
+        builder
+            .add_rule("right($f, \"write\") <- owner($f)")
+            .unwrap();
+        assert_eq!(
+            language_error(
+                builder
+                    .add_rule("right($n, \"read\") <- count($f, $n)")
+                    .unwrap_err()
+            ),
+            LanguageError::SchemaType {
+                predicate: "right".to_string(),
+                position: 0,
+                expected: "string".to_string(),
+                actual: "int".to_string(),
+            }
+        );
+        assert!(builder
+            .add_check("check if count($f, $n), owner($n)")
+            .is_err());
+
+        // predicates without a schema are not checked
+        builder.add_fact("other(1)").unwrap();
+        builder.add_fact("other(\"a\", true)").unwrap();
+
+        assert_eq!(
+            language_error(builder.add_schema("schema right(string)").unwrap_err()),
+            LanguageError::SchemaConflict {
+                predicate: "right".to_string()
+            }
+        );
+        builder.add_schema("schema right(string, string)").unwrap();
+
+        // existing facts are checked against new schemas
+        assert!(builder.add_schema("schema other(int)").is_err());
+        assert!(builder.schemas.iter().all(|schema| schema.name != "other"));
+    }
+
+    #[test]
+    fn expressions() {
+        let mut authorizer = Authorizer::new();
+        authorizer
+            .add_code(
+                r#"
+                schema resource(string);
+                schema expiration(date);
+                check if resource($r), $r.starts_with("/public/");
+                check if expiration($e), $e > 2022-01-01T00:00:00Z;
+                "#,
+            )
+            .unwrap();
+
+        let e = language_error(
+            authorizer
+                .add_check("check if resource($r), $r > 3")
+                .unwrap_err(),
+        );
+        assert!(matches!(e, LanguageError::ExpressionType { .. }), "{}", e);
+        assert!(authorizer
+            .add_policy("allow if expiration($e), $e.length() > 3")
+            .is_err());
+        assert!(authorizer
+            .add_rule("length($n) <- resource($r), $n == $r.length(), $r + 1")
+            .is_err());
+        assert!(authorizer
+            .add_code("deny if expiration($e), $e.contains(\"a\")")
+            .is_err());
+
+        // the type of unbound values is unknown
+        authorizer
+            .add_check("check if value($v), $v > 3 || $v.starts_with(\"a\")")
+            .unwrap();
+        authorizer
+            .add_check("check if resource($r), $r == \"/public/\" + \"index.html\"")
+            .unwrap();
+    }
+}
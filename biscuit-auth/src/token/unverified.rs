@@ -1,5 +1,5 @@
-use std::collections::HashMap;
 use std::convert::TryInto;
+use std::sync::Arc;
 
 use super::{default_symbol_table, Biscuit, Block};
 use crate::{
@@ -8,7 +8,9 @@ use crate::{
     crypto::PublicKey,
     datalog::SymbolTable,
     error,
-    format::{convert::proto_block_to_token_block, schema, SerializedBiscuit},
+    format::{
+        convert::proto_block_to_token_block, proto_external_signature, schema, SerializedBiscuit,
+    },
     token::{ThirdPartyBlockContents, ThirdPartyRequest},
     KeyPair, RootKeyProvider,
 };
@@ -23,10 +25,9 @@ use prost::Message;
 /// and then used for authorization
 #[derive(Clone, Debug)]
 pub struct UnverifiedBiscuit {
-    pub(crate) authority: schema::Block,
-    pub(crate) blocks: Vec<schema::Block>,
-    pub(crate) symbols: SymbolTable,
-    pub(crate) public_key_to_block_id: HashMap<usize, Vec<usize>>,
+    pub(crate) authority: Arc<schema::Block>,
+    pub(crate) blocks: Vec<Arc<schema::Block>>,
+    pub(crate) symbols: Arc<SymbolTable>,
     container: SerializedBiscuit,
 }
 
@@ -69,7 +70,6 @@ impl UnverifiedBiscuit {
             authority: self.authority,
             blocks: self.blocks,
             symbols: self.symbols,
-            public_key_to_block_id: self.public_key_to_block_id,
             container: self.container,
         })
     }
@@ -100,13 +100,12 @@ impl UnverifiedBiscuit {
     pub fn from_with_symbols(slice: &[u8], mut symbols: SymbolTable) -> Result<Self, error::Token> {
         let container = SerializedBiscuit::deserialize(slice)?;
 
-        let (authority, blocks, public_key_to_block_id) = container.extract_blocks(&mut symbols)?;
+        let (authority, blocks) = container.extract_blocks(&mut symbols)?;
 
         Ok(UnverifiedBiscuit {
             authority,
             blocks,
-            symbols,
-            public_key_to_block_id,
+            symbols: Arc::new(symbols),
             container,
         })
     }
@@ -129,7 +128,7 @@ impl UnverifiedBiscuit {
         keypair: &KeyPair,
         block_builder: BlockBuilder,
     ) -> Result<Self, error::Token> {
-        let block = block_builder.build(self.symbols.clone());
+        let block = block_builder.build(SymbolTable::derive(&self.symbols));
 
         if !self.symbols.is_disjoint(&block.symbols) {
             return Err(error::Token::Format(error::Format::SymbolTableOverlap));
@@ -137,25 +136,13 @@ impl UnverifiedBiscuit {
 
         let authority = self.authority.clone();
         let mut blocks = self.blocks.clone();
-        let mut symbols = self.symbols.clone();
-        let mut public_key_to_block_id = self.public_key_to_block_id.clone();
+        let mut symbols = SymbolTable::derive(&self.symbols);
 
         let container = self.container.append(keypair, &block, None)?;
 
         symbols.extend(&block.symbols)?;
         symbols.public_keys.extend(&block.public_keys)?;
 
-        if let Some(index) = block
-            .external_key
-            .as_ref()
-            .and_then(|pk| symbols.public_keys.get(pk))
-        {
-            public_key_to_block_id
-                .entry(index as usize)
-                .or_default()
-                .push(self.block_count() + 1);
-        }
-
         let deser = schema::Block::decode(
             &container
                 .blocks
@@ -169,13 +156,12 @@ impl UnverifiedBiscuit {
                 e
             )))
         })?;
-        blocks.push(deser);
+        blocks.push(Arc::new(deser));
 
         Ok(UnverifiedBiscuit {
             authority,
             blocks,
-            symbols,
-            public_key_to_block_id,
+            symbols: Arc::new(symbols),
             container,
         })
     }
@@ -264,6 +250,14 @@ impl UnverifiedBiscuit {
             .map_err(error::Token::Format)?
         };
 
+        if index > 0 {
+            block.co_signers = self.container.blocks[index - 1]
+                .co_signatures
+                .iter()
+                .map(|signature| signature.public_key)
+                .collect();
+        }
+
         // we have to add the entire list of public keys here because
         // they are used to validate 3rd party tokens
         block.symbols.public_keys = self.symbols.public_keys.clone();
@@ -290,6 +284,7 @@ impl UnverifiedBiscuit {
         let ThirdPartyBlockContents {
             payload,
             external_signature,
+            co_signatures,
         } = schema::ThirdPartyBlockContents::decode(slice).map_err(|e| {
             error::Format::DeserializationError(format!("deserialization error: {:?}", e))
         })?;
@@ -338,39 +333,43 @@ impl UnverifiedBiscuit {
             public_key: external_key,
             signature,
         };
+        let co_signatures = co_signatures
+            .iter()
+            .map(proto_external_signature)
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let mut symbols = self.symbols.clone();
-        let mut public_key_to_block_id = self.public_key_to_block_id.clone();
+        let mut symbols = SymbolTable::derive(&self.symbols);
         let mut blocks = self.blocks.clone();
 
-        let container =
-            self.container
-                .append_serialized(&next_keypair, payload, Some(external_signature))?;
-
-        let token_block = proto_block_to_token_block(&block, Some(external_key)).unwrap();
-        for key in &token_block.public_keys.keys {
-            symbols.public_keys.insert_fallible(key)?;
+        let co_signers = co_signatures
+            .iter()
+            .map(|signature| signature.public_key)
+            .collect::<Vec<_>>();
+        let container = self.container.append_serialized(
+            &next_keypair,
+            payload,
+            Some(external_signature),
+            co_signatures,
+        )?;
+
+        // each signer of the block can be trusted with a scope. The signers are
+        // inserted before the block's public keys, as when deserializing the token
+        for key in std::iter::once(&external_key).chain(&co_signers) {
+            symbols.public_keys.insert(key);
         }
 
-        if let Some(index) = token_block
-            .external_key
-            .as_ref()
-            .and_then(|pk| symbols.public_keys.get(pk))
-        {
-            public_key_to_block_id
-                .entry(index as usize)
-                .or_default()
-                .push(self.block_count());
+        let token_block = proto_block_to_token_block(&block, Some(external_key))?;
+        for key in token_block.public_keys.iter() {
+            symbols.public_keys.insert_fallible(key)?;
         }
 
-        blocks.push(block);
+        blocks.push(Arc::new(block));
 
         Ok(UnverifiedBiscuit {
             authority: self.authority.clone(),
             blocks,
-            symbols,
+            symbols: Arc::new(symbols),
             container,
-            public_key_to_block_id,
         })
     }
 
@@ -3,7 +3,7 @@ use biscuit_quote::{
     authorizer, authorizer_merge, biscuit, biscuit_merge, block, block_merge, check, fact, policy,
     rule,
 };
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 #[test]
 fn block_macro() {
@@ -169,6 +169,63 @@ fn rule_macro() {
     );
 }
 
+#[test]
+fn composite_parameters_macro() {
+    let roles = vec![builder::string("admin"), builder::string("dev")];
+    let mut claims = BTreeMap::new();
+    claims.insert("sub".to_string(), builder::string("alice"));
+
+    let f = fact!(
+        r#"claims({roles}, {claims})"#,
+        roles = roles,
+        claims = claims
+    );
+    assert_eq!(
+        f.to_string(),
+        r#"claims(array["admin", "dev"], {"sub": "alice"})"#
+    );
+}
+
+#[test]
+fn typed_parameters_macro() {
+    let f = fact!(
+        r#"fact({my_key: string}, {count: int})"#,
+        my_key = "my_value",
+        count = 1
+    );
+
+    assert_eq!(f.to_string(), r#"fact("my_value", 1)"#);
+    assert_eq!(
+        f.parameter_types.get("count"),
+        Some(&builder::ParameterType::Integer)
+    );
+}
+
+#[test]
+fn schema_macro() {
+    let b = block!(
+        r#"schema right(string, string);
+            right("file1", {kind});
+            "#,
+        kind = "read",
+    );
+    assert_eq!(
+        b.to_string(),
+        r#"schema right(string, string);
+right("file1", "read");
+"#,
+    );
+}
+
+#[test]
+#[should_panic]
+fn schema_macro_mismatch() {
+    let _ = block!(
+        r#"schema right(string, string); right("file1", {kind});"#,
+        kind = 1
+    );
+}
+
 #[test]
 fn fact_macro() {
     let mut term_set = BTreeSet::new();
@@ -0,0 +1,258 @@
+use biscuit_auth::{
+    builder::BlockBuilder, error, Authorizer, Biscuit, KeyPair, PublicKey, ThirdPartyRequest,
+    UnverifiedBiscuit,
+};
+
+use crate::{
+    error::Error,
+    input::{read_binary, read_string, write},
+    params, public_key, Append, Authorize, Command, Create, Keygen, Output, Print, Seal, Snapshot,
+    ThirdParty, TokenInput,
+};
+
+/// runs a command
+///
+/// returns false if a token was not authorized
+pub fn run(command: Command) -> Result<bool, Error> {
+    match command {
+        Command::Keygen(args) => keygen(args)?,
+        Command::Create(args) => create(args)?,
+        Command::Append(args) => append(args)?,
+        Command::Seal(args) => seal(args)?,
+        Command::Print(args) => print(args)?,
+        Command::Authorize(args) => return authorize(args),
+        Command::RevocationIds(args) => revocation_ids(args)?,
+        Command::ThirdParty(command) => third_party(command)?,
+        Command::Snapshot(args) => snapshot(args)?,
+    }
+
+    Ok(true)
+}
+
+fn keygen(args: Keygen) -> Result<(), Error> {
+    let keypair = KeyPair::new();
+    let (private_key, public_key) = if args.pem {
+        (
+            keypair.to_private_key_pem()?,
+            keypair.public().to_public_key_pem()?,
+        )
+    } else {
+        (
+            format!("{}\n", keypair.private().to_bytes_hex()),
+            format!("{}\n", keypair.public()),
+        )
+    };
+
+    match &args.private_key_output {
+        Some(path) => {
+            write(Some(path), private_key.as_bytes())?;
+            print!("{}", public_key);
+        }
+        None if args.pem => print!("{}{}", private_key, public_key),
+        None => print!("Private key: {}Public key: {}", private_key, public_key),
+    }
+
+    Ok(())
+}
+
+fn create(args: Create) -> Result<(), Error> {
+    let keypair = args.private_key.keypair()?;
+    let code = read_string(&args.datalog)?;
+    let (terms, keys) = params::split(&args.params.params);
+
+    let mut builder = Biscuit::builder();
+    builder.add_code_with_params(code, terms, keys)?;
+    if let Some(root_key_id) = args.root_key_id {
+        builder.set_root_key_id(root_key_id);
+    }
+
+    let token = builder.build(&keypair)?;
+    output(&args.output, &token.to_vec()?)
+}
+
+fn append(args: Append) -> Result<(), Error> {
+    let token = token(&args.token)?;
+    let code = read_string(&args.datalog)?;
+    let (terms, keys) = params::split(&args.params.params);
+
+    let mut builder = BlockBuilder::new();
+    builder.add_code_with_params(code, terms, keys)?;
+
+    let token = token.append(builder)?;
+    output(&args.output, &token.to_vec()?)
+}
+
+fn seal(args: Seal) -> Result<(), Error> {
+    let token = token(&args.token)?.seal()?;
+    output(&args.output, &token.to_vec()?)
+}
+
+fn print(args: Print) -> Result<(), Error> {
+    let data = read_binary(&args.token)?;
+    let token = UnverifiedBiscuit::from(&data)?;
+
+    if let Some(root) = public_key(&args.public_key, &args.public_key_file)? {
+        Biscuit::from(&data, root)?;
+        println!("Signatures verified with root key {}\n", root);
+    }
+    if let Some(root_key_id) = token.root_key_id() {
+        println!("Root key id: {}\n", root_key_id);
+    }
+
+    let external_keys = token.external_public_keys();
+    for index in 0..token.block_count() {
+        if index == 0 {
+            println!("Authority block:");
+        } else {
+            println!("Block {}:", index);
+        }
+        if let Some(Some(key)) = external_keys.get(index) {
+            println!("(signed by {})", PublicKey::from_bytes(key)?);
+        }
+        println!("{}", token.print_block_source(index)?);
+    }
+
+    Ok(())
+}
+
+fn authorize(args: Authorize) -> Result<bool, Error> {
+    let root = args.public_key.public_key()?;
+    let token = Biscuit::from(read_binary(&args.token)?, root)?;
+    let code = read_string(&args.authorizer)?;
+    let (terms, keys) = params::split(&args.params.params);
+
+    let mut authorizer = Authorizer::new();
+    authorizer.add_token(&token)?;
+    authorizer.add_code_with_params(code, terms, keys)?;
+    if args.include_time {
+        authorizer.set_time();
+    }
+
+    let result = authorizer.authorize();
+
+    if args.print_world {
+        println!("{}\n", authorizer.print_world());
+    }
+    if let Some(path) = &args.snapshot_output {
+        let snapshot = authorizer.to_base64_snapshot()?;
+        write(Some(path), format!("{}\n", snapshot).as_bytes())?;
+    }
+
+    let policies = authorizer.dump().3;
+    let checks = match result {
+        Ok(index) => {
+            println!("Authorized by policy {}: {}", index, policies[index]);
+            return Ok(true);
+        }
+        Err(error::Token::FailedLogic(error::Logic::Unauthorized { policy, checks })) => {
+            match policy {
+                error::MatchedPolicy::Allow(index) => println!(
+                    "Denied: policy {} matched ({}), but checks failed",
+                    index, policies[index]
+                ),
+                error::MatchedPolicy::Deny(index) => {
+                    println!("Denied by policy {}: {}", index, policies[index])
+                }
+            }
+            checks
+        }
+        Err(error::Token::FailedLogic(error::Logic::NoMatchingPolicy { checks })) => {
+            println!("Denied: no policy matched");
+            checks
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if !checks.is_empty() {
+        println!("Failed checks:");
+    }
+    for check in checks {
+        match check {
+            error::FailedCheck::Block(check) => println!(
+                "- block {}, check {}: {}",
+                check.block_id, check.check_id, check.rule
+            ),
+            error::FailedCheck::Authorizer(check) => {
+                println!("- authorizer, check {}: {}", check.check_id, check.rule)
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+fn revocation_ids(args: TokenInput) -> Result<(), Error> {
+    for id in token(&args.token)?.revocation_identifiers() {
+        println!("{}", hex::encode(id));
+    }
+
+    Ok(())
+}
+
+fn third_party(command: ThirdParty) -> Result<(), Error> {
+    match command {
+        ThirdParty::Request {
+            token: path,
+            output: out,
+        } => {
+            let request = token(&path)?.third_party_request()?;
+            output(&out, &request.serialize()?)
+        }
+        ThirdParty::CreateBlock {
+            request,
+            datalog,
+            private_key,
+            params,
+            output: out,
+        } => {
+            let keypair = private_key.keypair()?;
+            let request = ThirdPartyRequest::deserialize(&read_binary(&request)?)?;
+            let code = read_string(&datalog)?;
+            let (terms, keys) = params::split(&params.params);
+
+            let mut builder = BlockBuilder::new();
+            builder.add_code_with_params(code, terms, keys)?;
+
+            let block = request.create_block(&keypair.private(), builder)?;
+            output(&out, &block.serialize()?)
+        }
+        ThirdParty::Append {
+            token: path,
+            block,
+            output: out,
+        } => {
+            let token = token(&path)?.append_third_party(&read_binary(&block)?)?;
+            output(&out, &token.to_vec()?)
+        }
+    }
+}
+
+fn snapshot(args: Snapshot) -> Result<(), Error> {
+    let snapshot = read_string(&args.snapshot)?;
+    let authorizer = Authorizer::from_base64_snapshot(snapshot.trim())?;
+
+    if args.code {
+        print!("{}", authorizer.dump_code());
+    } else {
+        println!("{}", authorizer.print_world());
+    }
+
+    Ok(())
+}
+
+fn token(path: &std::path::Path) -> Result<UnverifiedBiscuit, Error> {
+    Ok(UnverifiedBiscuit::from(read_binary(path)?)?)
+}
+
+/// writes raw data, or URL safe base64
+fn output(output: &Output, data: &[u8]) -> Result<(), Error> {
+    if output.raw {
+        write(output.output.as_deref(), data)
+    } else {
+        let encoded = base64::encode_config(data, base64::URL_SAFE);
+        write(
+            output.output.as_deref(),
+            format!("{}\n", encoded).as_bytes(),
+        )
+    }
+}
@@ -0,0 +1,33 @@
+use std::{io, path::PathBuf};
+
+use biscuit_auth::error;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("cannot read {}: {error}", path.display())]
+    Read { path: PathBuf, error: io::Error },
+    #[error("cannot write {}: {error}", path.display())]
+    Write { path: PathBuf, error: io::Error },
+    #[error("invalid input: {0}")]
+    Input(String),
+    #[error("invalid parameter: {0}")]
+    Param(String),
+    #[error("{}", describe(.0))]
+    Token(#[from] error::Token),
+}
+
+impl From<error::Format> for Error {
+    fn from(e: error::Format) -> Self {
+        Error::Token(e.into())
+    }
+}
+
+/// adds the details of the underlying error to the generic message
+fn describe(e: &error::Token) -> String {
+    match e {
+        error::Token::Format(f) => format!("{}: {}", e, f),
+        error::Token::FailedLogic(l) => format!("{}: {}", e, l),
+        error::Token::RunLimit(l) => format!("{}: {}", e, l),
+        e => e.to_string(),
+    }
+}
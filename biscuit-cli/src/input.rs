@@ -0,0 +1,106 @@
+//! reading keys, tokens and datalog, and writing results
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use biscuit_auth::{KeyPair, PrivateKey, PublicKey};
+
+use crate::error::Error;
+
+/// reads a file, or the standard input if the path is `-`
+pub fn read(path: &Path) -> Result<Vec<u8>, Error> {
+    let result = if path == Path::new("-") {
+        let mut data = Vec::new();
+        io::stdin().read_to_end(&mut data).map(|_| data)
+    } else {
+        fs::read(path)
+    };
+
+    result.map_err(|error| Error::Read {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+pub fn read_string(path: &Path) -> Result<String, Error> {
+    String::from_utf8(read(path)?)
+        .map_err(|_| Error::Input(format!("{} is not valid UTF-8", path.display())))
+}
+
+/// reads base64 encoded data, or raw binary data
+pub fn read_binary(path: &Path) -> Result<Vec<u8>, Error> {
+    let data = read(path)?;
+    let decoded = std::str::from_utf8(&data)
+        .ok()
+        .and_then(|s| base64::decode_config(s.trim(), base64::URL_SAFE).ok());
+
+    Ok(decoded.unwrap_or(data))
+}
+
+/// writes to a file, or to the standard output
+pub fn write(path: Option<&Path>, data: &[u8]) -> Result<(), Error> {
+    match path {
+        None => io::stdout().write_all(data).map_err(|error| Error::Write {
+            path: PathBuf::from("-"),
+            error,
+        }),
+        Some(path) => fs::write(path, data).map_err(|error| Error::Write {
+            path: path.to_path_buf(),
+            error,
+        }),
+    }
+}
+
+/// parses a hex encoded private key, or a PKCS#8 PEM document
+pub fn private_key(key: &str) -> Result<KeyPair, Error> {
+    let key = key.trim();
+    if key.starts_with("-----BEGIN") {
+        Ok(KeyPair::from_private_key_pem(key)?)
+    } else {
+        let key = key.strip_prefix("ed25519-private/").unwrap_or(key);
+        Ok(KeyPair::from(&PrivateKey::from_bytes_hex(key)?))
+    }
+}
+
+/// parses a hex encoded public key, with or without the `ed25519/` prefix,
+/// or a SubjectPublicKeyInfo PEM document
+pub fn public_key(key: &str) -> Result<PublicKey, Error> {
+    let key = key.trim();
+    if key.starts_with("-----BEGIN") {
+        Ok(PublicKey::from_public_key_pem(key)?)
+    } else {
+        let key = key.strip_prefix("ed25519/").unwrap_or(key);
+        Ok(PublicKey::from_bytes_hex(key)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        let keypair = KeyPair::new();
+
+        for key in [
+            keypair.private().to_bytes_hex(),
+            format!("ed25519-private/{}\n", keypair.private().to_bytes_hex()),
+            keypair.to_private_key_pem().unwrap(),
+        ] {
+            assert_eq!(private_key(&key).unwrap().public(), keypair.public());
+        }
+
+        for key in [
+            keypair.public().to_bytes_hex(),
+            keypair.public().to_string(),
+            keypair.public().to_public_key_pem().unwrap(),
+        ] {
+            assert_eq!(public_key(&key).unwrap(), keypair.public());
+        }
+
+        assert!(private_key("abcd").is_err());
+        assert!(public_key("ed25519/zz").is_err());
+    }
+}
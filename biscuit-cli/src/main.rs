@@ -0,0 +1,254 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::{Args, Parser, Subcommand};
+
+mod commands;
+mod error;
+mod input;
+mod params;
+
+use error::Error;
+use params::Param;
+
+/// Create, attenuate, inspect and authorize biscuit tokens
+///
+/// Tokens, third party requests and blocks are read and written in URL safe
+/// base64, unless `--raw` is used. Raw tokens are detected when reading.
+/// Paths can be `-` to read from the standard input.
+#[derive(Parser)]
+#[command(name = "biscuit", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a key pair
+    Keygen(Keygen),
+    /// Create a token from a datalog file, signed with a root private key
+    Create(Create),
+    /// Attenuate a token with a new block
+    Append(Append),
+    /// Seal a token, preventing further attenuation
+    Seal(Seal),
+    /// Print the content of a token
+    Print(Print),
+    /// Authorize a token with datalog code
+    Authorize(Authorize),
+    /// List the revocation ids of a token
+    RevocationIds(TokenInput),
+    /// Third party blocks: request, create and append
+    #[command(subcommand)]
+    ThirdParty(ThirdParty),
+    /// Print the content of an authorizer snapshot
+    Snapshot(Snapshot),
+}
+
+#[derive(Args)]
+struct Keygen {
+    /// Write keys as PKCS#8 and SubjectPublicKeyInfo PEM documents instead of hex
+    #[arg(long)]
+    pem: bool,
+    /// Write the private key to a file and only print the public key
+    #[arg(long, value_name = "PATH")]
+    private_key_output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct PrivateKeyArgs {
+    /// Private key, hex encoded
+    #[arg(long, value_name = "KEY")]
+    private_key: Option<String>,
+    /// File containing a hex encoded or PEM private key
+    #[arg(long, value_name = "PATH")]
+    private_key_file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct PublicKeyArgs {
+    /// Root public key, hex encoded, with or without the `ed25519/` prefix
+    #[arg(long, value_name = "KEY")]
+    public_key: Option<String>,
+    /// File containing a hex encoded or PEM public key
+    #[arg(long, value_name = "PATH")]
+    public_key_file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ParamArgs {
+    /// Datalog parameter, TYPE is string (default), integer, bool, date, bytes or pubkey
+    #[arg(long = "param", value_name = "NAME[:TYPE]=VALUE")]
+    params: Vec<Param>,
+}
+
+#[derive(Args)]
+struct Output {
+    /// Write raw bytes instead of base64
+    #[arg(long)]
+    raw: bool,
+    /// Output file, defaults to the standard output
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct TokenInput {
+    /// Token file
+    token: PathBuf,
+}
+
+#[derive(Args)]
+struct Create {
+    /// Datalog file of the authority block
+    datalog: PathBuf,
+    #[command(flatten)]
+    private_key: PrivateKeyArgs,
+    #[command(flatten)]
+    params: ParamArgs,
+    /// Root key id, to help verifiers choose the root public key
+    #[arg(long)]
+    root_key_id: Option<u32>,
+    #[command(flatten)]
+    output: Output,
+}
+
+#[derive(Args)]
+struct Append {
+    /// Token file
+    token: PathBuf,
+    /// Datalog file of the new block
+    datalog: PathBuf,
+    #[command(flatten)]
+    params: ParamArgs,
+    #[command(flatten)]
+    output: Output,
+}
+
+#[derive(Args)]
+struct Seal {
+    /// Token file
+    token: PathBuf,
+    #[command(flatten)]
+    output: Output,
+}
+
+#[derive(Args)]
+struct Print {
+    /// Token file
+    token: PathBuf,
+    /// Verify the token signatures with this root public key
+    #[arg(long, value_name = "KEY")]
+    public_key: Option<String>,
+    /// Verify the token signatures with the public key in this file
+    #[arg(long, value_name = "PATH", conflicts_with = "public_key")]
+    public_key_file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct Authorize {
+    /// Token file
+    token: PathBuf,
+    #[command(flatten)]
+    public_key: PublicKeyArgs,
+    /// Datalog file of the authorizer
+    #[arg(long, value_name = "PATH")]
+    authorizer: PathBuf,
+    #[command(flatten)]
+    params: ParamArgs,
+    /// Add the current time as a `time` fact
+    #[arg(long)]
+    include_time: bool,
+    /// Print the facts, rules, checks and policies of the authorizer
+    #[arg(long)]
+    print_world: bool,
+    /// Write a snapshot of the authorizer, in base64
+    #[arg(long, value_name = "PATH")]
+    snapshot_output: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum ThirdParty {
+    /// Create a third party block request from a token
+    Request {
+        /// Token file
+        token: PathBuf,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Create a third party block, signed with the third party private key
+    CreateBlock {
+        /// Third party block request file
+        request: PathBuf,
+        /// Datalog file of the block
+        datalog: PathBuf,
+        #[command(flatten)]
+        private_key: PrivateKeyArgs,
+        #[command(flatten)]
+        params: ParamArgs,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// Append a third party block to the token it was requested from
+    Append {
+        /// Token file
+        token: PathBuf,
+        /// Third party block file
+        block: PathBuf,
+        #[command(flatten)]
+        output: Output,
+    },
+}
+
+#[derive(Args)]
+struct Snapshot {
+    /// Snapshot file, in base64
+    snapshot: PathBuf,
+    /// Print the authorizer code instead of the world
+    #[arg(long)]
+    code: bool,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match commands::run(cli.command) {
+        Ok(true) => ExitCode::SUCCESS,
+        // the command ran, but the token was not authorized
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+impl PrivateKeyArgs {
+    fn keypair(&self) -> Result<biscuit_auth::KeyPair, Error> {
+        match (&self.private_key, &self.private_key_file) {
+            (Some(key), _) => input::private_key(key),
+            (None, Some(path)) => input::private_key(&input::read_string(path)?),
+            (None, None) => Err(Error::Input("missing private key".to_string())),
+        }
+    }
+}
+
+impl PublicKeyArgs {
+    fn public_key(&self) -> Result<biscuit_auth::PublicKey, Error> {
+        public_key(&self.public_key, &self.public_key_file)?
+            .ok_or_else(|| Error::Input("missing public key".to_string()))
+    }
+}
+
+fn public_key(
+    key: &Option<String>,
+    path: &Option<PathBuf>,
+) -> Result<Option<biscuit_auth::PublicKey>, Error> {
+    match (key, path) {
+        (Some(key), _) => input::public_key(key).map(Some),
+        (None, Some(path)) => input::public_key(&input::read_string(path)?).map(Some),
+        (None, None) => Ok(None),
+    }
+}
@@ -0,0 +1,130 @@
+//! datalog parameters given on the command line
+use std::{collections::HashMap, str::FromStr};
+
+use biscuit_auth::{builder::Term, PublicKey};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::error::Error;
+
+/// a `--param NAME[:TYPE]=VALUE` argument
+///
+/// the type is one of `string` (the default), `integer`, `bool`, `date` (RFC 3339),
+/// `bytes` (hex encoded) or `pubkey` (for scope parameters, as in `trusting {key}`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub value: Value,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Term(Term),
+    PublicKey(PublicKey),
+}
+
+impl FromStr for Param {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| Error::Param(format!("expected NAME[:TYPE]=VALUE, got '{}'", s)))?;
+        let (name, kind) = name.split_once(':').unwrap_or((name, "string"));
+
+        let invalid = |e: &dyn std::fmt::Display| {
+            Error::Param(format!("invalid {} value for '{}': {}", kind, name, e))
+        };
+        let value = match kind {
+            "string" => Value::Term(Term::Str(value.to_string())),
+            "integer" => Value::Term(Term::Integer(value.parse().map_err(|e| invalid(&e))?)),
+            "bool" => Value::Term(Term::Bool(value.parse().map_err(|e| invalid(&e))?)),
+            "date" => {
+                let date = OffsetDateTime::parse(value, &Rfc3339).map_err(|e| invalid(&e))?;
+                let timestamp = u64::try_from(date.unix_timestamp())
+                    .map_err(|_| invalid(&"dates before 1970 are not supported"))?;
+                Value::Term(Term::Date(timestamp))
+            }
+            "bytes" => Value::Term(Term::Bytes(hex::decode(value).map_err(|e| invalid(&e))?)),
+            "pubkey" => Value::PublicKey(crate::input::public_key(value).map_err(|e| invalid(&e))?),
+            _ => {
+                return Err(Error::Param(format!(
+                "unknown type '{}' for '{}', expected string, integer, bool, date, bytes or pubkey",
+                kind, name
+            )))
+            }
+        };
+
+        Ok(Param {
+            name: name.to_string(),
+            value,
+        })
+    }
+}
+
+/// splits parameters between terms and public keys
+pub fn split(params: &[Param]) -> (HashMap<String, Term>, HashMap<String, PublicKey>) {
+    let mut terms = HashMap::new();
+    let mut keys = HashMap::new();
+
+    for param in params {
+        match &param.value {
+            Value::Term(term) => {
+                terms.insert(param.name.clone(), term.clone());
+            }
+            Value::PublicKey(key) => {
+                keys.insert(param.name.clone(), *key);
+            }
+        }
+    }
+
+    (terms, keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(s: &str) -> Term {
+        match s.parse::<Param>().unwrap().value {
+            Value::Term(term) => term,
+            Value::PublicKey(_) => panic!("expected a term"),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "user=alice".parse::<Param>().unwrap(),
+            Param {
+                name: "user".to_string(),
+                value: Value::Term(Term::Str("alice".to_string())),
+            }
+        );
+        assert_eq!(term("s:string=a=b"), Term::Str("a=b".to_string()));
+        assert_eq!(term("n:integer=-12"), Term::Integer(-12));
+        assert_eq!(term("b:bool=true"), Term::Bool(true));
+        assert_eq!(term("d:date=2023-12-01T00:00:00Z"), Term::Date(1701388800));
+        assert_eq!(term("x:bytes=aabb"), Term::Bytes(vec![0xaa, 0xbb]));
+
+        let key = "ed25519/acdd6d5b53bfee478bf689f8e012fe7988bf755e3d7c5152947abc149bc20189";
+        let param = format!("key:pubkey={}", key).parse::<Param>().unwrap();
+        assert_eq!(
+            param.value,
+            Value::PublicKey(key.parse::<PublicKey>().unwrap())
+        );
+
+        let errors = ["novalue", "n:integer=a", "d:date=yesterday", "x:float=1.0"]
+            .iter()
+            .map(|s| s.parse::<Param>().unwrap_err().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                "invalid parameter: expected NAME[:TYPE]=VALUE, got 'novalue'",
+                "invalid parameter: invalid integer value for 'n': invalid digit found in string",
+                "invalid parameter: invalid date value for 'd': the 'year' component could not be parsed",
+                "invalid parameter: unknown type 'float' for 'x', expected string, integer, bool, date, bytes or pubkey",
+            ]
+        );
+    }
+}
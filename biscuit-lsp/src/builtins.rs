@@ -0,0 +1,52 @@
+//! documentation of the methods available in datalog expressions
+
+pub(crate) struct Method {
+    pub name: &'static str,
+    pub signature: &'static str,
+    pub documentation: &'static str,
+}
+
+pub(crate) const METHODS: &[Method] = &[
+    Method {
+        name: "contains",
+        signature: "$set.contains($value) | $set.contains($subset) | $string.contains($substring)",
+        documentation: "Tests if a set contains a value, or all the values of another set, or if a string contains a substring.",
+    },
+    Method {
+        name: "starts_with",
+        signature: "$string.starts_with($prefix)",
+        documentation: "Tests if a string starts with the prefix.",
+    },
+    Method {
+        name: "ends_with",
+        signature: "$string.ends_with($suffix)",
+        documentation: "Tests if a string ends with the suffix.",
+    },
+    Method {
+        name: "matches",
+        signature: "$string.matches($regex)",
+        documentation: "Tests if a string matches the regular expression.",
+    },
+    Method {
+        name: "intersection",
+        signature: "$set.intersection($other)",
+        documentation: "Returns the set of values present in both sets.",
+    },
+    Method {
+        name: "union",
+        signature: "$set.union($other)",
+        documentation: "Returns the set of values present in either set.",
+    },
+    Method {
+        name: "length",
+        signature: "$value.length()",
+        documentation: "Returns the length of a string (in bytes), a byte array or a set.",
+    },
+];
+
+pub(crate) fn method(name: &str) -> Option<&'static Method> {
+    METHODS.iter().find(|method| method.name == name)
+}
+
+/// statement keywords, offered as completions at the start of a statement
+pub(crate) const KEYWORDS: &[&str] = &["check if", "check all", "allow if", "deny if", "trusting"];
@@ -0,0 +1,428 @@
+//! analysis of an open datalog document
+use std::collections::BTreeSet;
+
+use biscuit_parser::{
+    builder::{Op, Predicate, Rule, Scope, Term},
+    error::{ParseError, Span},
+    parser::{parse_block_source, parse_source, SourceResult},
+};
+use serde_json::{json, Value};
+
+use crate::builtins;
+
+// completion item kinds, from the LSP specification
+const COMPLETION_METHOD: u32 = 2;
+const COMPLETION_FUNCTION: u32 = 3;
+const COMPLETION_VARIABLE: u32 = 6;
+const COMPLETION_KEYWORD: u32 = 14;
+
+const SEVERITY_ERROR: u32 = 1;
+
+pub(crate) struct Document {
+    text: String,
+    errors: Vec<ParseError>,
+    index: Index,
+}
+
+/// symbols of the last version of the document that parsed successfully
+///
+/// ranges are converted to LSP positions when indexing, so they stay valid
+/// while the text is being edited
+#[derive(Default)]
+struct Index {
+    definitions: Vec<(String, Value)>,
+    predicates: BTreeSet<String>,
+    parameters: BTreeSet<String>,
+}
+
+impl Document {
+    pub fn new(text: String) -> Self {
+        let mut document = Document {
+            text: String::new(),
+            errors: Vec::new(),
+            index: Index::default(),
+        };
+        document.update(text);
+        document
+    }
+
+    /// replaces the text of the document
+    ///
+    /// the symbols are kept from the previous version if the new one does not parse
+    pub fn update(&mut self, text: String) {
+        self.text = text;
+
+        // block code can start with a `trusting` annotation, authorizer code cannot
+        let result = if self.text.trim_start().starts_with("trusting") {
+            parse_block_source(&self.text)
+        } else {
+            parse_source(&self.text)
+        };
+
+        match result {
+            Ok(result) => {
+                self.errors.clear();
+                self.index = Index::new(&self.text, &result);
+            }
+            Err(errors) => {
+                self.errors = errors.into_iter().map(ParseError::from).collect();
+            }
+        }
+    }
+
+    pub fn diagnostics(&self) -> Vec<Value> {
+        self.errors
+            .iter()
+            .map(|error| {
+                let range = match &error.span {
+                    Some(span) => range(&self.text, span),
+                    None => json!({
+                        "start": { "line": 0, "character": 0 },
+                        "end": { "line": 0, "character": 0 },
+                    }),
+                };
+                let message = match &error.message {
+                    Some(message) => message.clone(),
+                    None if error.input.is_empty() => "unexpected end of input".to_string(),
+                    None => format!("unexpected input: '{}'", error.input),
+                };
+
+                json!({
+                    "range": range,
+                    "severity": SEVERITY_ERROR,
+                    "source": "biscuit",
+                    "message": message,
+                })
+            })
+            .collect()
+    }
+
+    /// documentation of the built-in method under the cursor
+    pub fn hover(&self, position: &Value) -> Option<Value> {
+        let offset = offset(&self.text, position)?;
+        let (start, end) = word_at(&self.text, offset)?;
+        if !self.text[..start].ends_with('.') {
+            return None;
+        }
+
+        let method = builtins::method(&self.text[start..end])?;
+        Some(json!({
+            "contents": {
+                "kind": "markdown",
+                "value": format!("```\n{}\n```\n\n{}", method.signature, method.documentation),
+            },
+            "range": range_from_offsets(&self.text, start, end),
+        }))
+    }
+
+    /// locations of the facts and rules producing the predicate under the cursor
+    pub fn definition(&self, uri: &str, position: &Value) -> Vec<Value> {
+        let name = offset(&self.text, position)
+            .and_then(|offset| word_at(&self.text, offset))
+            .filter(|(start, end)| {
+                !self.text[..*start].ends_with(['.', '$', '{'])
+                    && self.text[*end..].trim_start().starts_with('(')
+            })
+            .map(|(start, end)| &self.text[start..end]);
+
+        match name {
+            None => Vec::new(),
+            Some(name) => self
+                .index
+                .definitions
+                .iter()
+                .filter(|(predicate, _)| predicate == name)
+                .map(|(_, range)| json!({ "uri": uri, "range": range }))
+                .collect(),
+        }
+    }
+
+    /// completions for the word under the cursor: methods after a `.`,
+    /// parameters after a `{`, predicates and keywords otherwise
+    pub fn completion(&self, position: &Value) -> Vec<Value> {
+        let offset = match offset(&self.text, position) {
+            Some(offset) => offset,
+            None => return Vec::new(),
+        };
+        let start = word_start(&self.text, offset);
+        let prefix = &self.text[start..offset];
+        let before = &self.text[..start];
+
+        if before.ends_with('.') {
+            builtins::METHODS
+                .iter()
+                .filter(|method| method.name.starts_with(prefix))
+                .map(|method| {
+                    json!({
+                        "label": method.name,
+                        "kind": COMPLETION_METHOD,
+                        "detail": method.signature,
+                        "documentation": method.documentation,
+                    })
+                })
+                .collect()
+        } else if before.ends_with('{') {
+            self.index
+                .parameters
+                .iter()
+                .filter(|parameter| parameter.starts_with(prefix))
+                .map(|parameter| json!({ "label": parameter, "kind": COMPLETION_VARIABLE }))
+                .collect()
+        } else if before.ends_with('$') {
+            Vec::new()
+        } else {
+            let predicates = self
+                .index
+                .predicates
+                .iter()
+                .filter(|predicate| predicate.starts_with(prefix))
+                .map(|predicate| json!({ "label": predicate, "kind": COMPLETION_FUNCTION }));
+            let keywords = builtins::KEYWORDS
+                .iter()
+                .filter(|keyword| keyword.starts_with(prefix))
+                .map(|keyword| json!({ "label": keyword, "kind": COMPLETION_KEYWORD }));
+
+            predicates.chain(keywords).collect()
+        }
+    }
+}
+
+impl Index {
+    fn new(text: &str, result: &SourceResult) -> Self {
+        let mut index = Index::default();
+
+        for (_, fact) in &result.facts {
+            index.predicate(&fact.predicate);
+            if let Some(span) = &fact.span {
+                index
+                    .definitions
+                    .push((fact.predicate.name.clone(), range(text, span)));
+            }
+        }
+
+        for (_, rule) in &result.rules {
+            index.rule(rule);
+            if let Some(span) = &rule.span {
+                index
+                    .definitions
+                    .push((rule.head.name.clone(), range(text, span)));
+            }
+        }
+
+        let queries = result
+            .checks
+            .iter()
+            .flat_map(|(_, check)| check.queries.iter())
+            .chain(
+                result
+                    .policies
+                    .iter()
+                    .flat_map(|(_, policy)| policy.queries.iter()),
+            );
+        for query in queries {
+            // the head of a check query is not part of the source
+            for predicate in &query.body {
+                index.predicate(predicate);
+            }
+            index.expressions_and_scopes(query);
+        }
+
+        for scope in &result.scopes {
+            index.scope(scope);
+        }
+
+        index
+    }
+
+    fn rule(&mut self, rule: &Rule) {
+        self.predicate(&rule.head);
+        for predicate in &rule.body {
+            self.predicate(predicate);
+        }
+        self.expressions_and_scopes(rule);
+    }
+
+    fn expressions_and_scopes(&mut self, rule: &Rule) {
+        for expression in &rule.expressions {
+            for op in &expression.ops {
+                if let Op::Value(term) = op {
+                    self.term(term);
+                }
+            }
+        }
+        for scope in &rule.scopes {
+            self.scope(scope);
+        }
+    }
+
+    fn predicate(&mut self, predicate: &Predicate) {
+        self.predicates.insert(predicate.name.clone());
+        for term in &predicate.terms {
+            self.term(term);
+        }
+    }
+
+    fn term(&mut self, term: &Term) {
+        match term {
+            Term::Parameter(name) => {
+                self.parameters.insert(name.clone());
+            }
+            Term::Set(set) => {
+                for term in set {
+                    self.term(term);
+                }
+            }
+            Term::Array(array) => {
+                for term in array {
+                    self.term(term);
+                }
+            }
+            Term::Map(map) => {
+                for term in map.values() {
+                    self.term(term);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn scope(&mut self, scope: &Scope) {
+        for name in scope.parameters() {
+            self.parameters.insert(name.to_string());
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == ':'
+}
+
+fn word_start(text: &str, offset: usize) -> usize {
+    text[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word_char(*c))
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or(offset)
+}
+
+/// byte range of the word around `offset`
+fn word_at(text: &str, offset: usize) -> Option<(usize, usize)> {
+    let start = word_start(text, offset);
+    let end = text[offset..]
+        .find(|c| !is_word_char(c))
+        .map(|i| offset + i)
+        .unwrap_or(text.len());
+
+    if start == end {
+        None
+    } else {
+        Some((start, end))
+    }
+}
+
+/// converts an LSP position (0 based line, UTF-16 column) to a byte offset
+fn offset(text: &str, position: &Value) -> Option<usize> {
+    let line = position.get("line")?.as_u64()? as usize;
+    let character = position.get("character")?.as_u64()? as usize;
+
+    let mut line_start = 0;
+    for _ in 0..line {
+        line_start += text[line_start..].find('\n')? + 1;
+    }
+
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(line_start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(text.len())
+}
+
+/// converts a byte offset to an LSP position
+fn position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+
+    json!({
+        "line": before.matches('\n').count(),
+        "character": before[line_start..].encode_utf16().count(),
+    })
+}
+
+fn range_from_offsets(text: &str, start: usize, end: usize) -> Value {
+    json!({ "start": position(text, start), "end": position(text, end) })
+}
+
+fn range(text: &str, span: &Span) -> Value {
+    range_from_offsets(text, span.start.offset, span.end.offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions() {
+        let text = "fact(\"é😀\", 1);\nrule($a) <- fact($a, 1);";
+
+        // "😀" is 2 UTF-16 code units long
+        let offset_1 = text.find('1').unwrap();
+        assert_eq!(
+            position(text, offset_1),
+            json!({ "line": 0, "character": 12 })
+        );
+        assert_eq!(
+            offset(text, &json!({ "line": 0, "character": 12 })),
+            Some(offset_1)
+        );
+
+        let rule = text.find("rule").unwrap();
+        assert_eq!(position(text, rule), json!({ "line": 1, "character": 0 }));
+        assert_eq!(
+            offset(text, &json!({ "line": 1, "character": 0 })),
+            Some(rule)
+        );
+
+        // columns past the end of a line stop at the line end
+        assert_eq!(
+            offset(text, &json!({ "line": 0, "character": 100 })),
+            Some(text.find('\n').unwrap())
+        );
+        assert_eq!(offset(text, &json!({ "line": 5, "character": 0 })), None);
+    }
+
+    #[test]
+    fn nested_parameters() {
+        // parameters can only appear in the sets of arrays and maps
+        let document = Document::new(
+            r#"user({"ids": [{id}], "groups": array[[{group}], "dev"]});"#.to_string(),
+        );
+        assert_eq!(
+            document.index.parameters.iter().collect::<Vec<_>>(),
+            vec!["group", "id"]
+        );
+    }
+
+    #[test]
+    fn keeps_symbols_on_errors() {
+        let mut document = Document::new("right($a) <- user($a);".to_string());
+        assert!(document.diagnostics().is_empty());
+
+        document.update("right($a) <- user($a);\ncheck if right(".to_string());
+        assert_eq!(document.diagnostics().len(), 1);
+        assert_eq!(
+            document.definition("file:///test", &json!({ "line": 1, "character": 10 })),
+            vec![json!({
+                "uri": "file:///test",
+                "range": {
+                    "start": { "line": 0, "character": 0 },
+                    "end": { "line": 0, "character": 21 },
+                },
+            })]
+        );
+    }
+}
@@ -0,0 +1,18 @@
+//! Language server for biscuit datalog
+//!
+//! The server implements the subset of the Language Server Protocol needed
+//! to edit datalog files:
+//! - diagnostics published on every change, from `biscuit-parser` errors
+//! - hover documentation for built-in methods
+//! - go to definition, from a predicate to the facts and rules producing it
+//! - completion for predicates, parameters and methods
+//!
+//! Documents are synchronized in full on every change. [`run`] reads messages
+//! from any reader and writes to any writer, so the server can be driven by an
+//! in-process client as well as over stdin and stdout.
+mod builtins;
+mod document;
+mod server;
+pub mod transport;
+
+pub use server::{run, Server};
@@ -0,0 +1,11 @@
+use std::io;
+
+fn main() -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+
+    let shutdown = biscuit_lsp::run(stdin.lock(), stdout.lock())?;
+
+    // the client must request a shutdown before the exit notification
+    std::process::exit(if shutdown { 0 } else { 1 })
+}
@@ -0,0 +1,214 @@
+//! request dispatch and document management
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    document::Document,
+    transport::{read_message, write_message},
+};
+
+// error codes, from the JSON-RPC and LSP specifications
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// full document synchronization
+const TEXT_DOCUMENT_SYNC_FULL: u32 = 1;
+
+/// runs a language server until the client sends the `exit` notification
+/// or closes the input
+///
+/// returns true if the client requested a shutdown before exiting
+pub fn run<R: BufRead, W: Write>(mut reader: R, writer: W) -> io::Result<bool> {
+    let mut server = Server::new(writer);
+
+    while let Some(message) = read_message(&mut reader)? {
+        if !server.handle(&message)? {
+            break;
+        }
+    }
+
+    Ok(server.shutdown)
+}
+
+/// Language server state: the open documents, and the output channel
+pub struct Server<W: Write> {
+    writer: W,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+impl<W: Write> Server<W> {
+    pub fn new(writer: W) -> Self {
+        Server {
+            writer,
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    /// handles one message from the client
+    ///
+    /// returns false once the client sent the `exit` notification
+    pub fn handle(&mut self, message: &str) -> io::Result<bool> {
+        let message: Value = match serde_json::from_str(message) {
+            Ok(message) => message,
+            Err(e) => {
+                self.send(error_response(Value::Null, PARSE_ERROR, e.to_string()))?;
+                return Ok(true);
+            }
+        };
+
+        let method = message.get("method").and_then(Value::as_str);
+        let params = message.get("params").unwrap_or(&Value::Null);
+
+        match (method, message.get("id")) {
+            (Some("exit"), _) => return Ok(false),
+            (Some(method), Some(id)) => {
+                let response = match self.request(method, params) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, message)) => error_response(id.clone(), code, message),
+                };
+                self.send(response)?;
+            }
+            (Some(method), None) => self.notification(method, params)?,
+            // the server does not send requests, so there are no responses to handle
+            (None, _) => {}
+        }
+
+        Ok(true)
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        if self.shutdown {
+            return Err((INVALID_REQUEST, "the server is shutting down".to_string()));
+        }
+
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": TEXT_DOCUMENT_SYNC_FULL,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "completionProvider": { "triggerCharacters": [".", "{"] },
+                },
+                "serverInfo": {
+                    "name": "biscuit-lsp",
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/hover" => {
+                let (uri, position) = text_document_position(params)?;
+                Ok(self
+                    .documents
+                    .get(uri)
+                    .and_then(|document| document.hover(position))
+                    .unwrap_or(Value::Null))
+            }
+            "textDocument/definition" => {
+                let (uri, position) = text_document_position(params)?;
+                Ok(self
+                    .documents
+                    .get(uri)
+                    .map(|document| Value::from(document.definition(uri, position)))
+                    .unwrap_or(Value::Null))
+            }
+            "textDocument/completion" => {
+                let (uri, position) = text_document_position(params)?;
+                Ok(self
+                    .documents
+                    .get(uri)
+                    .map(|document| Value::from(document.completion(position)))
+                    .unwrap_or(Value::Null))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> io::Result<()> {
+        let uri = params
+            .pointer("/textDocument/uri")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+
+        match (method, uri) {
+            ("textDocument/didOpen", Some(uri)) => {
+                if let Some(text) = params.pointer("/textDocument/text").and_then(Value::as_str) {
+                    self.documents
+                        .insert(uri.clone(), Document::new(text.to_string()));
+                    self.publish_diagnostics(&uri)?;
+                }
+            }
+            ("textDocument/didChange", Some(uri)) => {
+                // with full synchronization, the last change contains the whole text
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Value::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Value::as_str);
+
+                if let (Some(document), Some(text)) = (self.documents.get_mut(&uri), text) {
+                    document.update(text.to_string());
+                    self.publish_diagnostics(&uri)?;
+                }
+            }
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(&uri);
+                self.publish_diagnostics(&uri)?;
+            }
+            // other notifications, like `initialized`, need no action
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics = self
+            .documents
+            .get(uri)
+            .map(Document::diagnostics)
+            .unwrap_or_default();
+
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    fn send(&mut self, message: Value) -> io::Result<()> {
+        write_message(&mut self.writer, &message.to_string())
+    }
+}
+
+fn text_document_position(params: &Value) -> Result<(&str, &Value), (i64, String)> {
+    let uri = params.pointer("/textDocument/uri").and_then(Value::as_str);
+    let position = params.get("position");
+
+    match (uri, position) {
+        (Some(uri), Some(position)) => Ok((uri, position)),
+        _ => Err((
+            INVALID_PARAMS,
+            "expected a text document and a position".to_string(),
+        )),
+    }
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
@@ -0,0 +1,79 @@
+//! JSON-RPC message framing, as used by the Language Server Protocol
+//!
+//! Each message is a header part, containing at least a `Content-Length`
+//! field, followed by an empty line and the JSON content.
+use std::io::{self, BufRead, Write};
+
+/// reads the content of the next message
+///
+/// returns `None` when the input is closed
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut content_length = None;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                let length = value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                content_length = Some(length);
+            }
+        }
+    }
+
+    let mut content = vec![0; content_length.unwrap_or_default()];
+    reader.read_exact(&mut content)?;
+
+    String::from_utf8(content)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// writes a message with its header
+pub fn write_message<W: Write>(writer: &mut W, content: &str) -> io::Result<()> {
+    write!(
+        writer,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framing() {
+        let mut output = Vec::new();
+        write_message(&mut output, r#"{"jsonrpc":"2.0","method":"initialized"}"#).unwrap();
+        write_message(&mut output, "{}").unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            "Content-Length: 40\r\n\r\n{\"jsonrpc\":\"2.0\",\"method\":\"initialized\"}Content-Length: 2\r\n\r\n{}"
+        );
+
+        let mut input = io::Cursor::new(output);
+        assert_eq!(
+            read_message(&mut input).unwrap().as_deref(),
+            Some(r#"{"jsonrpc":"2.0","method":"initialized"}"#)
+        );
+        assert_eq!(read_message(&mut input).unwrap().as_deref(), Some("{}"));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+}
@@ -0,0 +1,346 @@
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, Read, Write},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+use biscuit_lsp::transport::{read_message, write_message};
+use serde_json::{json, Value};
+
+/// reading end of an in-memory pipe
+struct PipeReader {
+    receiver: Receiver<Vec<u8>>,
+    buffer: VecDeque<u8>,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer.is_empty() {
+            match self.receiver.recv() {
+                Ok(data) => self.buffer.extend(data),
+                // the writing end was dropped
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.buffer.len());
+        for (dest, byte) in buf.iter_mut().zip(self.buffer.drain(..len)) {
+            *dest = byte;
+        }
+        Ok(len)
+    }
+}
+
+/// writing end of an in-memory pipe
+struct PipeWriter(Sender<Vec<u8>>);
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .send(buf.to_vec())
+            .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn pipe() -> (PipeWriter, BufReader<PipeReader>) {
+    let (sender, receiver) = channel();
+    (
+        PipeWriter(sender),
+        BufReader::new(PipeReader {
+            receiver,
+            buffer: VecDeque::new(),
+        }),
+    )
+}
+
+/// language server client, running the server in a thread
+struct Client {
+    input: PipeWriter,
+    output: BufReader<PipeReader>,
+    server: JoinHandle<io::Result<bool>>,
+    next_id: i64,
+    notifications: VecDeque<Value>,
+}
+
+impl Client {
+    fn start() -> Self {
+        let (input, server_input) = pipe();
+        let (server_output, output) = pipe();
+        let server = thread::spawn(move || biscuit_lsp::run(server_input, server_output));
+
+        let mut client = Client {
+            input,
+            output,
+            server,
+            next_id: 0,
+            notifications: VecDeque::new(),
+        };
+
+        let result = client.request("initialize", json!({ "capabilities": {} }));
+        assert_eq!(result["result"]["capabilities"]["hoverProvider"], true);
+        client.notify("initialized", json!({}));
+        client
+    }
+
+    fn send(&mut self, message: Value) {
+        write_message(&mut self.input, &message.to_string()).unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let message = read_message(&mut self.output).unwrap().unwrap();
+        serde_json::from_str(&message).unwrap()
+    }
+
+    /// sends a request and waits for its response
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+
+        loop {
+            let message = self.receive();
+            if message.get("id") == Some(&json!(id)) {
+                return message;
+            }
+            self.notifications.push_back(message);
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// waits for the next diagnostics published by the server
+    fn diagnostics(&mut self) -> Value {
+        let message = match self.notifications.pop_front() {
+            Some(message) => message,
+            None => self.receive(),
+        };
+        assert_eq!(message["method"], "textDocument/publishDiagnostics");
+        message["params"].clone()
+    }
+
+    fn open(&mut self, uri: &str, text: &str) -> Value {
+        self.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": { "uri": uri, "languageId": "biscuit", "version": 1, "text": text }
+            }),
+        );
+        self.diagnostics()
+    }
+
+    fn at(&mut self, method: &str, uri: &str, line: u32, character: u32) -> Value {
+        self.request(
+            method,
+            json!({
+                "textDocument": { "uri": uri },
+                "position": { "line": line, "character": character },
+            }),
+        )["result"]
+            .clone()
+    }
+
+    fn stop(mut self) -> bool {
+        assert_eq!(self.request("shutdown", Value::Null)["result"], Value::Null);
+        self.notify("exit", Value::Null);
+        self.server.join().unwrap().unwrap()
+    }
+}
+
+const URI: &str = "file:///authorizer.biscuit";
+
+const SOURCE: &str = r#"user("alice");
+right($user, "read") <- user($user), {operation} == "read";
+right($user, "write") <- admin($user);
+check if right($user, $op), $op.starts_with("r");
+allow if user({name});
+"#;
+
+#[test]
+fn diagnostics() {
+    let mut client = Client::start();
+
+    let diagnostics = client.open(URI, SOURCE);
+    assert_eq!(diagnostics["uri"], URI);
+    assert_eq!(diagnostics["diagnostics"], json!([]));
+
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": "user(\"alice\");\nright($a) <- user($b);\n" }],
+        }),
+    );
+    let diagnostics = client.diagnostics();
+    assert_eq!(
+        diagnostics["diagnostics"],
+        json!([{
+            "range": {
+                "start": { "line": 1, "character": 0 },
+                "end": { "line": 1, "character": 9 },
+            },
+            "severity": 1,
+            "source": "biscuit",
+            "message": "rule head contains variables that are not used in predicates of the rule's body: $a",
+        }])
+    );
+
+    client.notify(
+        "textDocument/didClose",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    assert_eq!(client.diagnostics()["diagnostics"], json!([]));
+
+    assert!(client.stop());
+}
+
+#[test]
+fn hover() {
+    let mut client = Client::start();
+    client.open(URI, SOURCE);
+
+    // on `starts_with`
+    let hover = client.at("textDocument/hover", URI, 3, 34);
+    assert_eq!(
+        hover["range"],
+        json!({
+            "start": { "line": 3, "character": 32 },
+            "end": { "line": 3, "character": 43 },
+        })
+    );
+    let documentation = hover["contents"]["value"].as_str().unwrap();
+    assert!(documentation.contains("$string.starts_with($prefix)"));
+
+    // on a predicate
+    assert_eq!(client.at("textDocument/hover", URI, 3, 10), Value::Null);
+
+    assert!(client.stop());
+}
+
+#[test]
+fn definition() {
+    let mut client = Client::start();
+    client.open(URI, SOURCE);
+
+    // on `right` in the check
+    let locations = client.at("textDocument/definition", URI, 3, 11);
+    assert_eq!(
+        locations,
+        json!([
+            {
+                "uri": URI,
+                "range": {
+                    "start": { "line": 1, "character": 0 },
+                    "end": { "line": 1, "character": 58 },
+                },
+            },
+            {
+                "uri": URI,
+                "range": {
+                    "start": { "line": 2, "character": 0 },
+                    "end": { "line": 2, "character": 37 },
+                },
+            },
+        ])
+    );
+
+    // on `user` in the policy, defined by a fact
+    let locations = client.at("textDocument/definition", URI, 4, 10);
+    assert_eq!(
+        locations[0]["range"]["start"],
+        json!({ "line": 0, "character": 0 })
+    );
+    assert_eq!(locations.as_array().unwrap().len(), 1);
+
+    // on a variable
+    let locations = client.at("textDocument/definition", URI, 3, 17);
+    assert_eq!(locations, json!([]));
+
+    assert!(client.stop());
+}
+
+#[test]
+fn completion() {
+    let mut client = Client::start();
+    client.open(URI, SOURCE);
+
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": format!("{}check if r", SOURCE) }],
+        }),
+    );
+    // the incomplete check is reported, symbols are kept from the last valid version
+    assert_eq!(
+        client.diagnostics()["diagnostics"]
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+
+    let labels = |items: Value| -> Vec<String> {
+        items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    let items = client.at("textDocument/completion", URI, 5, 10);
+    assert_eq!(labels(items), vec!["right"]);
+
+    let items = client.at("textDocument/completion", URI, 5, 9);
+    assert_eq!(
+        labels(items),
+        vec![
+            "admin",
+            "right",
+            "user",
+            "check if",
+            "check all",
+            "allow if",
+            "deny if",
+            "trusting"
+        ]
+    );
+
+    // methods after a `.`
+    let items = client.at("textDocument/completion", URI, 3, 33);
+    assert_eq!(labels(items), vec!["starts_with"]);
+
+    // parameters after a `{`
+    let items = client.at("textDocument/completion", URI, 1, 38);
+    assert_eq!(labels(items), vec!["name", "operation"]);
+
+    assert!(client.stop());
+}
+
+#[test]
+fn protocol_errors() {
+    let mut client = Client::start();
+
+    let response = client.request("textDocument/unknown", json!({}));
+    assert_eq!(response["error"]["code"], -32601);
+
+    let response = client.request("textDocument/hover", json!({}));
+    assert_eq!(response["error"]["code"], -32602);
+
+    write_message(&mut client.input, "{ not json").unwrap();
+    let response = client.receive();
+    assert_eq!(response["id"], Value::Null);
+    assert_eq!(response["error"]["code"], -32700);
+
+    // exiting without a shutdown request
+    client.notify("exit", Value::Null);
+    assert!(!client.server.join().unwrap().unwrap());
+}
@@ -1,9 +1,12 @@
 //! helper functions and structure to create tokens and blocks
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
     time::{SystemTime, UNIX_EPOCH},
 };
 
+use crate::error::Span;
+
 #[cfg(feature = "datalog-macro")]
 use quote::{quote, ToTokens};
 
@@ -18,6 +21,8 @@ pub enum Term {
     Bool(bool),
     Set(BTreeSet<Term>),
     Parameter(String),
+    Array(Vec<Term>),
+    Map(BTreeMap<String, Term>),
 }
 
 impl From<&Term> for Term {
@@ -31,6 +36,8 @@ impl From<&Term> for Term {
             Term::Bool(b) => Term::Bool(*b),
             Term::Set(ref s) => Term::Set(s.clone()),
             Term::Parameter(ref p) => Term::Parameter(p.clone()),
+            Term::Array(ref a) => Term::Array(a.clone()),
+            Term::Map(ref m) => Term::Map(m.clone()),
         }
     }
 }
@@ -58,6 +65,22 @@ impl ToTokens for Term {
                     ::biscuit_auth::builder::Term::Set(::std::collections::BTreeSet::from_iter(<[::biscuit_auth::builder::Term]>::into_vec(Box::new([ #(#v),*])))) 
                 }}
             }
+            Term::Array(v) => {
+                quote! {
+                    ::biscuit_auth::builder::Term::Array(<[::biscuit_auth::builder::Term]>::into_vec(Box::new([ #(#v),*])))
+                }
+            }
+            Term::Map(v) => {
+                let keys = v.keys();
+                let values = v.values();
+                quote! {{
+                    let mut __biscuit_auth_map = ::std::collections::BTreeMap::new();
+                    #(
+                        __biscuit_auth_map.insert(#keys.to_string(), #values);
+                    )*
+                    ::biscuit_auth::builder::Term::Map(__biscuit_auth_map)
+                }}
+            }
         })
     }
 }
@@ -68,6 +91,22 @@ pub enum Scope {
     Previous,
     PublicKey(PublicKey),
     Parameter(String),
+    /// trusts blocks signed by at least `threshold` of the keys,
+    /// which are `PublicKey` or `Parameter` scopes
+    Threshold(u32, Vec<Scope>),
+    /// trusts blocks signed by the keys the authorizer registered under this name
+    Group(String),
+}
+
+impl Scope {
+    /// names of the parameters used in this scope
+    pub fn parameters(&self) -> Vec<&str> {
+        match self {
+            Scope::Parameter(name) => vec![name.as_str()],
+            Scope::Threshold(_, keys) => keys.iter().flat_map(Scope::parameters).collect(),
+            _ => vec![],
+        }
+    }
 }
 
 #[cfg(feature = "datalog-macro")]
@@ -85,10 +124,119 @@ impl ToTokens for Scope {
             Scope::Parameter(v) => {
                 quote! { ::biscuit_auth::builder::Scope::Parameter(#v.to_string())}
             }
+            Scope::Threshold(threshold, keys) => {
+                quote! { ::biscuit_auth::builder::Scope::Threshold(#threshold, vec![#(#keys),*])}
+            }
+            Scope::Group(name) => {
+                quote! { ::biscuit_auth::builder::Scope::Group(#name.to_string())}
+            }
+        })
+    }
+}
+
+/// Type annotation of a Datalog parameter, as in `{expiry: date}`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ParameterType {
+    Integer,
+    String,
+    Date,
+    Bytes,
+    Bool,
+    Set(Box<ParameterType>),
+}
+
+impl fmt::Display for ParameterType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParameterType::Integer => write!(f, "int"),
+            ParameterType::String => write!(f, "string"),
+            ParameterType::Date => write!(f, "date"),
+            ParameterType::Bytes => write!(f, "bytes"),
+            ParameterType::Bool => write!(f, "bool"),
+            ParameterType::Set(t) => write!(f, "set<{}>", t),
+        }
+    }
+}
+
+#[cfg(feature = "datalog-macro")]
+impl ToTokens for ParameterType {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        tokens.extend(match self {
+            ParameterType::Integer => quote! { ::biscuit_auth::builder::ParameterType::Integer },
+            ParameterType::String => quote! { ::biscuit_auth::builder::ParameterType::String },
+            ParameterType::Date => quote! { ::biscuit_auth::builder::ParameterType::Date },
+            ParameterType::Bytes => quote! { ::biscuit_auth::builder::ParameterType::Bytes },
+            ParameterType::Bool => quote! { ::biscuit_auth::builder::ParameterType::Bool },
+            ParameterType::Set(t) => {
+                quote! { ::biscuit_auth::builder::ParameterType::Set(Box::new(#t)) }
+            }
+        })
+    }
+}
+
+/// Declaration of the arity and term types of a predicate, as in `schema right(string, string)`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PredicateSchema {
+    pub name: String,
+    pub types: Vec<ParameterType>,
+}
+
+impl PredicateSchema {
+    pub fn new<T: Into<Vec<ParameterType>>>(name: String, types: T) -> PredicateSchema {
+        PredicateSchema {
+            name,
+            types: types.into(),
+        }
+    }
+}
+
+impl fmt::Display for PredicateSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "schema {}(", self.name)?;
+        for (i, t) in self.types.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", t)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(feature = "datalog-macro")]
+impl ToTokens for PredicateSchema {
+    fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
+        let name = &self.name;
+        let types = self.types.iter();
+        tokens.extend(quote! {
+            ::biscuit_auth::builder::PredicateSchema::new(
+              #name.to_string(),
+              <[::biscuit_auth::builder::ParameterType]>::into_vec(Box::new([#(#types),*]))
+            )
         })
     }
 }
 
+#[cfg(feature = "datalog-macro")]
+fn parameter_types_to_tokens(
+    item: proc_macro2::TokenStream,
+    parameter_types: &HashMap<String, ParameterType>,
+) -> proc_macro2::TokenStream {
+    if parameter_types.is_empty() {
+        return item;
+    }
+
+    let names = parameter_types.keys();
+    let types = parameter_types.values();
+    quote! {{
+        let mut __biscuit_auth_typed = #item;
+        #(
+            __biscuit_auth_typed.parameter_types.insert(#names.to_string(), #types);
+        )*
+        __biscuit_auth_typed
+    }}
+}
+
 /// Builder for a Datalog dicate, used in facts and rules
 #[derive(Debug, Clone, PartialEq, Hash, Eq)]
 pub struct Predicate {
@@ -120,10 +268,22 @@ impl ToTokens for Predicate {
 }
 
 /// Builder for a Datalog fact
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Eq)]
 pub struct Fact {
     pub predicate: Predicate,
     pub parameters: Option<HashMap<String, Option<Term>>>,
+    pub parameter_types: HashMap<String, ParameterType>,
+    /// location of the fact in the source text it was parsed from
+    pub span: Option<Span>,
+}
+
+// the span is not part of the fact's identity
+impl PartialEq for Fact {
+    fn eq(&self, other: &Self) -> bool {
+        self.predicate == other.predicate
+            && self.parameters == other.parameters
+            && self.parameter_types == other.parameter_types
+    }
 }
 
 impl Fact {
@@ -139,6 +299,8 @@ impl Fact {
         Fact {
             predicate: Predicate::new(name, terms),
             parameters: Some(parameters),
+            parameter_types: HashMap::new(),
+            span: None,
         }
     }
 }
@@ -148,12 +310,13 @@ impl ToTokens for Fact {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
         let name = &self.predicate.name;
         let terms = self.predicate.terms.iter();
-        tokens.extend(quote! {
+        let fact = quote! {
             ::biscuit_auth::builder::Fact::new(
               #name.to_string(),
               <[::biscuit_auth::builder::Term]>::into_vec(Box::new([#(#terms),*]))
             )
-        })
+        };
+        tokens.extend(parameter_types_to_tokens(fact, &self.parameter_types))
     }
 }
 
@@ -213,6 +376,7 @@ pub enum Binary {
     BitwiseOr,
     BitwiseXor,
     NotEqual,
+    Get,
 }
 
 #[cfg(feature = "datalog-macro")]
@@ -262,6 +426,7 @@ impl ToTokens for Binary {
             Binary::BitwiseOr => quote! { ::biscuit_auth::datalog::Binary::BitwiseOr  },
             Binary::BitwiseXor => quote! { ::biscuit_auth::datalog::Binary::BitwiseXor  },
             Binary::NotEqual => quote! { ::biscuit_auth::datalog::Binary::NotEqual },
+            Binary::Get => quote! { ::biscuit_auth::datalog::Binary::Get },
         });
     }
 }
@@ -269,14 +434,30 @@ impl ToTokens for Binary {
 pub type PublicKey = Vec<u8>;
 
 /// Builder for a Datalog rule
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Eq)]
 pub struct Rule {
     pub head: Predicate,
     pub body: Vec<Predicate>,
     pub expressions: Vec<Expression>,
     pub parameters: Option<HashMap<String, Option<Term>>>,
+    pub parameter_types: HashMap<String, ParameterType>,
     pub scopes: Vec<Scope>,
     pub scope_parameters: Option<HashMap<String, Option<PublicKey>>>,
+    /// location of the rule (or check query) in the source text it was parsed from
+    pub span: Option<Span>,
+}
+
+// the span is not part of the rule's identity
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        self.head == other.head
+            && self.body == other.body
+            && self.expressions == other.expressions
+            && self.parameters == other.parameters
+            && self.parameter_types == other.parameter_types
+            && self.scopes == other.scopes
+            && self.scope_parameters == other.scope_parameters
+    }
 }
 
 impl Rule {
@@ -312,7 +493,7 @@ impl Rule {
         }
 
         for scope in &scopes {
-            if let Scope::Parameter(name) = &scope {
+            for name in scope.parameters() {
                 scope_parameters.insert(name.to_string(), None);
             }
         }
@@ -322,8 +503,10 @@ impl Rule {
             body,
             expressions,
             parameters: Some(parameters),
+            parameter_types: HashMap::new(),
             scopes,
             scope_parameters: Some(scope_parameters),
+            span: None,
         }
     }
 
@@ -371,14 +554,15 @@ impl ToTokens for Rule {
         let body = self.body.iter();
         let expressions = self.expressions.iter();
         let scopes = self.scopes.iter();
-        tokens.extend(quote! {
+        let rule = quote! {
           ::biscuit_auth::builder::Rule::new(
             #head,
             <[::biscuit_auth::builder::Predicate]>::into_vec(Box::new([#(#body),*])),
             <[::biscuit_auth::builder::Expression]>::into_vec(Box::new([#(#expressions),*])),
             <[::biscuit_auth::builder::Scope]>::into_vec(Box::new([#(#scopes),*]))
           )
-        });
+        };
+        tokens.extend(parameter_types_to_tokens(rule, &self.parameter_types));
     }
 }
 
@@ -389,6 +573,20 @@ pub struct Check {
     pub kind: CheckKind,
 }
 
+impl Check {
+    /// location of the check in the source text it was parsed from
+    pub fn span(&self) -> Option<Span> {
+        queries_span(&self.queries)
+    }
+}
+
+fn queries_span(queries: &[Rule]) -> Option<Span> {
+    queries
+        .iter()
+        .filter_map(|query| query.span)
+        .reduce(|acc, span| acc.merge(&span))
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CheckKind {
     One,
@@ -450,6 +648,13 @@ pub struct Policy {
     pub kind: PolicyKind,
 }
 
+impl Policy {
+    /// location of the policy in the source text it was parsed from
+    pub fn span(&self) -> Option<Span> {
+        queries_span(&self.queries)
+    }
+}
+
 #[cfg(feature = "datalog-macro")]
 impl ToTokens for Policy {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
@@ -564,6 +769,16 @@ pub fn set(s: BTreeSet<Term>) -> Term {
     Term::Set(s)
 }
 
+/// creates an array
+pub fn array(a: Vec<Term>) -> Term {
+    Term::Array(a)
+}
+
+/// creates a map
+pub fn map(m: BTreeMap<String, Term>) -> Term {
+    Term::Map(m)
+}
+
 /// creates a parameter
 pub fn parameter(p: &str) -> Term {
     Term::Parameter(p.to_string())
@@ -10,6 +10,29 @@ pub enum LanguageError {
         missing_parameters: Vec<String>,
         unused_parameters: Vec<String>,
     },
+    #[error("datalog parameter {{{name}}} was declared with type {expected} but was given a value of type {actual}")]
+    ParameterType {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("predicate {predicate} was declared with {expected} terms but is used with {actual}")]
+    SchemaArity {
+        predicate: String,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("term {position} of predicate {predicate} was declared with type {expected} but is used with a value of type {actual}")]
+    SchemaType {
+        predicate: String,
+        position: usize,
+        expected: String,
+        actual: String,
+    },
+    #[error("predicate {predicate} was already declared with a different schema")]
+    SchemaConflict { predicate: String },
+    #[error("invalid expression {expression}: {message}")]
+    ExpressionType { expression: String, message: String },
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -23,6 +46,12 @@ pub struct ParseErrors {
 pub struct ParseError {
     pub input: String,
     pub message: Option<String>,
+    /// location of the error in the source text, when it was parsed
+    /// with [`parse_source`](crate::parser::parse_source) or
+    /// [`parse_block_source`](crate::parser::parse_block_source)
+    pub span: Option<Span>,
+    /// source lines containing the error
+    pub snippet: Option<String>,
 }
 
 impl<'a> From<crate::parser::Error<'a>> for ParseError {
@@ -30,8 +59,97 @@ impl<'a> From<crate::parser::Error<'a>> for ParseError {
         ParseError {
             input: e.input.to_string(),
             message: e.message,
+            span: e.span,
+            snippet: e.snippet.map(|s| s.to_string()),
+        }
+    }
+}
+
+/// Position in a Datalog source text
+///
+/// `offset` is a byte offset, `line` and `column` start at 1, and the
+/// column is counted in characters
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+pub struct Position {
+    pub offset: usize,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl Position {
+    /// computes the line and column of a byte offset in `source`
+    ///
+    /// offsets past the end of `source` or inside a character are moved back
+    pub fn from_offset(source: &str, offset: usize) -> Self {
+        let mut offset = offset.min(source.len());
+        while !source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+
+        Position {
+            offset,
+            line: before.matches('\n').count() as u32 + 1,
+            column: before[line_start..].chars().count() as u32 + 1,
+        }
+    }
+}
+
+/// Range of a Datalog source text, from `start` (inclusive) to `end` (exclusive)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde-error", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    /// creates a span from byte offsets in `source`
+    pub fn new(source: &str, start: usize, end: usize) -> Self {
+        Span {
+            start: Position::from_offset(source, start),
+            end: Position::from_offset(source, end),
         }
     }
+
+    /// locates `slice`, which must be a subslice of `source`
+    pub fn from_slice(source: &str, slice: &str) -> Self {
+        let start = (slice.as_ptr() as usize).saturating_sub(source.as_ptr() as usize);
+        Span::new(source, start, start + slice.len())
+    }
+
+    /// smallest span containing both spans
+    pub fn merge(&self, other: &Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    /// full lines of `source` covered by this span
+    pub fn lines<'a>(&self, source: &'a str) -> &'a str {
+        let start = self.start.offset.min(source.len());
+        let end = self.end.offset.clamp(start, source.len());
+        let line_start = source[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = source[end..]
+            .find('\n')
+            .map(|i| end + i)
+            .unwrap_or(source.len());
+
+        source[line_start..line_end].trim_end_matches('\r')
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}-{}:{}",
+            self.start.line, self.start.column, self.end.line, self.end.column
+        )
+    }
 }
 
 impl<'a> From<crate::parser::Error<'a>> for ParseErrors {
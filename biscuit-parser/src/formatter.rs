@@ -0,0 +1,688 @@
+//! canonical formatting of Datalog source
+//!
+//! The formatter parses the source and prints it back in a canonical layout:
+//! one statement per line, normalized spacing, and statements longer than the
+//! configured width split with one body element per line. Comments are kept
+//! next to the statement they precede (or follow, on the same line), and
+//! blank lines between statements are collapsed to a single one.
+//!
+//! Formatting is lossless: the formatted text parses to the same builder values
+//! as the original source.
+//!
+//! ```rust
+//! use biscuit_parser::formatter::{format_source, FormatOptions};
+//!
+//! let formatted = format_source(
+//!     "right( \"file1\",\"read\" ) ;check if right($f,\"read\")",
+//!     &FormatOptions::default(),
+//! )
+//! .unwrap();
+//!
+//! assert_eq!(
+//!     formatted,
+//!     "right(\"file1\", \"read\");\ncheck if right($f, \"read\");\n"
+//! );
+//! ```
+use std::collections::{HashMap, HashSet};
+
+use nom::Offset;
+
+use crate::{
+    builder::{
+        Binary, Check, CheckKind, Expression, Fact, Op, ParameterType, Policy, PolicyKind,
+        Predicate, Rule, Scope, Term, Unary,
+    },
+    error::LanguageError,
+    parser::{parse_elements, SourceElement},
+};
+
+/// order of the statements in the formatted source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ordering {
+    /// statements stay in their original order
+    Preserve,
+    /// schemas come first, then facts, rules, checks and policies, each group
+    /// keeping its original order
+    Grouped,
+    /// like [`Ordering::Grouped`], with schemas, facts and rules sorted alphabetically
+    ///
+    /// checks and policies are never sorted: the order of policies decides the
+    /// authorization result, and check ids follow the order of checks
+    Sorted,
+}
+
+/// formatting options
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FormatOptions {
+    pub ordering: Ordering,
+    /// statements longer than this (in characters) are split with one body
+    /// element per line
+    pub max_width: usize,
+    /// number of spaces before each body element of a split statement
+    pub indent: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            ordering: Ordering::Preserve,
+            max_width: 80,
+            indent: 4,
+        }
+    }
+}
+
+/// formats authorizer code, as accepted by [`crate::parser::parse_source`]
+pub fn format_source(source: &str, options: &FormatOptions) -> Result<String, LanguageError> {
+    format(source, false, options)
+}
+
+/// formats block code, as accepted by [`crate::parser::parse_block_source`]
+pub fn format_block_source(source: &str, options: &FormatOptions) -> Result<String, LanguageError> {
+    format(source, true, options)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum StatementKind {
+    Schema,
+    Fact,
+    Rule,
+    Check,
+    Policy,
+}
+
+/// a line of output, or a multiline statement
+struct Line {
+    blank_before: bool,
+    text: String,
+}
+
+/// a statement along with the comments attached to it
+struct Entry {
+    kind: StatementKind,
+    comments: Vec<Line>,
+    statement: Line,
+    trailing_comment: Option<String>,
+}
+
+impl Entry {
+    fn set_blank_before(&mut self, blank_before: bool) {
+        self.comments
+            .first_mut()
+            .unwrap_or(&mut self.statement)
+            .blank_before = blank_before;
+    }
+}
+
+fn format(source: &str, block: bool, options: &FormatOptions) -> Result<String, LanguageError> {
+    let (scopes, elements) = parse_elements(source, block)?;
+
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut comments = Vec::new();
+    let mut previous_end = 0;
+    let mut after_statement = false;
+
+    for element in elements {
+        let input = match &element {
+            SourceElement::Schema(i, _)
+            | SourceElement::Fact(i, _)
+            | SourceElement::Rule(i, _)
+            | SourceElement::Check(i, _, _)
+            | SourceElement::Policy(i, _, _)
+            | SourceElement::Comment(i) => i.trim(),
+        };
+        let start = source.offset(input);
+        let newlines = source[previous_end..start].matches('\n').count();
+        previous_end = start + input.len();
+
+        let (kind, text) = match element {
+            SourceElement::Comment(_) => {
+                let text = input.to_string();
+                match entries.last_mut() {
+                    Some(entry) if after_statement && newlines == 0 => {
+                        entry.trailing_comment = Some(text);
+                    }
+                    _ => comments.push(Line {
+                        blank_before: newlines > 1,
+                        text,
+                    }),
+                }
+                after_statement = false;
+                continue;
+            }
+            SourceElement::Schema(_, schema) => (StatementKind::Schema, format!("{};", schema)),
+            SourceElement::Fact(_, fact) => (StatementKind::Fact, format_fact(&fact)),
+            SourceElement::Rule(_, rule) => (StatementKind::Rule, format_rule(&rule, options)),
+            SourceElement::Check(_, check, _) => {
+                (StatementKind::Check, format_check(&check, options))
+            }
+            SourceElement::Policy(_, policy, _) => {
+                (StatementKind::Policy, format_policy(&policy, options))
+            }
+        };
+
+        entries.push(Entry {
+            kind,
+            comments: std::mem::take(&mut comments),
+            statement: Line {
+                blank_before: newlines > 1,
+                text,
+            },
+            trailing_comment: None,
+        });
+        after_statement = true;
+    }
+
+    let groups = match options.ordering {
+        Ordering::Preserve => vec![entries],
+        Ordering::Grouped | Ordering::Sorted => {
+            let mut groups: Vec<Vec<Entry>> = (0..5).map(|_| Vec::new()).collect();
+            for entry in entries {
+                groups[entry.kind as usize].push(entry);
+            }
+
+            if options.ordering == Ordering::Sorted {
+                for group in &mut groups[..=StatementKind::Rule as usize] {
+                    group.sort_by(|a, b| a.statement.text.cmp(&b.statement.text));
+                    for entry in group.iter_mut() {
+                        entry.set_blank_before(false);
+                    }
+                }
+            }
+
+            groups.retain(|group| !group.is_empty());
+            groups
+        }
+    };
+
+    let mut output = String::new();
+    if !scopes.is_empty() {
+        output.push_str(&format!("trusting {};\n", format_scopes(&scopes)));
+    }
+
+    for group in groups {
+        for (index, mut entry) in group.into_iter().enumerate() {
+            if index == 0 {
+                entry.set_blank_before(true);
+            }
+
+            for comment in entry.comments {
+                push_line(&mut output, comment.blank_before, &comment.text);
+            }
+
+            let mut statement = entry.statement.text;
+            if let Some(comment) = entry.trailing_comment {
+                statement.push(' ');
+                statement.push_str(&comment);
+            }
+            push_line(&mut output, entry.statement.blank_before, &statement);
+        }
+    }
+
+    for comment in comments {
+        push_line(&mut output, comment.blank_before, &comment.text);
+    }
+
+    Ok(output)
+}
+
+fn push_line(output: &mut String, blank_before: bool, text: &str) {
+    if blank_before && !output.is_empty() {
+        output.push('\n');
+    }
+    output.push_str(text);
+    output.push('\n');
+}
+
+fn format_fact(fact: &Fact) -> String {
+    let mut printer = Printer::new(&fact.parameter_types);
+    format!("{};", printer.predicate(&fact.predicate))
+}
+
+fn format_rule(rule: &Rule, options: &FormatOptions) -> String {
+    let mut printer = Printer::new(&rule.parameter_types);
+    let head = format!("{} <-", printer.predicate(&rule.head));
+    layout(&head, vec![printer.body(rule)], options)
+}
+
+fn format_check(check: &Check, options: &FormatOptions) -> String {
+    let keyword = match check.kind {
+        CheckKind::One => "check if",
+        CheckKind::All => "check all",
+    };
+    layout(keyword, queries(&check.queries), options)
+}
+
+fn format_policy(policy: &Policy, options: &FormatOptions) -> String {
+    let keyword = match policy.kind {
+        PolicyKind::Allow => "allow if",
+        PolicyKind::Deny => "deny if",
+    };
+    layout(keyword, queries(&policy.queries), options)
+}
+
+fn queries(queries: &[Rule]) -> Vec<Body> {
+    queries
+        .iter()
+        .map(|query| Printer::new(&query.parameter_types).body(query))
+        .collect()
+}
+
+/// printed body of a rule or check query
+struct Body {
+    elements: Vec<String>,
+    scopes: Option<String>,
+}
+
+impl Body {
+    fn single_line(&self) -> String {
+        let mut s = self.elements.join(", ");
+        if let Some(scopes) = &self.scopes {
+            s.push_str(" trusting ");
+            s.push_str(scopes);
+        }
+        s
+    }
+
+    fn split(&self, indent: &str) -> String {
+        let mut s = self
+            .elements
+            .iter()
+            .map(|element| format!("{}{}", indent, element))
+            .collect::<Vec<_>>()
+            .join(",\n");
+        if let Some(scopes) = &self.scopes {
+            s.push_str(&format!("\n{}trusting {}", indent, scopes));
+        }
+        s
+    }
+}
+
+/// prints a statement on one line if it fits, otherwise with one body element per line
+fn layout(prefix: &str, bodies: Vec<Body>, options: &FormatOptions) -> String {
+    let single_line = format!(
+        "{} {};",
+        prefix,
+        bodies
+            .iter()
+            .map(Body::single_line)
+            .collect::<Vec<_>>()
+            .join(" or ")
+    );
+    if single_line.chars().count() <= options.max_width {
+        return single_line;
+    }
+
+    let indent = " ".repeat(options.indent);
+    format!(
+        "{}\n{};",
+        prefix,
+        bodies
+            .iter()
+            .map(|body| body.split(&indent))
+            .collect::<Vec<_>>()
+            .join("\nor\n")
+    )
+}
+
+fn format_scopes(scopes: &[Scope]) -> String {
+    scopes
+        .iter()
+        .map(format_scope)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn format_scope(scope: &Scope) -> String {
+    match scope {
+        Scope::Authority => "authority".to_string(),
+        Scope::Previous => "previous".to_string(),
+        Scope::PublicKey(key) => format!("ed25519/{}", hex::encode(key)),
+        Scope::Parameter(name) => format!("{{{}}}", name),
+        Scope::Threshold(threshold, keys) => format!(
+            "{} of {{{}}}",
+            threshold,
+            keys.iter().map(format_scope).collect::<Vec<_>>().join(", ")
+        ),
+        Scope::Group(name) => format!("group(\"{}\")", escape(name)),
+    }
+}
+
+/// prints the elements of a fact, rule or query
+///
+/// the type annotation of a parameter is printed on its first occurrence
+struct Printer<'a> {
+    parameter_types: &'a HashMap<String, ParameterType>,
+    annotated: HashSet<&'a str>,
+}
+
+impl<'a> Printer<'a> {
+    fn new(parameter_types: &'a HashMap<String, ParameterType>) -> Self {
+        Printer {
+            parameter_types,
+            annotated: HashSet::new(),
+        }
+    }
+
+    fn body(&mut self, rule: &Rule) -> Body {
+        let mut elements: Vec<String> = rule
+            .body
+            .iter()
+            .map(|predicate| self.predicate(predicate))
+            .collect();
+        elements.extend(
+            rule.expressions
+                .iter()
+                .map(|expression| self.expression(expression)),
+        );
+
+        Body {
+            elements,
+            scopes: if rule.scopes.is_empty() {
+                None
+            } else {
+                Some(format_scopes(&rule.scopes))
+            },
+        }
+    }
+
+    fn predicate(&mut self, predicate: &Predicate) -> String {
+        let terms = predicate
+            .terms
+            .iter()
+            .map(|term| self.term(term))
+            .collect::<Vec<_>>();
+        format!("{}({})", predicate.name, terms.join(", "))
+    }
+
+    fn term(&mut self, term: &Term) -> String {
+        match term {
+            Term::Variable(name) => format!("${}", name),
+            Term::Integer(i) => i.to_string(),
+            Term::Str(s) => format!("\"{}\"", escape(s)),
+            Term::Date(d) => time::OffsetDateTime::from_unix_timestamp(*d as i64)
+                .ok()
+                .and_then(|t| {
+                    t.format(&time::format_description::well_known::Rfc3339)
+                        .ok()
+                })
+                .unwrap_or_else(|| "<invalid date>".to_string()),
+            Term::Bytes(bytes) => format!("hex:{}", hex::encode(bytes)),
+            Term::Bool(b) => b.to_string(),
+            Term::Set(set) => {
+                let terms = set.iter().map(|term| self.term(term)).collect::<Vec<_>>();
+                format!("[{}]", terms.join(", "))
+            }
+            Term::Array(array) => {
+                let terms = array.iter().map(|term| self.term(term)).collect::<Vec<_>>();
+                format!("array[{}]", terms.join(", "))
+            }
+            Term::Map(map) => {
+                let entries = map
+                    .iter()
+                    .map(|(key, term)| format!("\"{}\": {}", escape(key), self.term(term)))
+                    .collect::<Vec<_>>();
+                format!("{{{}}}", entries.join(", "))
+            }
+            Term::Parameter(name) => match self.parameter_types.get_key_value(name) {
+                Some((name, parameter_type)) if self.annotated.insert(name) => {
+                    format!("{{{}: {}}}", name, parameter_type)
+                }
+                _ => format!("{{{}}}", name),
+            },
+        }
+    }
+
+    /// prints an expression from its opcodes
+    ///
+    /// parentheses are kept as [`Unary::Parens`] operations by the parser, so
+    /// printing the operations in order gives back an equivalent source
+    fn expression(&mut self, expression: &Expression) -> String {
+        let mut stack: Vec<String> = Vec::new();
+
+        for op in &expression.ops {
+            match op {
+                Op::Value(term) => stack.push(self.term(term)),
+                Op::Unary(unary) => {
+                    let value = stack.pop().unwrap_or_default();
+                    stack.push(match unary {
+                        Unary::Negate => format!("!{}", value),
+                        Unary::Parens => format!("({})", value),
+                        Unary::Length => format!("{}.length()", value),
+                    });
+                }
+                Op::Binary(binary) => {
+                    let right = stack.pop().unwrap_or_default();
+                    let left = stack.pop().unwrap_or_default();
+                    stack.push(match binary {
+                        Binary::LessThan => format!("{} < {}", left, right),
+                        Binary::GreaterThan => format!("{} > {}", left, right),
+                        Binary::LessOrEqual => format!("{} <= {}", left, right),
+                        Binary::GreaterOrEqual => format!("{} >= {}", left, right),
+                        Binary::Equal => format!("{} == {}", left, right),
+                        Binary::NotEqual => format!("{} != {}", left, right),
+                        Binary::Contains => format!("{}.contains({})", left, right),
+                        Binary::Prefix => format!("{}.starts_with({})", left, right),
+                        Binary::Suffix => format!("{}.ends_with({})", left, right),
+                        Binary::Regex => format!("{}.matches({})", left, right),
+                        Binary::Add => format!("{} + {}", left, right),
+                        Binary::Sub => format!("{} - {}", left, right),
+                        Binary::Mul => format!("{} * {}", left, right),
+                        Binary::Div => format!("{} / {}", left, right),
+                        Binary::And => format!("{} && {}", left, right),
+                        Binary::Or => format!("{} || {}", left, right),
+                        Binary::Intersection => format!("{}.intersection({})", left, right),
+                        Binary::Union => format!("{}.union({})", left, right),
+                        Binary::BitwiseAnd => format!("{} & {}", left, right),
+                        Binary::BitwiseOr => format!("{} | {}", left, right),
+                        Binary::BitwiseXor => format!("{} ^ {}", left, right),
+                        Binary::Get => format!("{}.get({})", left, right),
+                    });
+                }
+            }
+        }
+
+        stack.pop().unwrap_or_default()
+    }
+}
+
+/// escapes the characters of a string that cannot appear as is in a string literal
+pub fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_block_source, parse_source, SourceResult};
+
+    #[allow(clippy::type_complexity)]
+    fn values(result: SourceResult) -> (Vec<Scope>, Vec<Fact>, Vec<Rule>, Vec<Check>, Vec<Policy>) {
+        (
+            result.scopes,
+            result.facts.into_iter().map(|(_, f)| f).collect(),
+            result.rules.into_iter().map(|(_, r)| r).collect(),
+            result.checks.into_iter().map(|(_, c)| c).collect(),
+            result.policies.into_iter().map(|(_, p)| p).collect(),
+        )
+    }
+
+    #[test]
+    fn layout() {
+        let source = r#"
+// header comment
+
+fact( "a",1 )  ;   other(true);
+rule($a,$b)<-fact($a,$b),$b>1; // trailing comment
+
+
+/* multiline
+   comment */
+check if  fact($a, $b) , $b > 2 or other(true)
+  trusting authority,previous;
+allow if true;
+"#;
+
+        let formatted = format_source(source, &FormatOptions::default()).unwrap();
+        assert_eq!(
+            formatted,
+            r#"// header comment
+
+fact("a", 1);
+other(true);
+rule($a, $b) <- fact($a, $b), $b > 1; // trailing comment
+
+/* multiline
+   comment */
+check if fact($a, $b), $b > 2 or other(true) trusting authority, previous;
+allow if true;
+"#
+        );
+
+        assert_eq!(
+            format_source(&formatted, &FormatOptions::default()).unwrap(),
+            formatted
+        );
+        assert_eq!(format_source("", &FormatOptions::default()).unwrap(), "");
+    }
+
+    #[test]
+    fn line_wrapping() {
+        let source = r#"right($resource, "read") <- resource($resource), operation("read"), user($user), owner($user, $resource);
+check if resource($resource), $resource.starts_with("/public/") or admin(true) trusting authority;
+check if time($time), $time < 2030-01-01T00:00:00Z;"#;
+
+        let options = FormatOptions {
+            max_width: 60,
+            ..FormatOptions::default()
+        };
+        assert_eq!(
+            format_source(source, &options).unwrap(),
+            r#"right($resource, "read") <-
+    resource($resource),
+    operation("read"),
+    user($user),
+    owner($user, $resource);
+check if
+    resource($resource),
+    $resource.starts_with("/public/")
+or
+    admin(true)
+    trusting authority;
+check if time($time), $time < 2030-01-01T00:00:00Z;
+"#
+        );
+    }
+
+    #[test]
+    fn ordering() {
+        let source = r#"
+allow if user("admin");
+check if user($u);
+// users
+user("b");
+right($u) <- user($u);
+user("a");
+schema user( string );
+deny if true;
+"#;
+
+        let grouped = FormatOptions {
+            ordering: Ordering::Grouped,
+            ..FormatOptions::default()
+        };
+        assert_eq!(
+            format_source(source, &grouped).unwrap(),
+            r#"schema user(string);
+
+// users
+user("b");
+user("a");
+
+right($u) <- user($u);
+
+check if user($u);
+
+allow if user("admin");
+deny if true;
+"#
+        );
+
+        let sorted = FormatOptions {
+            ordering: Ordering::Sorted,
+            ..FormatOptions::default()
+        };
+        assert_eq!(
+            format_source(source, &sorted).unwrap(),
+            r#"schema user(string);
+
+user("a");
+// users
+user("b");
+
+right($u) <- user($u);
+
+check if user($u);
+
+allow if user("admin");
+deny if true;
+"#
+        );
+    }
+
+    #[test]
+    fn block_source() {
+        let source = "trusting authority ,ed25519/acdd6d5b53bfee478bf689f8e012fe7988bf755e3d7c5152947abc149bc20189 ;\nfact(1);check if fact($a) trusting previous;";
+        assert_eq!(
+            format_block_source(source, &FormatOptions::default()).unwrap(),
+            "trusting authority, ed25519/acdd6d5b53bfee478bf689f8e012fe7988bf755e3d7c5152947abc149bc20189;\n\nfact(1);\ncheck if fact($a) trusting previous;\n"
+        );
+
+        assert!(format_block_source("allow if true;", &FormatOptions::default()).is_err());
+        assert!(format_source("fact(1);\nrule($a) <- ;", &FormatOptions::default()).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let source = r#"
+fact("quote \" backslash \\ newline \n", -12, 2022-12-05T10:43:00Z, hex:0AFF, false, [3, 1], ["a"], {p1: int});
+typed({p1}, {p2: set<string>}, {p2});
+rule($a, {p: string}) <- fact($a, {p}), other($a), $a.length() >= 2 || !($a == "x") && $a != "y" trusting {pk}, authority;
+check all fact($a, $b), ($a + 2) * 3 / $b - -1 == 4, $a & 1 | 2 ^ 3 == 0;
+check if operation($op), ["read", "write"].contains($op), !$op.starts_with("r") && $op.ends_with("e"), $op.matches("^w.*");
+check if set($s), $s.intersection([1, 2]).union([3]).length() == {n: int} or other({n}, true);
+allow if right($u), $u.length() * (1 + 2) > 3 trusting ed25519/acdd6d5b53bfee478bf689f8e012fe7988bf755e3d7c5152947abc149bc20189;
+check if approved("deploy") trusting 2 of {ed25519/acdd6d5b53bfee478bf689f8e012fe7988bf755e3d7c5152947abc149bc20189, {auditor}};
+check if attested("release") trusting group("partners"), previous;
+deny if true;
+"#;
+
+        for ordering in [Ordering::Preserve, Ordering::Grouped] {
+            for max_width in [20, 80, 200] {
+                let options = FormatOptions {
+                    ordering,
+                    max_width,
+                    indent: 2,
+                };
+                let formatted = format_source(source, &options).unwrap();
+                assert_eq!(
+                    values(parse_source(&formatted).unwrap()),
+                    values(parse_source(source).unwrap()),
+                    "formatted source:\n{}",
+                    formatted
+                );
+                assert_eq!(format_source(&formatted, &options).unwrap(), formatted);
+            }
+        }
+
+        let block =
+            "trusting previous;\nfact(1);\nrule($a) <- fact($a), $a > 0;\ncheck if rule(1);";
+        let formatted = format_block_source(block, &FormatOptions::default()).unwrap();
+        assert_eq!(
+            values(parse_block_source(&formatted).unwrap()),
+            values(parse_block_source(block).unwrap())
+        );
+    }
+}
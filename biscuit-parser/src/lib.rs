@@ -1,3 +1,4 @@
 pub mod builder;
 pub mod error;
+pub mod formatter;
 pub mod parser;
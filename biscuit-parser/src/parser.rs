@@ -1,9 +1,12 @@
-use crate::builder::{self, CheckKind};
+use crate::{
+    builder::{self, CheckKind},
+    error::Span,
+};
 use nom::{
     branch::alt,
     bytes::complete::{escaped_transform, tag, tag_no_case, take_until, take_while, take_while1},
     character::{
-        complete::{char, digit1, multispace0 as space0},
+        complete::{char, digit1, multispace0 as space0, multispace1 as space1},
         is_alphanumeric,
     },
     combinator::{consumed, cut, eof, map, map_res, opt, recognize, value},
@@ -12,7 +15,10 @@ use nom::{
     sequence::{delimited, pair, preceded, terminated, tuple},
     IResult, Offset,
 };
-use std::{collections::BTreeSet, convert::TryInto};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    convert::TryInto,
+};
 use thiserror::Error;
 
 /// parse a Datalog fact
@@ -41,8 +47,59 @@ pub fn fact_inner(i: &str) -> IResult<&str, builder::Fact, Error> {
         )),
         preceded(space0, char(')')),
     )(i)?;
+    let (terms, types) = split_types(terms);
 
-    Ok((i, builder::Fact::new(fact_name.to_string(), terms)))
+    let mut fact = builder::Fact::new(fact_name.to_string(), terms);
+    fact.parameter_types = parameter_types(types)?;
+
+    Ok((i, fact))
+}
+
+/// parse a predicate schema declaration
+pub fn schema(i: &str) -> IResult<&str, builder::PredicateSchema, Error<'_>> {
+    let (i, schema) = schema_inner(i)?;
+
+    let (i, _) = error(
+        preceded(space0, eof),
+        |input| format!("unexpected trailing data after schema: '{}'", input),
+        " ,\n",
+    )(i)?;
+
+    Ok((i, schema))
+}
+
+/// parses `schema name(type, ...)`, with the types of parameter annotations
+fn schema_inner(i: &str) -> IResult<&str, builder::PredicateSchema, Error<'_>> {
+    let (i, _) = space0(i)?;
+    let (i, _) = terminated(tag("schema"), space1)(i)?;
+    let (i, schema_name) = name(i)?;
+
+    let (i, _) = space0(i)?;
+    let (i, types) = cut(delimited(
+        char('('),
+        separated_list1(
+            preceded(space0, char(',')),
+            preceded(
+                space0,
+                error(
+                    parameter_type,
+                    |input| {
+                        format!(
+                            "unknown schema type '{}', expected one of int, string, date, bytes, bool or set<type>",
+                            input
+                        )
+                    },
+                    " ,)\n",
+                ),
+            ),
+        ),
+        preceded(space0, char(')')),
+    ))(i)?;
+
+    Ok((
+        i,
+        builder::PredicateSchema::new(schema_name.to_string(), types),
+    ))
 }
 
 /// parse a Datalog check
@@ -65,6 +122,11 @@ pub fn check(i: &str) -> IResult<&str, builder::Check, Error> {
 }
 
 fn check_inner(i: &str) -> IResult<&str, builder::Check, Error> {
+    map(check_inner_spanned, |(check, _)| check)(i)
+}
+
+/// parses a check, along with the source text of each of its queries
+fn check_inner_spanned(i: &str) -> IResult<&str, (builder::Check, Vec<&str>), Error<'_>> {
     let (i, _) = space0(i)?;
 
     let (i, kind) = alt((
@@ -72,8 +134,9 @@ fn check_inner(i: &str) -> IResult<&str, builder::Check, Error> {
         map(tag_no_case("check all"), |_| CheckKind::All),
     ))(i)?;
 
-    let (i, queries) = cut(check_body)(i)?;
-    Ok((i, builder::Check { queries, kind }))
+    let (i, queries) = cut(check_body_spanned)(i)?;
+    let (inputs, queries) = queries.into_iter().unzip();
+    Ok((i, (builder::Check { queries, kind }, inputs)))
 }
 
 /// parse an allow or deny rule
@@ -99,49 +162,72 @@ fn policy_inner(i: &str) -> IResult<&str, builder::Policy, Error> {
     alt((allow, deny))(i)
 }
 
-/// parse an allow rule
-pub fn allow(i: &str) -> IResult<&str, builder::Policy, Error> {
-    let (i, _) = space0(i)?;
+/// parses a policy, along with the source text of each of its queries
+fn policy_inner_spanned(i: &str) -> IResult<&str, (builder::Policy, Vec<&str>), Error<'_>> {
+    alt((
+        policy_with_kind("allow if", builder::PolicyKind::Allow),
+        policy_with_kind("deny if", builder::PolicyKind::Deny),
+    ))(i)
+}
 
-    let (i, _) = tag_no_case("allow if")(i)?;
+fn policy_with_kind<'a>(
+    keyword: &'static str,
+    kind: builder::PolicyKind,
+) -> impl FnMut(&'a str) -> IResult<&'a str, (builder::Policy, Vec<&'a str>), Error<'a>> {
+    move |i: &'a str| {
+        let (i, _) = space0(i)?;
 
-    let (i, queries) = cut(check_body)(i)?;
-    Ok((
-        i,
-        builder::Policy {
-            queries,
-            kind: builder::PolicyKind::Allow,
-        },
-    ))
+        let (i, _) = tag_no_case(keyword)(i)?;
+
+        let (i, queries) = cut(check_body_spanned)(i)?;
+        let (inputs, queries) = queries.into_iter().unzip();
+        Ok((
+            i,
+            (
+                builder::Policy {
+                    queries,
+                    kind: kind.clone(),
+                },
+                inputs,
+            ),
+        ))
+    }
+}
+
+/// parse an allow rule
+pub fn allow(i: &str) -> IResult<&str, builder::Policy, Error> {
+    map(
+        policy_with_kind("allow if", builder::PolicyKind::Allow),
+        |(policy, _)| policy,
+    )(i)
 }
 
 /// parse a deny rule
 pub fn deny(i: &str) -> IResult<&str, builder::Policy, Error> {
-    let (i, _) = space0(i)?;
-
-    let (i, _) = tag_no_case("deny if")(i)?;
-
-    let (i, queries) = cut(check_body)(i)?;
-    Ok((
-        i,
-        builder::Policy {
-            queries,
-            kind: builder::PolicyKind::Deny,
-        },
-    ))
+    map(
+        policy_with_kind("deny if", builder::PolicyKind::Deny),
+        |(policy, _)| policy,
+    )(i)
 }
 
 /// parse a Datalog check body
 pub fn check_body(i: &str) -> IResult<&str, Vec<builder::Rule>, Error> {
+    map(check_body_spanned, |queries| {
+        queries.into_iter().map(|(_, query)| query).collect()
+    })(i)
+}
+
+/// parses a Datalog check body, along with the source text of each query
+fn check_body_spanned(i: &str) -> IResult<&str, Vec<(&str, builder::Rule)>, Error<'_>> {
     let (i, mut queries) = separated_list1(
         preceded(space0, tag_no_case("or")),
-        preceded(space0, cut(rule_body)),
+        preceded(space0, consumed(cut(typed_rule_body))),
     )(i)?;
 
     let queries = queries
         .drain(..)
-        .map(|(predicates, expressions, scopes)| {
-            builder::Rule::new(
+        .map(|(input, ((predicates, expressions, scopes), types))| {
+            let mut rule = builder::Rule::new(
                 builder::Predicate {
                     name: "query".to_string(),
                     terms: Vec::new(),
@@ -149,9 +235,11 @@ pub fn check_body(i: &str) -> IResult<&str, Vec<builder::Rule>, Error> {
                 predicates,
                 expressions,
                 scopes,
-            )
+            );
+            rule.parameter_types = parameter_types(types)?;
+            Ok((input, rule))
         })
-        .collect();
+        .collect::<Result<_, _>>()?;
     Ok((i, queries))
 }
 
@@ -175,20 +263,24 @@ pub fn rule(i: &str) -> IResult<&str, builder::Rule, Error> {
 }
 
 pub fn rule_inner(i: &str) -> IResult<&str, builder::Rule, Error> {
-    let (i, (head_input, head)) = consumed(rule_head)(i)?;
+    let (i, (head_input, (head, mut types))) = consumed(rule_head)(i)?;
     let (i, _) = space0(i)?;
 
     let (i, _) = tag("<-")(i)?;
 
-    let (i, (body, expressions, scopes)) = cut(rule_body)(i)?;
+    let (i, ((body, expressions, scopes), mut body_types)) = cut(typed_rule_body)(i)?;
+    types.append(&mut body_types);
 
-    let rule = builder::Rule::new(head, body, expressions, scopes);
+    let mut rule = builder::Rule::new(head, body, expressions, scopes);
+    rule.parameter_types = parameter_types(types)?;
 
     if let Err(message) = rule.validate_variables() {
         return Err(nom::Err::Failure(Error {
             input: head_input,
             code: ErrorKind::Satisfy,
             message: Some(message),
+            span: None,
+            snippet: None,
         }));
     }
 
@@ -267,7 +359,7 @@ impl FromStr for builder::Predicate {
     }
 }*/
 
-fn predicate(i: &str) -> IResult<&str, builder::Predicate, Error> {
+fn predicate(i: &str) -> IResult<&str, Typed<'_, builder::Predicate>, Error> {
     let (i, _) = space0(i)?;
     let (i, fact_name) = name(i)?;
 
@@ -277,17 +369,21 @@ fn predicate(i: &str) -> IResult<&str, builder::Predicate, Error> {
         cut(separated_list1(preceded(space0, char(',')), cut(term))),
         preceded(space0, char(')')),
     )(i)?;
+    let (terms, types) = split_types(terms);
 
     Ok((
         i,
-        builder::Predicate {
-            name: fact_name.to_string(),
-            terms,
-        },
+        (
+            builder::Predicate {
+                name: fact_name.to_string(),
+                terms,
+            },
+            types,
+        ),
     ))
 }
 
-fn rule_head(i: &str) -> IResult<&str, builder::Predicate, Error> {
+fn rule_head(i: &str) -> IResult<&str, Typed<'_, builder::Predicate>, Error> {
     let (i, _) = space0(i)?;
     let (i, fact_name) = name(i)?;
 
@@ -297,13 +393,17 @@ fn rule_head(i: &str) -> IResult<&str, builder::Predicate, Error> {
         cut(separated_list0(preceded(space0, char(',')), cut(term))),
         preceded(space0, char(')')),
     )(i)?;
+    let (terms, types) = split_types(terms);
 
     Ok((
         i,
-        builder::Predicate {
-            name: fact_name.to_string(),
-            terms,
-        },
+        (
+            builder::Predicate {
+                name: fact_name.to_string(),
+                terms,
+            },
+            types,
+        ),
     ))
 }
 
@@ -318,6 +418,25 @@ pub fn rule_body(
         Vec<builder::Scope>,
     ),
     Error,
+> {
+    map(typed_rule_body, |(body, _)| body)(i)
+}
+
+/// parses a Datalog rule body, along with the parameter type annotations it contains
+#[allow(clippy::type_complexity)]
+fn typed_rule_body(
+    i: &str,
+) -> IResult<
+    &str,
+    Typed<
+        '_,
+        (
+            Vec<builder::Predicate>,
+            Vec<builder::Expression>,
+            Vec<builder::Scope>,
+        ),
+    >,
+    Error<'_>,
 > {
     let (i, mut elements) = separated_list1(
         preceded(space0, char(',')),
@@ -326,8 +445,9 @@ pub fn rule_body(
 
     let mut predicates = Vec::new();
     let mut expressions = Vec::new();
+    let mut types = Vec::new();
 
-    for el in elements.drain(..) {
+    for (el, mut el_types) in elements.drain(..) {
         match el {
             PredOrExpr::P(predicate) => predicates.push(predicate),
             PredOrExpr::E(expression) => {
@@ -336,11 +456,12 @@ pub fn rule_body(
                 expressions.push(e);
             }
         }
+        types.append(&mut el_types);
     }
 
     let (i, scopes) = scopes(i)?;
 
-    Ok((i, (predicates, expressions, scopes)))
+    Ok((i, ((predicates, expressions, scopes), types)))
 }
 
 enum PredOrExpr {
@@ -348,9 +469,12 @@ enum PredOrExpr {
     E(Expr),
 }
 
-fn predicate_or_expression(i: &str) -> IResult<&str, PredOrExpr, Error> {
+fn predicate_or_expression(i: &str) -> IResult<&str, Typed<'_, PredOrExpr>, Error> {
     reduce(
-        alt((map(predicate, PredOrExpr::P), map(expr, PredOrExpr::E))),
+        alt((
+            map(predicate, |(p, types)| (PredOrExpr::P(p), types)),
+            map(typed_expr, |(e, types)| (PredOrExpr::E(e), types)),
+        )),
         ",;",
     )(i)
 }
@@ -367,6 +491,29 @@ fn scope(i: &str) -> IResult<&str, builder::Scope, Error> {
     alt((
         map(tag("authority"), |_| builder::Scope::Authority),
         map(tag("previous"), |_| builder::Scope::Previous),
+        group_scope,
+        threshold_scope,
+        scope_key,
+    ))(i)
+}
+
+/// `group("partners")`
+fn group_scope(i: &str) -> IResult<&str, builder::Scope, Error<'_>> {
+    map(
+        preceded(
+            terminated(tag("group"), preceded(space0, char('('))),
+            cut(terminated(
+                preceded(space0, parse_string),
+                preceded(space0, char(')')),
+            )),
+        ),
+        builder::Scope::Group,
+    )(i)
+}
+
+/// a public key or a parameter
+fn scope_key(i: &str) -> IResult<&str, builder::Scope, Error<'_>> {
+    alt((
         map(public_key, |bytes| builder::Scope::PublicKey(bytes)),
         map(delimited(char('{'), name, char('}')), |n| {
             builder::Scope::Parameter(n.to_string())
@@ -374,6 +521,34 @@ fn scope(i: &str) -> IResult<&str, builder::Scope, Error> {
     ))(i)
 }
 
+/// `2 of {ed25519/..., ed25519/..., {key}}`
+fn threshold_scope(i: &str) -> IResult<&str, builder::Scope, Error<'_>> {
+    let (i, threshold) = map_res(
+        terminated(digit1, delimited(space0, tag("of"), space0)),
+        |s: &str| s.parse::<u32>(),
+    )(i)?;
+    let (i, keys) = cut(delimited(
+        char('{'),
+        separated_list1(preceded(space0, char(',')), preceded(space0, scope_key)),
+        preceded(space0, char('}')),
+    ))(i)?;
+
+    if threshold == 0 || threshold as usize > keys.len() {
+        return Err(nom::Err::Failure(Error {
+            input: i,
+            code: ErrorKind::Fail,
+            message: Some(format!(
+                "the threshold must be between 1 and the number of keys ({})",
+                keys.len()
+            )),
+            span: None,
+            snippet: None,
+        }));
+    }
+
+    Ok((i, builder::Scope::Threshold(threshold, keys)))
+}
+
 pub fn public_key(i: &str) -> IResult<&str, builder::PublicKey, Error> {
     preceded(tag("ed25519/"), parse_hex)(i)
 }
@@ -408,29 +583,35 @@ impl Expr {
     }
 }
 
-fn unary_negate(i: &str) -> IResult<&str, Expr, Error> {
+fn unary_negate(i: &str) -> IResult<&str, Typed<'_, Expr>, Error> {
     let (i, _) = space0(i)?;
     let (i, _) = tag("!")(i)?;
     let (i, _) = space0(i)?;
-    let (i, value) = expr6(i)?;
+    let (i, (value, types)) = expr6(i)?;
 
     Ok((
         i,
-        Expr::Unary(builder::Op::Unary(builder::Unary::Negate), Box::new(value)),
+        (
+            Expr::Unary(builder::Op::Unary(builder::Unary::Negate), Box::new(value)),
+            types,
+        ),
     ))
 }
 
-fn unary_parens(i: &str) -> IResult<&str, Expr, Error> {
+fn unary_parens(i: &str) -> IResult<&str, Typed<'_, Expr>, Error> {
     let (i, _) = space0(i)?;
     let (i, _) = tag("(")(i)?;
     let (i, _) = space0(i)?;
-    let (i, value) = expr(i)?;
+    let (i, (value, types)) = typed_expr(i)?;
     let (i, _) = space0(i)?;
     let (i, _) = tag(")")(i)?;
 
     Ok((
         i,
-        Expr::Unary(builder::Op::Unary(builder::Unary::Parens), Box::new(value)),
+        (
+            Expr::Unary(builder::Op::Unary(builder::Unary::Parens), Box::new(value)),
+            types,
+        ),
     ))
 }
 
@@ -491,20 +672,32 @@ fn binary_op_8(i: &str) -> IResult<&str, builder::Binary, Error> {
         value(Binary::Regex, tag("matches")),
         value(Binary::Intersection, tag("intersection")),
         value(Binary::Union, tag("union")),
+        value(Binary::Get, tag("get")),
     ))(i)
 }
 
 /// Innermost parser for an expression: either a parenthesised expression,
 /// or a single term.
-fn expr_term(i: &str) -> IResult<&str, Expr, Error> {
-    alt((unary_parens, reduce(map(term, Expr::Value), " ,\n);")))(i)
+fn expr_term(i: &str) -> IResult<&str, Typed<'_, Expr>, Error> {
+    alt((
+        unary_parens,
+        reduce(map(term, |(t, types)| (Expr::Value(t), types)), " ,\n);"),
+    ))(i)
 }
 
-fn fold_exprs(initial: Expr, remainder: Vec<(builder::Binary, Expr)>) -> Expr {
-    remainder.into_iter().fold(initial, |acc, pair| {
-        let (op, expr) = pair;
-        Expr::Binary(builder::Op::Binary(op), Box::new(acc), Box::new(expr))
-    })
+fn fold_exprs<'a>(
+    initial: Typed<'a, Expr>,
+    remainder: Vec<(builder::Binary, Typed<'a, Expr>)>,
+) -> Typed<'a, Expr> {
+    remainder
+        .into_iter()
+        .fold(initial, |(acc, mut types), (op, (expr, mut expr_types))| {
+            types.append(&mut expr_types);
+            (
+                Expr::Binary(builder::Op::Binary(op), Box::new(acc), Box::new(expr)),
+                types,
+            )
+        })
 }
 
 /// Top-lever parser for an expression. Expression parsers are layered in
@@ -521,6 +714,11 @@ fn fold_exprs(initial: Expr, remainder: Vec<(builder::Binary, Expr)>) -> Expr {
 /// `||` is left associative, so multiple `||` expressions can be combined:
 /// `a || b || c <=> (a || b) || c`
 pub fn expr(i: &str) -> IResult<&str, Expr, Error> {
+    map(typed_expr, |(e, _)| e)(i)
+}
+
+/// parses an expression, along with the parameter type annotations it contains
+fn typed_expr(i: &str) -> IResult<&str, Typed<'_, Expr>, Error<'_>> {
     let (i, initial) = expr1(i)?;
 
     let (i, remainder) = many0(tuple((preceded(space0, binary_op_0), expr1)))(i)?;
@@ -531,7 +729,7 @@ pub fn expr(i: &str) -> IResult<&str, Expr, Error> {
 /// This level handles `&&`
 /// `&&` is left associative, so multiple `&&` expressions can be combined:
 /// `a && b && c <=> (a && b) && c`
-fn expr1(i: &str) -> IResult<&str, Expr, Error> {
+fn expr1(i: &str) -> IResult<&str, Typed<'_, Expr>, Error> {
     let (i, initial) = expr2(i)?;
 
     let (i, remainder) = many0(tuple((preceded(space0, binary_op_1), expr2)))(i)?;
@@ -542,18 +740,11 @@ fn expr1(i: &str) -> IResult<&str, Expr, Error> {
 /// This level handles comparison operators (`==`, `>`, `>=`, `<`, `<=`).
 /// Those operators are _not_ associative and require explicit grouping
 /// with parentheses.
-fn expr2(i: &str) -> IResult<&str, Expr, Error> {
+fn expr2(i: &str) -> IResult<&str, Typed<'_, Expr>, Error> {
     let (i, initial) = expr3(i)?;
 
     if let Ok((i, (op, remainder))) = tuple((preceded(space0, binary_op_2), expr3))(i) {
-        Ok((
-            i,
-            Expr::Binary(
-                builder::Op::Binary(op),
-                Box::new(initial),
-                Box::new(remainder),
-            ),
-        ))
+        Ok((i, fold_exprs(initial, vec![(op, remainder)])))
     } else {
         Ok((i, initial))
     }
@@ -562,7 +753,7 @@ fn expr2(i: &str) -> IResult<&str, Expr, Error> {
 /// This level handles `|`.
 /// It is left associative, so multiple expressions can be combined:
 /// `a | b | c <=> (a | b) | c`
-fn expr3(i: &str) -> IResult<&str, Expr, Error> {
+fn expr3(i: &str) -> IResult<&str, Typed<'_, Expr>, Error> {
     let (i, initial) = expr4(i)?;
 
     let (i, remainder) = many0(tuple((preceded(space0, binary_op_3), expr4)))(i)?;
@@ -573,7 +764,7 @@ fn expr3(i: &str) -> IResult<&str, Expr, Error> {
 /// This level handles `^`.
 /// It is left associative, so multiple expressions can be combined:
 /// `a ^ b ^ c <=> (a ^ b) ^ c`
-fn expr4(i: &str) -> IResult<&str, Expr, Error> {
+fn expr4(i: &str) -> IResult<&str, Typed<'_, Expr>, Error> {
     let (i, initial) = expr5(i)?;
 
     let (i, remainder) = many0(tuple((preceded(space0, binary_op_4), expr5)))(i)?;
@@ -584,7 +775,7 @@ fn expr4(i: &str) -> IResult<&str, Expr, Error> {
 /// This level handles `&`.
 /// It is left associative, so multiple expressions can be combined:
 /// `a & b & c <=> (a & b) & c`
-fn expr5(i: &str) -> IResult<&str, Expr, Error> {
+fn expr5(i: &str) -> IResult<&str, Typed<'_, Expr>, Error> {
     let (i, initial) = expr6(i)?;
 
     let (i, remainder) = many0(tuple((preceded(space0, binary_op_5), expr6)))(i)?;
@@ -595,7 +786,7 @@ fn expr5(i: &str) -> IResult<&str, Expr, Error> {
 /// This level handles `+` and `-`.
 /// They are left associative, so multiple expressions can be combined:
 /// `a + b - c <=> (a + b) - c`
-fn expr6(i: &str) -> IResult<&str, Expr, Error> {
+fn expr6(i: &str) -> IResult<&str, Typed<'_, Expr>, Error> {
     let (i, initial) = expr7(i)?;
 
     let (i, remainder) = many0(tuple((preceded(space0, binary_op_6), expr7)))(i)?;
@@ -606,7 +797,7 @@ fn expr6(i: &str) -> IResult<&str, Expr, Error> {
 /// This level handles `*` and `/`.
 /// They are left associative, so multiple expressions can be combined:
 /// `a * b / c <=> (a * b) / c`
-fn expr7(i: &str) -> IResult<&str, Expr, Error> {
+fn expr7(i: &str) -> IResult<&str, Typed<'_, Expr>, Error> {
     let (i, initial) = expr8(i)?;
 
     let (i, remainder) = many0(tuple((preceded(space0, binary_op_7), expr8)))(i)?;
@@ -615,25 +806,26 @@ fn expr7(i: &str) -> IResult<&str, Expr, Error> {
 }
 
 /// This level handles `!` (prefix negation)
-fn expr8(i: &str) -> IResult<&str, Expr, Error> {
+fn expr8(i: &str) -> IResult<&str, Typed<'_, Expr>, Error> {
     alt((unary_negate, expr9))(i)
 }
 
 /// This level handles methods. Methods can take either zero or one
 /// argument in addition to the expression they are called on.
 /// The name of the method decides its arity.
-fn expr9(i: &str) -> IResult<&str, Expr, Error> {
-    let (mut input, mut initial) = expr_term(i)?;
+fn expr9(i: &str) -> IResult<&str, Typed<'_, Expr>, Error> {
+    let (mut input, (mut initial, mut types)) = expr_term(i)?;
 
     loop {
         if let Ok((i, _)) = char::<_, ()>('.')(input) {
             let bin_result = binary_method(i);
             let un_result = unary_method(i);
             match (bin_result, un_result) {
-                (Ok((i, (op, arg))), _) => {
+                (Ok((i, (op, (arg, mut arg_types)))), _) => {
                     input = i;
                     initial =
                         Expr::Binary(builder::Op::Binary(op), Box::new(initial), Box::new(arg));
+                    types.append(&mut arg_types);
                 }
                 (_, Ok((i, op))) => {
                     input = i;
@@ -643,18 +835,18 @@ fn expr9(i: &str) -> IResult<&str, Expr, Error> {
                 (_, Err(e)) => return Err(e),
             }
         } else {
-            return Ok((input, initial));
+            return Ok((input, (initial, types)));
         }
     }
 }
 
-fn binary_method(i: &str) -> IResult<&str, (builder::Binary, Expr), Error> {
+fn binary_method(i: &str) -> IResult<&str, (builder::Binary, Typed<'_, Expr>), Error> {
     let (i, op) = binary_op_8(i)?;
 
     let (i, _) = char('(')(i)?;
     let (i, _) = space0(i)?;
     // we only support a single argument for now
-    let (i, arg) = expr(i)?;
+    let (i, arg) = typed_expr(i)?;
     let (i, _) = space0(i)?;
     let (i, _) = char(')')(i)?;
 
@@ -716,7 +908,9 @@ fn integer(i: &str) -> IResult<&str, builder::Term, Error> {
 fn parse_date(i: &str) -> IResult<&str, u64, Error> {
     map_res(
         map_res(
-            take_while1(|c: char| c != ',' && c != ' ' && c != ')' && c != ']' && c != ';'),
+            take_while1(|c: char| {
+                c != ',' && c != ' ' && c != ')' && c != ']' && c != '}' && c != ';'
+            }),
             |s| time::OffsetDateTime::parse(s, &time::format_description::well_known::Rfc3339),
         ),
         |t| t.unix_timestamp().try_into(),
@@ -749,8 +943,147 @@ fn variable(i: &str) -> IResult<&str, builder::Term, Error> {
     map(preceded(char('$'), name), builder::variable)(i)
 }
 
-fn parameter(i: &str) -> IResult<&str, builder::Term, Error> {
-    map(delimited(char('{'), name, char('}')), builder::parameter)(i)
+fn parameter(i: &str) -> IResult<&str, Typed<'_, builder::Term>, Error> {
+    let (remaining, (name, parameter_type)) = typed_parameter(i)?;
+
+    let types = parameter_type
+        .map(|parameter_type| (&i[..i.offset(remaining)], name.to_string(), parameter_type))
+        .into_iter()
+        .collect();
+
+    Ok((remaining, (builder::parameter(name), types)))
+}
+
+fn is_parameter_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| is_alphanumeric(c as u8) || c == '_' || c == ':')
+}
+
+/// parses a parameter with an optional type annotation: `{name}` or `{name: type}`
+///
+/// parameter names can contain ':', so the annotation is only taken from the last ':'
+/// separator, and only if it is a valid type: `{ns:x}` is an untyped parameter named
+/// `ns:x`, while `{ns:x:int}` is a parameter named `ns:x` of type int
+fn typed_parameter(i: &str) -> IResult<&str, (&str, Option<builder::ParameterType>), Error<'_>> {
+    let (remaining, content) = delimited(
+        char('{'),
+        take_while1(|c: char| c != '}' && c != '{' && c != '\n'),
+        char('}'),
+    )(i)?;
+
+    if let Some((name, annotation)) = content.rsplit_once(':') {
+        let name = name.trim_end();
+
+        if is_parameter_name(name) {
+            match terminated(preceded(space0, parameter_type), pair(space0, eof))(annotation) {
+                Ok((_, parameter_type)) => return Ok((remaining, (name, Some(parameter_type)))),
+                Err(_) if !is_parameter_name(content) => {
+                    return Err(nom::Err::Failure(Error {
+                        input: annotation.trim(),
+                        code: ErrorKind::Verify,
+                        message: Some(format!(
+                            "unknown parameter type '{}', expected one of int, string, date, bytes, bool or set<type>",
+                            annotation.trim()
+                        )),
+                        span: None,
+                        snippet: None,
+                    }));
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    if is_parameter_name(content) {
+        Ok((remaining, (content, None)))
+    } else {
+        Err(nom::Err::Error(Error::from_error_kind(i, ErrorKind::Char)))
+    }
+}
+
+fn scalar_parameter_type(i: &str) -> IResult<&str, builder::ParameterType, Error<'_>> {
+    alt((
+        value(builder::ParameterType::Integer, tag("int")),
+        value(builder::ParameterType::String, tag("string")),
+        value(builder::ParameterType::Date, tag("date")),
+        value(builder::ParameterType::Bytes, tag("bytes")),
+        value(builder::ParameterType::Bool, tag("bool")),
+    ))(i)
+}
+
+fn parameter_type(i: &str) -> IResult<&str, builder::ParameterType, Error<'_>> {
+    alt((
+        map(
+            preceded(
+                pair(tag("set"), preceded(space0, char('<'))),
+                terminated(
+                    preceded(space0, scalar_parameter_type),
+                    preceded(space0, char('>')),
+                ),
+            ),
+            |t| builder::ParameterType::Set(Box::new(t)),
+        ),
+        scalar_parameter_type,
+    ))(i)
+}
+
+/// parameter type annotations found in a parsed element, along with the source
+/// text of the annotated parameter
+type ParameterTypes<'a> = Vec<(&'a str, String, builder::ParameterType)>;
+
+/// a parsed element, along with the parameter type annotations it contains
+type Typed<'a, T> = (T, ParameterTypes<'a>);
+
+fn untyped<'a, T>(value: T) -> Typed<'a, T> {
+    (value, Vec::new())
+}
+
+/// separates parsed elements from their parameter type annotations
+fn split_types<T>(elements: Vec<Typed<'_, T>>) -> (Vec<T>, ParameterTypes<'_>) {
+    let mut types = Vec::new();
+    let values = elements
+        .into_iter()
+        .map(|(value, mut value_types)| {
+            types.append(&mut value_types);
+            value
+        })
+        .collect();
+
+    (values, types)
+}
+
+/// gathers the parameter type annotations of a fact, rule or query
+///
+/// a parameter can be annotated in any of its occurrences, but all the annotations
+/// for the same parameter must agree
+fn parameter_types(
+    annotations: ParameterTypes<'_>,
+) -> Result<HashMap<String, builder::ParameterType>, nom::Err<Error<'_>>> {
+    let mut types: HashMap<String, builder::ParameterType> = HashMap::new();
+
+    for (input, name, parameter_type) in annotations {
+        match types.get(&name) {
+            Some(previous) if previous != &parameter_type => {
+                return Err(nom::Err::Failure(Error {
+                    input,
+                    code: ErrorKind::Verify,
+                    message: Some(format!(
+                        "parameter {{{}}} is declared with conflicting types {} and {}",
+                        name, previous, parameter_type
+                    )),
+                    span: None,
+                    snippet: None,
+                }));
+            }
+            _ => {
+                types.insert(name, parameter_type);
+            }
+        }
+    }
+
+    Ok(types)
 }
 
 fn parse_bool(i: &str) -> IResult<&str, bool, Error> {
@@ -761,10 +1094,11 @@ fn boolean(i: &str) -> IResult<&str, builder::Term, Error> {
     parse_bool(i).map(|(i, b)| (i, builder::boolean(b)))
 }
 
-fn set(i: &str) -> IResult<&str, builder::Term, Error> {
+fn set(i: &str) -> IResult<&str, Typed<'_, builder::Term>, Error> {
     //println!("set:\t{}", i);
     let (i, _) = preceded(space0, char('['))(i)?;
-    let (i, mut list) = cut(separated_list0(preceded(space0, char(',')), term_in_set))(i)?;
+    let (i, list) = cut(separated_list0(preceded(space0, char(',')), term_in_set))(i)?;
+    let (mut list, types) = split_types(list);
 
     let mut set = BTreeSet::new();
 
@@ -776,6 +1110,8 @@ fn set(i: &str) -> IResult<&str, builder::Term, Error> {
                     input: i,
                     code: ErrorKind::Fail,
                     message: Some("variables are not permitted in sets".to_string()),
+                    span: None,
+                    snippet: None,
                 }))
             }
             builder::Term::Integer(_) => 2,
@@ -788,9 +1124,20 @@ fn set(i: &str) -> IResult<&str, builder::Term, Error> {
                     input: i,
                     code: ErrorKind::Fail,
                     message: Some("sets cannot contain other sets".to_string()),
+                    span: None,
+                    snippet: None,
                 }))
             }
             builder::Term::Parameter(_) => 7,
+            builder::Term::Array(_) | builder::Term::Map(_) => {
+                return Err(nom::Err::Failure(Error {
+                    input: i,
+                    code: ErrorKind::Fail,
+                    message: Some("sets cannot contain arrays or maps".to_string()),
+                    span: None,
+                    snippet: None,
+                }))
+            }
         };
 
         if let Some(k) = kind {
@@ -799,6 +1146,8 @@ fn set(i: &str) -> IResult<&str, builder::Term, Error> {
                     input: i,
                     code: ErrorKind::Fail,
                     message: Some("set elements must have the same type".to_string()),
+                    span: None,
+                    snippet: None,
                 }));
             }
         } else {
@@ -810,23 +1159,82 @@ fn set(i: &str) -> IResult<&str, builder::Term, Error> {
 
     let (i, _) = preceded(space0, char(']'))(i)?;
 
-    Ok((i, builder::set(set)))
+    Ok((i, (builder::set(set), types)))
+}
+
+/// parses an array: `array[1, "a", [2, 3]]`
+fn array(i: &str) -> IResult<&str, Typed<'_, builder::Term>, Error<'_>> {
+    let (i, _) = tag("array")(i)?;
+    let (i, _) = preceded(space0, char('['))(i)?;
+    let (i, list) = cut(separated_list0(
+        preceded(space0, char(',')),
+        term_in_composite,
+    ))(i)?;
+    let (i, _) = cut(preceded(space0, char(']')))(i)?;
+    let (list, types) = split_types(list);
+
+    Ok((i, (builder::array(list), types)))
+}
+
+fn map_entry(i: &str) -> IResult<&str, Typed<'_, (String, builder::Term)>, Error<'_>> {
+    let (i, key) = preceded(space0, parse_string)(i)?;
+    let (i, _) = cut(preceded(space0, char(':')))(i)?;
+    let (i, (value, types)) = cut(term_in_composite)(i)?;
+
+    Ok((i, ((key, value), types)))
 }
 
-fn term(i: &str) -> IResult<&str, builder::Term, Error> {
+/// parses a map with string keys: `{"a": 1, "b": array[2]}`
+fn map_term(i: &str) -> IResult<&str, Typed<'_, builder::Term>, Error<'_>> {
+    let (i, _) = char('{')(i)?;
+    let (i, entries) = separated_list0(preceded(space0, char(',')), map_entry)(i)?;
+    let (entries, types) = split_types(entries);
+
+    let mut map = BTreeMap::new();
+    for (key, value) in entries {
+        if map.insert(key.clone(), value).is_some() {
+            return Err(nom::Err::Failure(Error {
+                input: i,
+                code: ErrorKind::Fail,
+                message: Some(format!("duplicate key \"{}\" in map", key)),
+                span: None,
+                snippet: None,
+            }));
+        }
+    }
+
+    let (i, _) = cut(preceded(space0, char('}')))(i)?;
+
+    Ok((i, (builder::map(map), types)))
+}
+
+fn term(i: &str) -> IResult<&str, Typed<'_, builder::Term>, Error> {
     preceded(
         space0,
         alt((
-            parameter, string, date, variable, integer, bytes, boolean, set,
+            parameter,
+            map_term,
+            map(
+                alt((string, date, variable, integer, bytes, boolean)),
+                untyped,
+            ),
+            array,
+            set,
         )),
     )(i)
 }
 
-fn term_in_fact(i: &str) -> IResult<&str, builder::Term, Error> {
+fn term_in_fact(i: &str) -> IResult<&str, Typed<'_, builder::Term>, Error> {
     preceded(
         space0,
         error(
-            alt((parameter, string, date, integer, bytes, boolean, set)),
+            alt((
+                parameter,
+                map_term,
+                map(alt((string, date, integer, bytes, boolean)), untyped),
+                array,
+                set,
+            )),
             |input| match input.chars().next() {
                 None | Some(',') | Some(')') => "missing term".to_string(),
                 Some('$') => "variables are not allowed in facts".to_string(),
@@ -837,11 +1245,14 @@ fn term_in_fact(i: &str) -> IResult<&str, builder::Term, Error> {
     )(i)
 }
 
-fn term_in_set(i: &str) -> IResult<&str, builder::Term, Error> {
+fn term_in_set(i: &str) -> IResult<&str, Typed<'_, builder::Term>, Error> {
     preceded(
         space0,
         error(
-            alt((parameter, string, date, integer, bytes, boolean)),
+            alt((
+                parameter,
+                map(alt((string, date, integer, bytes, boolean)), untyped),
+            )),
             |input| match input.chars().next() {
                 None | Some(',') | Some(']') => "missing term".to_string(),
                 Some('$') => "variables are not allowed in sets".to_string(),
@@ -852,6 +1263,26 @@ fn term_in_set(i: &str) -> IResult<&str, builder::Term, Error> {
     )(i)
 }
 
+fn term_in_composite(i: &str) -> IResult<&str, Typed<'_, builder::Term>, Error<'_>> {
+    preceded(
+        space0,
+        error(
+            alt((
+                map_term,
+                map(alt((string, date, integer, bytes, boolean)), untyped),
+                array,
+                set,
+            )),
+            |input| match input.chars().next() {
+                None | Some(',') | Some(']') | Some('}') => "missing term".to_string(),
+                Some('$') => "variables are not allowed in arrays and maps".to_string(),
+                _ => "expected a valid term".to_string(),
+            },
+            " ,]}\n;",
+        ),
+    )(i)
+}
+
 fn line_comment(i: &str) -> IResult<&str, (), Error> {
     let (i, _) = space0(i)?;
     let (i, _) = tag("//")(i)?;
@@ -877,144 +1308,146 @@ pub struct SourceResult<'a> {
     pub rules: Vec<(&'a str, builder::Rule)>,
     pub checks: Vec<(&'a str, builder::Check)>,
     pub policies: Vec<(&'a str, builder::Policy)>,
+    pub schemas: Vec<(&'a str, builder::PredicateSchema)>,
 }
 
-enum SourceElement<'a> {
+/// element of a Datalog source, along with the source text it was parsed from
+pub(crate) enum SourceElement<'a> {
+    Schema(&'a str, builder::PredicateSchema),
     Fact(&'a str, builder::Fact),
     Rule(&'a str, builder::Rule),
-    Check(&'a str, builder::Check),
-    Policy(&'a str, builder::Policy),
-    Comment,
+    Check(&'a str, builder::Check, Vec<&'a str>),
+    Policy(&'a str, builder::Policy, Vec<&'a str>),
+    Comment(&'a str),
 }
 
-pub fn sep(i: &str) -> IResult<&str, &str, Error> {
-    let (i, _) = space0(i)?;
-    alt((tag(";"), eof))(i)
-}
+impl<'a> SourceElement<'a> {
+    /// records the location of the element in the source text
+    fn with_spans(self, source: &'a str) -> Self {
+        let span = |input: &'a str| Some(Span::from_slice(source, input.trim()));
 
-pub fn parse_source(mut i: &str) -> Result<SourceResult, Vec<Error>> {
-    let mut result = SourceResult::default();
-    let mut errors = Vec::new();
-
-    loop {
-        if i.is_empty() {
-            if errors.is_empty() {
-                return Ok(result);
-            } else {
-                return Err(errors);
+        match self {
+            SourceElement::Schema(i, s) => SourceElement::Schema(i, s),
+            SourceElement::Fact(i, mut f) => {
+                f.span = span(i);
+                SourceElement::Fact(i, f)
             }
-        }
-
-        match terminated(
-            alt((
-                map(terminated(consumed(rule_inner), sep), |(i, r)| {
-                    SourceElement::Rule(i, r)
-                }),
-                map(terminated(consumed(fact_inner), sep), |(i, f)| {
-                    SourceElement::Fact(i, f)
-                }),
-                map(terminated(consumed(check_inner), sep), |(i, c)| {
-                    SourceElement::Check(i, c)
-                }),
-                map(terminated(consumed(policy_inner), sep), |(i, p)| {
-                    SourceElement::Policy(i, p)
-                }),
-                map(line_comment, |_| SourceElement::Comment),
-                map(multiline_comment, |_| SourceElement::Comment),
-            )),
-            space0,
-        )(i)
-        {
-            Ok((i2, o)) => {
-                match o {
-                    SourceElement::Fact(i, f) => result.facts.push((i, f)),
-                    SourceElement::Rule(i, r) => result.rules.push((i, r)),
-                    SourceElement::Check(i, c) => result.checks.push((i, c)),
-                    SourceElement::Policy(i, p) => result.policies.push((i, p)),
-                    SourceElement::Comment => {}
-                }
-
-                i = i2;
+            SourceElement::Rule(i, mut r) => {
+                r.span = span(i);
+                SourceElement::Rule(i, r)
             }
-            Err(nom::Err::Incomplete(_)) => panic!(),
-            Err(nom::Err::Error(mut e)) => {
-                if let Some(index) = e.input.find(|c| c == ';') {
-                    e.input = &(e.input)[..index];
-                }
-
-                let offset = i.offset(e.input);
-                if let Some(index) = &i[offset..].find(|c| c == ';') {
-                    i = &i[offset + index + 1..];
-                } else {
-                    i = &i[i.len()..];
+            SourceElement::Check(i, mut c, inputs) => {
+                for (query, input) in c.queries.iter_mut().zip(inputs.iter()) {
+                    query.span = span(input);
                 }
-
-                errors.push(e);
+                SourceElement::Check(i, c, inputs)
             }
-            Err(nom::Err::Failure(mut e)) => {
-                if let Some(index) = e.input.find(|c| c == ';') {
-                    e.input = &(e.input)[..index];
-                }
-
-                let offset = i.offset(e.input);
-                if let Some(index) = &i[offset..].find(|c| c == ';') {
-                    i = &i[offset + index + 1..];
-                } else {
-                    i = &i[i.len()..];
+            SourceElement::Policy(i, mut p, inputs) => {
+                for (query, input) in p.queries.iter_mut().zip(inputs.iter()) {
+                    query.span = span(input);
                 }
-
-                errors.push(e);
+                SourceElement::Policy(i, p, inputs)
             }
+            SourceElement::Comment(i) => SourceElement::Comment(i),
         }
     }
 }
 
-pub fn parse_block_source(mut i: &str) -> Result<SourceResult, Vec<Error>> {
-    let mut result = SourceResult::default();
-    let mut errors = Vec::new();
+/// records the location of a parse error in the source text
+fn locate<'a>(source: &'a str, mut e: Error<'a>) -> Error<'a> {
+    let span = Span::from_slice(source, e.input);
+    e.snippet = Some(span.lines(source));
+    e.span = Some(span);
+    e
+}
 
-    match opt(terminated(consumed(scopes), sep))(i) {
-        Ok((i2, opt_scopes)) => {
-            if let Some((_, scopes)) = opt_scopes {
-                i = i2;
-                result.scopes = scopes;
+/// skips the statement containing a parse error, so parsing can resume after it
+fn recover<'a>(i: &'a str, mut e: Error<'a>) -> (&'a str, Error<'a>) {
+    if let Some(index) = e.input.find(|c| c == ';') {
+        e.input = &(e.input)[..index];
+    }
+
+    let offset = i.offset(e.input);
+    let i = if let Some(index) = &i[offset..].find(|c| c == ';') {
+        &i[offset + index + 1..]
+    } else {
+        &i[i.len()..]
+    };
+
+    // trailing whitespace would otherwise be reported as an unexpected end of input
+    (i.trim_start(), e)
+}
+
+pub fn sep(i: &str) -> IResult<&str, &str, Error> {
+    let (i, _) = space0(i)?;
+    alt((tag(";"), eof))(i)
+}
+
+pub fn parse_source(i: &str) -> Result<SourceResult, Vec<Error>> {
+    let (scopes, elements) = parse_elements(i, false)?;
+    Ok(SourceResult::from_elements(scopes, elements))
+}
+
+pub fn parse_block_source(i: &str) -> Result<SourceResult, Vec<Error>> {
+    let (scopes, elements) = parse_elements(i, true)?;
+    Ok(SourceResult::from_elements(scopes, elements))
+}
+
+impl<'a> SourceResult<'a> {
+    fn from_elements(scopes: Vec<builder::Scope>, elements: Vec<SourceElement<'a>>) -> Self {
+        let mut result = SourceResult {
+            scopes,
+            ..Default::default()
+        };
+
+        for element in elements {
+            match element {
+                SourceElement::Schema(i, s) => result.schemas.push((i, s)),
+                SourceElement::Fact(i, f) => result.facts.push((i, f)),
+                SourceElement::Rule(i, r) => result.rules.push((i, r)),
+                SourceElement::Check(i, c, _) => result.checks.push((i, c)),
+                SourceElement::Policy(i, p, _) => result.policies.push((i, p)),
+                SourceElement::Comment(_) => {}
             }
         }
-        Err(nom::Err::Incomplete(_)) => panic!(),
-        Err(nom::Err::Error(mut e)) => {
-            if let Some(index) = e.input.find(|c| c == ';') {
-                e.input = &(e.input)[..index];
-            }
 
-            let offset = i.offset(e.input);
-            if let Some(index) = &i[offset..].find(|c| c == ';') {
-                i = &i[offset + index + 1..];
-            } else {
-                i = &i[i.len()..];
-            }
+        result
+    }
+}
 
-            errors.push(e);
-        }
-        Err(nom::Err::Failure(mut e)) => {
-            if let Some(index) = e.input.find(|c| c == ';') {
-                e.input = &(e.input)[..index];
-            }
+/// parses the elements of a Datalog source in order, comments included
+///
+/// a block source can start with a `trusting` annotation, and cannot contain policies
+pub(crate) fn parse_elements(
+    mut i: &str,
+    block: bool,
+) -> Result<(Vec<builder::Scope>, Vec<SourceElement>), Vec<Error>> {
+    let source = i;
+    let mut block_scopes = Vec::new();
+    let mut elements = Vec::new();
+    let mut errors = Vec::new();
 
-            let offset = i.offset(e.input);
-            if let Some(index) = &i[offset..].find(|c| c == ';') {
-                i = &i[offset + index + 1..];
-            } else {
-                i = &i[i.len()..];
+    if block {
+        match opt(terminated(consumed(scopes), sep))(i) {
+            Ok((i2, opt_scopes)) => {
+                if let Some((_, s)) = opt_scopes {
+                    i = i2;
+                    block_scopes = s;
+                }
+            }
+            Err(nom::Err::Incomplete(_)) => panic!(),
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                let (i2, e) = recover(i, e);
+                i = i2;
+                errors.push(locate(source, e));
             }
-
-            errors.push(e);
         }
     }
 
     loop {
         if i.is_empty() {
             if errors.is_empty() {
-                return Ok(result);
+                return Ok((block_scopes, elements));
             } else {
                 return Err(errors);
             }
@@ -1022,60 +1455,44 @@ pub fn parse_block_source(mut i: &str) -> Result<SourceResult, Vec<Error>> {
 
         match terminated(
             alt((
+                map(terminated(consumed(schema_inner), sep), |(i, s)| {
+                    SourceElement::Schema(i, s)
+                }),
                 map(terminated(consumed(rule_inner), sep), |(i, r)| {
                     SourceElement::Rule(i, r)
                 }),
                 map(terminated(consumed(fact_inner), sep), |(i, f)| {
                     SourceElement::Fact(i, f)
                 }),
-                map(terminated(consumed(check_inner), sep), |(i, c)| {
-                    SourceElement::Check(i, c)
-                }),
-                map(line_comment, |_| SourceElement::Comment),
-                map(multiline_comment, |_| SourceElement::Comment),
+                map(
+                    terminated(consumed(check_inner_spanned), sep),
+                    |(i, (c, q))| SourceElement::Check(i, c, q),
+                ),
+                |i| {
+                    if block {
+                        Err(nom::Err::Error(Error::from_error_kind(i, ErrorKind::Alt)))
+                    } else {
+                        map(
+                            terminated(consumed(policy_inner_spanned), sep),
+                            |(i, (p, q))| SourceElement::Policy(i, p, q),
+                        )(i)
+                    }
+                },
+                map(recognize(line_comment), SourceElement::Comment),
+                map(recognize(multiline_comment), SourceElement::Comment),
             )),
             space0,
         )(i)
         {
             Ok((i2, o)) => {
-                match o {
-                    SourceElement::Fact(i, f) => result.facts.push((i, f)),
-                    SourceElement::Rule(i, r) => result.rules.push((i, r)),
-                    SourceElement::Check(i, c) => result.checks.push((i, c)),
-                    SourceElement::Policy(_, _) => {}
-                    SourceElement::Comment => {}
-                }
-
+                elements.push(o.with_spans(source));
                 i = i2;
             }
             Err(nom::Err::Incomplete(_)) => panic!(),
-            Err(nom::Err::Error(mut e)) => {
-                if let Some(index) = e.input.find(|c| c == ';') {
-                    e.input = &(e.input)[..index];
-                }
-
-                let offset = i.offset(e.input);
-                if let Some(index) = &i[offset..].find(|c| c == ';') {
-                    i = &i[offset + index + 1..];
-                } else {
-                    i = &i[i.len()..];
-                }
-
-                errors.push(e);
-            }
-            Err(nom::Err::Failure(mut e)) => {
-                if let Some(index) = e.input.find(|c| c == ';') {
-                    e.input = &(e.input)[..index];
-                }
-
-                let offset = i.offset(e.input);
-                if let Some(index) = &i[offset..].find(|c| c == ';') {
-                    i = &i[offset + index + 1..];
-                } else {
-                    i = &i[i.len()..];
-                }
-
-                errors.push(e);
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                let (i2, e) = recover(i, e);
+                i = i2;
+                errors.push(locate(source, e));
             }
         }
     }
@@ -1087,6 +1504,10 @@ pub struct Error<'a> {
     pub input: &'a str,
     pub code: ErrorKind,
     pub message: Option<String>,
+    /// location of the error, filled by [`parse_source`] and [`parse_block_source`]
+    pub span: Option<Span>,
+    /// source lines containing the error, filled by [`parse_source`] and [`parse_block_source`]
+    pub snippet: Option<&'a str>,
 }
 
 impl<'a> ParseError<&'a str> for Error<'a> {
@@ -1095,6 +1516,8 @@ impl<'a> ParseError<&'a str> for Error<'a> {
             input,
             code: kind,
             message: None,
+            span: None,
+            snippet: None,
         }
     }
 
@@ -1110,6 +1533,8 @@ impl<'a, E> FromExternalError<&'a str, E> for Error<'a> {
             input,
             code: kind,
             message: None,
+            span: None,
+            snippet: None,
         }
     }
 }
@@ -1226,7 +1651,110 @@ mod tests {
     fn parameter() {
         assert_eq!(
             super::parameter("{param}"),
-            Ok(("", builder::parameter("param")))
+            Ok(("", (builder::parameter("param"), vec![])))
+        );
+    }
+
+    #[test]
+    fn typed_parameter() {
+        use builder::ParameterType;
+
+        assert_eq!(
+            super::typed_parameter("{expiry: date}"),
+            Ok(("", ("expiry", Some(ParameterType::Date))))
+        );
+        assert_eq!(
+            super::typed_parameter("{ids:set<int>}"),
+            Ok((
+                "",
+                (
+                    "ids",
+                    Some(ParameterType::Set(Box::new(ParameterType::Integer)))
+                )
+            ))
+        );
+        assert!(super::typed_parameter("{ids: set<set<int>>}").is_err());
+        assert!(super::typed_parameter("{p: float}").is_err());
+
+        assert_eq!(
+            super::parameter("{ns:x}"),
+            Ok(("", (builder::parameter("ns:x"), vec![])))
+        );
+        assert_eq!(
+            super::parameter("{ns:x: int}"),
+            Ok((
+                "",
+                (
+                    builder::parameter("ns:x"),
+                    vec![("{ns:x: int}", "ns:x".to_string(), ParameterType::Integer)]
+                )
+            ))
+        );
+        assert_eq!(super::typed_parameter("{ns:x}"), Ok(("", ("ns:x", None))));
+        assert_eq!(
+            super::typed_parameter("{ns:x:int}"),
+            Ok(("", ("ns:x", Some(ParameterType::Integer))))
+        );
+
+        let fact = super::fact("f({ns:x:int}, {ns:x}, {ns:y})").unwrap().1;
+        assert_eq!(
+            fact.predicate.terms,
+            vec![
+                builder::parameter("ns:x"),
+                builder::parameter("ns:x"),
+                builder::parameter("ns:y")
+            ]
+        );
+        let mut expected = std::collections::HashMap::new();
+        expected.insert("ns:x".to_string(), ParameterType::Integer);
+        assert_eq!(fact.parameter_types, expected);
+
+        let rule = super::rule(
+            r#"r($a) <- f($a, {p: string}, "{q: int}"), $a > {n: int}, {p}.length() > 1"#,
+        )
+        .unwrap()
+        .1;
+        let mut expected = std::collections::HashMap::new();
+        expected.insert("p".to_string(), ParameterType::String);
+        expected.insert("n".to_string(), ParameterType::Integer);
+        assert_eq!(rule.parameter_types, expected);
+
+        let check = super::check("check if f({p: int}) or g({p: bool})")
+            .unwrap()
+            .1;
+        assert_eq!(
+            check.queries[1].parameter_types.get("p"),
+            Some(&ParameterType::Bool)
+        );
+
+        assert!(super::fact("f({p: int}, {p: string})").is_err());
+
+        // annotations are collected from nested terms and method arguments
+        let fact = super::fact(r#"f(array[[{a: int}]], {"k": [{b: bytes}]})"#)
+            .unwrap()
+            .1;
+        let mut expected = std::collections::HashMap::new();
+        expected.insert("a".to_string(), ParameterType::Integer);
+        expected.insert("b".to_string(), ParameterType::Bytes);
+        assert_eq!(fact.parameter_types, expected);
+
+        let rule = super::rule(r#"r($a) <- f($a), !($a.contains({s: string}))"#)
+            .unwrap()
+            .1;
+        assert_eq!(rule.parameter_types.get("s"), Some(&ParameterType::String));
+        assert!(super::rule("r($a) <- f($a, {p: int}), $a == {p: date}").is_err());
+
+        // parsing a standalone expression keeps the terms
+        assert_eq!(
+            super::expr("{p: int} + 1").map(|(i, o)| (i, o.opcodes())),
+            Ok((
+                "",
+                vec![
+                    builder::Op::Value(builder::parameter("p")),
+                    builder::Op::Value(builder::int(1)),
+                    builder::Op::Binary(builder::Binary::Add),
+                ]
+            ))
         );
     }
 
@@ -1592,6 +2120,8 @@ mod tests {
                 code: ErrorKind::Char,
                 input: "$operation",
                 message: Some("variables are not allowed in facts".to_string()),
+                span: None,
+                snippet: None,
             }))
         );
     }
@@ -1693,6 +2223,8 @@ mod tests {
                 input: "right($0, $test)",
                 code: ErrorKind::Satisfy,
                 message: Some("rule head contains variables that are not used in predicates of the rule's body: $test".to_string()),
+                span: None,
+                snippet: None,
             }))
         );
     }
@@ -1735,6 +2267,8 @@ mod tests {
                 input: "and",
                 code: ErrorKind::Eof,
                 message: Some("expected either the next term after ',' or the next check variant after 'or', but got 'and'".to_string()),
+                span: None,
+                snippet: None,
             }))
         );
 
@@ -1744,6 +2278,8 @@ mod tests {
                 input: ")",
                 code: ErrorKind::Eof,
                 message: Some("unexpected parens".to_string()),
+                span: None,
+                snippet: None,
             }))
         );
 
@@ -1755,6 +2291,8 @@ mod tests {
                 input: "&&",
                 code: ErrorKind::Eof,
                 message: Some("expected either the next term after ',' or the next check variant after 'or', but got '&&'".to_string()),
+                span: None,
+                snippet: None,
             }))
         );
     }
@@ -2241,4 +2779,202 @@ mod tests {
             ))
         );
     }
+
+    #[test]
+    fn source_spans() {
+        use crate::error::{Position, Span};
+
+        let input =
+            "fact(1);\n  rule($a) <- fact($a);\ncheck if fact(1)\n  or fact(2);\nallow if true;\n";
+        let res = super::parse_source(input).unwrap();
+
+        assert_eq!(
+            res.facts[0].1.span,
+            Some(Span {
+                start: Position {
+                    offset: 0,
+                    line: 1,
+                    column: 1
+                },
+                end: Position {
+                    offset: 7,
+                    line: 1,
+                    column: 8
+                },
+            })
+        );
+        let rule_span = res.rules[0].1.span.unwrap();
+        assert_eq!((rule_span.start.line, rule_span.start.column), (2, 3));
+        assert_eq!(
+            &input[rule_span.start.offset..rule_span.end.offset],
+            "rule($a) <- fact($a)"
+        );
+
+        let check = &res.checks[0].1;
+        let query_span = check.queries[1].span.unwrap();
+        assert_eq!(
+            &input[query_span.start.offset..query_span.end.offset],
+            "fact(2)"
+        );
+        let check_span = check.span().unwrap();
+        assert_eq!((check_span.start.line, check_span.end.line), (3, 4));
+
+        assert_eq!(res.policies[0].1.span().unwrap().start.line, 5);
+
+        // spans are not taken into account when comparing elements
+        assert_eq!(res.facts[0].1, builder::fact("fact", &[builder::int(1)]));
+    }
+
+    #[test]
+    fn error_spans() {
+        // trailing whitespace after an error is not reported
+        let input = "fact(1);\nrule($a) <- fact($b);\n  check if fact(é, 1);\n";
+        let errors = super::parse_source(input).unwrap_err();
+        assert_eq!(errors.len(), 2);
+
+        let span = errors[0].span.unwrap();
+        assert_eq!((span.start.line, span.start.column), (2, 1));
+        assert_eq!(errors[0].snippet, Some("rule($a) <- fact($b);"));
+
+        let span = errors[1].span.unwrap();
+        assert_eq!(span.start.line, 3);
+        assert_eq!(span.start.column, 17);
+        assert_eq!(span.start.offset, input.find('é').unwrap());
+        assert_eq!(errors[1].snippet, Some("  check if fact(é, 1);"));
+
+        let error: crate::error::ParseError = errors.into_iter().nth(1).unwrap().into();
+        assert_eq!(error.span, Some(span));
+        assert_eq!(error.span.unwrap().to_string(), "3:17-3:18");
+    }
+
+    #[test]
+    fn threshold_scope() {
+        let key1 = "ed25519/acdd6d5b53bfee478bf689f8e012fe7988bf755e3d7c5152947abc149bc20189";
+        let key2 = "ed25519/a060270db7e9c9f06e8f9cc33a64e99f6596af12cb01c4b638df8afc7b642463";
+
+        let input = format!(
+            "valid($c) <- compliance($c) trusting 2 of {{ {}, {},{{auditor}} }}",
+            key1, key2
+        );
+        let (rest, rule) = super::rule(&input).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(
+            rule.scopes,
+            vec![builder::Scope::Threshold(
+                2,
+                vec![
+                    builder::Scope::PublicKey(hex::decode(&key1[8..]).unwrap()),
+                    builder::Scope::PublicKey(hex::decode(&key2[8..]).unwrap()),
+                    builder::Scope::Parameter("auditor".to_string()),
+                ]
+            )]
+        );
+        assert!(rule.scope_parameters.unwrap().contains_key("auditor"));
+
+        let input = format!("a(1) <- b(1) trusting authority, 1 of {{{}}}", key1);
+        let (_, rule) = super::rule(&input).unwrap();
+        assert_eq!(rule.scopes.len(), 2);
+
+        for invalid in [
+            format!("a(1) <- b(1) trusting 0 of {{{}}}", key1),
+            format!("a(1) <- b(1) trusting 2 of {{{}}}", key1),
+            "a(1) <- b(1) trusting 1 of {}".to_string(),
+            "a(1) <- b(1) trusting 1 of {previous}".to_string(),
+        ] {
+            assert!(super::rule(&invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn group_scope() {
+        let (rest, rule) =
+            super::rule("valid($c) <- attested($c) trusting group(\"partners\"), authority")
+                .unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(
+            rule.scopes,
+            vec![
+                builder::Scope::Group("partners".to_string()),
+                builder::Scope::Authority
+            ]
+        );
+
+        assert!(super::rule("a(1) <- b(1) trusting group(partners)").is_err());
+        assert!(super::rule("a(1) <- b(1) trusting group(\"partners\"").is_err());
+    }
+
+    #[test]
+    fn schema() {
+        let (rest, schema) = super::schema("schema right(string, set<int>)").unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(
+            schema,
+            builder::PredicateSchema::new(
+                "right".to_string(),
+                vec![
+                    builder::ParameterType::String,
+                    builder::ParameterType::Set(Box::new(builder::ParameterType::Integer)),
+                ]
+            )
+        );
+        assert_eq!(schema.to_string(), "schema right(string, set<int>)");
+
+        assert!(super::schema("schema right()").is_err());
+        assert!(super::schema("schema right(string, text)").is_err());
+
+        let source = super::parse_block_source(
+            r#"
+            schema right(string, string);
+            right("file1", "read");
+            schema("not a declaration");
+            "#,
+        )
+        .unwrap();
+        assert_eq!(source.schemas.len(), 1);
+        assert_eq!(source.schemas[0].0.trim(), "schema right(string, string)");
+        assert_eq!(source.facts.len(), 2);
+    }
+
+    #[test]
+    fn arrays_and_maps() {
+        use builder::{array, int, map, string};
+
+        let (rest, fact) =
+            super::fact(r#"user({"name": "alice", "groups": array["admin", 1]}, array[], {})"#)
+                .unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(
+            fact.predicate.terms,
+            vec![
+                map([
+                    ("name".to_string(), string("alice")),
+                    ("groups".to_string(), array(vec![string("admin"), int(1)])),
+                ]
+                .into_iter()
+                .collect()),
+                array(vec![]),
+                map(Default::default()),
+            ]
+        );
+
+        let (_, check) =
+            super::check(r#"check if user($u), $u.get("groups").get(0) == "admin""#).unwrap();
+        let mut ops = check.queries[0].expressions[0].ops.clone();
+        ops.truncate(5);
+        assert_eq!(
+            ops,
+            vec![
+                builder::Op::Value(builder::variable("u")),
+                builder::Op::Value(string("groups")),
+                builder::Op::Binary(builder::Binary::Get),
+                builder::Op::Value(int(0)),
+                builder::Op::Binary(builder::Binary::Get),
+            ]
+        );
+
+        assert!(super::fact(r#"user(array[$name])"#).is_err());
+        assert!(super::fact(r#"user({"a": 1, "a": 2})"#).is_err());
+        assert!(super::fact(r#"user([array[1]])"#).is_err());
+        assert!(super::fact(r#"user({1: 2})"#).is_err());
+    }
 }
@@ -1,16 +1,17 @@
 //! Procedural macros to build biscuit-auth tokens and authorizers
 
 use biscuit_parser::{
-    builder::{Check, Fact, Policy, Rule},
+    builder::{Check, Fact, ParameterType, Policy, PredicateSchema, Rule},
     error,
     parser::{parse_block_source, parse_source},
 };
 use proc_macro2::{Span, TokenStream};
 use proc_macro_error::{abort_call_site, proc_macro_error};
-use quote::{quote, ToTokens};
+use quote::{quote, quote_spanned, ToTokens};
 use std::collections::{HashMap, HashSet};
 use syn::{
     parse::{self, Parse, ParseStream},
+    spanned::Spanned,
     Expr, Ident, LitStr, Token, TypePath,
 };
 
@@ -203,6 +204,10 @@ struct Builder {
     // parameters provided to the macro
     pub macro_parameters: HashSet<String>,
 
+    // type annotations of the parameters used in the datalog source
+    pub parameter_types: HashMap<String, ParameterType>,
+
+    pub schemas: Vec<PredicateSchema>,
     pub facts: Vec<Fact>,
     pub rules: Vec<Rule>,
     pub checks: Vec<Check>,
@@ -224,7 +229,9 @@ impl Builder {
 
             datalog_parameters: HashSet::new(),
             macro_parameters,
+            parameter_types: HashMap::new(),
 
+            schemas: Vec::new(),
             facts: Vec::new(),
             rules: Vec::new(),
             checks: Vec::new(),
@@ -241,6 +248,7 @@ impl Builder {
         let mut builder = Builder::new(builder_type, target, parameters);
         let source = parse_block_source(source.as_ref())?;
 
+        builder.schemas = source.schemas.into_iter().map(|(_, s)| s).collect();
         builder.facts(source.facts.into_iter().map(|(_name, fact)| fact));
         builder.rules(source.rules.into_iter().map(|(_name, rule)| rule));
         builder.checks(source.checks.into_iter().map(|(_name, check)| check));
@@ -258,6 +266,7 @@ impl Builder {
         let mut builder = Builder::new(builder_type, target, parameters);
         let source = parse_source(source.as_ref())?;
 
+        builder.schemas = source.schemas.into_iter().map(|(_, s)| s).collect();
         builder.facts(source.facts.into_iter().map(|(_name, fact)| fact));
         builder.rules(source.rules.into_iter().map(|(_name, rule)| rule));
         builder.checks(source.checks.into_iter().map(|(_name, check)| check));
@@ -311,7 +320,9 @@ impl Builder {
         }
     }
 
-    fn validate(&self) -> Result<(), error::LanguageError> {
+    fn validate(&mut self) -> Result<(), error::LanguageError> {
+        self.parameter_types = self.collect_parameter_types()?;
+
         if self.macro_parameters.is_subset(&self.datalog_parameters) {
             Ok(())
         } else {
@@ -326,6 +337,82 @@ impl Builder {
             })
         }
     }
+
+    // a macro parameter is bound to a single value, so all the type
+    // annotations it gets across the datalog source must agree
+    fn collect_parameter_types(
+        &self,
+    ) -> Result<HashMap<String, ParameterType>, error::LanguageError> {
+        let mut types: HashMap<&str, &ParameterType> = HashMap::new();
+
+        let annotations = self
+            .facts
+            .iter()
+            .map(|fact| &fact.parameter_types)
+            .chain(self.rules.iter().map(|rule| &rule.parameter_types))
+            .chain(
+                self.checks
+                    .iter()
+                    .flat_map(|check| check.queries.iter().map(|rule| &rule.parameter_types)),
+            )
+            .chain(
+                self.policies
+                    .iter()
+                    .flat_map(|policy| policy.queries.iter().map(|rule| &rule.parameter_types)),
+            );
+
+        for parameter_types in annotations {
+            for (name, parameter_type) in parameter_types {
+                match types.get(name.as_str()) {
+                    Some(expected) if *expected != parameter_type => {
+                        return Err(error::LanguageError::ParameterType {
+                            name: name.to_string(),
+                            expected: expected.to_string(),
+                            actual: parameter_type.to_string(),
+                        });
+                    }
+                    _ => {
+                        types.insert(name, parameter_type);
+                    }
+                }
+            }
+        }
+
+        Ok(types
+            .into_iter()
+            .map(|(name, parameter_type)| (name.to_string(), parameter_type.clone()))
+            .collect())
+    }
+
+    // checks at compile time that the values given to the macro match the
+    // parameter type annotations. Set elements are still checked when the
+    // parameter is set, since a `BTreeSet<Term>` can hold any term
+    fn parameter_type_checks(&self) -> TokenStream {
+        self.parameters
+            .iter()
+            .filter_map(|(name, expr)| {
+                let parameter_type = parameter_type_marker(self.parameter_types.get(name)?);
+                let ident = Ident::new(name, Span::call_site());
+                Some(quote_spanned! { expr.span()=>
+                    ::biscuit_auth::builder::assert_parameter_type::<#parameter_type, _>(&#ident);
+                })
+            })
+            .collect()
+    }
+}
+
+fn parameter_type_marker(parameter_type: &ParameterType) -> TokenStream {
+    match parameter_type {
+        ParameterType::Integer => quote! { ::biscuit_auth::builder::parameter_type::Integer },
+        ParameterType::String => quote! { ::biscuit_auth::builder::parameter_type::String },
+        ParameterType::Date => quote! { ::biscuit_auth::builder::parameter_type::Date },
+        ParameterType::Bytes => quote! { ::biscuit_auth::builder::parameter_type::Bytes },
+        ParameterType::Bool => quote! { ::biscuit_auth::builder::parameter_type::Bool },
+        ParameterType::Set(t) => {
+            let t = parameter_type_marker(t);
+            quote! { ::biscuit_auth::builder::parameter_type::Set<#t> }
+        }
+    }
 }
 
 struct Item {
@@ -473,6 +560,8 @@ impl ToTokens for Builder {
             }
         }
 
+        let type_checks = self.parameter_type_checks();
+        let schemas = self.schemas.iter();
         let builder_type = &self.builder_type;
         let builder_quote = if let Some(target) = &self.target {
             quote! {
@@ -488,6 +577,8 @@ impl ToTokens for Builder {
             {
                 #builder_quote
                 #params_quote
+                #type_checks
+                #(__biscuit_auth_builder.add_schema(#schemas).unwrap();)*
                 #(#items)*
                 __biscuit_auth_builder
             }
@@ -554,9 +645,12 @@ pub fn rule(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
         }
     }
 
+    let type_checks = builder.parameter_type_checks();
+
     (quote! {
         {
             #params_quote
+            #type_checks
             #rule_item
         }
     })
@@ -622,9 +716,12 @@ pub fn fact(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
         }
     }
 
+    let type_checks = builder.parameter_type_checks();
+
     (quote! {
         {
             #params_quote
+            #type_checks
             #fact_item
         }
     })
@@ -690,9 +787,12 @@ pub fn check(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
         }
     }
 
+    let type_checks = builder.parameter_type_checks();
+
     (quote! {
         {
             #params_quote
+            #type_checks
             #check_item
         }
     })
@@ -762,9 +862,12 @@ pub fn policy(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
         }
     }
 
+    let type_checks = builder.parameter_type_checks();
+
     (quote! {
         {
             #params_quote
+            #type_checks
             #policy_item
         }
     })